@@ -1,17 +1,23 @@
 pub mod endpoints;
+pub mod lockout;
 pub mod service;
 
 use crate::service::PostgresAuthenticationService;
 use actix_web::web;
+use drogue_cloud_endpoint_common::{
+    sender::{DownstreamSender, ExternalClientPoolConfig},
+    sink::{self, MqttClientConfig},
+};
 use drogue_cloud_service_api::{
     health::HealthChecked,
+    kafka::KafkaClientConfig,
     webapp::{self as actix_web},
 };
 use drogue_cloud_service_common::{
     actix::http::{HttpBuilder, HttpConfig},
     app::{Startup, StartupExt},
     auth::openid::{Authenticator, AuthenticatorConfig},
-    openid_auth,
+    defaults, openid_auth,
 };
 use serde::Deserialize;
 use service::AuthenticationServiceConfig;
@@ -31,6 +37,19 @@ pub struct Config {
     #[serde(flatten)]
     pub auth_service_config: AuthenticationServiceConfig,
 
+    #[serde(default = "defaults::instance")]
+    pub instance: String,
+    #[serde(default = "defaults::check_kafka_topic_ready")]
+    pub check_kafka_topic_ready: bool,
+    /// Send an event to the application, when a device gets locked out.
+    #[serde(default)]
+    pub kafka_downstream_config: Option<KafkaClientConfig>,
+    /// Use an MQTT broker for events, instead of Kafka.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
+    #[serde(default)]
+    pub endpoint_pool: ExternalClientPoolConfig,
+
     #[serde(default)]
     pub http: HttpConfig,
 }
@@ -61,9 +80,26 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
     let authenticator = config.oauth.into_client().await?;
     let enable_auth = authenticator.is_some();
 
+    let mut service = service::PostgresAuthenticationService::new(config.auth_service_config)?;
+    match (config.kafka_downstream_config, config.mqtt_backbone) {
+        (None, None) => {}
+        (kafka_downstream_config, mqtt_backbone) => {
+            service = service.with_events(DownstreamSender::new(
+                sink::from_config(
+                    kafka_downstream_config.unwrap_or_default(),
+                    Default::default(),
+                    mqtt_backbone,
+                    config.check_kafka_topic_ready,
+                )?,
+                config.instance,
+                config.endpoint_pool,
+            )?);
+        }
+    }
+
     let data = web::Data::new(WebData {
         authenticator,
-        service: service::PostgresAuthenticationService::new(config.auth_service_config)?,
+        service,
     });
 
     let data_service = data.service.clone();
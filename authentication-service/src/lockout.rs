@@ -0,0 +1,323 @@
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Deserialize;
+use std::{
+    hash::Hash,
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LockoutConfig {
+    /// Number of consecutive failed attempts of a device, locking out the device.
+    ///
+    /// Setting this to zero disables the lockout of devices.
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    /// Number of consecutive failed attempts from a single address, locking out the address.
+    ///
+    /// Setting this to zero, the default, disables the lockout of addresses. Only enable it if
+    /// the endpoints see the actual addresses of devices, rather than those of a proxy or NAT
+    /// gateway shared by many devices.
+    #[serde(default = "default_max_source_failures")]
+    pub max_source_failures: u32,
+    /// Duration of the first lockout. Every further failed attempt doubles it.
+    #[serde(with = "humantime_serde", default = "default_initial_lockout")]
+    pub initial_lockout: Duration,
+    /// Maximum duration of a lockout. Failed attempts older than this are forgotten.
+    #[serde(with = "humantime_serde", default = "default_max_lockout")]
+    pub max_lockout: Duration,
+    /// Number of devices, and of addresses, to keep track of.
+    #[serde(default = "default_capacity")]
+    pub capacity: NonZeroUsize,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: default_max_failures(),
+            max_source_failures: default_max_source_failures(),
+            initial_lockout: default_initial_lockout(),
+            max_lockout: default_max_lockout(),
+            capacity: default_capacity(),
+        }
+    }
+}
+
+const fn default_max_failures() -> u32 {
+    5
+}
+
+const fn default_max_source_failures() -> u32 {
+    0
+}
+
+const fn default_initial_lockout() -> Duration {
+    Duration::from_secs(10)
+}
+
+const fn default_max_lockout() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+const DEFAULT_CAPACITY: NonZeroUsize = match NonZeroUsize::new(10_000) {
+    Some(capacity) => capacity,
+    None => panic!(),
+};
+
+const fn default_capacity() -> NonZeroUsize {
+    DEFAULT_CAPACITY
+}
+
+/// Failed attempts of a single device or address.
+#[derive(Clone, Debug, Default)]
+struct Failures {
+    failures: u32,
+    last: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn locked(&self, now: Instant) -> Option<Instant> {
+        self.locked_until.filter(|until| now < *until)
+    }
+
+    /// Record a failed attempt, returning the end of the lockout, if the attempt caused one.
+    fn failure(
+        &mut self,
+        now: Instant,
+        max_failures: u32,
+        config: &LockoutConfig,
+    ) -> Option<Instant> {
+        if matches!(self.last, Some(last) if now.saturating_duration_since(last) > config.max_lockout)
+        {
+            *self = Default::default();
+        }
+
+        self.failures = self.failures.saturating_add(1);
+        self.last = Some(now);
+
+        if self.failures < max_failures {
+            return None;
+        }
+
+        // double the lockout with every attempt beyond the threshold
+        let exp = (self.failures - max_failures).min(31);
+        let lockout = config
+            .initial_lockout
+            .saturating_mul(1 << exp)
+            .min(config.max_lockout);
+
+        let until = now + lockout;
+        self.locked_until = Some(until);
+        Some(until)
+    }
+}
+
+/// The result of recording a failed attempt.
+#[derive(Clone, Debug, Default)]
+pub struct Failure {
+    /// The number of consecutive failed attempts of the device.
+    pub failures: u32,
+    /// The end of the lockout of the device, if the attempt caused one.
+    pub device: Option<DateTime<Utc>>,
+    /// The end of the lockout of the address, if the attempt caused one.
+    pub source: Option<DateTime<Utc>>,
+}
+
+impl Failure {
+    /// The end of the lockout caused by the attempt, if any.
+    pub fn locked_until(&self) -> Option<DateTime<Utc>> {
+        self.device.max(self.source)
+    }
+}
+
+/// Keeps track of failed authentication attempts, per device and per address.
+///
+/// The state is kept in memory, so each instance of the service enforces its own limits. With
+/// multiple instances, an attacker gets up to the number of instances times the attempts. Only
+/// a limited number of devices is kept track of, failed attempts for many different devices
+/// evict the failed attempts of others.
+#[derive(Debug)]
+pub struct Lockout {
+    config: LockoutConfig,
+    devices: Mutex<LruCache<(String, String), Failures>>,
+    sources: Mutex<LruCache<IpAddr, Failures>>,
+}
+
+impl Lockout {
+    pub fn new(config: LockoutConfig) -> Self {
+        Self {
+            devices: Mutex::new(LruCache::new(config.capacity)),
+            sources: Mutex::new(LruCache::new(config.capacity)),
+            config,
+        }
+    }
+
+    /// Check if the device, or the address, is currently locked out.
+    pub fn check(
+        &self,
+        application: &str,
+        device: &str,
+        source: Option<IpAddr>,
+    ) -> Option<DateTime<Utc>> {
+        let now = Instant::now();
+
+        let device = self
+            .devices
+            .lock()
+            .unwrap()
+            .peek(&(application.to_string(), device.to_string()))
+            .and_then(|failures| failures.locked(now));
+        let source = source.and_then(|source| {
+            self.sources
+                .lock()
+                .unwrap()
+                .peek(&source)
+                .and_then(|failures| failures.locked(now))
+        });
+
+        device.max(source).map(|until| to_utc(now, until))
+    }
+
+    /// Record a failed attempt of a device.
+    pub fn failure(&self, application: &str, device: &str, source: Option<IpAddr>) -> Failure {
+        let now = Instant::now();
+        let mut result = Failure::default();
+
+        if self.config.max_failures > 0 {
+            let (failures, until) = self.record(
+                &self.devices,
+                (application.to_string(), device.to_string()),
+                now,
+                self.config.max_failures,
+            );
+            result.failures = failures;
+            result.device = until.map(|until| to_utc(now, until));
+        }
+
+        if let Some(source) = source.filter(|_| self.config.max_source_failures > 0) {
+            let (_, until) =
+                self.record(&self.sources, source, now, self.config.max_source_failures);
+            result.source = until.map(|until| to_utc(now, until));
+        }
+
+        result
+    }
+
+    /// Record a successful attempt of a device, resetting its failed attempts.
+    ///
+    /// The failed attempts of the address are kept, as the address might be shared by many devices.
+    pub fn success(&self, application: &str, device: &str) {
+        self.devices
+            .lock()
+            .unwrap()
+            .pop(&(application.to_string(), device.to_string()));
+    }
+
+    fn record<K: Hash + Eq>(
+        &self,
+        cache: &Mutex<LruCache<K, Failures>>,
+        key: K,
+        now: Instant,
+        max_failures: u32,
+    ) -> (u32, Option<Instant>) {
+        let mut cache = cache.lock().unwrap();
+        let mut failures = cache.pop(&key).unwrap_or_default();
+        let until = failures.failure(now, max_failures, &self.config);
+        let result = (failures.failures, until);
+        cache.put(key, failures);
+        result
+    }
+}
+
+fn to_utc(now: Instant, until: Instant) -> DateTime<Utc> {
+    Utc::now()
+        + chrono::Duration::from_std(until.saturating_duration_since(now))
+            .unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let config = LockoutConfig {
+            max_failures: 3,
+            initial_lockout: Duration::from_secs(10),
+            max_lockout: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut state = Failures::default();
+
+        assert_eq!(state.failure(now, 3, &config), None);
+        assert_eq!(state.failure(now, 3, &config), None);
+        assert!(state.locked(now).is_none());
+
+        // reaching the threshold locks
+        let until = state.failure(now, 3, &config);
+        assert_eq!(until, Some(now + Duration::from_secs(10)));
+        assert_eq!(state.locked(now), until);
+        assert!(state.locked(now + Duration::from_secs(10)).is_none());
+
+        // every further failure doubles the lockout
+        let now = now + Duration::from_secs(10);
+        assert_eq!(
+            state.failure(now, 3, &config),
+            Some(now + Duration::from_secs(20))
+        );
+        assert_eq!(
+            state.failure(now, 3, &config),
+            Some(now + Duration::from_secs(40))
+        );
+        // up to the maximum
+        assert_eq!(
+            state.failure(now, 3, &config),
+            Some(now + Duration::from_secs(60))
+        );
+
+        // after the maximum lockout, failures are forgotten
+        let now = now + Duration::from_secs(61);
+        assert_eq!(state.failure(now, 3, &config), None);
+        assert_eq!(state.failures, 1);
+    }
+
+    #[test]
+    fn test_lockout() {
+        let lockout = Lockout::new(LockoutConfig {
+            max_failures: 2,
+            max_source_failures: 3,
+            ..Default::default()
+        });
+        let source: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let failure = lockout.failure("app1", "device1", Some(source));
+        assert_eq!(failure.failures, 1);
+        assert!(failure.locked_until().is_none());
+
+        // a success resets the device, but not the address
+        lockout.success("app1", "device1");
+        let failure = lockout.failure("app1", "device1", Some(source));
+        assert_eq!(failure.failures, 1);
+        assert!(failure.locked_until().is_none());
+
+        // the third failure from the same address locks the address
+        let failure = lockout.failure("app1", "device2", Some(source));
+        assert!(failure.device.is_none());
+        assert!(failure.source.is_some());
+
+        assert!(lockout.check("app1", "device3", Some(source)).is_some());
+        assert!(lockout.check("app1", "device3", None).is_none());
+
+        // the second failure of the device locks the device
+        let failure = lockout.failure("app1", "device1", None);
+        assert_eq!(failure.failures, 2);
+        assert!(failure.device.is_some());
+        assert!(lockout.check("app1", "device1", None).is_some());
+        assert!(lockout.check("app2", "device1", None).is_none());
+    }
+}
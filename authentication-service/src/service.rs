@@ -1,3 +1,4 @@
+use crate::lockout::{Lockout, LockoutConfig};
 use actix_web::ResponseError;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -9,22 +10,26 @@ use drogue_client::{
 use drogue_cloud_database_common::{
     error::ServiceError,
     models::Lock,
-    models::{app::*, device::*},
+    models::{app::*, device::*, group::*, profile::*},
     postgres, Client, DatabaseService,
 };
+use drogue_cloud_endpoint_common::sender::{
+    DownstreamSender, Publish, PublishId, PublishOptions, PublishOutcome, Publisher,
+};
 use drogue_cloud_service_api::{
     auth::device::authn::{
-        self, AuthenticationRequest, AuthorizeGatewayRequest, GatewayOutcome, Outcome,
-        PreSharedKeyOutcome, PreSharedKeyRequest,
+        self, AuthFailedEvent, AuthenticationRequest, AuthorizeGatewayRequest, GatewayOutcome,
+        Outcome, PreSharedKeyOutcome, PreSharedKeyRequest, AUTH_FAILED_TYPE_EVENT,
     },
+    groups::{self, DeviceGroup},
     health::{HealthCheckError, HealthChecked},
-    webapp as actix_web,
+    profiles, webapp as actix_web,
 };
 use rustls::{server::AllowAnyAuthenticatedClient, Certificate, RootCertStore};
 use rustls_pemfile::Item;
 use serde::Deserialize;
 use sha_crypt::sha512_check;
-use std::{io::Cursor, ops::Add, time::Duration, time::SystemTime};
+use std::{io::Cursor, net::IpAddr, ops::Add, sync::Arc, time::Duration, time::SystemTime};
 use tracing::instrument;
 
 macro_rules! pass {
@@ -61,6 +66,9 @@ pub trait AuthenticationService: Clone {
 #[derive(Clone, Debug, Deserialize)]
 pub struct AuthenticationServiceConfig {
     pub pg: postgres::Config,
+    /// Lockout of devices and addresses, after failed authentication attempts.
+    #[serde(default)]
+    pub lockout: LockoutConfig,
 }
 
 impl DatabaseService for PostgresAuthenticationService {
@@ -78,33 +86,181 @@ impl HealthChecked for PostgresAuthenticationService {
     }
 }
 
+/// The identifiers of a failed authentication request.
+struct FailedRequest<'a> {
+    application: &'a str,
+    device: &'a str,
+    source: Option<IpAddr>,
+}
+
 #[derive(Clone)]
 pub struct PostgresAuthenticationService {
     pool: Pool,
+    lockout: Arc<Lockout>,
+    events: Option<DownstreamSender>,
 }
 
 impl PostgresAuthenticationService {
     pub fn new(config: AuthenticationServiceConfig) -> anyhow::Result<Self> {
         Ok(Self {
             pool: config.pg.create_pool()?,
+            lockout: Arc::new(Lockout::new(config.lockout)),
+            events: None,
         })
     }
 
-    #[instrument(skip(accessor), err)]
-    async fn validate_gateway<'c, C>(
+    /// Send an event to the application when a device gets locked out, using the provided sender.
+    pub fn with_events(mut self, events: DownstreamSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Record a failed attempt, and evaluate the outcome.
+    ///
+    /// If the attempt locked out the device, an event is sent to the application, if it exists.
+    async fn failed(
+        &self,
+        application: Option<&registry::v1::Application>,
+        request: &FailedRequest<'_>,
+    ) -> Outcome {
+        let failure = self
+            .lockout
+            .failure(request.application, request.device, request.source);
+
+        if let Some(until) = failure.device {
+            log::info!(
+                "Locking out device {}/{} after {} failed attempts, until: {}",
+                request.application,
+                request.device,
+                failure.failures,
+                until
+            );
+            if let Some(application) = application {
+                self.send_locked_event(
+                    application,
+                    request.device,
+                    AuthFailedEvent {
+                        failures: failure.failures,
+                        locked_until: until,
+                        source: request.source,
+                    },
+                )
+                .await;
+            }
+        }
+
+        match failure.locked_until() {
+            Some(until) => Outcome::Locked { until },
+            None => Outcome::Fail,
+        }
+    }
+
+    /// Send the event of a locked out device.
+    ///
+    /// Failing to send the event doesn't fail the authentication request, so errors are only
+    /// logged.
+    async fn send_locked_event(
+        &self,
+        application: &registry::v1::Application,
+        device: &str,
+        event: AuthFailedEvent,
+    ) {
+        let events = match &self.events {
+            Some(events) => events,
+            None => return,
+        };
+
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                log::warn!("Failed to encode lockout event: {err}");
+                return;
+            }
+        };
+
+        let id = PublishId {
+            name: device.to_string(),
+            uid: None,
+        };
+
+        let outcome = events
+            .publish(
+                Publish {
+                    application,
+                    device: id.clone(),
+                    sender: id,
+                    channel: "auth".to_string(),
+                    options: PublishOptions {
+                        r#type: Some(AUTH_FAILED_TYPE_EVENT.to_string()),
+                        content_type: Some("application/json".to_string()),
+                        ..Default::default()
+                    },
+                },
+                body,
+            )
+            .await;
+
+        match outcome {
+            Ok(PublishOutcome::Accepted) => {}
+            outcome => log::warn!("Failed to send lockout event: {outcome:?}"),
+        }
+    }
+
+    /// Apply the defaults and gateways of the groups of a device, returning the groups.
+    ///
+    /// The groups start with the group of the device, followed by its parents.
+    async fn apply_groups<C: Client>(
+        client: &C,
+        device: &mut registry::v1::Device,
+    ) -> Result<Vec<DeviceGroup>, ServiceError> {
+        let group = match groups::device_group(&device.spec) {
+            Ok(Some(group)) => group,
+            Ok(None) => return Ok(vec![]),
+            Err(err) => {
+                log::debug!("Ignoring invalid group of device: {}", err);
+                return Ok(vec![]);
+            }
+        };
+
+        let groups: Vec<DeviceGroup> = PostgresGroupAccessor::new(client)
+            .ancestors(&device.metadata.application, group)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        log::debug!("Found groups: {:?}", groups);
+
+        groups::apply_groups(&mut device.spec, &groups);
+
+        Ok(groups)
+    }
+
+    #[instrument(skip(client), err)]
+    async fn validate_gateway<C>(
         as_id: String,
-        accessor: PostgresDeviceAccessor<'c, C>,
+        client: &C,
         application: registry::v1::Application,
         device: registry::v1::Device,
     ) -> Result<Outcome, ServiceError>
     where
-        C: Client + 'c,
+        C: Client,
     {
         let device_id = &device.metadata.name;
+        let accessor = PostgresDeviceAccessor::new(client);
         Ok(
             match accessor.lookup(&application.metadata.name, &as_id).await? {
                 Some(as_device) if as_device.deletion_timestamp.is_none() => {
-                    let as_manage: registry::v1::Device = as_device.into();
+                    let mut as_manage: registry::v1::Device = as_device.into();
+                    let as_groups = Self::apply_groups(client, &mut as_manage).await?;
+                    if groups::is_group_gateway(device_id, &as_groups) {
+                        log::debug!(
+                            "Device {:?} allowed to publish as {:?}, by group",
+                            device_id,
+                            as_id
+                        );
+                        return Ok(pass!(application, device, Some(as_manage)));
+                    }
                     match as_manage.section::<registry::v1::DeviceSpecGatewaySelector>() {
                         Some(Ok(gateway_selector)) => {
                             if gateway_selector.match_names.contains(device_id) {
@@ -186,7 +342,7 @@ impl AuthenticationService for PostgresAuthenticationService {
         // lookup the device
 
         let accessor = PostgresDeviceAccessor::new(&c);
-        let device = match accessor
+        let mut device = match accessor
             .lookup(&application.metadata.name, &request.device)
             .await?
         {
@@ -196,6 +352,8 @@ impl AuthenticationService for PostgresAuthenticationService {
             }
         };
 
+        Self::apply_groups(&c, &mut device).await?;
+
         log::debug!("Found device: {:?}", device);
 
         // find
@@ -213,6 +371,22 @@ impl AuthenticationService for PostgresAuthenticationService {
 
     #[instrument(skip(self), err)]
     async fn authenticate(&self, request: AuthenticationRequest) -> Result<Outcome, Self::Error> {
+        // check for a lockout first, before spending any effort on the request
+
+        if let Some(until) =
+            self.lockout
+                .check(&request.application, &request.device, request.source)
+        {
+            log::debug!("Device or address is locked out until: {}", until);
+            return Ok(Outcome::Locked { until });
+        }
+
+        let failed = FailedRequest {
+            application: &request.application,
+            device: &request.device,
+            source: request.source,
+        };
+
         let c = self.pool.get().await?;
 
         // lookup the application
@@ -221,7 +395,7 @@ impl AuthenticationService for PostgresAuthenticationService {
         let application = match application.lookup(&request.application).await? {
             Some(application) => application.into(),
             None => {
-                return Ok(Outcome::Fail);
+                return Ok(self.failed(None, &failed).await);
             }
         };
 
@@ -230,42 +404,44 @@ impl AuthenticationService for PostgresAuthenticationService {
         // validate application
 
         if !validate_app(&application) {
-            return Ok(Outcome::Fail);
+            return Ok(self.failed(None, &failed).await);
         }
 
         // lookup the device
 
         let accessor = PostgresDeviceAccessor::new(&c);
-        let device = match accessor
+        let mut device = match accessor
             .lookup(&application.metadata.name, &request.device)
             .await?
         {
             Some(device) => device.into(),
             None => {
-                return Ok(Outcome::Fail);
+                return Ok(self.failed(Some(&application), &failed).await);
             }
         };
 
+        Self::apply_groups(&c, &mut device).await?;
+
         log::debug!("Found device: {:?}", device);
 
         // validate credential
 
-        Ok(
-            match validate_credential(&application, &device, &request.device, request.credential) {
-                true => {
-                    // check gateway
-                    match request.r#as {
-                        Some(as_id) if as_id != request.device => {
-                            Self::validate_gateway(as_id, accessor, application, device).await?
-                        }
-                        _ => {
-                            pass!(application, device, None)
-                        }
-                    }
-                }
-                false => Outcome::Fail,
-            },
-        )
+        if !validate_credential(&application, &device, &request.device, request.credential) {
+            return Ok(self.failed(Some(&application), &failed).await);
+        }
+
+        self.lockout.success(&request.application, &request.device);
+
+        // check gateway
+
+        Ok(match request.r#as {
+            Some(as_id) if as_id != request.device => {
+                Self::validate_gateway(as_id, &c, application, device).await?
+            }
+            _ => {
+                pass!(application, device, None)
+            }
+        })
     }
 
     #[instrument(skip(self), err)]
@@ -297,7 +473,7 @@ impl AuthenticationService for PostgresAuthenticationService {
 
         let accessor = PostgresDeviceAccessor::new(&c);
 
-        let device = match accessor
+        let mut device: registry::v1::Device = match accessor
             .get(&application.metadata.name, &request.device, Lock::None)
             .await?
         {
@@ -307,10 +483,20 @@ impl AuthenticationService for PostgresAuthenticationService {
             }
         };
 
+        // unlike the lookup by alias, getting the device doesn't merge its profile
+        if let Some(profile) = PostgresProfileAccessor::new(&c)
+            .device_profile(&application.metadata.name, &device.spec)
+            .await?
+        {
+            profiles::apply_profile(&mut device.spec, &profile);
+        }
+
+        Self::apply_groups(&c, &mut device).await?;
+
         log::debug!("Found device: {:?}", device);
 
         Ok(
-            match Self::validate_gateway(request.r#as, accessor, application, device).await? {
+            match Self::validate_gateway(request.r#as, &c, application, device).await? {
                 Outcome::Pass {
                     r#as: Some(r#as), ..
                 } => GatewayOutcome::Pass { r#as },
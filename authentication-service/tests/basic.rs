@@ -65,6 +65,7 @@ async fn test_auth_passes_password() {
         device: "device1".into(),
         credential: Credential::Password("foo".into()),
         r#as: None,
+        source: None,
     } => device1_json());
 }
 
@@ -77,6 +78,7 @@ async fn test_auth_passes_password_alias() {
         device: "foo".into(),
         credential: Credential::Password("bar".into()),
         r#as: None,
+        source: None,
     } => device3_json());
 }
 
@@ -90,6 +92,7 @@ async fn test_auth_passes_password_with_device_username() {
         device: "device1".into(),
         credential: Credential::UsernamePassword{username: "device1".into(), password: "foo".into()},
         r#as: None,
+        source: None,
     } => device1_json());
 }
 
@@ -103,6 +106,7 @@ async fn test_auth_fails_password_with_non_matching_device_username() {
         device: "device1".into(),
         credential: Credential::UsernamePassword{username: "device2".into(), password: "foo".into()},
         r#as: None,
+        source: None,
     } => json!("fail"));
 }
 
@@ -114,6 +118,7 @@ async fn test_auth_fails_wrong_password() {
             device: "device1".into(),
             credential: Credential::Password("foo1".into()),
             r#as: None,
+            source: None,
     } => json!("fail"));
 }
 
@@ -125,6 +130,7 @@ async fn test_auth_fails_missing_tenant() {
             device: "device1".into(),
             credential: Credential::Password("foo".into()),
             r#as: None,
+            source: None,
     } => json!("fail"));
 }
 
@@ -136,6 +142,7 @@ async fn test_auth_fails_missing_device() {
             device: "device2".into(),
             credential: Credential::Password("foo".into()),
             r#as: None,
+            source: None,
     } => json!("fail"));
 }
 
@@ -147,6 +154,7 @@ async fn test_auth_passes_username_password() {
             device: "device3".into(),
             credential: Credential::UsernamePassword{username: "foo".into(), password: "bar".into()},
             r#as: None,
+            source: None,
     } => device3_json());
 }
 
@@ -159,6 +167,7 @@ async fn test_auth_fails_password_only() {
             device: "device3".into(),
             credential: Credential::Password("bar".into()),
             r#as: None,
+            source: None,
     } => json!("fail"));
 }
 
@@ -171,5 +180,6 @@ async fn test_auth_passes_password_only() {
             device: "device3".into(),
             credential: Credential::Password("baz".into()),
             r#as: None,
+            source: None,
     }  => device3_json());
 }
@@ -10,14 +10,35 @@ pub fn init() {
 #[macro_export]
 macro_rules! test {
     ($v:ident => $code:tt) => {{
+        test!(($v, _sink) => $code)
+    }};
+    (($v:ident, $sink:ident) => $code:tt) => {{
+        test!(($v, $sink), Default::default() => $code)
+    }};
+    (($v:ident, $sink:ident), $lockout:expr => $code:tt) => {{
         common::init();
 
         let cli = client();
-        let db = db(&cli, |pg| service::AuthenticationServiceConfig { pg }).unwrap();
+        let db = db(&cli, |pg| service::AuthenticationServiceConfig {
+            pg,
+            lockout: $lockout,
+        })
+        .unwrap();
+
+        let $sink = drogue_cloud_test_common::sink::MockSink::new();
 
         let data = web::Data::new(WebData {
             authenticator: None,
-            service: service::PostgresAuthenticationService::new(db.config.clone()).unwrap(),
+            service: service::PostgresAuthenticationService::new(db.config.clone())
+                .unwrap()
+                .with_events(
+                    drogue_cloud_endpoint_common::sender::DownstreamSender::new(
+                        $sink.clone(),
+                        "drogue".to_string(),
+                        Default::default(),
+                    )
+                    .unwrap(),
+                ),
         });
 
         let auth = drogue_cloud_service_common::mock_auth!();
@@ -52,3 +73,26 @@ macro_rules! test_auth {
         })
     };
 }
+
+/// Authenticate a device using a password, returning the JSON response.
+#[macro_export]
+macro_rules! auth {
+    ($app:expr, $application:literal, $device:literal, $pass:literal, $as:expr) => {{
+        let resp = actix_web::test::TestRequest::post()
+            .uri("/api/v1/auth")
+            .set_json(&AuthenticationRequest {
+                application: $application.into(),
+                device: $device.into(),
+                credential: Credential::Password($pass.into()),
+                r#as: $as,
+                source: None,
+            })
+            .send_request(&$app)
+            .await;
+
+        assert!(resp.status().is_success());
+
+        let result: Value = actix_web::test::read_body_json(resp).await;
+        result
+    }};
+}
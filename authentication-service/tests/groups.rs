@@ -0,0 +1,51 @@
+mod common;
+
+use actix_web::{web, App};
+use drogue_cloud_authentication_service::{endpoints, service, WebData};
+use drogue_cloud_service_api::auth::device::authn::{AuthenticationRequest, Credential};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_test_common::{client, db};
+use serde_json::{json, Value};
+use serial_test::serial;
+
+/// Authorize a device using a password, inherited from its group.
+#[actix_rt::test]
+#[serial]
+async fn test_auth_inherits_defaults() {
+    test!(app => {
+        let result = auth!(app, "app4", "device1", "foo", None);
+
+        // nested groups take precedence, credentials must be stripped, gateways are added
+        assert_eq!(
+            result["outcome"]["pass"]["device"]["spec"],
+            json!({
+                "group": "floor-1",
+                "foo": "baz",
+                "gatewaySelector": {"matchNames": ["gateway1"]},
+            })
+        );
+
+        let result = auth!(app, "app4", "device1", "bar", None);
+        assert_eq!(result, json!({"outcome": "fail"}));
+    })
+}
+
+/// Authorize a gateway, listed by a parent group of the device.
+#[actix_rt::test]
+#[serial]
+async fn test_auth_group_gateway() {
+    test!(app => {
+        let result = auth!(app, "app4", "gateway1", "bar", Some("device1".into()));
+        assert_eq!(
+            result["outcome"]["pass"]["device"]["metadata"]["name"],
+            json!("gateway1")
+        );
+        assert_eq!(
+            result["outcome"]["pass"]["as"]["metadata"]["name"],
+            json!("device1")
+        );
+
+        let result = auth!(app, "app4", "gateway2", "bar", Some("device1".into()));
+        assert_eq!(result, json!({"outcome": "fail"}));
+    })
+}
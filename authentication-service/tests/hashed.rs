@@ -104,5 +104,6 @@ async fn test_auth_password_with_hashes(
         device: device.to_string(),
         credential: Credential::Password(password.to_string()),
         r#as: None,
+        source: None,
     } => outcome);
 }
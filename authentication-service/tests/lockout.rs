@@ -0,0 +1,165 @@
+mod common;
+
+use actix_web::{web, App};
+use cloudevents::{AttributesReader, Data};
+use drogue_cloud_authentication_service::{endpoints, lockout::LockoutConfig, service, WebData};
+use drogue_cloud_service_api::auth::device::authn::{
+    AuthFailedEvent, AuthenticationRequest, Credential, AUTH_FAILED_TYPE_EVENT,
+};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_test_common::{client, db};
+use serde_json::{json, Value};
+use serial_test::serial;
+use std::net::IpAddr;
+
+/// Authenticate a device using a password, from an address, returning the JSON response.
+macro_rules! auth_from {
+    ($app:expr, $device:expr, $pass:literal, $source:expr) => {{
+        auth_from!($app, "app1", $device, $pass, $source)
+    }};
+    ($app:expr, $application:expr, $device:expr, $pass:literal, $source:expr) => {{
+        let resp = actix_web::test::TestRequest::post()
+            .uri("/api/v1/auth")
+            .set_json(&AuthenticationRequest {
+                application: $application.into(),
+                device: $device.into(),
+                credential: Credential::Password($pass.into()),
+                r#as: None,
+                source: $source,
+            })
+            .send_request(&$app)
+            .await;
+
+        assert!(resp.status().is_success());
+
+        let result: Value = actix_web::test::read_body_json(resp).await;
+        result
+    }};
+}
+
+fn is_locked(result: &Value) -> bool {
+    result["outcome"]["locked"]["until"].is_string()
+}
+
+/// Lock out a device after failed attempts.
+#[actix_rt::test]
+#[serial]
+async fn test_lockout_device() {
+    test!((app, sink) => {
+        for _ in 0..4 {
+            let result = auth_from!(app, "device1", "bar", None);
+            assert_eq!(result, json!({"outcome": "fail"}));
+        }
+
+        // the fifth attempt locks out the device
+        let result = auth_from!(app, "device1", "bar", None);
+        assert!(is_locked(&result), "Result: {result}");
+
+        // even with the correct password
+        let result = auth_from!(app, "device1", "foo", None);
+        assert!(is_locked(&result), "Result: {result}");
+
+        // other devices are not affected
+        let result = auth_from!(app, "foo", "bar", None);
+        assert!(result["outcome"]["pass"].is_object(), "Result: {result}");
+
+        // the application got notified
+        let events = sink.events().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].ty(), AUTH_FAILED_TYPE_EVENT);
+        assert_eq!(events[0].subject(), Some("auth"));
+        let event: AuthFailedEvent = match events[0].data() {
+            Some(Data::Json(value)) => serde_json::from_value(value.clone()).unwrap(),
+            Some(Data::Binary(data)) => serde_json::from_slice(data).unwrap(),
+            data => panic!("Unexpected payload: {data:?}"),
+        };
+        assert_eq!(event.failures, 5);
+        assert_eq!(event.source, None);
+    });
+}
+
+/// Attempts for a disabled application count as failed attempts.
+#[actix_rt::test]
+#[serial]
+async fn test_lockout_disabled_application() {
+    test!((app, sink) => {
+        for _ in 0..4 {
+            let result = auth_from!(app, "app6", "device1", "foo", None);
+            assert_eq!(result, json!({"outcome": "fail"}));
+        }
+
+        let result = auth_from!(app, "app6", "device1", "foo", None);
+        assert!(is_locked(&result), "Result: {result}");
+
+        // the disabled application doesn't get notified
+        assert!(sink.events().await.is_empty());
+    });
+}
+
+/// A successful attempt resets the failed attempts of a device.
+#[actix_rt::test]
+#[serial]
+async fn test_lockout_reset() {
+    test!(app => {
+        for _ in 0..4 {
+            let result = auth_from!(app, "device1", "bar", None);
+            assert_eq!(result, json!({"outcome": "fail"}));
+        }
+
+        let result = auth_from!(app, "device1", "foo", None);
+        assert!(result["outcome"]["pass"].is_object(), "Result: {result}");
+
+        let result = auth_from!(app, "device1", "bar", None);
+        assert_eq!(result, json!({"outcome": "fail"}));
+    });
+}
+
+/// Lock out an address after failed attempts, for different devices.
+#[actix_rt::test]
+#[serial]
+async fn test_lockout_source() {
+    let lockout = LockoutConfig {
+        max_source_failures: 50,
+        ..Default::default()
+    };
+    test!((app, sink), lockout => {
+        let source: Option<IpAddr> = Some("192.0.2.1".parse().unwrap());
+
+        for i in 0..49 {
+            let result = auth_from!(app, format!("unknown-{i}"), "bar", source);
+            assert_eq!(result, json!({"outcome": "fail"}));
+        }
+
+        // the 50th attempt locks out the address
+        let result = auth_from!(app, "unknown-49", "bar", source);
+        assert!(is_locked(&result), "Result: {result}");
+
+        // even for a device with the correct password
+        let result = auth_from!(app, "device1", "foo", source);
+        assert!(is_locked(&result), "Result: {result}");
+
+        // other addresses are not affected
+        let result = auth_from!(app, "device1", "foo", Some("192.0.2.2".parse().unwrap()));
+        assert!(result["outcome"]["pass"].is_object(), "Result: {result}");
+
+        // no device got locked out
+        assert!(sink.events().await.is_empty());
+    });
+}
+
+/// Addresses are not locked out by default, as they might be shared by many devices.
+#[actix_rt::test]
+#[serial]
+async fn test_lockout_source_disabled() {
+    test!(app => {
+        let source: Option<IpAddr> = Some("192.0.2.1".parse().unwrap());
+
+        for i in 0..60 {
+            let result = auth_from!(app, format!("unknown-{i}"), "bar", source);
+            assert_eq!(result, json!({"outcome": "fail"}));
+        }
+
+        let result = auth_from!(app, "device1", "foo", source);
+        assert!(result["outcome"]["pass"].is_object(), "Result: {result}");
+    });
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use actix_web::{web, App};
+use drogue_cloud_authentication_service::{endpoints, service, WebData};
+use drogue_cloud_service_api::auth::device::authn::{
+    AuthenticationRequest, AuthorizeGatewayRequest, Credential,
+};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_test_common::{client, db};
+use serde_json::{json, Value};
+use serial_test::serial;
+
+/// Authorize a device, using the effective spec from its profile.
+#[actix_rt::test]
+#[serial]
+async fn test_auth_applies_profile() {
+    test!(app => {
+        let result = auth!(app, "app5", "device1", "foo", None);
+
+        // sections of the device take precedence
+        assert_eq!(
+            result["outcome"]["pass"]["device"]["spec"],
+            json!({
+                "profile": "sensor",
+                "foo": "override",
+                "bar": "baz",
+                "gatewaySelector": {"matchNames": ["gateway1"]},
+            })
+        );
+    })
+}
+
+/// Authorize a gateway, selected by the profile of the device.
+#[actix_rt::test]
+#[serial]
+async fn test_auth_profile_gateway() {
+    test!(app => {
+        let result = auth!(app, "app5", "gateway1", "bar", Some("device1".into()));
+        assert_eq!(
+            result["outcome"]["pass"]["as"]["metadata"]["name"],
+            json!("device1")
+        );
+    })
+}
+
+/// Authorize a gateway to act as a device, returning the effective spec of the device.
+#[actix_rt::test]
+#[serial]
+async fn test_authorize_as_applies_profile() {
+    test!(app => {
+        let resp = actix_web::test::TestRequest::post()
+            .uri("/api/v1/authorize_as")
+            .set_json(&AuthorizeGatewayRequest {
+                application: "app5".into(),
+                device: "gateway1".into(),
+                r#as: "device1".into(),
+            })
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let result: Value = actix_web::test::read_body_json(resp).await;
+        assert_eq!(result["outcome"]["pass"]["as"]["spec"]["bar"], json!("baz"));
+    })
+}
@@ -0,0 +1,225 @@
+--
+-- app4
+--
+
+INSERT INTO APPLICATIONS (
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app4',
+    '4e185ea6-7c26-11eb-a319-d45d6455d240',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{}'::JSONB
+);
+
+INSERT INTO APPLICATION_ALIASES (
+    APP,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app4',
+    'id',
+    'app4'
+);
+
+--
+-- building-1 -> gateways: gateway1, pass: foo
+--
+
+INSERT INTO DEVICE_GROUPS (
+    APP,
+    NAME,
+    UID,
+    PARENT,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    DATA
+) VALUES (
+    'app4',
+    'building-1',
+    '4e185ea6-7c26-11eb-a319-d45d6455d241',
+    NULL,
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    '{
+      "spec": {
+        "gateways": ["gateway1"],
+        "defaults": {
+          "credentials": {
+            "credentials": [
+              { "pass": "foo"}
+            ]
+          },
+          "foo": "bar"
+        }
+      }
+    }'::JSONB
+);
+
+--
+-- floor-1 (in building-1)
+--
+
+INSERT INTO DEVICE_GROUPS (
+    APP,
+    NAME,
+    UID,
+    PARENT,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    DATA
+) VALUES (
+    'app4',
+    'floor-1',
+    '4e185ea6-7c26-11eb-a319-d45d6455d242',
+    'building-1',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    '{
+      "spec": {
+        "defaults": {
+          "foo": "baz"
+        }
+      }
+    }'::JSONB
+);
+
+--
+-- device1 (in floor-1) -> inherits pass: foo
+--
+
+INSERT INTO DEVICES (
+    APP,
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app4',
+    'device1',
+    '4e185ea6-7c26-11eb-a319-d45d6455d243',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{
+      "spec": {
+        "group": "floor-1"
+      }
+    }'::JSONB
+);
+
+INSERT INTO DEVICE_ALIASES(
+    APP,
+    DEVICE,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app4',
+    'device1',
+    'id',
+    'device1'
+);
+
+--
+-- gateway1 -> pass: bar
+--
+
+INSERT INTO DEVICES (
+    APP,
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app4',
+    'gateway1',
+    '4e185ea6-7c26-11eb-a319-d45d6455d244',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{
+      "spec": {
+        "credentials": {
+          "credentials": [
+            { "pass": "bar"}
+          ]
+        }
+      }
+    }'::JSONB
+);
+
+INSERT INTO DEVICE_ALIASES(
+    APP,
+    DEVICE,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app4',
+    'gateway1',
+    'id',
+    'gateway1'
+);
+
+--
+-- gateway2 -> pass: bar, not a gateway of any group
+--
+
+INSERT INTO DEVICES (
+    APP,
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app4',
+    'gateway2',
+    '4e185ea6-7c26-11eb-a319-d45d6455d245',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{
+      "spec": {
+        "credentials": {
+          "credentials": [
+            { "pass": "bar"}
+          ]
+        }
+      }
+    }'::JSONB
+);
+
+INSERT INTO DEVICE_ALIASES(
+    APP,
+    DEVICE,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app4',
+    'gateway2',
+    'id',
+    'gateway2'
+);
@@ -0,0 +1,151 @@
+--
+-- app5
+--
+
+INSERT INTO APPLICATIONS (
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app5',
+    '4e185ea6-7c26-11eb-a319-d45d6455d250',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{}'::JSONB
+);
+
+INSERT INTO APPLICATION_ALIASES (
+    APP,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app5',
+    'id',
+    'app5'
+);
+
+--
+-- sensor -> gateway: gateway1
+--
+
+INSERT INTO DEVICE_PROFILES (
+    APP,
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    DATA
+) VALUES (
+    'app5',
+    'sensor',
+    '4e185ea6-7c26-11eb-a319-d45d6455d251',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    '{
+      "spec": {
+        "gatewaySelector": {
+          "matchNames": ["gateway1"]
+        },
+        "foo": "bar",
+        "bar": "baz"
+      }
+    }'::JSONB
+);
+
+--
+-- device1 (profile: sensor) -> pass: foo
+--
+
+INSERT INTO DEVICES (
+    APP,
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app5',
+    'device1',
+    '4e185ea6-7c26-11eb-a319-d45d6455d252',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{
+      "spec": {
+        "profile": "sensor",
+        "foo": "override",
+        "credentials": {
+          "credentials": [
+            { "pass": "foo"}
+          ]
+        }
+      }
+    }'::JSONB
+);
+
+INSERT INTO DEVICE_ALIASES(
+    APP,
+    DEVICE,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app5',
+    'device1',
+    'id',
+    'device1'
+);
+
+--
+-- gateway1 -> pass: bar
+--
+
+INSERT INTO DEVICES (
+    APP,
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app5',
+    'gateway1',
+    '4e185ea6-7c26-11eb-a319-d45d6455d253',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{
+      "spec": {
+        "credentials": {
+          "credentials": [
+            { "pass": "bar"}
+          ]
+        }
+      }
+    }'::JSONB
+);
+
+INSERT INTO DEVICE_ALIASES(
+    APP,
+    DEVICE,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app5',
+    'gateway1',
+    'id',
+    'gateway1'
+);
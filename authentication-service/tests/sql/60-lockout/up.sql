@@ -0,0 +1,37 @@
+--
+-- app6 -> disabled
+--
+
+INSERT INTO APPLICATIONS (
+    NAME,
+    UID,
+    CREATION_TIMESTAMP,
+    RESOURCE_VERSION,
+    GENERATION,
+    REVISION,
+    DATA
+) VALUES (
+    'app6',
+    '4e185ea6-7c26-11eb-a319-d45d6455d260',
+    '2020-01-01 00:00:00',
+    'A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11',
+    0,
+    0,
+    '{
+      "spec": {
+        "core": {
+          "disabled": true
+        }
+      }
+    }'::JSONB
+);
+
+INSERT INTO APPLICATION_ALIASES (
+    APP,
+    TYPE,
+    ALIAS
+) VALUES (
+    'app6',
+    'id',
+    'app6'
+);
@@ -261,7 +261,8 @@ async fn test_x509_client_cert() {
         application: app_id.into(),
         device: device_id.into(),
         credential: Credential::Certificate(from_pem(DEVICE1_CRT).unwrap()),
-        r#as: None
+        r#as: None,
+        source: None,
     } => device1_json());
 }
 
@@ -277,6 +278,7 @@ async fn test_x509_client_cert_bad() {
         application: app_id.into(),
         device: device_id.into(),
         credential: Credential::Certificate(from_pem(DEVICE1_CRT_BAD).unwrap()),
-        r#as: None
+        r#as: None,
+        source: None,
     } => json!("fail"));
 }
@@ -2,7 +2,7 @@
 //!
 //! Contains actors that handles commands for CoAP endpoint
 
-use crate::{error::CoapEndpointError, HEADER_COMMAND};
+use crate::{error::CoapEndpointError, HEADER_COMMAND, HEADER_CORRELATION_ID};
 use actix_rt::time::timeout;
 use coap_lite::{CoapRequest, CoapResponse, ResponseType};
 use drogue_cloud_endpoint_common::command::{CommandFilter, Commands, Subscription};
@@ -21,29 +21,34 @@ pub async fn wait_for_command(
                 mut receiver,
                 handle,
             } = commands.subscribe(filter).await;
-            match timeout(Duration::from_secs(ttd), receiver.recv()).await {
+            let result = timeout(Duration::from_secs(ttd), receiver.recv()).await;
+            commands.unsubscribe(handle).await;
+            // return commands we received, but cannot deliver anymore
+            commands.requeue(receiver).await;
+
+            match result {
                 // Command is received
                 Ok(Some(cmd)) => {
-                    commands.unsubscribe(handle).await;
                     log::debug!("Got command: {:?}", cmd);
                     // Construct response
                     Ok(req.response.map(|mut v| {
                         v.set_status(ResponseType::Content);
                         v.message
                             .add_option(HEADER_COMMAND, cmd.command.as_bytes().to_vec());
+                        if let Some(correlation_id) = cmd.correlation_id {
+                            v.message
+                                .add_option(HEADER_CORRELATION_ID, correlation_id.into_bytes());
+                        }
                         v.message.payload = cmd.payload.unwrap_or_default();
                         v
                     }))
                 }
                 // If time limit is reached
-                _ => {
-                    commands.unsubscribe(handle).await;
-                    Ok(req.response.map(|mut v| {
-                        v.set_status(ResponseType::Changed);
-                        v.message.payload = vec![];
-                        v
-                    }))
-                }
+                _ => Ok(req.response.map(|mut v| {
+                    v.set_status(ResponseType::Changed);
+                    v.message.payload = vec![];
+                    v
+                })),
             }
         }
         _ => Ok(req.response.map(|mut v| {
@@ -13,6 +13,7 @@ impl CoapEndpointError {
             EndpointError::ConfigurationError { .. } => ResponseType::InternalServerError,
             EndpointError::AuthenticationServiceError { .. } => ResponseType::ServiceUnavailable,
             EndpointError::AuthenticationError { .. } => ResponseType::Forbidden,
+            EndpointError::AuthenticationLocked { .. } => ResponseType::TooManyRequests,
         }
     }
 }
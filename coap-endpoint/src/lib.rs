@@ -12,15 +12,19 @@ use coap_lite::{CoapOption, CoapRequest, CoapResponse};
 use drogue_cloud_endpoint_common::psk::{set_ssl_identity, Identity, VerifiedIdentity};
 use drogue_cloud_endpoint_common::{
     auth::AuthConfig,
-    command::{Commands, KafkaCommandSource, KafkaCommandSourceConfig},
+    command::{
+        CommandQueue, CommandQueueConfig, CommandSource, CommandStatusConfig,
+        CommandStatusEventSender, Commands, KafkaCommandSourceConfig,
+    },
     error::EndpointError,
     sender::{DownstreamSender, ExternalClientPoolConfig},
-    sink::KafkaSink,
+    sink::{self, KafkaSinkOptions, MqttClientConfig, SpoolConfig},
 };
 use drogue_cloud_service_api::auth::device::authn::PreSharedKeyOutcome;
 use drogue_cloud_service_api::kafka::KafkaClientConfig;
 use drogue_cloud_service_common::{
     app::{Startup, StartupExt},
+    client::{DeviceStateClient, DeviceStateClientConfig},
     defaults,
 };
 use tokio_dtls_stream_sink::Server as DtlsServer;
@@ -43,6 +47,10 @@ pub const AUTH_OPTION: CoapOption = CoapOption::Unknown(4209);
 // Option Number 4210 corresponds to the option assigned to carry command information,
 // which is meant for commands to be sent back to the device in the response
 pub const HEADER_COMMAND: CoapOption = CoapOption::Unknown(4210);
+//
+// Option Number 4211 carries the correlation ID of a command, which the device must use
+// when replying to the command
+pub const HEADER_CORRELATION_ID: CoapOption = CoapOption::Unknown(4211);
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
@@ -53,6 +61,16 @@ pub struct Config {
 
     pub kafka_downstream_config: KafkaClientConfig,
     pub kafka_command_config: KafkaClientConfig,
+    #[serde(default)]
+    pub kafka_downstream_options: KafkaSinkOptions,
+
+    /// Use an MQTT broker for events and commands, instead of Kafka.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
+
+    /// Spool events on disk, while the sink is unavailable.
+    #[serde(default)]
+    pub spool: Option<SpoolConfig>,
 
     pub instance: String,
 
@@ -64,6 +82,20 @@ pub struct Config {
     #[serde(default)]
     pub endpoint_pool: ExternalClientPoolConfig,
 
+    #[serde(default)]
+    pub command_queue: CommandQueueConfig,
+
+    #[serde(default)]
+    pub command_status: CommandStatusConfig,
+
+    /// Report events on the twin channel of an application to the device state service.
+    #[serde(default)]
+    pub twin: Option<DeviceStateClientConfig>,
+
+    /// Record the last event of each device and channel in the device state service.
+    #[serde(default)]
+    pub last_values: Option<DeviceStateClientConfig>,
+
     #[serde(default)]
     pub disable_dtls: bool,
 
@@ -93,20 +125,40 @@ pub struct App {
 }
 
 pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()> {
-    let commands = Commands::new();
     let addr = config
         .bind_addr_coap
         .unwrap_or_else(|| "[::]:5683".to_string());
-    let coap_server_commands = commands.clone();
 
-    let sender = DownstreamSender::new(
-        KafkaSink::from_config(
-            config.kafka_downstream_config,
-            config.check_kafka_topic_ready,
-        )?,
+    let mut sender = DownstreamSender::new(
+        sink::with_spool(
+            sink::from_config(
+                config.kafka_downstream_config,
+                config.kafka_downstream_options,
+                config.mqtt_backbone.clone(),
+                config.check_kafka_topic_ready,
+            )?,
+            config.spool,
+        )
+        .await?,
         config.instance,
         config.endpoint_pool,
     )?;
+    if let Some(twin) = config.twin {
+        sender = sender.with_twin(DeviceStateClient::from_config(twin).await?);
+    }
+    if let Some(last_values) = config.last_values {
+        sender = sender.with_last_values(DeviceStateClient::from_config(last_values).await?);
+    }
+
+    let status =
+        CommandStatusEventSender::from_config(config.command_status, sender.clone()).await?;
+    let queue = CommandQueue::from_config(config.command_queue, sender.clone())
+        .await?
+        .map(|queue| queue.with_status_handler(status.clone()));
+    let commands = Commands::new()
+        .with_queue(queue.clone())
+        .with_status_handler(status);
+    let coap_server_commands = commands.clone();
 
     let app = App {
         downstream: sender,
@@ -117,10 +169,11 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
         disable_psk: config.disable_psk,
     };
 
-    let command_source = KafkaCommandSource::new(
+    let command_source = CommandSource::new(
         commands,
         config.kafka_command_config,
         config.command_source_kafka,
+        config.mqtt_backbone,
     )?;
 
     let server = UdpSocket::bind(&addr).await?;
@@ -211,6 +264,9 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
         }
     });
     startup.check(command_source);
+    if let Some(queue) = queue {
+        startup.spawn(queue.run_pruner());
+    }
 
     Ok(())
 }
@@ -1,5 +1,5 @@
 use crate::error::CoapEndpointError;
-use coap_lite::{CoapRequest, CoapResponse};
+use coap_lite::{CoapOption, CoapRequest, CoapResponse};
 use std::net::SocketAddr;
 
 pub trait Responder {
@@ -13,6 +13,11 @@ impl Responder for Result<Option<CoapResponse>, CoapEndpointError> {
             Err(e) => {
                 req.response.as_mut().map(|v| {
                     v.set_status(e.status_code());
+                    if let Some(retry_after) = e.0.retry_after() {
+                        // RFC 8516: Max-Age indicates when the client may retry
+                        v.message
+                            .add_option(CoapOption::MaxAge, encode_uint(retry_after));
+                    }
                     v.message.payload = e.to_string()[..].as_bytes().to_vec();
                     v
                 });
@@ -21,3 +26,12 @@ impl Responder for Result<Option<CoapResponse>, CoapEndpointError> {
         }
     }
 }
+
+/// Encode an unsigned integer option value, using the minimal number of bytes.
+fn encode_uint(value: u64) -> Vec<u8> {
+    value
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|b| *b == 0)
+        .collect()
+}
@@ -105,7 +105,7 @@ pub async fn publish(
             HeaderValue::from_bytes(auth).as_ref().ok()
         );
     }
-    let (application, device, r#as) = match authenticator
+    let (application, device, r#as, labels) = match authenticator
         .authenticate_coap(
             opts.common.application,
             opts.common.device,
@@ -114,6 +114,7 @@ pub async fn publish(
                 .as_ref(),
             certs,
             verified_identity,
+            req.source.map(|addr| addr.ip()),
         )
         .await
         .map_err(|err| CoapEndpointError(err.into()))?
@@ -122,11 +123,20 @@ pub async fn publish(
         authn::Outcome::Fail => {
             return Err(CoapEndpointError(EndpointError::AuthenticationError));
         }
+        authn::Outcome::Locked { until } => {
+            return Err(CoapEndpointError(EndpointError::AuthenticationLocked {
+                until,
+            }));
+        }
         authn::Outcome::Pass {
             application,
             device,
             r#as,
-        } => (application, device.metadata.name, r#as),
+        } => {
+            // labels of the device the event originates from
+            let labels = r#as.as_ref().unwrap_or(&device).metadata.labels.clone();
+            (application, device.metadata.name, r#as, labels)
+        }
     };
 
     // If we have an "as" parameter, we publish as another device.
@@ -151,6 +161,7 @@ pub async fn publish(
                 .get_content_format()
                 .and_then(|v| content_format_to_media_type(v))
                 .map(|s| s.to_string()),
+            labels,
             ..Default::default()
         },
     };
@@ -1,11 +1,15 @@
+mod scheduler;
 mod v1alpha1;
 
+pub use scheduler::{CommandDelivery, EndpointDelivery, PostgresCommandScheduler, SchedulerConfig};
+
 use actix_web::{web, HttpResponse, Responder};
 use drogue_client::{registry, user::v1::authz::Permission};
 use drogue_cloud_endpoint_common::{
-    sender::{ExternalClientPoolConfig, UpstreamSender},
-    sink::KafkaSink,
+    sender::{DownstreamSender, ExternalClientPoolConfig, UpstreamSender},
+    sink::{self, MqttClientConfig},
 };
+use drogue_cloud_integration_common::commands::ReplyRouter;
 use drogue_cloud_service_api::{
     health::HealthChecked,
     kafka::KafkaClientConfig,
@@ -25,7 +29,7 @@ use drogue_cloud_service_common::{
 };
 use serde::Deserialize;
 use serde_json::json;
-use std::str;
+use std::{str, time::Duration};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
@@ -38,6 +42,10 @@ pub struct Config {
 
     pub command_kafka_sink: KafkaClientConfig,
 
+    /// Use an MQTT broker for events and commands, instead of Kafka.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
+
     #[serde(default = "defaults::check_kafka_topic_ready")]
     pub check_kafka_topic_ready: bool,
 
@@ -47,10 +55,30 @@ pub struct Config {
     #[serde(default)]
     pub endpoint_pool: ExternalClientPoolConfig,
 
+    /// The maximum time a request may wait for the reply of a device.
+    #[serde(with = "humantime_serde", default = "default_max_reply_timeout")]
+    pub max_reply_timeout: Duration,
+
+    /// Enable sending commands at a later point in time.
+    #[serde(default)]
+    pub scheduler: Option<SchedulerConfig>,
+
     #[serde(default)]
     pub http: HttpConfig,
 }
 
+const fn default_max_reply_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Settings for waiting on replies to commands.
+#[derive(Clone, Debug)]
+pub struct ReplyConfig {
+    /// Consumes the device replies.
+    pub router: ReplyRouter,
+    pub max_timeout: Duration,
+}
+
 #[derive(Debug)]
 pub struct WebData {
     pub authenticator: Option<Authenticator>,
@@ -60,16 +88,32 @@ async fn index() -> impl Responder {
     HttpResponse::Ok().json(json!({"success": true}))
 }
 
+/// Create the configurator of the endpoint.
+///
+/// Background tasks, like the scheduler, get registered with the provided startup context.
 pub async fn configurator(
     config: Config,
+    startup: &mut dyn Startup,
 ) -> anyhow::Result<(
     impl Fn(&mut ServiceConfig) + Send + Sync + Clone,
     Vec<Box<dyn HealthChecked>>,
 )> {
+    // replies are consumed from the same Kafka cluster, or MQTT broker
+    let reply = ReplyConfig {
+        router: ReplyRouter::new(config.command_kafka_sink.clone())
+            .with_mqtt(config.mqtt_backbone.clone()),
+        max_timeout: config.max_reply_timeout,
+    };
+
     let sender = UpstreamSender::new(
-        config.instance,
-        KafkaSink::from_config(config.command_kafka_sink, config.check_kafka_topic_ready)?,
-        config.endpoint_pool,
+        config.instance.clone(),
+        sink::from_config(
+            config.command_kafka_sink.clone(),
+            Default::default(),
+            config.mqtt_backbone.clone(),
+            config.check_kafka_topic_ready,
+        )?,
+        config.endpoint_pool.clone(),
     )?;
 
     // set up authentication
@@ -84,26 +128,72 @@ pub async fn configurator(
     let client = reqwest::Client::new();
     let registry: registry::v1::Client = config.registry.into_client().await?;
 
+    // set up the scheduler, status events go to the same Kafka cluster, or MQTT broker
+
+    let mut checks: Vec<Box<dyn HealthChecked>> = vec![];
+
+    let scheduler = match config.scheduler {
+        Some(scheduler) => {
+            let events = DownstreamSender::new(
+                sink::from_config(
+                    config.command_kafka_sink,
+                    Default::default(),
+                    config.mqtt_backbone,
+                    config.check_kafka_topic_ready,
+                )?,
+                config.instance,
+                config.endpoint_pool,
+            )?;
+            let scheduler = PostgresCommandScheduler::new(
+                scheduler,
+                EndpointDelivery {
+                    sender: sender.clone(),
+                    events,
+                    registry: registry.clone(),
+                    client: client.clone(),
+                },
+            )?;
+            checks.push(Box::new(scheduler.clone()));
+            startup.spawn(scheduler::run_scheduler(scheduler.clone()));
+            Some(scheduler)
+        }
+        None => None,
+    };
+
     Ok((
         move |cfg: &mut ServiceConfig| {
             cfg.app_data(web::Data::new(sender.clone()))
                 .app_data(web::Data::new(registry.clone()))
                 .app_data(web::Data::new(client.clone()))
+                .app_data(web::Data::new(reply.clone()))
+                .app_data(web::Data::new(scheduler.clone()))
                 .service(web::resource("/").route(web::get().to(index)))
                 .service(
                     web::scope("/api/command/v1alpha1/apps/{application}/devices/{deviceId}")
                         .wrap(ApplicationAuthorizer::wrapping(
                             user_auth.clone(),
-                            Permission::Read,
+                            Permission::Write,
                         ))
                         .wrap(AuthN::from((
                             authenticator.clone(),
                             user_auth.clone().map(pat::Authenticator::new),
                         )))
                         .route("", web::post().to(v1alpha1::command)),
+                )
+                .service(
+                    web::resource("/api/command/v1alpha1/apps/{application}/devices")
+                        .wrap(ApplicationAuthorizer::wrapping(
+                            user_auth.clone(),
+                            Permission::Write,
+                        ))
+                        .wrap(AuthN::from((
+                            authenticator.clone(),
+                            user_auth.clone().map(pat::Authenticator::new),
+                        )))
+                        .route(web::post().to(v1alpha1::broadcast)),
                 );
         },
-        vec![],
+        checks,
     ))
 }
 
@@ -112,7 +202,7 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     // main server
 
-    let (cfg, checks) = configurator(config.clone()).await?;
+    let (cfg, checks) = configurator(config.clone(), startup).await?;
     HttpBuilder::new(config.http, Some(startup.runtime_config()), cfg)
         .default_cors(CorsConfig::permissive())
         .start(startup)?;
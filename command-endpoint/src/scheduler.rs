@@ -0,0 +1,386 @@
+//! Holding back commands, until they are due.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use drogue_client::registry;
+use drogue_cloud_database_common::{error::ServiceError, postgres, DatabaseService};
+use drogue_cloud_endpoint_common::{
+    command::{publish_status, CommandStatus, CommandStatusEvent},
+    sender::{DownstreamSender, ToPublishId, UpstreamSender},
+};
+use drogue_cloud_integration_common::commands::{process_command, CommandOptions};
+use drogue_cloud_service_api::{
+    health::{HealthCheckError, HealthChecked},
+    webapp::http::StatusCode,
+};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio::time::sleep;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SchedulerConfig {
+    pub pg: postgres::Config,
+    /// The interval of checking for due commands.
+    #[serde(with = "humantime_serde", default = "default_poll_interval")]
+    pub poll_interval: Duration,
+    /// The maximum number of commands claimed at once.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: i64,
+    /// The time a due command is claimed by an instance, while sending it.
+    ///
+    /// If the command could not be sent, it will be retried once the claim timed out.
+    #[serde(with = "humantime_serde", default = "default_claim_timeout")]
+    pub claim_timeout: Duration,
+}
+
+const fn default_poll_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+const fn default_batch_size() -> i64 {
+    100
+}
+
+const fn default_claim_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Delivering commands, once they are due.
+#[async_trait]
+pub trait CommandDelivery: Send + Sync {
+    /// Send a command, returns `false` if sending should be retried.
+    async fn send(&self, opts: CommandOptions, payload: Vec<u8>) -> bool;
+
+    /// Notify the application about a command, which expired before it could be sent.
+    async fn expired(&self, opts: CommandOptions);
+}
+
+/// Deliver commands using the same path as commands which are sent immediately.
+#[derive(Clone)]
+pub struct EndpointDelivery {
+    pub sender: UpstreamSender,
+    pub events: DownstreamSender,
+    pub registry: registry::v1::Client,
+    pub client: reqwest::Client,
+}
+
+#[async_trait]
+impl CommandDelivery for EndpointDelivery {
+    async fn send(&self, opts: CommandOptions, payload: Vec<u8>) -> bool {
+        let response = futures::try_join!(
+            self.registry.get_app(&opts.application),
+            self.registry
+                .get_device_and_gateways(&opts.application, &opts.device)
+        );
+
+        let (application, (device, gateways)) = match response {
+            Ok((Some(application), Some(device_gateways))) => (application, device_gateways),
+            Ok(_) => {
+                log::info!(
+                    "Dropping scheduled command, target no longer found: {}/{}",
+                    opts.application,
+                    opts.device
+                );
+                return true;
+            }
+            Err(err) => {
+                log::info!("Failed to look up target of scheduled command: {err}");
+                return false;
+            }
+        };
+
+        match process_command(
+            application,
+            device,
+            gateways,
+            &self.sender,
+            self.client.clone(),
+            opts,
+            payload.into(),
+        )
+        .await
+        {
+            Ok(response) if response.status() == StatusCode::SERVICE_UNAVAILABLE => false,
+            Ok(response) => {
+                if !response.status().is_success() {
+                    log::info!("Scheduled command was rejected: {}", response.status());
+                }
+                true
+            }
+            Err(err) => {
+                log::info!("Failed to send scheduled command: {err}");
+                true
+            }
+        }
+    }
+
+    async fn expired(&self, opts: CommandOptions) {
+        let application = match self.registry.get_app(&opts.application).await {
+            Ok(Some(application)) => application,
+            Ok(None) => return,
+            Err(err) => {
+                log::info!("Failed to look up application of expired command: {err}");
+                return;
+            }
+        };
+
+        let mut status = CommandStatusEvent::new(opts.command, CommandStatus::Expired);
+        status.correlation_id = opts.correlation_id;
+
+        let device = opts.device.to_id();
+        if let Err(err) =
+            publish_status(&self.events, &application, device.clone(), device, &status).await
+        {
+            log::info!("Failed to send status of expired command: {err}");
+        }
+    }
+}
+
+/// A scheduler for commands, backed by PostgreSQL.
+///
+/// Commands are stored until they are due. Due commands are claimed by one instance, and
+/// removed once they got sent. Commands which expired before they could be sent are discarded,
+/// and a status event is sent to the application instead.
+#[derive(Clone)]
+pub struct PostgresCommandScheduler {
+    pool: Pool,
+    poll_interval: Duration,
+    batch_size: i64,
+    claim_timeout: Duration,
+    delivery: Arc<dyn CommandDelivery>,
+}
+
+impl DatabaseService for PostgresCommandScheduler {
+    fn pool(&self) -> &Pool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl HealthChecked for PostgresCommandScheduler {
+    async fn is_ready(&self) -> Result<(), HealthCheckError> {
+        Ok(DatabaseService::is_ready(self)
+            .await
+            .map_err(HealthCheckError::from)?)
+    }
+}
+
+impl PostgresCommandScheduler {
+    pub fn new<D>(config: SchedulerConfig, delivery: D) -> anyhow::Result<Self>
+    where
+        D: CommandDelivery + 'static,
+    {
+        Ok(Self {
+            pool: config.pg.create_pool()?,
+            poll_interval: config.poll_interval,
+            batch_size: config.batch_size,
+            claim_timeout: config.claim_timeout,
+            delivery: Arc::new(delivery),
+        })
+    }
+
+    /// Store a command, for sending it once it is due.
+    pub async fn schedule(
+        &self,
+        opts: &CommandOptions,
+        payload: &[u8],
+    ) -> Result<Uuid, ServiceError> {
+        let c = self.pool.get().await?;
+
+        let id = Uuid::new_v4();
+        let not_before = opts.not_before.unwrap_or_else(Utc::now);
+
+        c.execute(
+            r#"
+INSERT INTO
+    scheduled_commands
+(
+    ID,
+    APPLICATION,
+    DEVICE,
+    COMMAND,
+    CONTENT_TYPE,
+    CORRELATION_ID,
+    PAYLOAD,
+    NOT_BEFORE,
+    EXPIRES_AT
+) VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6,
+    $7,
+    $8,
+    $9
+)"#,
+            &[
+                &id,
+                &opts.application,
+                &opts.device,
+                &opts.command,
+                &opts.content_type,
+                &opts.correlation_id,
+                &payload,
+                &not_before,
+                &opts.expires_at,
+            ],
+        )
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Send, or discard, all commands which are due.
+    pub async fn process(&self) -> Result<(), ServiceError> {
+        loop {
+            let now = Utc::now();
+            let commands = self.claim(now).await?;
+            let len = commands.len();
+
+            for (id, opts, payload) in commands {
+                self.process_command(id, opts, payload, now).await?;
+            }
+
+            // commands which need to be retried stay claimed, so we will not see them again
+            if len < self.batch_size as usize {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Claim a batch of due commands.
+    ///
+    /// The claim is committed before sending the commands, so that other instances skip them,
+    /// without holding a transaction open while sending.
+    async fn claim(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(Uuid, CommandOptions, Vec<u8>)>, ServiceError> {
+        let c = self.pool.get().await?;
+
+        let claimed_until = chrono::Duration::from_std(self.claim_timeout)
+            .ok()
+            .and_then(|timeout| now.checked_add_signed(timeout))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+        let rows = c
+            .query(
+                r#"
+UPDATE
+    scheduled_commands
+SET
+    CLAIMED_UNTIL = $2
+WHERE ID IN (
+    SELECT
+        ID
+    FROM
+        scheduled_commands
+    WHERE
+            NOT_BEFORE <= $1
+        AND
+            (CLAIMED_UNTIL IS NULL OR CLAIMED_UNTIL <= $1)
+    ORDER BY
+        NOT_BEFORE ASC
+    LIMIT
+        $3
+    FOR UPDATE SKIP LOCKED
+)
+RETURNING
+    ID,
+    APPLICATION,
+    DEVICE,
+    COMMAND,
+    CONTENT_TYPE,
+    CORRELATION_ID,
+    PAYLOAD,
+    NOT_BEFORE,
+    EXPIRES_AT
+"#,
+                &[&now, &claimed_until, &self.batch_size],
+            )
+            .await?;
+
+        let mut commands = rows
+            .into_iter()
+            .map(Self::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        // the order of returned rows is undefined
+        commands.sort_by_key(|(_, opts, _)| opts.not_before);
+
+        Ok(commands)
+    }
+
+    fn from_row(row: Row) -> Result<(Uuid, CommandOptions, Vec<u8>), ServiceError> {
+        Ok((
+            row.try_get("ID")?,
+            CommandOptions {
+                application: row.try_get("APPLICATION")?,
+                device: row.try_get("DEVICE")?,
+                command: row.try_get("COMMAND")?,
+                content_type: row.try_get("CONTENT_TYPE")?,
+                correlation_id: row.try_get("CORRELATION_ID")?,
+                not_before: row.try_get("NOT_BEFORE")?,
+                expires_at: row.try_get("EXPIRES_AT")?,
+            },
+            row.try_get("PAYLOAD")?,
+        ))
+    }
+
+    /// Process a single, claimed command.
+    ///
+    /// The command is removed once it was sent, or discarded. Otherwise it stays claimed, and
+    /// will be retried once the claim timed out.
+    async fn process_command(
+        &self,
+        id: Uuid,
+        opts: CommandOptions,
+        payload: Vec<u8>,
+        now: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let delivered = if opts.is_expired(now) {
+            log::debug!(
+                "Discarding expired command: {id} - {}/{}",
+                opts.application,
+                opts.device
+            );
+            self.delivery.expired(opts).await;
+            true
+        } else {
+            self.delivery.send(opts, payload).await
+        };
+
+        if delivered {
+            let c = self.pool.get().await?;
+            c.execute(
+                r#"
+DELETE FROM
+    scheduled_commands
+WHERE
+    ID = $1
+"#,
+                &[&id],
+            )
+            .await?;
+        } else {
+            log::debug!("Retrying scheduled command later: {id}");
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn run_scheduler(scheduler: PostgresCommandScheduler) -> anyhow::Result<()> {
+    loop {
+        sleep(scheduler.poll_interval).await;
+        if let Err(err) = scheduler.process().await {
+            log::warn!("Failed to process scheduled commands: {err}");
+        }
+    }
+}
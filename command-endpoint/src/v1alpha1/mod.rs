@@ -1,12 +1,63 @@
-use drogue_client::registry;
-use drogue_cloud_endpoint_common::{error::HttpEndpointError, sender::UpstreamSender};
-use drogue_cloud_integration_common::{self, commands::CommandOptions};
-use drogue_cloud_service_api::webapp::{http::header, web, HttpRequest, HttpResponse};
-use serde::Deserialize;
+use crate::{scheduler::PostgresCommandScheduler, ReplyConfig};
+use chrono::{DateTime, Utc};
+use drogue_client::{registry, Translator};
+use drogue_cloud_endpoint_common::{
+    error::{EndpointError, HttpEndpointError},
+    sender::UpstreamSender,
+};
+use drogue_cloud_integration_common::{
+    self,
+    commands::{CommandOptions, ReplyOptions},
+};
+use drogue_cloud_service_api::{
+    labels::LabelSelector,
+    webapp::{http::header, web, HttpRequest, HttpResponse},
+};
+use futures::{stream, Future, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    time::Duration,
+};
+
+/// Number of devices to send a broadcast command to, in parallel.
+const BROADCAST_CONCURRENCY: usize = 16;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CommandQuery {
     pub command: String,
+    /// Wait for the reply of the device, up to this amount of time.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    /// Hold back the command until this point in time.
+    #[serde(default, rename = "notBefore")]
+    pub not_before: Option<DateTime<Utc>>,
+    /// Discard the command, if it could not be sent until this point in time.
+    #[serde(default, rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BroadcastQuery {
+    pub command: String,
+    /// Label selector of the target devices.
+    pub labels: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BroadcastSummary {
+    /// Devices which accepted the command.
+    pub accepted: Vec<String>,
+    /// Devices which rejected the command.
+    pub rejected: Vec<RejectedTarget>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RejectedTarget {
+    pub device: String,
+    pub reason: String,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -18,8 +69,30 @@ pub async fn command(
     req: HttpRequest,
     body: web::Bytes,
     registry: web::Data<registry::v1::Client>,
+    reply: web::Data<ReplyConfig>,
+    scheduler: web::Data<Option<PostgresCommandScheduler>>,
 ) -> Result<HttpResponse, HttpEndpointError> {
     let (app_name, device_name) = path.into_inner();
+    let timeout = opts.timeout.filter(|timeout| !timeout.is_zero());
+
+    let now = Utc::now();
+    let not_before = opts.not_before.filter(|not_before| *not_before > now);
+
+    if opts
+        .expires_at
+        .map_or(false, |expires_at| expires_at <= now)
+    {
+        return Err(EndpointError::InvalidRequest {
+            details: "Command is already expired".into(),
+        }
+        .into());
+    }
+    if not_before.is_some() && timeout.is_some() {
+        return Err(EndpointError::InvalidRequest {
+            details: "Waiting for the reply of a scheduled command is not supported".into(),
+        }
+        .into());
+    }
 
     log::debug!(
         "Send command '{}' to '{}' / '{}'",
@@ -35,26 +108,50 @@ pub async fn command(
 
     match response {
         Ok((Some(application), Some(device_gateways))) => {
-            let content_type = req
-                .headers()
-                .get(header::CONTENT_TYPE)
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.to_string());
-            drogue_cloud_integration_common::commands::process_command(
-                application,
-                device_gateways.0,
-                device_gateways.1,
-                &sender,
-                client.get_ref().clone(),
-                CommandOptions {
-                    application: app_name,
-                    device: device_name,
-                    command: opts.command,
-                    content_type,
-                },
-                body,
-            )
-            .await
+            let content_type = content_type(&req);
+            let opts = CommandOptions {
+                application: app_name,
+                device: device_name,
+                command: opts.command,
+                content_type,
+                correlation_id: None,
+                not_before,
+                expires_at: opts.expires_at,
+            };
+
+            if not_before.is_some() {
+                return schedule(scheduler.get_ref().as_ref(), opts, body).await;
+            }
+
+            match timeout {
+                Some(timeout) => drogue_cloud_integration_common::commands::request_command(
+                    application,
+                    device_gateways.0,
+                    device_gateways.1,
+                    &sender,
+                    client.get_ref().clone(),
+                    opts,
+                    body,
+                    &ReplyOptions {
+                        router: reply.router.clone(),
+                        timeout: timeout.min(reply.max_timeout),
+                    },
+                )
+                .await
+                .map(Into::into),
+                None => {
+                    drogue_cloud_integration_common::commands::process_command(
+                        application,
+                        device_gateways.0,
+                        device_gateways.1,
+                        &sender,
+                        client.get_ref().clone(),
+                        opts,
+                        body,
+                    )
+                    .await
+                }
+            }
         }
         Ok(_) => Ok(HttpResponse::NotAcceptable().finish()),
         Err(err) => {
@@ -63,3 +160,307 @@ pub async fn command(
         }
     }
 }
+
+/// Store a command, for sending it later on.
+async fn schedule(
+    scheduler: Option<&PostgresCommandScheduler>,
+    opts: CommandOptions,
+    body: web::Bytes,
+) -> Result<HttpResponse, HttpEndpointError> {
+    let scheduler = scheduler.ok_or_else(|| EndpointError::InvalidRequest {
+        details: "Scheduling commands is not enabled".into(),
+    })?;
+
+    match scheduler.schedule(&opts, &body).await {
+        Ok(id) => Ok(HttpResponse::Accepted().json(json!({ "id": id }))),
+        Err(err) => {
+            log::warn!("Failed to schedule command: {err}");
+            Ok(HttpResponse::ServiceUnavailable().finish())
+        }
+    }
+}
+
+fn content_type(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// Send a command to all devices of an application, matching a label selector.
+#[allow(clippy::too_many_arguments)]
+pub async fn broadcast(
+    sender: web::Data<UpstreamSender>,
+    client: web::Data<reqwest::Client>,
+    path: web::Path<String>,
+    web::Query(opts): web::Query<BroadcastQuery>,
+    req: HttpRequest,
+    body: web::Bytes,
+    registry: web::Data<registry::v1::Client>,
+) -> Result<HttpResponse, HttpEndpointError> {
+    let app_name = path.into_inner();
+
+    // validate, before passing it on to the registry
+    LabelSelector::try_from(opts.labels.as_str()).map_err(|err| EndpointError::InvalidRequest {
+        details: err.to_string(),
+    })?;
+
+    log::debug!(
+        "Broadcast command '{}' to '{}' / '{}'",
+        opts.command,
+        app_name,
+        opts.labels
+    );
+
+    let response = futures::try_join!(
+        registry.get_app(&app_name),
+        registry.list_devices(&app_name, Some(vec![opts.labels.as_str()]))
+    );
+
+    let (application, devices) = match response {
+        Ok((Some(application), Some(devices))) => (application, devices),
+        Ok(_) => return Ok(HttpResponse::NotAcceptable().finish()),
+        Err(err) => {
+            log::info!("Error {:?}", err);
+            return Ok(HttpResponse::NotAcceptable().finish());
+        }
+    };
+
+    // look up the gateways which are not part of the selected devices, once for all devices
+
+    let mut broadcast = Broadcast::new(devices);
+    let gateways = stream::iter(broadcast.missing_gateways())
+        .map(|gateway| {
+            let registry = registry.clone();
+            let app_name = app_name.clone();
+            async move { registry.get_device(&app_name, &gateway).await }
+        })
+        .buffer_unordered(BROADCAST_CONCURRENCY)
+        .try_collect::<Vec<_>>()
+        .await;
+
+    match gateways {
+        Ok(gateways) => broadcast.add_gateways(gateways.into_iter().flatten()),
+        Err(err) => {
+            log::info!("Error {:?}", err);
+            return Ok(HttpResponse::NotAcceptable().finish());
+        }
+    }
+
+    let content_type = content_type(&req);
+
+    let summary = broadcast
+        .send(|device, gateways| {
+            let opts = CommandOptions {
+                application: app_name.clone(),
+                device: device.metadata.name.clone(),
+                command: opts.command.clone(),
+                content_type: content_type.clone(),
+                correlation_id: None,
+                not_before: None,
+                expires_at: None,
+            };
+            let application = application.clone();
+            let sender = sender.clone();
+            let client = client.get_ref().clone();
+            let body = body.clone();
+
+            async move {
+                match drogue_cloud_integration_common::commands::process_command(
+                    application,
+                    device,
+                    gateways,
+                    &sender,
+                    client,
+                    opts,
+                    body,
+                )
+                .await
+                {
+                    Ok(response) if response.status().is_success() => Ok(()),
+                    Ok(response) => Err(response.status().to_string()),
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+        })
+        .await;
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// The devices targeted by a broadcast command, and their gateways.
+struct Broadcast {
+    devices: Vec<registry::v1::Device>,
+    /// The gateways of the devices, by name.
+    gateways: HashMap<String, registry::v1::Device>,
+}
+
+impl Broadcast {
+    /// Create a new broadcast to the selected devices.
+    ///
+    /// Gateways which are selected devices themselves don't need to be looked up again.
+    fn new(devices: Vec<registry::v1::Device>) -> Self {
+        let names: HashSet<_> = devices.iter().flat_map(gateway_names).collect();
+        let gateways = devices
+            .iter()
+            .filter(|device| names.contains(&device.metadata.name))
+            .map(|device| (device.metadata.name.clone(), device.clone()))
+            .collect();
+
+        Self { devices, gateways }
+    }
+
+    /// The names of the gateways, which still need to be looked up.
+    fn missing_gateways(&self) -> BTreeSet<String> {
+        self.devices
+            .iter()
+            .flat_map(gateway_names)
+            .filter(|name| !self.gateways.contains_key(name))
+            .collect()
+    }
+
+    fn add_gateways(&mut self, gateways: impl IntoIterator<Item = registry::v1::Device>) {
+        for gateway in gateways {
+            self.gateways.insert(gateway.metadata.name.clone(), gateway);
+        }
+    }
+
+    /// Send the command to all devices, collecting the outcome of each device.
+    ///
+    /// The function is called with each device and its gateways. Gateways which don't exist are
+    /// skipped.
+    async fn send<F, Fut>(self, f: F) -> BroadcastSummary
+    where
+        F: Fn(registry::v1::Device, Vec<registry::v1::Device>) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        let gateways = &self.gateways;
+
+        let results = stream::iter(self.devices)
+            .map(|device| {
+                let name = device.metadata.name.clone();
+                let device_gateways = gateway_names(&device)
+                    .iter()
+                    .filter_map(|gateway| gateways.get(gateway).cloned())
+                    .collect();
+                let result = f(device, device_gateways);
+                async move { (name, result.await) }
+            })
+            .buffer_unordered(BROADCAST_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut summary = BroadcastSummary::default();
+        for (device, result) in results {
+            match result {
+                Ok(()) => summary.accepted.push(device),
+                Err(reason) => summary.rejected.push(RejectedTarget { device, reason }),
+            }
+        }
+
+        // report in a stable order, independent of the order of completion
+        summary.accepted.sort_unstable();
+        summary
+            .rejected
+            .sort_unstable_by(|a, b| a.device.cmp(&b.device));
+
+        summary
+    }
+}
+
+/// Get the names of the gateways of a device.
+fn gateway_names(device: &registry::v1::Device) -> Vec<String> {
+    device
+        .section::<registry::v1::DeviceSpecGatewaySelector>()
+        .and_then(|selector| selector.ok())
+        .map(|selector| selector.match_names)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn device(name: &str, spec: serde_json::Value) -> registry::v1::Device {
+        serde_json::from_value(json!({
+            "metadata": {
+                "application": "app1",
+                "name": name,
+            },
+            "spec": spec,
+        }))
+        .unwrap()
+    }
+
+    fn names(devices: &[registry::v1::Device]) -> Vec<&str> {
+        devices
+            .iter()
+            .map(|device| device.metadata.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        // the devices selected by the labels, "gw1" is selected as well
+        let mut broadcast = Broadcast::new(vec![
+            device("gw1", json!({})),
+            device(
+                "device1",
+                json!({"gatewaySelector": {"matchNames": ["gw1", "gw2"]}}),
+            ),
+            device(
+                "device2",
+                json!({"gatewaySelector": {"matchNames": ["gw2", "gw3"]}}),
+            ),
+            device("device3", json!({"core": {"disabled": true}})),
+        ]);
+
+        // only gateways which are not selected are looked up, once
+        assert_eq!(
+            broadcast.missing_gateways().into_iter().collect::<Vec<_>>(),
+            vec!["gw2", "gw3"]
+        );
+
+        // "gw3" doesn't exist
+        broadcast.add_gateways(vec![device("gw2", json!({}))]);
+        assert!(broadcast.missing_gateways().contains("gw3"));
+
+        let sent = Mutex::new(Vec::new());
+        let summary = broadcast
+            .send(|device, gateways| {
+                sent.lock().unwrap().push(format!(
+                    "{}:{}",
+                    device.metadata.name,
+                    names(&gateways).join(",")
+                ));
+                async move {
+                    if device.attribute::<registry::v1::DeviceEnabled>() {
+                        Ok(())
+                    } else {
+                        Err("406 Not Acceptable".to_string())
+                    }
+                }
+            })
+            .await;
+
+        // a gateway which isn't selected, only receives the command on behalf of its devices
+        let mut sent = sent.into_inner().unwrap();
+        sent.sort_unstable();
+        assert_eq!(
+            sent,
+            vec!["device1:gw1,gw2", "device2:gw2", "device3:", "gw1:"]
+        );
+
+        assert_eq!(
+            summary,
+            BroadcastSummary {
+                accepted: vec!["device1".into(), "device2".into(), "gw1".into()],
+                rejected: vec![RejectedTarget {
+                    device: "device3".into(),
+                    reason: "406 Not Acceptable".into(),
+                }],
+            }
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use drogue_cloud_command_endpoint::{CommandDelivery, PostgresCommandScheduler, SchedulerConfig};
+use drogue_cloud_database_common::DatabaseService;
+use drogue_cloud_integration_common::commands::CommandOptions;
+use drogue_cloud_test_common::{client, db};
+use serial_test::serial;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+#[derive(Debug, Default)]
+struct MockDelivery {
+    failing: AtomicBool,
+    attempts: AtomicUsize,
+    sent: Mutex<Vec<String>>,
+    expired: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl CommandDelivery for Arc<MockDelivery> {
+    async fn send(&self, opts: CommandOptions, _payload: Vec<u8>) -> bool {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        if self.failing.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.sent.lock().unwrap().push(opts.command);
+        true
+    }
+
+    async fn expired(&self, opts: CommandOptions) {
+        self.expired.lock().unwrap().push(opts.command);
+    }
+}
+
+fn config(pg: drogue_cloud_database_common::postgres::Config, batch_size: i64) -> SchedulerConfig {
+    SchedulerConfig {
+        pg,
+        poll_interval: std::time::Duration::from_secs(1),
+        batch_size,
+        claim_timeout: std::time::Duration::from_secs(60),
+    }
+}
+
+/// Create command options, with times relative to now, in seconds.
+fn opts(command: &str, not_before: i64, expires_at: Option<i64>) -> CommandOptions {
+    let now = Utc::now();
+    CommandOptions {
+        application: "app1".into(),
+        device: "device1".into(),
+        command: command.into(),
+        content_type: None,
+        correlation_id: None,
+        not_before: Some(now + Duration::seconds(not_before)),
+        expires_at: expires_at.map(|expires_at| now + Duration::seconds(expires_at)),
+    }
+}
+
+async fn count(scheduler: &PostgresCommandScheduler) -> anyhow::Result<i64> {
+    let c = scheduler.pool().get().await?;
+    Ok(c.query_one("SELECT COUNT(*) FROM scheduled_commands", &[])
+        .await?
+        .try_get(0)?)
+}
+
+#[tokio::test]
+#[serial]
+async fn test_due_and_expired() -> anyhow::Result<()> {
+    let cli = client();
+    let db = db(&cli, |pg| config(pg, 1))?;
+
+    let delivery = Arc::new(MockDelivery::default());
+    let scheduler = PostgresCommandScheduler::new(db.config.clone(), delivery.clone())?;
+
+    scheduler.schedule(&opts("due1", -10, None), b"").await?;
+    scheduler.schedule(&opts("due2", -5, Some(60)), b"").await?;
+    scheduler
+        .schedule(&opts("expired", -8, Some(-5)), b"")
+        .await?;
+    scheduler.schedule(&opts("later", 60, None), b"").await?;
+
+    // a batch size of one requires multiple batches
+    scheduler.process().await?;
+
+    assert_eq!(*delivery.sent.lock().unwrap(), vec!["due1", "due2"]);
+    assert_eq!(*delivery.expired.lock().unwrap(), vec!["expired"]);
+    // only the command which is not yet due is left
+    assert_eq!(count(&scheduler).await?, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn test_failed() -> anyhow::Result<()> {
+    let cli = client();
+    let db = db(&cli, |pg| config(pg, 10))?;
+
+    let delivery = Arc::new(MockDelivery::default());
+    delivery.failing.store(true, Ordering::SeqCst);
+
+    let scheduler = PostgresCommandScheduler::new(db.config.clone(), delivery.clone())?;
+    scheduler.schedule(&opts("c1", -10, None), b"").await?;
+
+    scheduler.process().await?;
+    assert_eq!(delivery.attempts.load(Ordering::SeqCst), 1);
+    assert_eq!(count(&scheduler).await?, 1);
+
+    // the failed command stays claimed, and is not retried by any instance right away
+    let other = PostgresCommandScheduler::new(db.config.clone(), delivery.clone())?;
+    scheduler.process().await?;
+    other.process().await?;
+    assert_eq!(delivery.attempts.load(Ordering::SeqCst), 1);
+
+    // once the claim timed out, it gets retried
+    scheduler
+        .pool()
+        .get()
+        .await?
+        .execute(
+            "UPDATE scheduled_commands SET CLAIMED_UNTIL = NOW() - INTERVAL '1 second'",
+            &[],
+        )
+        .await?;
+    delivery.failing.store(false, Ordering::SeqCst);
+
+    other.process().await?;
+    assert_eq!(delivery.attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(*delivery.sent.lock().unwrap(), vec!["c1"]);
+    assert_eq!(count(&scheduler).await?, 0);
+
+    Ok(())
+}
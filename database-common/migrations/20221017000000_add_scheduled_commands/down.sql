@@ -0,0 +1 @@
+DROP TABLE scheduled_commands;
\ No newline at end of file
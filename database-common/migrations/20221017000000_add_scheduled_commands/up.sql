@@ -0,0 +1,20 @@
+CREATE TABLE scheduled_commands
+(
+    ID             UUID                     NOT NULL,
+
+    APPLICATION    VARCHAR(64)              NOT NULL,
+    DEVICE         VARCHAR(255)             NOT NULL,
+    COMMAND        VARCHAR(255)             NOT NULL,
+    CONTENT_TYPE   VARCHAR(255),
+    CORRELATION_ID VARCHAR(255),
+    PAYLOAD        BYTEA                    NOT NULL,
+
+    CREATED        TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    NOT_BEFORE     TIMESTAMP WITH TIME ZONE NOT NULL,
+    EXPIRES_AT     TIMESTAMP WITH TIME ZONE,
+    CLAIMED_UNTIL  TIMESTAMP WITH TIME ZONE,
+
+    PRIMARY KEY (ID)
+);
+
+CREATE INDEX scheduled_commands_not_before ON scheduled_commands (NOT_BEFORE);
@@ -0,0 +1,2 @@
+DROP INDEX DEVICES_BY_GROUP;
+DROP TABLE device_groups;
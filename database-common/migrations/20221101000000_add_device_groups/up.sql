@@ -0,0 +1,24 @@
+CREATE TABLE device_groups
+(
+    APP                VARCHAR(64)              NOT NULL,
+    NAME               VARCHAR(255)             NOT NULL,
+    UID                UUID                     NOT NULL,
+    PARENT             VARCHAR(255),
+
+    LABELS             JSONB,
+    ANNOTATIONS        JSONB,
+    CREATION_TIMESTAMP TIMESTAMP WITH TIME ZONE NOT NULL,
+    GENERATION         BIGINT                   NOT NULL,
+    RESOURCE_VERSION   UUID                     NOT NULL,
+
+    DATA               JSONB,
+
+    PRIMARY KEY (APP, NAME),
+    FOREIGN KEY (APP) REFERENCES applications (NAME) ON DELETE CASCADE,
+    FOREIGN KEY (APP, PARENT) REFERENCES device_groups (APP, NAME)
+);
+
+CREATE INDEX DEVICE_GROUPS_BY_PARENT ON device_groups (APP, PARENT);
+
+-- devices reference their group in the "group" section of their spec
+CREATE INDEX DEVICES_BY_GROUP ON devices (APP, (DATA -> 'spec' ->> 'group'));
@@ -0,0 +1,2 @@
+DROP INDEX DEVICES_BY_PROFILE;
+DROP TABLE device_profiles;
\ No newline at end of file
@@ -0,0 +1,20 @@
+CREATE TABLE device_profiles
+(
+    APP                VARCHAR(64)              NOT NULL,
+    NAME               VARCHAR(255)             NOT NULL,
+    UID                UUID                     NOT NULL,
+
+    LABELS             JSONB,
+    ANNOTATIONS        JSONB,
+    CREATION_TIMESTAMP TIMESTAMP WITH TIME ZONE NOT NULL,
+    GENERATION         BIGINT                   NOT NULL,
+    RESOURCE_VERSION   UUID                     NOT NULL,
+
+    DATA               JSONB,
+
+    PRIMARY KEY (APP, NAME),
+    FOREIGN KEY (APP) REFERENCES applications (NAME) ON DELETE CASCADE
+);
+
+-- devices reference their profile in the "profile" section of their spec
+CREATE INDEX DEVICES_BY_PROFILE ON devices (APP, (DATA -> 'spec' ->> 'profile'));
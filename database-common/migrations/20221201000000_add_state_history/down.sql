@@ -0,0 +1,2 @@
+DROP INDEX STATE_HISTORY_BY_DISCONNECTED;
+DROP TABLE state_history;
\ No newline at end of file
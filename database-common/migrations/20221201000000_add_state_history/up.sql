@@ -0,0 +1,17 @@
+CREATE TABLE state_history
+(
+    APPLICATION  VARCHAR(64)              NOT NULL,
+    DEVICE       VARCHAR(255)             NOT NULL,
+    SESSION      UUID                     NOT NULL,
+    ENDPOINT     VARCHAR(255)             NOT NULL,
+
+    CONNECTED    TIMESTAMP WITH TIME ZONE NOT NULL,
+    DISCONNECTED TIMESTAMP WITH TIME ZONE,
+    CAUSE        VARCHAR(32),
+
+    -- there is only one state per device, so the creation timestamp identifies the connection
+    PRIMARY KEY (APPLICATION, DEVICE, CONNECTED)
+);
+
+-- used when pruning entries
+CREATE INDEX STATE_HISTORY_BY_DISCONNECTED ON state_history (DISCONNECTED);
@@ -0,0 +1 @@
+DROP TABLE twins;
\ No newline at end of file
@@ -0,0 +1,16 @@
+CREATE TABLE twins
+(
+    APPLICATION      VARCHAR(64)  NOT NULL,
+    DEVICE           VARCHAR(255) NOT NULL,
+    DEVICE_UID       VARCHAR(64)  NOT NULL,
+
+    DESIRED          JSONB        NOT NULL DEFAULT '{}',
+    DESIRED_VERSION  BIGINT       NOT NULL DEFAULT 0,
+    DESIRED_UPDATED  TIMESTAMP WITH TIME ZONE,
+
+    REPORTED         JSONB        NOT NULL DEFAULT '{}',
+    REPORTED_VERSION BIGINT       NOT NULL DEFAULT 0,
+    REPORTED_UPDATED TIMESTAMP WITH TIME ZONE,
+
+    PRIMARY KEY (APPLICATION, DEVICE)
+);
@@ -0,0 +1,22 @@
+CREATE TABLE command_queue
+(
+    ID             BIGSERIAL                NOT NULL,
+    COMMAND_ID     VARCHAR(255),
+
+    APPLICATION    VARCHAR(64)              NOT NULL,
+    GATEWAY        VARCHAR(255)             NOT NULL,
+    DEVICE         VARCHAR(255)             NOT NULL,
+    COMMAND        VARCHAR(255)             NOT NULL,
+    PAYLOAD        BYTEA,
+    CORRELATION_ID VARCHAR(255),
+
+    QUEUED         TIMESTAMP WITH TIME ZONE NOT NULL,
+    EXPIRES        TIMESTAMP WITH TIME ZONE NOT NULL,
+    DONE           BOOLEAN                  NOT NULL DEFAULT FALSE,
+
+    PRIMARY KEY (ID),
+    UNIQUE (COMMAND_ID)
+);
+
+CREATE INDEX command_queue_by_device ON command_queue (APPLICATION, GATEWAY, DEVICE);
+CREATE INDEX command_queue_by_expires ON command_queue (EXPIRES);
@@ -0,0 +1,4 @@
+ALTER TABLE outbox
+    DROP COLUMN SEQ;
+
+DROP SEQUENCE outbox_seq;
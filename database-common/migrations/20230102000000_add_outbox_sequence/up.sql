@@ -0,0 +1,6 @@
+-- a sequence, ordering all changes recorded in the outbox
+
+CREATE SEQUENCE outbox_seq;
+
+ALTER TABLE outbox
+    ADD COLUMN SEQ BIGINT NOT NULL DEFAULT nextval('outbox_seq');
@@ -4,14 +4,14 @@ use crate::{
     generation,
     models::{
         sql::{slice_iter, SelectBuilder},
-        Lock, TypedAlias,
+        Cursor, Lock, SortBy, TypedAlias,
     },
     revision, update_aliases, Client,
 };
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use drogue_client::{meta, registry};
-use drogue_cloud_service_api::labels::LabelSelector;
+use drogue_cloud_service_api::{fields::FieldSelector, labels::LabelSelector};
 use futures::{future, Stream, TryStreamExt};
 use serde_json::Value;
 use std::{
@@ -45,6 +45,26 @@ pub struct Device {
     pub data: Value,
 }
 
+/// A device, which got advanced because its effective spec changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdvancedDevice {
+    pub name: String,
+    pub uid: Uuid,
+    /// The new revision
+    pub revision: u64,
+}
+
+impl AdvancedDevice {
+    /// Read from a row, returning the columns `NAME`, `UID` and `REVISION`.
+    pub fn from_row(row: Row) -> Result<Self, tokio_postgres::Error> {
+        Ok(Self {
+            name: row.try_get("NAME")?,
+            uid: row.try_get("UID")?,
+            revision: row.try_get::<_, i64>("REVISION")? as u64,
+        })
+    }
+}
+
 diffable!(Device);
 generation!(Device => generation);
 revision!(Device => revision);
@@ -77,6 +97,9 @@ impl From<Device> for registry::v1::Device {
 #[async_trait]
 pub trait DeviceAccessor {
     /// Lookup a device by alias.
+    ///
+    /// The spec of the returned device is the effective spec, including the sections of its
+    /// profile.
     async fn lookup(&self, app: &str, alias: &str) -> Result<Option<Device>, ServiceError>;
 
     /// Delete a device.
@@ -124,6 +147,14 @@ pub trait DeviceAccessor {
     /// Count devices remaining for an application.
     async fn count_devices(&self, app: &str) -> Result<u64, ServiceError>;
 
+    /// Count devices of an application, matching the label and field selectors.
+    async fn count(
+        &self,
+        app: &str,
+        labels: LabelSelector,
+        fields: FieldSelector,
+    ) -> Result<u64, ServiceError>;
+
     /// Get a list of applications
     async fn list(
         &self,
@@ -134,8 +165,39 @@ pub trait DeviceAccessor {
         offset: Option<usize>,
         lock: Lock,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Device, ServiceError>> + Send>>, ServiceError>;
+
+    /// Get a page of devices, sorted and continuing after the cursor.
+    #[allow(clippy::too_many_arguments)]
+    async fn list_page(
+        &self,
+        app: &str,
+        labels: LabelSelector,
+        fields: FieldSelector,
+        sort: SortBy,
+        cursor: Option<Cursor>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Device, ServiceError>> + Send>>, ServiceError>;
 }
 
+const SELECT_DEVICES: &str = r#"
+SELECT
+    UID,
+    NAME,
+    APP,
+    LABELS,
+    ANNOTATIONS,
+    CREATION_TIMESTAMP,
+    GENERATION,
+    REVISION,
+    RESOURCE_VERSION,
+    DELETION_TIMESTAMP,
+    FINALIZERS,
+    DATA
+FROM DEVICES
+WHERE APP=$1
+"#;
+
 pub struct PostgresDeviceAccessor<'c, C: Client> {
     client: &'c C,
 }
@@ -164,6 +226,30 @@ impl<'c, C: Client> PostgresDeviceAccessor<'c, C> {
         })
     }
 
+    /// Run a select, returning a stream of devices.
+    async fn query(
+        &self,
+        builder: SelectBuilder<'_>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Device, ServiceError>> + Send>>, ServiceError>
+    {
+        let (select, params, types) = builder.build();
+
+        let stmt = self.client.prepare_typed(&select, &types).await?;
+
+        let stream = self
+            .client
+            .query_raw(&stmt, slice_iter(&params[..]))
+            .await
+            .map_err(|err| {
+                log::debug!("Failed to get: {}", err);
+                err
+            })?
+            .and_then(|row| future::ready(Self::from_row(row)))
+            .map_err(ServiceError::Database);
+
+        Ok(Box::pin(stream))
+    }
+
     async fn insert_aliases(
         &self,
         app: &str,
@@ -196,6 +282,8 @@ impl<'c, C: Client> PostgresDeviceAccessor<'c, C> {
 impl<'c, C: Client> DeviceAccessor for PostgresDeviceAccessor<'c, C> {
     #[instrument(name = "database_device_alias_lookup", skip(self), err)]
     async fn lookup(&self, app: &str, alias: &str) -> Result<Option<Device>, ServiceError> {
+        // the spec of the profile is merged with the spec of the device, sections of the
+        // device take precedence
         let sql = r#"
 SELECT
     D.NAME,
@@ -209,11 +297,21 @@ SELECT
     D.ANNOTATIONS,
     D.DELETION_TIMESTAMP,
     D.FINALIZERS,
-    D.DATA
+    CASE
+        WHEN P.NAME IS NULL THEN D.DATA
+        ELSE JSONB_SET(
+            D.DATA,
+            '{spec}',
+            COALESCE(P.DATA -> 'spec', '{}'::JSONB) || COALESCE(D.DATA -> 'spec', '{}'::JSONB)
+        )
+    END AS DATA
 FROM
         DEVICE_ALIASES A INNER JOIN DEVICES D
     ON
         (A.DEVICE=D.NAME AND A.APP=D.APP)
+    LEFT JOIN DEVICE_PROFILES P
+    ON
+        (P.APP=D.APP AND P.NAME=D.DATA -> 'spec' ->> 'profile')
 WHERE
         A.APP = $1
     AND
@@ -262,30 +360,11 @@ WHERE
         lock: Lock,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Device, ServiceError>> + Send>>, ServiceError>
     {
-        let select = r#"
-SELECT
-    UID,
-    NAME,
-    APP,
-    LABELS,
-    ANNOTATIONS,
-    CREATION_TIMESTAMP,
-    GENERATION,
-    REVISION,
-    RESOURCE_VERSION,
-    DELETION_TIMESTAMP,
-    FINALIZERS,
-    DATA
-FROM DEVICES
-WHERE APP=$1
-"#
-        .to_string();
-
         let types: Vec<Type> = vec![Type::VARCHAR];
 
         let params: Vec<&(dyn ToSql + Sync)> = vec![&app];
 
-        let builder = SelectBuilder::new(select, params, types)
+        let builder = SelectBuilder::new(SELECT_DEVICES, params, types)
             .has_where()
             .name(&name)
             .labels(&labels.0)
@@ -294,22 +373,63 @@ WHERE APP=$1
             .limit(limit)
             .offset(offset);
 
-        let (select, params, types) = builder.build();
+        self.query(builder).await
+    }
 
-        let stmt = self.client.prepare_typed(&select, &types).await?;
+    async fn list_page(
+        &self,
+        app: &str,
+        labels: LabelSelector,
+        fields: FieldSelector,
+        sort: SortBy,
+        cursor: Option<Cursor>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Device, ServiceError>> + Send>>, ServiceError>
+    {
+        let types: Vec<Type> = vec![Type::VARCHAR];
 
-        let stream = self
+        let params: Vec<&(dyn ToSql + Sync)> = vec![&app];
+
+        let builder = SelectBuilder::new(SELECT_DEVICES, params, types)
+            .has_where()
+            .labels(&labels.0)
+            .fields(&fields.0)
+            .page(sort, &cursor)
+            .limit(limit)
+            .offset(offset);
+
+        self.query(builder).await
+    }
+
+    async fn count(
+        &self,
+        app: &str,
+        labels: LabelSelector,
+        fields: FieldSelector,
+    ) -> Result<u64, ServiceError> {
+        let types: Vec<Type> = vec![Type::VARCHAR];
+
+        let params: Vec<&(dyn ToSql + Sync)> = vec![&app];
+
+        let (select, params, types) = SelectBuilder::new(
+            "SELECT COUNT(NAME) AS COUNT FROM DEVICES WHERE APP=$1",
+            params,
+            types,
+        )
+        .has_where()
+        .labels(&labels.0)
+        .fields(&fields.0)
+        .build();
+
+        let stmt = self.client.prepare_typed(&select, &types).await?;
+        let count = self
             .client
-            .query_raw(&stmt, slice_iter(&params[..]))
-            .await
-            .map_err(|err| {
-                log::debug!("Failed to get: {}", err);
-                err
-            })?
-            .and_then(|row| future::ready(Self::from_row(row)))
-            .map_err(ServiceError::Database);
+            .query_opt(&stmt, &params)
+            .await?
+            .ok_or_else(|| ServiceError::Internal("Unable to retrieve number of devices".into()))?;
 
-        Ok(Box::pin(stream))
+        Ok(count.try_get::<_, i64>("COUNT")? as u64)
     }
 
     async fn create(
@@ -0,0 +1,396 @@
+use crate::{
+    default_resource,
+    error::ServiceError,
+    models::{device::AdvancedDevice, sql::slice_iter, Lock},
+    Client,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use drogue_client::meta;
+use drogue_cloud_service_api::groups::{self, DeviceGroupSpec, MAX_GROUP_DEPTH};
+use futures::TryStreamExt;
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio_postgres::{
+    types::{Json, ToSql, Type},
+    Row,
+};
+use uuid::Uuid;
+
+/// A device group entity record.
+pub struct Group {
+    pub application: String,
+    pub name: String,
+    pub uid: Uuid,
+    /// The name of the parent group, if any.
+    pub parent: Option<String>,
+    pub labels: HashMap<String, String>,
+    pub annotations: HashMap<String, String>,
+    pub creation_timestamp: DateTime<Utc>,
+    /// Incremented with each change to the spec
+    pub generation: u64,
+    /// Updated with each change
+    pub resource_version: Uuid,
+
+    /// Additional spec information (gateways, defaults).
+    pub data: Value,
+}
+
+default_resource!(Group);
+
+impl From<Group> for groups::DeviceGroup {
+    fn from(group: Group) -> Self {
+        let mut spec: DeviceGroupSpec =
+            serde_json::from_value(group.data["spec"].clone()).unwrap_or_default();
+        spec.parent = group.parent;
+
+        groups::DeviceGroup {
+            metadata: meta::v1::ScopedMetadata {
+                uid: group.uid.to_string(),
+                name: group.name,
+                application: group.application,
+                labels: group.labels,
+                annotations: group.annotations,
+                creation_timestamp: group.creation_timestamp,
+                generation: group.generation,
+                resource_version: group.resource_version.to_string(),
+                deletion_timestamp: None,
+                finalizers: vec![],
+            },
+            spec,
+        }
+    }
+}
+
+#[async_trait]
+pub trait GroupAccessor {
+    /// Get a group.
+    async fn get(&self, app: &str, name: &str, lock: Lock) -> Result<Option<Group>, ServiceError>;
+
+    /// Get all groups of an application, sorted by name.
+    async fn list(&self, app: &str) -> Result<Vec<Group>, ServiceError>;
+
+    /// Get a group, followed by all of its parents.
+    ///
+    /// The result is empty if the group doesn't exist.
+    async fn ancestors(&self, app: &str, name: &str) -> Result<Vec<Group>, ServiceError>;
+
+    /// Create a new group.
+    async fn create(&self, group: Group) -> Result<(), ServiceError>;
+
+    /// Update an existing group.
+    async fn update(&self, group: Group) -> Result<u64, ServiceError>;
+
+    /// Delete a group.
+    async fn delete(&self, app: &str, name: &str) -> Result<u64, ServiceError>;
+
+    /// Count the groups, which have the group as their parent.
+    async fn count_children(&self, app: &str, name: &str) -> Result<u64, ServiceError>;
+
+    /// Count the devices, which are a member of the group.
+    async fn count_devices(&self, app: &str, name: &str) -> Result<u64, ServiceError>;
+
+    /// Advance the generation and revision of all devices, which are a member of the group, or
+    /// one of its nested groups.
+    ///
+    /// Returns the advanced devices, sorted by name.
+    async fn advance_devices(
+        &self,
+        app: &str,
+        name: &str,
+    ) -> Result<Vec<AdvancedDevice>, ServiceError>;
+}
+
+const SELECT_GROUPS: &str = r#"
+SELECT
+    APP,
+    NAME,
+    UID,
+    PARENT,
+    LABELS,
+    ANNOTATIONS,
+    CREATION_TIMESTAMP,
+    GENERATION,
+    RESOURCE_VERSION,
+    DATA
+FROM DEVICE_GROUPS
+WHERE APP=$1
+"#;
+
+pub struct PostgresGroupAccessor<'c, C: Client> {
+    client: &'c C,
+}
+
+impl<'c, C: Client> PostgresGroupAccessor<'c, C> {
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+
+    pub fn from_row(row: Row) -> Result<Group, tokio_postgres::Error> {
+        Ok(Group {
+            application: row.try_get("APP")?,
+            name: row.try_get("NAME")?,
+            uid: row.try_get("UID")?,
+            parent: row.try_get("PARENT")?,
+            labels: super::row_to_map(&row, "LABELS")?,
+            annotations: super::row_to_map(&row, "ANNOTATIONS")?,
+            creation_timestamp: row.try_get("CREATION_TIMESTAMP")?,
+            generation: row.try_get::<_, i64>("GENERATION")? as u64,
+            resource_version: row.try_get("RESOURCE_VERSION")?,
+            data: row.try_get::<_, Json<_>>("DATA")?.0,
+        })
+    }
+
+    /// Run a query, returning all groups.
+    async fn query(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Group>, ServiceError> {
+        let types = vec![Type::VARCHAR; params.len()];
+        let stmt = self.client.prepare_typed(sql, &types).await?;
+
+        let rows: Vec<Row> = self
+            .client
+            .query_raw(&stmt, slice_iter(params))
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(Self::from_row)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn count(&self, sql: &str, app: &str, name: &str) -> Result<u64, ServiceError> {
+        let stmt = self
+            .client
+            .prepare_typed(sql, &[Type::VARCHAR, Type::VARCHAR])
+            .await?;
+        let count = self
+            .client
+            .query_opt(&stmt, &[&app, &name])
+            .await?
+            .ok_or_else(|| ServiceError::Internal("Unable to count entries".into()))?;
+
+        Ok(count.try_get::<_, i64>("COUNT")? as u64)
+    }
+}
+
+#[async_trait]
+impl<'c, C: Client> GroupAccessor for PostgresGroupAccessor<'c, C> {
+    async fn get(&self, app: &str, name: &str, lock: Lock) -> Result<Option<Group>, ServiceError> {
+        let sql = format!("{SELECT_GROUPS} AND NAME=$2\n{}", lock.as_ref());
+
+        let stmt = self
+            .client
+            .prepare_typed(&sql, &[Type::VARCHAR, Type::VARCHAR])
+            .await?;
+
+        Ok(self
+            .client
+            .query_opt(&stmt, &[&app, &name])
+            .await?
+            .map(Self::from_row)
+            .transpose()?)
+    }
+
+    async fn list(&self, app: &str) -> Result<Vec<Group>, ServiceError> {
+        let sql = format!("{SELECT_GROUPS} ORDER BY NAME");
+
+        self.query(&sql, &[&app]).await
+    }
+
+    async fn ancestors(&self, app: &str, name: &str) -> Result<Vec<Group>, ServiceError> {
+        // the depth limit protects against cycles, which the service should prevent anyway
+        let sql = format!(
+            r#"
+WITH RECURSIVE ANCESTORS AS (
+        SELECT *, 0 AS DEPTH FROM DEVICE_GROUPS WHERE APP=$1 AND NAME=$2
+    UNION ALL
+        SELECT G.*, A.DEPTH + 1 FROM
+            DEVICE_GROUPS G INNER JOIN ANCESTORS A
+        ON
+            (G.APP=A.APP AND G.NAME=A.PARENT)
+        WHERE
+            A.DEPTH + 1 < {MAX_GROUP_DEPTH}
+)
+SELECT
+    APP,
+    NAME,
+    UID,
+    PARENT,
+    LABELS,
+    ANNOTATIONS,
+    CREATION_TIMESTAMP,
+    GENERATION,
+    RESOURCE_VERSION,
+    DATA
+FROM ANCESTORS
+ORDER BY DEPTH
+"#
+        );
+
+        self.query(&sql, &[&app, &name]).await
+    }
+
+    async fn create(&self, group: Group) -> Result<(), ServiceError> {
+        self.client
+            .execute(
+                r#"
+INSERT INTO DEVICE_GROUPS (
+    APP,
+    NAME,
+    UID,
+    PARENT,
+    LABELS,
+    ANNOTATIONS,
+    CREATION_TIMESTAMP,
+    GENERATION,
+    RESOURCE_VERSION,
+    DATA
+) VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6,
+    $7,
+    $8,
+    $9,
+    $10
+)"#,
+                &[
+                    &group.application,
+                    &group.name,
+                    &group.uid,
+                    &group.parent,
+                    &Json(&group.labels),
+                    &Json(&group.annotations),
+                    &Utc::now(),
+                    &(group.generation as i64),
+                    &Uuid::new_v4(),
+                    &Json(&group.data),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update(&self, group: Group) -> Result<u64, ServiceError> {
+        Ok(self
+            .client
+            .execute(
+                r#"
+UPDATE
+    DEVICE_GROUPS
+SET
+    PARENT = $3,
+    LABELS = $4,
+    ANNOTATIONS = $5,
+    GENERATION = $6,
+    RESOURCE_VERSION = $7,
+    DATA = $8
+WHERE
+    APP = $1 AND NAME = $2
+"#,
+                &[
+                    &group.application,
+                    &group.name,
+                    &group.parent,
+                    &Json(&group.labels),
+                    &Json(&group.annotations),
+                    &(group.generation as i64),
+                    &Uuid::new_v4(),
+                    &Json(&group.data),
+                ],
+            )
+            .await?)
+    }
+
+    async fn delete(&self, app: &str, name: &str) -> Result<u64, ServiceError> {
+        let stmt = self
+            .client
+            .prepare_typed(
+                "DELETE FROM DEVICE_GROUPS WHERE APP = $1 AND NAME = $2",
+                &[Type::VARCHAR, Type::VARCHAR],
+            )
+            .await?;
+
+        Ok(self.client.execute(&stmt, &[&app, &name]).await?)
+    }
+
+    async fn count_children(&self, app: &str, name: &str) -> Result<u64, ServiceError> {
+        self.count(
+            "SELECT COUNT(NAME) AS COUNT FROM DEVICE_GROUPS WHERE APP = $1 AND PARENT = $2",
+            app,
+            name,
+        )
+        .await
+    }
+
+    async fn count_devices(&self, app: &str, name: &str) -> Result<u64, ServiceError> {
+        self.count(
+            "SELECT COUNT(NAME) AS COUNT FROM DEVICES WHERE APP = $1 AND DATA -> 'spec' ->> 'group' = $2",
+            app,
+            name,
+        )
+        .await
+    }
+
+    async fn advance_devices(
+        &self,
+        app: &str,
+        name: &str,
+    ) -> Result<Vec<AdvancedDevice>, ServiceError> {
+        let sql = format!(
+            r#"
+WITH RECURSIVE DESCENDANTS AS (
+        SELECT APP, NAME, 0 AS DEPTH FROM DEVICE_GROUPS WHERE APP=$1 AND NAME=$2
+    UNION ALL
+        SELECT G.APP, G.NAME, D.DEPTH + 1 FROM
+            DEVICE_GROUPS G INNER JOIN DESCENDANTS D
+        ON
+            (G.APP=D.APP AND G.PARENT=D.NAME)
+        WHERE
+            D.DEPTH + 1 < {MAX_GROUP_DEPTH}
+)
+UPDATE
+    DEVICES
+SET
+    GENERATION = GENERATION + 1,
+    REVISION = REVISION + 1,
+    RESOURCE_VERSION = $3
+WHERE
+    APP = $1 AND DATA -> 'spec' ->> 'group' IN (SELECT NAME FROM DESCENDANTS)
+RETURNING
+    NAME, UID, REVISION
+"#
+        );
+
+        let stmt = self
+            .client
+            .prepare_typed(&sql, &[Type::VARCHAR, Type::VARCHAR, Type::UUID])
+            .await?;
+
+        let resource_version = Uuid::new_v4();
+        let rows: Vec<Row> = self
+            .client
+            .query_raw(&stmt, slice_iter(&[&app, &name, &resource_version]))
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut devices = rows
+            .into_iter()
+            .map(AdvancedDevice::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(devices)
+    }
+}
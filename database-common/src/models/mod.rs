@@ -2,11 +2,15 @@ pub mod app;
 pub mod device;
 pub mod diff;
 mod gen;
+pub mod group;
 pub mod outbox;
+pub mod profile;
 pub mod sql;
 
 pub use gen::*;
 
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, error::Error, fmt};
 use tokio_postgres::{
     row::RowIndex,
@@ -94,6 +98,51 @@ impl AsRef<str> for Lock {
     }
 }
 
+/// The sort order of a list operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortBy {
+    #[default]
+    Name,
+    CreationTimestamp,
+}
+
+impl SortBy {
+    /// The columns to sort by, the name is always included to get a stable order.
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Self::Name => &["NAME"],
+            Self::CreationTimestamp => &["CREATION_TIMESTAMP", "NAME"],
+        }
+    }
+}
+
+/// A position in a sorted list, pointing to the last item of the previous page.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Cursor {
+    Name(String),
+    CreationTimestamp(DateTime<Utc>, String),
+}
+
+impl Cursor {
+    /// Create a cursor pointing to an item, for the sort order.
+    pub fn new(sort: SortBy, name: String, creation_timestamp: DateTime<Utc>) -> Self {
+        match sort {
+            SortBy::Name => Self::Name(name),
+            SortBy::CreationTimestamp => Self::CreationTimestamp(creation_timestamp, name),
+        }
+    }
+
+    /// The sort order this cursor can be used with.
+    pub fn sort(&self) -> SortBy {
+        match self {
+            Self::Name(_) => SortBy::Name,
+            Self::CreationTimestamp(..) => SortBy::CreationTimestamp,
+        }
+    }
+}
+
 pub trait Resource {
     fn resource_version(&self) -> Uuid;
     fn uid(&self) -> Uuid;
@@ -18,6 +18,9 @@ pub struct OutboxEntry {
 
     pub revision: u64,
     pub uid: String,
+
+    /// The position of the change in the outbox, assigned when the entry is created.
+    pub sequence: Option<u64>,
 }
 
 impl TryFrom<Row> for OutboxEntry {
@@ -38,6 +41,7 @@ impl TryFrom<Row> for OutboxEntry {
             path: row.try_get("PATH")?,
             revision: row.try_get::<_, i64>("REVISION")? as u64,
             uid: row.try_get("UID")?,
+            sequence: Some(row.try_get::<_, i64>("SEQ")? as u64),
         })
     }
 }
@@ -45,7 +49,10 @@ impl TryFrom<Row> for OutboxEntry {
 #[async_trait]
 pub trait OutboxAccessor {
     /// Create a new outbox entry.
-    async fn create(&self, entry: OutboxEntry) -> Result<(), ServiceError>;
+    ///
+    /// Returns the sequence number assigned to the entry, or `None` if the outbox already holds a
+    /// newer entry for the same path.
+    async fn create(&self, entry: OutboxEntry) -> Result<Option<u64>, ServiceError>;
     /// Mark the outbox entry as seen.
     async fn mark_seen(&self, entry: OutboxEntry) -> Result<bool, ServiceError>;
     /// Fetch unread.
@@ -70,7 +77,7 @@ impl<'c, C: Client> PostgresOutboxAccessor<'c, C> {
 
 #[async_trait]
 impl<'c, C: Client> OutboxAccessor for PostgresOutboxAccessor<'c, C> {
-    async fn create(&self, entry: OutboxEntry) -> Result<(), ServiceError> {
+    async fn create(&self, entry: OutboxEntry) -> Result<Option<u64>, ServiceError> {
         let sql = r#"
 INSERT INTO outbox (
     INSTANCE,
@@ -94,11 +101,13 @@ DO
     UPDATE SET
         REVISION = EXCLUDED.REVISION,
         UID = EXCLUDED.UID,
-        TS = EXCLUDED.TS
+        TS = EXCLUDED.TS,
+        SEQ = EXCLUDED.SEQ
     WHERE
             outbox.REVISION < EXCLUDED.REVISION
         OR
             outbox.UID != EXCLUDED.UID
+RETURNING SEQ
 "#;
 
         let stmt = self
@@ -116,9 +125,9 @@ DO
             )
             .await?;
 
-        let num = self
+        let row = self
             .client
-            .execute(
+            .query_opt(
                 &stmt,
                 &[
                     &entry.instance,
@@ -131,9 +140,14 @@ DO
             )
             .await?;
 
-        log::debug!("Rows changed by create: {}", num);
+        let sequence = row
+            .map(|row| row.try_get::<_, i64>("SEQ"))
+            .transpose()?
+            .map(|seq| seq as u64);
+
+        log::debug!("Sequence assigned by create: {:?}", sequence);
 
-        Ok(())
+        Ok(sequence)
     }
 
     async fn mark_seen(&self, entry: OutboxEntry) -> Result<bool, ServiceError> {
@@ -196,7 +210,7 @@ WHERE
 
         let sql = r#"
 SELECT
-    INSTANCE, APP, DEVICE, PATH, REVISION, UID
+    INSTANCE, APP, DEVICE, PATH, REVISION, UID, SEQ
 FROM
     outbox
 WHERE
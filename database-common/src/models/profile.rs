@@ -0,0 +1,343 @@
+use crate::{
+    default_resource,
+    error::ServiceError,
+    models::{device::AdvancedDevice, sql::slice_iter, Lock},
+    Client,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use drogue_client::meta;
+use drogue_cloud_service_api::profiles;
+use futures::TryStreamExt;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use tokio_postgres::{
+    types::{Json, ToSql, Type},
+    Row,
+};
+use uuid::Uuid;
+
+/// A device profile entity record.
+pub struct Profile {
+    pub application: String,
+    pub name: String,
+    pub uid: Uuid,
+    pub labels: HashMap<String, String>,
+    pub annotations: HashMap<String, String>,
+    pub creation_timestamp: DateTime<Utc>,
+    /// Incremented with each change to the spec
+    pub generation: u64,
+    /// Updated with each change
+    pub resource_version: Uuid,
+
+    /// The spec sections of the profile.
+    pub data: Value,
+}
+
+default_resource!(Profile);
+
+impl From<Profile> for profiles::DeviceProfile {
+    fn from(profile: Profile) -> Self {
+        let spec = match profile.data {
+            Value::Object(mut data) => match data.remove("spec") {
+                Some(Value::Object(spec)) => spec,
+                _ => Default::default(),
+            },
+            _ => Default::default(),
+        };
+
+        profiles::DeviceProfile {
+            metadata: meta::v1::ScopedMetadata {
+                uid: profile.uid.to_string(),
+                name: profile.name,
+                application: profile.application,
+                labels: profile.labels,
+                annotations: profile.annotations,
+                creation_timestamp: profile.creation_timestamp,
+                generation: profile.generation,
+                resource_version: profile.resource_version.to_string(),
+                deletion_timestamp: None,
+                finalizers: vec![],
+            },
+            spec,
+        }
+    }
+}
+
+#[async_trait]
+pub trait ProfileAccessor {
+    /// Get a profile.
+    async fn get(&self, app: &str, name: &str, lock: Lock)
+        -> Result<Option<Profile>, ServiceError>;
+
+    /// Get all profiles of an application, sorted by name.
+    async fn list(&self, app: &str) -> Result<Vec<Profile>, ServiceError>;
+
+    /// Create a new profile.
+    async fn create(&self, profile: Profile) -> Result<(), ServiceError>;
+
+    /// Update an existing profile.
+    async fn update(&self, profile: Profile) -> Result<u64, ServiceError>;
+
+    /// Delete a profile.
+    async fn delete(&self, app: &str, name: &str) -> Result<u64, ServiceError>;
+
+    /// Count the devices, which reference the profile.
+    async fn count_devices(&self, app: &str, name: &str) -> Result<u64, ServiceError>;
+
+    /// Advance the generation and revision of all devices, which reference the profile.
+    ///
+    /// Returns the advanced devices, sorted by name.
+    async fn advance_devices(
+        &self,
+        app: &str,
+        name: &str,
+    ) -> Result<Vec<AdvancedDevice>, ServiceError>;
+
+    /// Get the spec of the profile, which the spec of a device references.
+    ///
+    /// Returns `None` if the device doesn't reference a valid profile.
+    async fn device_profile(
+        &self,
+        app: &str,
+        spec: &Map<String, Value>,
+    ) -> Result<Option<Map<String, Value>>, ServiceError> {
+        Ok(match profiles::device_profile(spec) {
+            Ok(Some(name)) => self
+                .get(app, name, Lock::None)
+                .await?
+                .map(|profile| profiles::DeviceProfile::from(profile).spec),
+            Ok(None) | Err(_) => None,
+        })
+    }
+}
+
+const SELECT_PROFILES: &str = r#"
+SELECT
+    APP,
+    NAME,
+    UID,
+    LABELS,
+    ANNOTATIONS,
+    CREATION_TIMESTAMP,
+    GENERATION,
+    RESOURCE_VERSION,
+    DATA
+FROM DEVICE_PROFILES
+WHERE APP=$1
+"#;
+
+pub struct PostgresProfileAccessor<'c, C: Client> {
+    client: &'c C,
+}
+
+impl<'c, C: Client> PostgresProfileAccessor<'c, C> {
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+
+    pub fn from_row(row: Row) -> Result<Profile, tokio_postgres::Error> {
+        Ok(Profile {
+            application: row.try_get("APP")?,
+            name: row.try_get("NAME")?,
+            uid: row.try_get("UID")?,
+            labels: super::row_to_map(&row, "LABELS")?,
+            annotations: super::row_to_map(&row, "ANNOTATIONS")?,
+            creation_timestamp: row.try_get("CREATION_TIMESTAMP")?,
+            generation: row.try_get::<_, i64>("GENERATION")? as u64,
+            resource_version: row.try_get("RESOURCE_VERSION")?,
+            data: row.try_get::<_, Json<_>>("DATA")?.0,
+        })
+    }
+
+    /// Run a query, returning all rows.
+    async fn query(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, ServiceError> {
+        let types = vec![Type::VARCHAR; params.len()];
+        let stmt = self.client.prepare_typed(sql, &types).await?;
+
+        Ok(self
+            .client
+            .query_raw(&stmt, slice_iter(params))
+            .await?
+            .try_collect()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl<'c, C: Client> ProfileAccessor for PostgresProfileAccessor<'c, C> {
+    async fn get(
+        &self,
+        app: &str,
+        name: &str,
+        lock: Lock,
+    ) -> Result<Option<Profile>, ServiceError> {
+        let sql = format!("{SELECT_PROFILES} AND NAME=$2\n{}", lock.as_ref());
+
+        let stmt = self
+            .client
+            .prepare_typed(&sql, &[Type::VARCHAR, Type::VARCHAR])
+            .await?;
+
+        Ok(self
+            .client
+            .query_opt(&stmt, &[&app, &name])
+            .await?
+            .map(Self::from_row)
+            .transpose()?)
+    }
+
+    async fn list(&self, app: &str) -> Result<Vec<Profile>, ServiceError> {
+        let sql = format!("{SELECT_PROFILES} ORDER BY NAME");
+
+        Ok(self
+            .query(&sql, &[&app])
+            .await?
+            .into_iter()
+            .map(Self::from_row)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn create(&self, profile: Profile) -> Result<(), ServiceError> {
+        self.client
+            .execute(
+                r#"
+INSERT INTO DEVICE_PROFILES (
+    APP,
+    NAME,
+    UID,
+    LABELS,
+    ANNOTATIONS,
+    CREATION_TIMESTAMP,
+    GENERATION,
+    RESOURCE_VERSION,
+    DATA
+) VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5,
+    $6,
+    $7,
+    $8,
+    $9
+)"#,
+                &[
+                    &profile.application,
+                    &profile.name,
+                    &profile.uid,
+                    &Json(&profile.labels),
+                    &Json(&profile.annotations),
+                    &Utc::now(),
+                    &(profile.generation as i64),
+                    &Uuid::new_v4(),
+                    &Json(&profile.data),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update(&self, profile: Profile) -> Result<u64, ServiceError> {
+        Ok(self
+            .client
+            .execute(
+                r#"
+UPDATE
+    DEVICE_PROFILES
+SET
+    LABELS = $3,
+    ANNOTATIONS = $4,
+    GENERATION = $5,
+    RESOURCE_VERSION = $6,
+    DATA = $7
+WHERE
+    APP = $1 AND NAME = $2
+"#,
+                &[
+                    &profile.application,
+                    &profile.name,
+                    &Json(&profile.labels),
+                    &Json(&profile.annotations),
+                    &(profile.generation as i64),
+                    &Uuid::new_v4(),
+                    &Json(&profile.data),
+                ],
+            )
+            .await?)
+    }
+
+    async fn delete(&self, app: &str, name: &str) -> Result<u64, ServiceError> {
+        let stmt = self
+            .client
+            .prepare_typed(
+                "DELETE FROM DEVICE_PROFILES WHERE APP = $1 AND NAME = $2",
+                &[Type::VARCHAR, Type::VARCHAR],
+            )
+            .await?;
+
+        Ok(self.client.execute(&stmt, &[&app, &name]).await?)
+    }
+
+    async fn count_devices(&self, app: &str, name: &str) -> Result<u64, ServiceError> {
+        let rows = self
+            .query(
+                "SELECT COUNT(NAME) AS COUNT FROM DEVICES WHERE APP = $1 AND DATA -> 'spec' ->> 'profile' = $2",
+                &[&app, &name],
+            )
+            .await?;
+
+        let count = rows
+            .first()
+            .ok_or_else(|| ServiceError::Internal("Unable to count entries".into()))?;
+
+        Ok(count.try_get::<_, i64>("COUNT")? as u64)
+    }
+
+    async fn advance_devices(
+        &self,
+        app: &str,
+        name: &str,
+    ) -> Result<Vec<AdvancedDevice>, ServiceError> {
+        let sql = r#"
+UPDATE
+    DEVICES
+SET
+    GENERATION = GENERATION + 1,
+    REVISION = REVISION + 1,
+    RESOURCE_VERSION = $3
+WHERE
+    APP = $1 AND DATA -> 'spec' ->> 'profile' = $2
+RETURNING
+    NAME, UID, REVISION
+"#;
+
+        let stmt = self
+            .client
+            .prepare_typed(sql, &[Type::VARCHAR, Type::VARCHAR, Type::UUID])
+            .await?;
+
+        let resource_version = Uuid::new_v4();
+        let rows: Vec<Row> = self
+            .client
+            .query_raw(&stmt, slice_iter(&[&app, &name, &resource_version]))
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut devices = rows
+            .into_iter()
+            .map(AdvancedDevice::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(devices)
+    }
+}
@@ -1,6 +1,7 @@
-use crate::models::Lock;
+use crate::models::{Cursor, Lock, SortBy};
 use drogue_cloud_service_api::{
     auth::user::{IsAdmin, UserInformation},
+    fields::FieldOperation,
     labels::Operation,
 };
 use tokio_postgres::types::{ToSql, Type};
@@ -72,6 +73,35 @@ impl<'a> SelectBuilder<'a> {
         self
     }
 
+    /// Sort by the sort order, and only return items after the cursor.
+    ///
+    /// The cursor must be created for the same sort order.
+    pub fn page(mut self, sort: SortBy, cursor: &'a Option<Cursor>) -> Self {
+        match cursor {
+            Some(Cursor::Name(name)) => {
+                self.ensure_where_or_and();
+                self.params.push(name);
+                self.types.push(Type::VARCHAR);
+                self.select
+                    .push_str(&format!(" NAME > ${}", self.params.len()));
+            }
+            Some(Cursor::CreationTimestamp(creation_timestamp, name)) => {
+                self.ensure_where_or_and();
+                self.params.push(creation_timestamp);
+                self.params.push(name);
+                self.types.push(Type::TIMESTAMPTZ);
+                self.types.push(Type::VARCHAR);
+                self.select.push_str(&format!(
+                    " (CREATION_TIMESTAMP, NAME) > (${}, ${})",
+                    self.params.len() - 1,
+                    self.params.len()
+                ));
+            }
+            None => {}
+        }
+        self.sort(sort.columns())
+    }
+
     pub fn sort<I>(mut self, sort: I) -> Self
     where
         I: IntoIterator + Sized,
@@ -210,6 +240,77 @@ impl<'a> SelectBuilder<'a> {
                         self.params.len()
                     ));
                 }
+                Operation::Gt(label, value) => self.numeric(label, ">", value),
+                Operation::Lt(label, value) => self.numeric(label, "<", value),
+                Operation::Prefix(label, prefix) => {
+                    self.params.push(label);
+                    self.params.push(prefix);
+                    self.types.push(Type::VARCHAR);
+                    self.types.push(Type::VARCHAR);
+                    self.select.push_str(&format!(
+                        " LEFT(LABELS ->> ${label}, LENGTH(${prefix})) = ${prefix}",
+                        label = self.params.len() - 1,
+                        prefix = self.params.len()
+                    ));
+                }
+            }
+        }
+        self
+    }
+
+    /// Compare the numeric value of a label.
+    ///
+    /// Labels which don't have a numeric value don't match. This must be aligned with
+    /// [`drogue_cloud_service_api::labels::numeric`].
+    fn numeric(&mut self, label: &'a String, op: &str, value: &'a String) {
+        self.params.push(label);
+        self.params.push(value);
+        self.types.push(Type::VARCHAR);
+        self.types.push(Type::VARCHAR);
+        self.select.push_str(&format!(
+            r#" CASE WHEN LABELS ->> ${label} ~ '^-?[0-9]+(\.[0-9]+)?$' THEN (LABELS ->> ${label})::NUMERIC {op} ${value}::NUMERIC ELSE FALSE END"#,
+            label = self.params.len() - 1,
+            value = self.params.len()
+        ));
+    }
+
+    /// Add a fields filter.
+    pub fn fields(mut self, fields: &'a [FieldOperation]) -> Self {
+        for op in fields {
+            self.ensure_where_or_and();
+            match op {
+                FieldOperation::CreatedAfter(timestamp) => {
+                    self.params.push(timestamp);
+                    self.types.push(Type::TIMESTAMPTZ);
+                    self.select
+                        .push_str(&format!(" CREATION_TIMESTAMP > ${}", self.params.len()));
+                }
+                FieldOperation::CreatedBefore(timestamp) => {
+                    self.params.push(timestamp);
+                    self.types.push(Type::TIMESTAMPTZ);
+                    self.select
+                        .push_str(&format!(" CREATION_TIMESTAMP < ${}", self.params.len()));
+                }
+                FieldOperation::Deleted => {
+                    self.select.push_str(" DELETION_TIMESTAMP IS NOT NULL");
+                }
+                FieldOperation::NotDeleted => {
+                    self.select.push_str(" DELETION_TIMESTAMP IS NULL");
+                }
+                FieldOperation::SpecExists(section) => {
+                    self.params.push(section);
+                    self.types.push(Type::VARCHAR);
+                    self.select
+                        .push_str(&format!(" (DATA -> 'spec') ? ${}", self.params.len()));
+                }
+                FieldOperation::SpecNotExists(section) => {
+                    self.params.push(section);
+                    self.types.push(Type::VARCHAR);
+                    self.select.push_str(&format!(
+                        " (NOT COALESCE((DATA -> 'spec') ? ${}, FALSE))",
+                        self.params.len()
+                    ));
+                }
             }
         }
         self
@@ -243,7 +344,7 @@ impl<'a> SelectBuilder<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use drogue_cloud_service_api::labels::LabelSelector;
+    use drogue_cloud_service_api::{fields::FieldSelector, labels::LabelSelector};
     use std::convert::TryInto;
     use std::fmt::Debug;
 
@@ -345,6 +446,94 @@ AND LABELS ->> $4 <> $5
         );
     }
 
+    #[test]
+    fn test_to_labels_compare() {
+        let mut builder = SelectBuilder::new("SELECT * FROM TABLE", Vec::new(), Vec::new());
+
+        let selector: LabelSelector = r#"foo>1,bar^=baz"#.try_into().unwrap();
+        builder = builder.labels(&selector.0);
+
+        let (sql, params, types) = builder.build();
+
+        assert_eq!(
+            sql,
+            r#"SELECT * FROM TABLE
+WHERE CASE WHEN LABELS ->> $1 ~ '^-?[0-9]+(\.[0-9]+)?$' THEN (LABELS ->> $1)::NUMERIC > $2::NUMERIC ELSE FALSE END
+AND LEFT(LABELS ->> $3, LENGTH($4)) = $4
+"#
+        );
+        assert_eq!(
+            params
+                .into_iter()
+                .map(|p| format!("{:?}", p))
+                .collect::<Vec<String>>(),
+            to_debug(&[&"foo", &"1", &"bar", &"baz"])
+        );
+        assert_eq!(
+            types,
+            vec![Type::VARCHAR, Type::VARCHAR, Type::VARCHAR, Type::VARCHAR]
+        );
+    }
+
+    #[test]
+    fn test_to_fields() {
+        let builder = SelectBuilder::new("SELECT * FROM TABLE", Vec::new(), Vec::new());
+
+        let selector: FieldSelector =
+            "metadata.creationTimestamp>2022-01-01T00:00:00Z,!metadata.deletionTimestamp,!spec.credentials"
+                .try_into()
+                .unwrap();
+        let (sql, _, types) = builder.fields(&selector.0).build();
+
+        assert_eq!(
+            sql,
+            r#"SELECT * FROM TABLE
+WHERE CREATION_TIMESTAMP > $1
+AND DELETION_TIMESTAMP IS NULL
+AND (NOT COALESCE((DATA -> 'spec') ? $2, FALSE))
+"#
+        );
+        assert_eq!(types, vec![Type::TIMESTAMPTZ, Type::VARCHAR]);
+    }
+
+    #[test]
+    fn test_page() {
+        let builder = SelectBuilder::new("SELECT * FROM TABLE", Vec::new(), Vec::new());
+
+        let cursor = Some(Cursor::Name("device-1".into()));
+        let (sql, params, types) = builder.page(SortBy::Name, &cursor).limit(Some(10)).build();
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM TABLE\nWHERE NAME > $1\nORDER BY NAME\nLIMIT 10\n"
+        );
+        assert_eq!(
+            params
+                .into_iter()
+                .map(|p| format!("{:?}", p))
+                .collect::<Vec<String>>(),
+            to_debug(&[&"device-1"])
+        );
+        assert_eq!(types, vec![Type::VARCHAR]);
+    }
+
+    #[test]
+    fn test_page_creation_timestamp() {
+        let builder = SelectBuilder::new("SELECT * FROM TABLE", Vec::new(), Vec::new());
+
+        let cursor = Some(Cursor::CreationTimestamp(
+            chrono::Utc::now(),
+            "device-1".into(),
+        ));
+        let (sql, _, types) = builder.page(SortBy::CreationTimestamp, &cursor).build();
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM TABLE\nWHERE (CREATION_TIMESTAMP, NAME) > ($1, $2)\nORDER BY CREATION_TIMESTAMP,NAME\n"
+        );
+        assert_eq!(types, vec![Type::TIMESTAMPTZ, Type::VARCHAR]);
+    }
+
     fn to_debug(list: &[&dyn Debug]) -> Vec<String> {
         list.iter().map(|s| format!("{:?}", s)).collect()
     }
@@ -33,7 +33,7 @@ async fn test_outbox() -> anyhow::Result<()> {
 
     let ms1 = millis_since_epoch();
 
-    outbox
+    let seq1 = outbox
         .create(OutboxEntry {
             instance: "instance1".to_string(),
             app: "app1".to_string(),
@@ -41,6 +41,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms1,
+            sequence: None,
         })
         .await?;
 
@@ -54,6 +55,8 @@ async fn test_outbox() -> anyhow::Result<()> {
 
     // there should be one entry now
 
+    assert!(seq1.is_some());
+
     assert_eq!(
         entries,
         vec![OutboxEntry {
@@ -63,6 +66,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms1,
+            sequence: seq1,
         }]
     );
 
@@ -70,7 +74,7 @@ async fn test_outbox() -> anyhow::Result<()> {
 
     let ms2 = millis_since_epoch();
 
-    outbox
+    let seq2 = outbox
         .create(OutboxEntry {
             instance: "instance1".to_string(),
             app: "app1".to_string(),
@@ -78,6 +82,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms2,
+            sequence: None,
         })
         .await?;
 
@@ -89,7 +94,9 @@ async fn test_outbox() -> anyhow::Result<()> {
         .try_collect()
         .await?;
 
-    // there still should be only one entry
+    // there still should be only one entry, with a new sequence number
+
+    assert!(seq2 > seq1);
 
     assert_eq!(
         entries,
@@ -100,6 +107,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms2,
+            sequence: seq2,
         }]
     );
 
@@ -113,6 +121,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms1,
+            sequence: None,
         })
         .await?;
 
@@ -135,6 +144,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms2,
+            sequence: seq2,
         }]
     );
 
@@ -148,6 +158,7 @@ async fn test_outbox() -> anyhow::Result<()> {
             uid: "a".to_string(),
             path: ".path".to_string(),
             revision: ms2,
+            sequence: None,
         })
         .await?;
 
@@ -183,8 +194,11 @@ impl CreateApp {
         }
     }
 
-    async fn run<C: Client>(self, outbox: &PostgresOutboxAccessor<'_, C>) -> anyhow::Result<()> {
-        outbox
+    async fn run<C: Client>(
+        self,
+        outbox: &PostgresOutboxAccessor<'_, C>,
+    ) -> anyhow::Result<Option<u64>> {
+        Ok(outbox
             .create(OutboxEntry {
                 instance: "instance1".to_string(),
                 app: self.app,
@@ -192,10 +206,9 @@ impl CreateApp {
                 uid: self.uid,
                 path: self.path,
                 revision: self.revision,
+                sequence: None,
             })
-            .await?;
-
-        Ok(())
+            .await?)
     }
 }
 
@@ -212,8 +225,9 @@ async fn test_recreate_resource() -> anyhow::Result<()> {
 
     let outbox = PostgresOutboxAccessor::new(&c);
 
-    // create a flow of events
+    // create a flow of events, every event replaces the previous one
 
+    let mut sequence = None;
     for i in vec![
         CreateApp::new("app1", "a", ".path", 1),
         CreateApp::new("app1", "a", ".path", 2),
@@ -221,7 +235,9 @@ async fn test_recreate_resource() -> anyhow::Result<()> {
         CreateApp::new("app1", "b", ".path", 1),
         CreateApp::new("app1", "b", ".path", 2),
     ] {
-        i.run(&outbox).await?;
+        let seq = i.run(&outbox).await?;
+        assert!(seq > sequence);
+        sequence = seq;
     }
 
     // now check
@@ -245,6 +261,7 @@ async fn test_recreate_resource() -> anyhow::Result<()> {
             uid: "b".to_string(),
             path: ".path".to_string(),
             revision: 2,
+            sequence,
         }]
     );
 
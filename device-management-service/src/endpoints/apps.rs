@@ -1,7 +1,15 @@
 use super::streamer::ArrayStreamer;
 use crate::{
-    endpoints::params::{DeleteParams, ListParams},
-    service::{management::ManagementService, PostgresManagementService},
+    endpoints::{
+        params::{DeleteParams, ListParams},
+        parse_patch,
+        watch::watch,
+    },
+    service::{
+        management::ManagementService,
+        watch::{WatchTarget, Watcher},
+        PostgresManagementService,
+    },
     WebData,
 };
 use actix_web::{http::header, web, web::Json, HttpRequest, HttpResponse};
@@ -74,6 +82,32 @@ where
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[instrument(skip(data, body))]
+pub async fn patch<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    body: web::Bytes,
+    user: UserInformation,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+
+    log::debug!("Patching app: '{}'", app_id);
+
+    let patch = match parse_patch(&req, &body)? {
+        Some(patch) => patch,
+        None => return Ok(HttpResponse::UnsupportedMediaType().finish()),
+    };
+
+    data.service.patch_app(&user, &app_id, patch).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[instrument(skip(data))]
 pub async fn delete<S, K>(
     data: web::Data<WebData<PostgresManagementService<S, K>>>,
@@ -125,27 +159,48 @@ where
     })
 }
 
-#[instrument(skip(data))]
+#[instrument(skip(data, watcher))]
 pub async fn list<S, K>(
     data: web::Data<WebData<PostgresManagementService<S, K>>>,
     params: web::Query<ListParams>,
     user: UserInformation,
+    watcher: Option<web::Data<Watcher>>,
 ) -> Result<HttpResponse, actix_web::Error>
 where
-    S: EventSender + Clone,
-    K: KeycloakClient + Send + Sync,
+    S: EventSender + Clone + 'static,
+    K: KeycloakClient + Send + Sync + 'static,
 {
     log::debug!("Listing apps");
 
+    let params = params.into_inner();
+
     let selector = params
-        .0
         .labels
         .try_into()
         .map_err(|err: ParserError| ServiceError::InvalidRequest(err.to_string()))?;
 
+    if params.watch {
+        if params.limit.is_some() || params.offset.is_some() {
+            return Err(ServiceError::InvalidRequest(
+                "Watching cannot be combined with paging".into(),
+            )
+            .into());
+        }
+
+        return watch(
+            &data,
+            watcher,
+            user,
+            WatchTarget::Applications,
+            selector,
+            params.revision.as_deref(),
+        )
+        .await;
+    }
+
     let apps = data
         .service
-        .list_apps(user, selector, params.0.limit, params.0.offset)
+        .list_apps(user, selector, params.limit, params.offset)
         .await?;
 
     Ok(HttpResponse::Ok()
@@ -0,0 +1,715 @@
+use crate::{
+    endpoints::params::{BulkFormat, ExportParams, ImportParams},
+    service::{
+        management::{ImportOptions, ImportOutcome, ListOptions, ManagementService},
+        PostgresManagementService,
+    },
+    WebData,
+};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use bytes::{Bytes, BytesMut};
+use csv_core::ReadRecordResult;
+use drogue_client::registry;
+use drogue_cloud_registry_events::EventSender;
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_api::{
+    auth::user::UserInformation,
+    labels::{LabelSelector, ParserError},
+};
+use drogue_cloud_service_common::error::ServiceError;
+use drogue_cloud_service_common::keycloak::KeycloakClient;
+use futures::{future, stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::convert::TryInto;
+use tracing::instrument;
+
+/// The maximum number of devices stored in a single transaction.
+const MAX_BATCH_SIZE: usize = 1000;
+/// The maximum length of a single line, or CSV record.
+const MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/// The columns of the CSV format.
+const CSV_COLUMNS: &[&str] = &["name", "labels", "aliases", "username", "password"];
+
+/// The result of an import.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ImportError>,
+}
+
+/// A device which failed to import.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportError {
+    /// The line number, starting with 1. For CSV, this is the number of the record, including the
+    /// header.
+    pub line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub message: String,
+}
+
+impl ImportReport {
+    fn failed(&mut self, line: usize, device: Option<String>, message: String) {
+        self.failed += 1;
+        self.errors.push(ImportError {
+            line,
+            device,
+            message,
+        });
+    }
+}
+
+/// A parsed device, `None` if the input contained no device, or the name of the device and the
+/// reason it failed to parse.
+type Entry = Result<Option<registry::v1::Device>, (Option<String>, String)>;
+
+/// Read devices from chunks of input.
+enum Reader {
+    Ndjson {
+        buffer: BytesMut,
+        line: usize,
+    },
+    Csv {
+        reader: CsvReader,
+        header: Option<Vec<String>>,
+    },
+}
+
+impl Reader {
+    fn new(format: BulkFormat) -> Self {
+        match format {
+            BulkFormat::Ndjson => Self::Ndjson {
+                buffer: BytesMut::new(),
+                line: 0,
+            },
+            BulkFormat::Csv => Self::Csv {
+                reader: CsvReader::new(),
+                header: None,
+            },
+        }
+    }
+
+    /// The number of the next line, or CSV record.
+    fn next_line(&self) -> usize {
+        match self {
+            Self::Ndjson { line, .. } => *line + 1,
+            Self::Csv { reader, .. } => reader.records + 1,
+        }
+    }
+
+    /// Read the devices of a chunk of input, `None` marks the end of the input.
+    ///
+    /// Returns the parsed devices, with their line number. An error is returned if the input
+    /// can't be read any further.
+    fn read(&mut self, app: &str, chunk: Option<&[u8]>) -> Result<Vec<(usize, Entry)>, String> {
+        let mut entries = vec![];
+
+        match self {
+            Self::Ndjson { buffer, line } => {
+                match chunk {
+                    Some(chunk) => buffer.extend_from_slice(chunk),
+                    // process the remainder as the last line
+                    None if !buffer.is_empty() => buffer.extend_from_slice(b"\n"),
+                    None => {}
+                }
+
+                while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                    let bytes = buffer.split_to(pos + 1);
+                    *line += 1;
+                    entries.push((*line, validate(parse_json(&bytes[..pos]))));
+                }
+
+                if buffer.len() > MAX_LINE_LENGTH {
+                    return Err(format!(
+                        "Line {} exceeds the maximum length of {} bytes",
+                        *line + 1,
+                        MAX_LINE_LENGTH
+                    ));
+                }
+            }
+            Self::Csv { reader, header } => {
+                for (record, fields) in reader.read(chunk)? {
+                    let entry = fields
+                        .map_err(|err| (None, err))
+                        .and_then(|fields| parse_csv(app, header, fields));
+                    entries.push((record, validate(entry)));
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Parse a line of JSON.
+fn parse_json(line: &[u8]) -> Entry {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+
+    serde_json::from_slice(line)
+        .map(Some)
+        .map_err(|err| (None, format!("Invalid device: {err}")))
+}
+
+/// Parse a CSV record, the first one being the header.
+fn parse_csv(app: &str, header: &mut Option<Vec<String>>, fields: Vec<String>) -> Entry {
+    if fields.iter().all(|field| field.trim().is_empty()) {
+        return Ok(None);
+    }
+
+    match header {
+        Some(header) => device_from_csv(app, header, fields).map(Some),
+        None => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|field| field.trim().to_lowercase())
+                .collect();
+            if !fields.iter().any(|column| column == "name") {
+                return Err((None, "Missing column 'name' in header".into()));
+            }
+            *header = Some(fields);
+            Ok(None)
+        }
+    }
+}
+
+/// Validate a parsed device.
+fn validate(entry: Entry) -> Entry {
+    if let Ok(Some(device)) = &entry {
+        let name = &device.metadata.name;
+        if name.is_empty() || name.len() > 255 {
+            return Err((Some(name.clone()), "Invalid device name".into()));
+        }
+    }
+
+    entry
+}
+
+/// Read CSV records from chunks of input.
+///
+/// Records are only split at line breaks outside of quoted fields, so a quoted field may contain
+/// line breaks, and a record may span multiple chunks.
+struct CsvReader {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    ends: Vec<usize>,
+    /// The number of bytes of the current record in `output`.
+    len: usize,
+    /// The number of fields of the current record in `ends`.
+    fields: usize,
+    /// The number of records read so far.
+    records: usize,
+}
+
+impl CsvReader {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            ends: vec![0; 16],
+            len: 0,
+            fields: 0,
+            records: 0,
+        }
+    }
+
+    /// Read the complete records of a chunk of input, `None` marks the end of the input.
+    ///
+    /// Returns the fields of each record, with its number.
+    #[allow(clippy::type_complexity)]
+    fn read(
+        &mut self,
+        chunk: Option<&[u8]>,
+    ) -> Result<Vec<(usize, Result<Vec<String>, String>)>, String> {
+        let end = chunk.is_none();
+        let mut input = chunk.unwrap_or_default();
+        let mut records = vec![];
+
+        loop {
+            // an empty input signals the end to the reader
+            if input.is_empty() && !end {
+                break;
+            }
+
+            let (result, nin, nout, nend) = self.reader.read_record(
+                input,
+                &mut self.output[self.len..],
+                &mut self.ends[self.fields..],
+            );
+            input = &input[nin..];
+            self.len += nout;
+            self.fields += nend;
+
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => break,
+                ReadRecordResult::OutputFull => {
+                    if self.output.len() >= MAX_LINE_LENGTH {
+                        return Err(format!(
+                            "Record {} exceeds the maximum length of {} bytes",
+                            self.records + 1,
+                            MAX_LINE_LENGTH
+                        ));
+                    }
+                    self.output.resize(self.output.len() * 2, 0);
+                }
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    self.records += 1;
+                    records.push((self.records, self.take()));
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Take the fields of the current record.
+    fn take(&mut self) -> Result<Vec<String>, String> {
+        let mut start = 0;
+        let fields = self.ends[..self.fields]
+            .iter()
+            .map(|&end| {
+                let field = &self.output[start..end];
+                start = end;
+                std::str::from_utf8(field).map(ToString::to_string)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Invalid CSV: {err}"));
+
+        self.len = 0;
+        self.fields = 0;
+
+        fields
+    }
+}
+
+/// Split a list of values, separated by semicolons.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(';').map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Create a device from a CSV record.
+///
+/// Whitespace around names, labels and aliases is removed. Credentials are taken as they are.
+fn device_from_csv(
+    app: &str,
+    header: &[String],
+    record: Vec<String>,
+) -> Result<registry::v1::Device, (Option<String>, String)> {
+    let mut values = Map::new();
+    for (column, value) in header.iter().zip(record) {
+        if !value.is_empty() {
+            values.insert(column.clone(), Value::String(value));
+        }
+    }
+    let value = |column: &str| values.get(column).and_then(Value::as_str);
+
+    let name = value("name").unwrap_or_default().trim().to_string();
+
+    let mut labels = Map::new();
+    for label in split_list(value("labels").unwrap_or_default()) {
+        let (k, v) = label.split_once('=').unwrap_or((label, ""));
+        labels.insert(k.trim().to_string(), Value::String(v.trim().to_string()));
+    }
+
+    let mut spec = Map::new();
+
+    let aliases: Vec<_> = split_list(value("aliases").unwrap_or_default()).collect();
+    if !aliases.is_empty() {
+        spec.insert("alias".into(), json!(aliases));
+    }
+
+    let credential = match (value("username"), value("password")) {
+        (Some(username), Some(password)) => {
+            Some(json!({"user": {"username": username, "password": password}}))
+        }
+        (None, Some(password)) => Some(json!({ "pass": password })),
+        (Some(_), None) => {
+            return Err((Some(name), "A username requires a password".into()));
+        }
+        (None, None) => None,
+    };
+    if let Some(credential) = credential {
+        spec.insert(
+            "authentication".into(),
+            json!({ "credentials": [credential] }),
+        );
+    }
+
+    serde_json::from_value(json!({
+        "metadata": {
+            "application": app,
+            "name": name,
+            "labels": labels,
+        },
+        "spec": spec,
+    }))
+    .map_err(|err| (Some(name), format!("Invalid device: {err}")))
+}
+
+/// Convert a device into a line of CSV.
+fn device_to_csv(device: &registry::v1::Device) -> Bytes {
+    let mut labels: Vec<_> = device
+        .metadata
+        .labels
+        .iter()
+        .map(|(k, v)| {
+            if v.is_empty() {
+                k.clone()
+            } else {
+                format!("{k}={v}")
+            }
+        })
+        .collect();
+    labels.sort();
+
+    let aliases: Vec<_> = device
+        .spec
+        .get("alias")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+
+    // only plain text passwords can be represented
+    let (username, password) = device
+        .spec
+        .get("authentication")
+        .and_then(|auth| auth["credentials"].as_array())
+        .into_iter()
+        .flatten()
+        .find_map(|credential| {
+            if let Some(password) = credential["pass"].as_str() {
+                Some(("", password))
+            } else {
+                let user = &credential["user"];
+                user["username"].as_str().zip(user["password"].as_str())
+            }
+        })
+        .unwrap_or_default();
+
+    csv_line(&[
+        &device.metadata.name,
+        &labels.join(";"),
+        &aliases.join(";"),
+        username,
+        password,
+    ])
+}
+
+fn csv_line(fields: &[&str]) -> Bytes {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    // writing to a vector doesn't fail
+    let _ = writer.write_record(fields);
+    writer.into_inner().map(Bytes::from).unwrap_or_default()
+}
+
+/// Detect the format from the content type of the request.
+fn detect_format(req: &HttpRequest) -> BulkFormat {
+    match req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+    {
+        Some(ct) if ct.starts_with("text/csv") => BulkFormat::Csv,
+        _ => BulkFormat::Ndjson,
+    }
+}
+
+/// Store devices of a batch, and record the outcome.
+///
+/// Earlier batches may already be stored, so a failing batch is recorded in the report, instead
+/// of failing the whole import.
+async fn import_batch<S, K>(
+    service: &PostgresManagementService<S, K>,
+    user: &UserInformation,
+    app_id: &str,
+    batch: Vec<(usize, registry::v1::Device)>,
+    options: ImportOptions,
+    report: &mut ImportReport,
+) where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    if batch.is_empty() {
+        return;
+    }
+
+    let (lines, devices): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|(line, device)| ((line, device.metadata.name.clone()), device))
+        .unzip();
+
+    let results = match service.import_devices(user, app_id, devices, options).await {
+        Ok(results) => results,
+        Err(err) => {
+            let message = err.to_string();
+            for (line, name) in lines {
+                report.failed(line, Some(name), message.clone());
+            }
+            return;
+        }
+    };
+
+    for ((line, name), result) in lines.into_iter().zip(results) {
+        match result {
+            Ok(ImportOutcome::Created) => report.created += 1,
+            Ok(ImportOutcome::Updated) => report.updated += 1,
+            Ok(ImportOutcome::Unchanged) => report.unchanged += 1,
+            Err(err) => report.failed(line, Some(name), err.to_string()),
+        }
+    }
+}
+
+#[instrument(skip(data, payload))]
+pub async fn import<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    params: web::Query<ImportParams>,
+    user: UserInformation,
+    req: HttpRequest,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+    let params = params.into_inner();
+
+    log::debug!("Importing devices: '{}'", app_id);
+
+    let format = params.format.unwrap_or_else(|| detect_format(&req));
+    let batch_size = params.batch_size.clamp(1, MAX_BATCH_SIZE);
+    let options = ImportOptions {
+        dry_run: params.dry_run,
+        upsert: params.upsert,
+    };
+
+    let mut report = ImportReport {
+        dry_run: params.dry_run,
+        ..Default::default()
+    };
+    let mut reader = Reader::new(format);
+    let mut batch = Vec::with_capacity(batch_size);
+
+    loop {
+        let result = match payload.try_next().await {
+            Ok(chunk) => reader
+                .read(&app_id, chunk.as_deref())
+                .map(|entries| (entries, chunk.is_none())),
+            Err(err) => Err(format!("Failed to read the request: {err}")),
+        };
+
+        let (entries, done) = match result {
+            Ok(result) => result,
+            Err(message) => {
+                // stop reading, but still store, and report, what we have so far
+                report.failed(reader.next_line(), None, message);
+                break;
+            }
+        };
+
+        for (line, entry) in entries {
+            match entry {
+                Ok(Some(device)) => batch.push((line, device)),
+                Ok(None) => {}
+                Err((device, message)) => report.failed(line, device, message),
+            }
+
+            if batch.len() >= batch_size {
+                let batch = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                import_batch(&data.service, &user, &app_id, batch, options, &mut report).await;
+            }
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    import_batch(&data.service, &user, &app_id, batch, options, &mut report).await;
+
+    report.errors.sort_by_key(|error| error.line);
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[instrument(skip(data))]
+pub async fn export<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    params: web::Query<ExportParams>,
+    user: UserInformation,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+    let params = params.into_inner();
+
+    log::debug!("Exporting devices: '{}'", app_id);
+
+    let selector: LabelSelector = params
+        .labels
+        .try_into()
+        .map_err(|err: ParserError| ServiceError::InvalidRequest(err.to_string()))?;
+
+    let devices = data
+        .service
+        .list_devices(user, &app_id, selector, ListOptions::default())
+        .await?;
+
+    Ok(match params.format.unwrap_or(BulkFormat::Ndjson) {
+        BulkFormat::Ndjson => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(devices.map_ok(|device| {
+                let mut json = serde_json::to_vec(&device).unwrap_or_default();
+                json.push(b'\n');
+                Bytes::from(json)
+            })),
+        BulkFormat::Csv => HttpResponse::Ok().content_type("text/csv").streaming(
+            stream::once(future::ok(csv_line(CSV_COLUMNS)))
+                .chain(devices.map_ok(|device| device_to_csv(&device))),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(reader: &mut Reader, chunk: Option<&[u8]>) -> Vec<(usize, Entry)> {
+        reader.read("app1", chunk).unwrap()
+    }
+
+    fn device(entry: &(usize, Entry)) -> &registry::v1::Device {
+        entry.1.as_ref().unwrap().as_ref().unwrap()
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let mut reader = Reader::new(BulkFormat::Csv);
+
+        let entries = read(
+            &mut reader,
+            Some(b"name,labels,aliases,password\n device1 ,floor=1; room=101,foo; bar,secret\r\n"),
+        );
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[0], (1, Ok(None))));
+
+        let device = device(&entries[1]);
+        assert_eq!(entries[1].0, 2);
+        assert_eq!(device.metadata.application, "app1");
+        assert_eq!(device.metadata.name, "device1");
+        assert_eq!(
+            device.metadata.labels.get("room").map(String::as_str),
+            Some("101")
+        );
+        assert_eq!(
+            Value::Object(device.spec.clone()),
+            json!({
+                "alias": ["foo", "bar"],
+                "authentication": {"credentials": [{"pass": "secret"}]},
+            })
+        );
+
+        assert_eq!(
+            device_to_csv(device),
+            Bytes::from_static(b"device1,floor=1;room=101,foo;bar,,secret\n")
+        );
+
+        // empty lines are skipped, names are required
+        let entries = read(&mut reader, Some(b"\n,floor=1\n"));
+        assert!(entries
+            .iter()
+            .all(|(_, entry)| !matches!(entry, Ok(Some(_)))));
+        assert!(entries.iter().any(|(_, entry)| entry.is_err()));
+    }
+
+    #[test]
+    fn test_parse_csv_quoted() {
+        let mut reader = Reader::new(BulkFormat::Csv);
+
+        assert!(read(&mut reader, Some(b"name,password\n")).len() == 1);
+
+        // a quoted field, containing a line break, split across chunks
+        assert!(read(&mut reader, Some(b"device1,\"sec")).is_empty());
+        assert!(read(&mut reader, Some(b"\nret\"\ndevice2,\"a,")).len() == 1);
+
+        // the last record doesn't need a line break
+        let entries = read(&mut reader, Some(b"b\"\"c\""));
+        assert!(entries.is_empty());
+        let entries = read(&mut reader, None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, 3);
+        assert_eq!(
+            Value::Object(device(&entries[0]).spec.clone()),
+            json!({"authentication": {"credentials": [{"pass": "a,b\"c"}]}})
+        );
+    }
+
+    #[test]
+    fn test_parse_csv_credentials() {
+        let mut reader = Reader::new(BulkFormat::Csv);
+
+        let entries = read(
+            &mut reader,
+            Some(b" Name , Username , Password \ndevice1, user1 , secret \n"),
+        );
+        assert_eq!(entries.len(), 2);
+
+        // credentials are not trimmed
+        let device = device(&entries[1]);
+        assert_eq!(device.metadata.name, "device1");
+        assert_eq!(
+            Value::Object(device.spec.clone()),
+            json!({
+                "authentication": {"credentials": [
+                    {"user": {"username": " user1 ", "password": " secret "}},
+                ]},
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let mut reader = Reader::new(BulkFormat::Ndjson);
+
+        let entries = read(
+            &mut reader,
+            Some(br#"{"metadata": {"application": "app1", "name": "device1"}}"#),
+        );
+        assert!(entries.is_empty());
+
+        let entries = read(&mut reader, Some(b"\n\n{"));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(device(&entries[0]).metadata.name, "device1");
+        assert!(matches!(entries[1], (2, Ok(None))));
+
+        let entries = read(&mut reader, None);
+        assert!(matches!(entries[..], [(3, Err(_))]));
+    }
+
+    #[test]
+    fn test_missing_name_column() {
+        let mut reader = Reader::new(BulkFormat::Csv);
+        let entries = read(&mut reader, Some(b"labels,aliases\n"));
+        assert!(matches!(entries[..], [(1, Err(_))]));
+    }
+}
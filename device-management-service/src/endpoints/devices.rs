@@ -1,21 +1,38 @@
 use crate::{
     endpoints::{
-        params::{DeleteParams, ListParams},
+        params::{encode_continue, DeleteParams, DeviceListParams, DeviceReadParams},
+        parse_patch,
         streamer::ArrayStreamer,
+        watch::watch,
+    },
+    service::{
+        management::{ListOptions, ManagementService},
+        watch::{WatchTarget, Watcher},
+        PostgresManagementService,
     },
-    service::{management::ManagementService, PostgresManagementService},
     WebData,
 };
 use actix_web::{http::header, web, web::Json, HttpRequest, HttpResponse};
 use drogue_client::registry;
+use drogue_cloud_database_common::models::Cursor;
 use drogue_cloud_registry_events::EventSender;
 use drogue_cloud_service_api::webapp as actix_web;
-use drogue_cloud_service_api::{auth::user::UserInformation, labels::ParserError};
+use drogue_cloud_service_api::{
+    auth::user::UserInformation,
+    fields::FieldSelector,
+    labels::{LabelSelector, ParserError},
+};
 use drogue_cloud_service_common::error::ServiceError;
 use drogue_cloud_service_common::keycloak::KeycloakClient;
+use futures::TryStreamExt;
 use std::convert::TryInto;
 use tracing::instrument;
 
+/// The header carrying the total number of items.
+pub const HEADER_TOTAL_COUNT: &str = "X-Total-Count";
+/// The header carrying the token for requesting the next page.
+pub const HEADER_CONTINUE_TOKEN: &str = "X-Continue-Token";
+
 #[instrument(skip(data))]
 pub async fn create<S, K>(
     data: web::Data<WebData<PostgresManagementService<S, K>>>,
@@ -82,6 +99,34 @@ where
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[instrument(skip(data, body))]
+pub async fn patch<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+    user: UserInformation,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, device_id) = path.into_inner();
+
+    log::debug!("Patching device: '{}' / '{}'", app_id, device_id);
+
+    let patch = match parse_patch(&req, &body)? {
+        Some(patch) => patch,
+        None => return Ok(HttpResponse::UnsupportedMediaType().finish()),
+    };
+
+    data.service
+        .patch_device(&user, &app_id, &device_id, patch)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[instrument(skip(data))]
 pub async fn delete<S, K>(
     data: web::Data<WebData<PostgresManagementService<S, K>>>,
@@ -117,6 +162,7 @@ where
 pub async fn read<S, K>(
     data: web::Data<WebData<PostgresManagementService<S, K>>>,
     path: web::Path<(String, String)>,
+    params: web::Query<DeviceReadParams>,
     user: UserInformation,
 ) -> Result<HttpResponse, actix_web::Error>
 where
@@ -131,7 +177,10 @@ where
         return Ok(HttpResponse::BadRequest().finish());
     }
 
-    let device = data.service.get_device(&user, &app_id, &device_id).await?;
+    let device = data
+        .service
+        .get_device(&user, &app_id, &device_id, params.effective)
+        .await?;
 
     let result = match device {
         None => HttpResponse::NotFound().finish(),
@@ -141,18 +190,20 @@ where
     Ok(result)
 }
 
-#[instrument(skip(data))]
+#[instrument(skip(data, watcher))]
 pub async fn list<S, K>(
     data: web::Data<WebData<PostgresManagementService<S, K>>>,
     path: web::Path<String>,
-    params: web::Query<ListParams>,
+    params: web::Query<DeviceListParams>,
     user: UserInformation,
+    watcher: Option<web::Data<Watcher>>,
 ) -> Result<HttpResponse, actix_web::Error>
 where
-    S: EventSender + Clone,
-    K: KeycloakClient + Send + Sync,
+    S: EventSender + Clone + 'static,
+    K: KeycloakClient + Send + Sync + 'static,
 {
     let app_id = path.into_inner();
+    let params = params.into_inner();
 
     log::debug!("Listing devices: '{}' ", app_id);
 
@@ -160,18 +211,123 @@ where
         return Ok(HttpResponse::BadRequest().finish());
     }
 
-    let selector = params
-        .0
+    let selector: LabelSelector = params
         .labels
+        .as_str()
         .try_into()
         .map_err(|err: ParserError| ServiceError::InvalidRequest(err.to_string()))?;
+    let fields: FieldSelector = params
+        .field_selector
+        .as_str()
+        .try_into()
+        .map_err(|err: ParserError| ServiceError::InvalidRequest(err.to_string()))?;
+
+    if params.watch {
+        if params.limit.is_some()
+            || params.offset.is_some()
+            || params.continue_token.is_some()
+            || params.count
+        {
+            return Err(ServiceError::InvalidRequest(
+                "Watching cannot be combined with paging".into(),
+            )
+            .into());
+        }
+        if !fields.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                "Watching cannot be combined with a field selector".into(),
+            )
+            .into());
+        }
+
+        return watch(
+            &data,
+            watcher,
+            user,
+            WatchTarget::Devices {
+                application: app_id,
+                effective: params.effective,
+            },
+            selector,
+            params.revision.as_deref(),
+        )
+        .await;
+    }
+
+    let sort = params.sort.unwrap_or_default();
+    let cursor = params.cursor().map_err(ServiceError::InvalidRequest)?;
+    let excluded = params.excluded().map_err(ServiceError::InvalidRequest)?;
+    let exclude_spec = excluded.contains(&"spec");
+    let exclude_status = excluded.contains(&"status");
+
+    if let Some(cursor) = &cursor {
+        if cursor.sort() != sort {
+            return Err(ServiceError::InvalidRequest(
+                "The continue token doesn't match the sort order".into(),
+            )
+            .into());
+        }
+        if params.offset.is_some() {
+            return Err(ServiceError::InvalidRequest(
+                "A continue token cannot be combined with an offset".into(),
+            )
+            .into());
+        }
+    }
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/json");
 
-    let apps = data
+    if params.count {
+        let count = data
+            .service
+            .count_devices(&user, &app_id, selector.clone(), fields.clone())
+            .await?;
+        response.insert_header((HEADER_TOTAL_COUNT, count.to_string()));
+    }
+
+    let options = ListOptions {
+        fields,
+        sort,
+        cursor,
+        // fetch one more, to find out if there is a next page, within the range of a BIGINT
+        limit: params
+            .limit
+            .map(|limit| limit.saturating_add(1).min(i64::MAX as usize)),
+        offset: params.offset.map(|offset| offset.min(i64::MAX as usize)),
+        effective: params.effective,
+    };
+
+    let devices = data
         .service
-        .list_devices(user, &app_id, selector, params.0.limit, params.0.offset)
-        .await?;
+        .list_devices(user, &app_id, selector, options)
+        .await?
+        .map_ok(move |mut device| {
+            if exclude_spec {
+                device.spec.clear();
+            }
+            if exclude_status {
+                device.status.clear();
+            }
+            device
+        });
 
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .streaming(ArrayStreamer::new(apps)))
+    match params.limit {
+        Some(limit) => {
+            let mut devices: Vec<_> = devices.try_collect().await?;
+            if devices.len() > limit {
+                devices.truncate(limit);
+                if let Some(last) = devices.last() {
+                    let cursor = Cursor::new(
+                        sort,
+                        last.metadata.name.clone(),
+                        last.metadata.creation_timestamp,
+                    );
+                    response.insert_header((HEADER_CONTINUE_TOKEN, encode_continue(&cursor)));
+                }
+            }
+            Ok(response.json(devices))
+        }
+        None => Ok(response.streaming(ArrayStreamer::new(devices))),
+    }
 }
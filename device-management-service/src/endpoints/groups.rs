@@ -0,0 +1,186 @@
+use crate::{
+    endpoints::{params::DeleteParams, parse_patch},
+    service::{management::ManagementService, PostgresManagementService},
+    WebData,
+};
+use actix_web::{http::header, web, web::Json, HttpRequest, HttpResponse};
+use drogue_cloud_registry_events::EventSender;
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_api::{auth::user::UserInformation, groups::DeviceGroup};
+use drogue_cloud_service_common::keycloak::KeycloakClient;
+use tracing::instrument;
+
+#[instrument(skip(data))]
+pub async fn create<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    group: Json<DeviceGroup>,
+    user: UserInformation,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+    log::debug!("Creating group: '{}' / '{:?}'", app_id, group);
+
+    if group.metadata.name.is_empty()
+        || group.metadata.name.len() > 255
+        || app_id != group.metadata.application
+    {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let location = req.url_for("group", &[&app_id, &group.metadata.name])?;
+
+    data.service.create_group(&user, group.0).await?;
+
+    let response = HttpResponse::Created()
+        .append_header((header::LOCATION, location.as_str()))
+        .finish();
+
+    Ok(response)
+}
+
+#[instrument(skip(data))]
+pub async fn update<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    user: UserInformation,
+    group: Json<DeviceGroup>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, group_id) = path.into_inner();
+
+    log::debug!(
+        "Updating group: '{}' / '{}' / '{:?}'",
+        app_id,
+        group_id,
+        group
+    );
+
+    if app_id.is_empty() || group_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    if app_id != group.metadata.application || group_id != group.metadata.name {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    data.service.update_group(&user, group.0).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(data, body))]
+pub async fn patch<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+    user: UserInformation,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, group_id) = path.into_inner();
+
+    log::debug!("Patching group: '{}' / '{}'", app_id, group_id);
+
+    let patch = match parse_patch(&req, &body)? {
+        Some(patch) => patch,
+        None => return Ok(HttpResponse::UnsupportedMediaType().finish()),
+    };
+
+    data.service
+        .patch_group(&user, &app_id, &group_id, patch)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(data))]
+pub async fn delete<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    user: UserInformation,
+    params: Option<web::Json<DeleteParams>>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, group_id) = path.into_inner();
+
+    log::debug!("Deleting group: '{}' / '{}'", app_id, group_id);
+
+    if app_id.is_empty() || group_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    data.service
+        .delete_group(
+            &user,
+            &app_id,
+            &group_id,
+            params.map(|p| p.0).unwrap_or_default(),
+        )
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(data))]
+pub async fn read<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    user: UserInformation,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, group_id) = path.into_inner();
+
+    log::debug!("Reading group: '{}' / '{}'", app_id, group_id);
+
+    if app_id.is_empty() || group_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let group = data.service.get_group(&user, &app_id, &group_id).await?;
+
+    let result = match group {
+        None => HttpResponse::NotFound().finish(),
+        Some(group) => HttpResponse::Ok().json(group),
+    };
+
+    Ok(result)
+}
+
+#[instrument(skip(data))]
+pub async fn list<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    user: UserInformation,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+
+    log::debug!("Listing groups: '{}'", app_id);
+
+    if app_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let groups = data.service.list_groups(&user, &app_id).await?;
+
+    Ok(HttpResponse::Ok().json(groups))
+}
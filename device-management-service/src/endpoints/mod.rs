@@ -1,4 +1,21 @@
 pub mod apps;
+pub mod bulk;
 pub mod devices;
+pub mod groups;
 pub mod params;
+pub mod profiles;
 pub mod streamer;
+pub mod watch;
+
+use crate::service::patch::Patch;
+use actix_web::{HttpMessage, HttpRequest};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_common::error::ServiceError;
+
+/// Parse the patch of a request, using its content type.
+///
+/// Returns `Ok(None)` if the content type is not a supported patch format.
+pub(crate) fn parse_patch(req: &HttpRequest, body: &[u8]) -> Result<Option<Patch>, ServiceError> {
+    Patch::parse(req.content_type(), body)
+        .map_err(|err| ServiceError::InvalidRequest(format!("Invalid patch: {err}")))
+}
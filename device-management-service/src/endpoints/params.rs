@@ -1,3 +1,4 @@
+use drogue_cloud_database_common::models::{Cursor, SortBy};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -31,4 +32,178 @@ pub struct ListParams {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub offset: Option<usize>,
+    /// Watch for changes, instead of returning the current state.
+    #[serde(default)]
+    pub watch: bool,
+    /// The revision to resume a watch from.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceReadParams {
+    /// Return the effective spec of the device, instead of the stored one.
+    #[serde(default)]
+    pub effective: bool,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceListParams {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub labels: String,
+    /// Select devices based on their fields, rather than their labels.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub field_selector: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// The continue token, returned by a previous, limited, request.
+    #[serde(default, rename = "continue")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continue_token: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortBy>,
+    /// Return the total number of matching items in a header.
+    #[serde(default)]
+    pub count: bool,
+    /// A comma separated list of top level fields to strip from the result.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    /// Return the effective spec of the devices, instead of the stored one.
+    #[serde(default)]
+    pub effective: bool,
+    /// Watch for changes, instead of returning the current state.
+    #[serde(default)]
+    pub watch: bool,
+    /// The revision to resume a watch from.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+impl DeviceListParams {
+    /// The fields which may be excluded from a list result.
+    const EXCLUDABLE: &'static [&'static str] = &["spec", "status"];
+
+    /// Parse the continue token, if present.
+    pub fn cursor(&self) -> Result<Option<Cursor>, String> {
+        self.continue_token
+            .as_deref()
+            .map(decode_continue)
+            .transpose()
+    }
+
+    /// Parse the list of excluded fields.
+    pub fn excluded(&self) -> Result<Vec<&str>, String> {
+        self.exclude
+            .iter()
+            .flat_map(|exclude| exclude.split(','))
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                if Self::EXCLUDABLE.contains(&field) {
+                    Ok(field)
+                } else {
+                    Err(format!("Field '{field}' cannot be excluded"))
+                }
+            })
+            .collect()
+    }
+}
+
+/// The format of a bulk import, or export, of devices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkFormat {
+    /// One JSON encoded device per line.
+    Ndjson,
+    /// One device per line, using a subset of the device information.
+    Csv,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportParams {
+    /// The format, detected from the content type if absent.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<BulkFormat>,
+    /// Only validate the devices, without storing them.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Update existing devices, instead of reporting them as failed.
+    #[serde(default)]
+    pub upsert: bool,
+    /// The number of devices stored in a single transaction.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+const fn default_batch_size() -> usize {
+    100
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportParams {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub labels: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<BulkFormat>,
+}
+
+/// Encode a cursor into an opaque continue token.
+pub fn encode_continue(cursor: &Cursor) -> String {
+    // serializing a cursor cannot fail
+    let json = serde_json::to_vec(cursor).unwrap_or_default();
+    base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+}
+
+/// Decode a continue token into a cursor.
+pub fn decode_continue(token: &str) -> Result<Cursor, String> {
+    base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| "Invalid continue token".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_continue_token() {
+        let cursor = Cursor::Name("device-1".into());
+        let token = encode_continue(&cursor);
+        assert_eq!(decode_continue(&token), Ok(cursor));
+
+        assert!(decode_continue("foo").is_err());
+    }
+
+    #[test]
+    fn test_excluded() {
+        let params = DeviceListParams {
+            exclude: Some("spec, status".into()),
+            ..Default::default()
+        };
+        assert_eq!(params.excluded(), Ok(vec!["spec", "status"]));
+
+        let params = DeviceListParams {
+            exclude: Some("metadata".into()),
+            ..Default::default()
+        };
+        assert!(params.excluded().is_err());
+    }
 }
@@ -0,0 +1,189 @@
+use crate::{
+    endpoints::{params::DeleteParams, parse_patch},
+    service::{management::ManagementService, PostgresManagementService},
+    WebData,
+};
+use actix_web::{http::header, web, web::Json, HttpRequest, HttpResponse};
+use drogue_cloud_registry_events::EventSender;
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_api::{auth::user::UserInformation, profiles::DeviceProfile};
+use drogue_cloud_service_common::keycloak::KeycloakClient;
+use tracing::instrument;
+
+#[instrument(skip(data))]
+pub async fn create<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    profile: Json<DeviceProfile>,
+    user: UserInformation,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+    log::debug!("Creating profile: '{}' / '{:?}'", app_id, profile);
+
+    if profile.metadata.name.is_empty()
+        || profile.metadata.name.len() > 255
+        || app_id != profile.metadata.application
+    {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let location = req.url_for("profile", &[&app_id, &profile.metadata.name])?;
+
+    data.service.create_profile(&user, profile.0).await?;
+
+    let response = HttpResponse::Created()
+        .append_header((header::LOCATION, location.as_str()))
+        .finish();
+
+    Ok(response)
+}
+
+#[instrument(skip(data))]
+pub async fn update<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    user: UserInformation,
+    profile: Json<DeviceProfile>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, profile_id) = path.into_inner();
+
+    log::debug!(
+        "Updating profile: '{}' / '{}' / '{:?}'",
+        app_id,
+        profile_id,
+        profile
+    );
+
+    if app_id.is_empty() || profile_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    if app_id != profile.metadata.application || profile_id != profile.metadata.name {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    data.service.update_profile(&user, profile.0).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(data, body))]
+pub async fn patch<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    body: web::Bytes,
+    user: UserInformation,
+    req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, profile_id) = path.into_inner();
+
+    log::debug!("Patching profile: '{}' / '{}'", app_id, profile_id);
+
+    let patch = match parse_patch(&req, &body)? {
+        Some(patch) => patch,
+        None => return Ok(HttpResponse::UnsupportedMediaType().finish()),
+    };
+
+    data.service
+        .patch_profile(&user, &app_id, &profile_id, patch)
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(data))]
+pub async fn delete<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    user: UserInformation,
+    params: Option<web::Json<DeleteParams>>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, profile_id) = path.into_inner();
+
+    log::debug!("Deleting profile: '{}' / '{}'", app_id, profile_id);
+
+    if app_id.is_empty() || profile_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    data.service
+        .delete_profile(
+            &user,
+            &app_id,
+            &profile_id,
+            params.map(|p| p.0).unwrap_or_default(),
+        )
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(data))]
+pub async fn read<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<(String, String)>,
+    user: UserInformation,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let (app_id, profile_id) = path.into_inner();
+
+    log::debug!("Reading profile: '{}' / '{}'", app_id, profile_id);
+
+    if app_id.is_empty() || profile_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let profile = data
+        .service
+        .get_profile(&user, &app_id, &profile_id)
+        .await?;
+
+    let result = match profile {
+        None => HttpResponse::NotFound().finish(),
+        Some(profile) => HttpResponse::Ok().json(profile),
+    };
+
+    Ok(result)
+}
+
+#[instrument(skip(data))]
+pub async fn list<S, K>(
+    data: web::Data<WebData<PostgresManagementService<S, K>>>,
+    path: web::Path<String>,
+    user: UserInformation,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    let app_id = path.into_inner();
+
+    log::debug!("Listing profiles: '{}'", app_id);
+
+    if app_id.is_empty() {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let profiles = data.service.list_profiles(&user, &app_id).await?;
+
+    Ok(HttpResponse::Ok().json(profiles))
+}
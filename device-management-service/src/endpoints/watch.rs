@@ -0,0 +1,59 @@
+use crate::{
+    service::{
+        management::ManagementService,
+        watch::{self, WatchTarget, Watcher},
+        PostgresManagementService,
+    },
+    WebData,
+};
+use actix_web::{web, HttpResponse};
+use bytes::Bytes;
+use drogue_cloud_registry_events::EventSender;
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_api::{auth::user::UserInformation, labels::LabelSelector};
+use drogue_cloud_service_common::error::ServiceError;
+use drogue_cloud_service_common::keycloak::KeycloakClient;
+use futures::StreamExt;
+
+/// Stream changes to the client, one JSON encoded event per line.
+pub async fn watch<S, K>(
+    data: &WebData<PostgresManagementService<S, K>>,
+    watcher: Option<web::Data<Watcher>>,
+    user: UserInformation,
+    target: WatchTarget,
+    labels: LabelSelector,
+    revision: Option<&str>,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    S: EventSender + Clone + 'static,
+    K: KeycloakClient + Send + Sync + 'static,
+{
+    let watcher =
+        watcher.ok_or_else(|| ServiceError::InvalidRequest("Watching is not enabled".into()))?;
+
+    if let WatchTarget::Devices { application, .. } = &target {
+        // fail early, if we don't have access to the application
+        data.service
+            .get_app(&user, application)
+            .await?
+            .ok_or_else(|| ServiceError::NotFound("Application".into(), application.clone()))?;
+    }
+
+    let events = watch::watch(
+        data.service.clone(),
+        &watcher,
+        user,
+        target,
+        labels,
+        revision,
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(events.map(|event| {
+            serde_json::to_vec(&event).map(|mut json| {
+                json.push(b'\n');
+                Bytes::from(json)
+            })
+        })))
+}
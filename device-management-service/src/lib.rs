@@ -8,6 +8,7 @@ use actix_web::web;
 use anyhow::Context;
 use drogue_client::user;
 use drogue_cloud_admin_service::apps;
+use drogue_cloud_database_common::DatabaseService;
 use drogue_cloud_registry_events::sender::{KafkaEventSender, KafkaSenderConfig};
 use drogue_cloud_service_api::{
     health::BoxedHealthChecked, health::HealthChecked, webapp as actix_web,
@@ -22,7 +23,10 @@ use drogue_cloud_service_common::{
     keycloak::{client::KeycloakAdminClient, KeycloakAdminClientConfig, KeycloakClient},
 };
 use serde::Deserialize;
-use service::PostgresManagementServiceConfig;
+use service::{
+    watch::{WatchConfig, Watcher},
+    PostgresManagementServiceConfig,
+};
 
 #[derive(Debug)]
 pub struct WebData<S: ManagementService> {
@@ -46,6 +50,10 @@ pub struct Config {
 
     #[serde(default)]
     pub http: HttpConfig,
+
+    /// Enable watching for changes, by consuming the registry events.
+    #[serde(default)]
+    pub watch: Option<WatchConfig>,
 }
 
 #[macro_export]
@@ -82,6 +90,10 @@ macro_rules! crud {
                         use $module as m;
                         m::update::<$sender, $keycloak>
                     }))
+                    .route(web::patch().to({
+                        use $module as m;
+                        m::patch::<$sender, $keycloak>
+                    }))
                     .route(web::delete().to({
                         use $module as m;
                         m::delete::<$sender, $keycloak>
@@ -111,6 +123,34 @@ macro_rules! app {
                 device
             );
 
+            let scope = crud!(
+                $sender,
+                $keycloak,
+                scope,
+                "apps/{app}/",
+                endpoints::groups,
+                group
+            );
+
+            let scope = crud!(
+                $sender,
+                $keycloak,
+                scope,
+                "apps/{app}/",
+                endpoints::profiles,
+                profile
+            );
+
+            let scope = scope
+                .service(
+                    web::resource("apps/{app}/import")
+                        .route(web::post().to(endpoints::bulk::import::<$sender, $keycloak>)),
+                )
+                .service(
+                    web::resource("apps/{app}/export")
+                        .route(web::get().to(endpoints::bulk::export::<$sender, $keycloak>)),
+                );
+
             app.service(scope)
         };
 
@@ -156,8 +196,13 @@ macro_rules! app {
     }};
 }
 
+/// Create the configurator of the service.
+///
+/// Background tasks, like processing registry events for watches, get registered with the
+/// provided startup context.
 pub async fn configurator(
     config: Config,
+    startup: &mut dyn Startup,
 ) -> anyhow::Result<(
     impl Fn(&mut ServiceConfig) + Send + Sync + Clone,
     Vec<Box<dyn HealthChecked>>,
@@ -187,6 +232,12 @@ pub async fn configurator(
         service: service.clone(),
     });
 
+    let watcher = config.watch.map(|watch| {
+        let watcher = Watcher::new(watch.buffer_size, service.pool().clone());
+        startup.spawn(watcher.clone().run(watch.kafka));
+        web::Data::new(watcher)
+    });
+
     // main server
 
     let db_service = service.clone();
@@ -197,6 +248,9 @@ pub async fn configurator(
                 authenticator.clone(),
                 user_auth.clone().map(pat::Authenticator::new),
             ));
+            if let Some(watcher) = &watcher {
+                cfg.app_data(watcher.clone());
+            }
             app!(cfg, KafkaEventSender, KeycloakAdminClient, auth)
                 // for the management service
                 .app_data(data.clone())
@@ -212,7 +266,7 @@ pub async fn configurator(
 pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()> {
     log::info!("Running device management service!");
 
-    let (builder, checks) = configurator(config.clone()).await?;
+    let (builder, checks) = configurator(config.clone(), startup).await?;
     HttpBuilder::new(config.http, Some(startup.runtime_config()), builder).start(startup)?;
 
     // run
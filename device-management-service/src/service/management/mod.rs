@@ -1,7 +1,10 @@
 use super::utils;
 use crate::{
     endpoints::params::DeleteParams,
-    service::{error::PostgresManagementServiceError, PostgresManagementService},
+    service::{
+        effective_device, error::PostgresManagementServiceError, patch::Patch,
+        PostgresManagementService,
+    },
 };
 use async_trait::async_trait;
 use chrono::Utc;
@@ -14,18 +17,58 @@ use drogue_cloud_database_common::{
         app::{ApplicationAccessor, PostgresApplicationAccessor},
         device::{DeviceAccessor, PostgresDeviceAccessor},
         diff::diff_paths,
-        Advance, Lock,
+        group::{GroupAccessor, PostgresGroupAccessor},
+        profile::{PostgresProfileAccessor, ProfileAccessor},
+        Advance, Cursor, Lock, SortBy,
     },
 };
 use drogue_cloud_registry_events::{Event, EventSender, SendEvent};
 use drogue_cloud_service_api::{
-    auth::user::UserInformation, labels::LabelSelector, webapp::ResponseError,
+    auth::user::UserInformation,
+    fields::FieldSelector,
+    groups::{self, DeviceGroup},
+    labels::LabelSelector,
+    profiles::{self, DeviceProfile},
+    webapp::ResponseError,
 };
 use drogue_cloud_service_common::keycloak::KeycloakClient;
 use futures::{future, Stream, TryStreamExt};
+use std::collections::HashMap;
 use tokio_postgres::error::SqlState;
 use uuid::Uuid;
 
+/// Options for listing devices.
+#[derive(Clone, Debug, Default)]
+pub struct ListOptions {
+    /// Select devices based on their fields.
+    pub fields: FieldSelector,
+    pub sort: SortBy,
+    /// Continue after the cursor, which must match the sort order.
+    pub cursor: Option<Cursor>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// Return the effective spec of the devices, including what they inherit from their profile
+    /// and groups.
+    pub effective: bool,
+}
+
+/// Options for importing devices.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    /// Only validate the devices, roll back all changes.
+    pub dry_run: bool,
+    /// Update existing devices, instead of failing.
+    pub upsert: bool,
+}
+
+/// The outcome of importing a single device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Created,
+    Updated,
+    Unchanged,
+}
+
 #[async_trait]
 pub trait ManagementService: Clone {
     type Error: ResponseError;
@@ -59,6 +102,14 @@ pub trait ManagementService: Clone {
         data: registry::v1::Application,
     ) -> Result<(), Self::Error>;
 
+    /// Apply a patch to the current state of an application, and store the result.
+    async fn patch_app(
+        &self,
+        identity: &UserInformation,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error>;
+
     async fn delete_app(
         &self,
         identity: &UserInformation,
@@ -72,11 +123,13 @@ pub trait ManagementService: Clone {
         device: registry::v1::Device,
     ) -> Result<(), Self::Error>;
 
+    /// Get a device, either as stored, or with its effective spec.
     async fn get_device(
         &self,
         identity: &UserInformation,
         app: &str,
         name: &str,
+        effective: bool,
     ) -> Result<Option<registry::v1::Device>, Self::Error>;
 
     async fn list_devices(
@@ -84,19 +137,50 @@ pub trait ManagementService: Clone {
         identity: UserInformation,
         app: &str,
         labels: LabelSelector,
-        limit: Option<usize>,
-        offset: Option<usize>,
+        options: ListOptions,
     ) -> Result<
         Pin<Box<dyn Stream<Item = Result<registry::v1::Device, Self::Error>> + Send>>,
         Self::Error,
     >;
 
+    /// Count the devices of an application, matching the label and field selectors.
+    async fn count_devices(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        labels: LabelSelector,
+        fields: FieldSelector,
+    ) -> Result<u64, Self::Error>;
+
     async fn update_device(
         &self,
         identity: &UserInformation,
         device: registry::v1::Device,
     ) -> Result<(), Self::Error>;
 
+    /// Apply a patch to the current state of a device, and store the result.
+    ///
+    /// The patch applies to the device as stored, which is what reading the device returns by
+    /// default, not to its effective spec.
+    async fn patch_device(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error>;
+
+    /// Create, or update, a batch of devices of an application in a single transaction.
+    ///
+    /// A failing device doesn't fail the batch, the result contains the outcome of each device.
+    async fn import_devices(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        devices: Vec<registry::v1::Device>,
+        options: ImportOptions,
+    ) -> Result<Vec<Result<ImportOutcome, Self::Error>>, Self::Error>;
+
     async fn delete_device(
         &self,
         identity: &UserInformation,
@@ -104,6 +188,101 @@ pub trait ManagementService: Clone {
         name: &str,
         params: DeleteParams,
     ) -> Result<(), Self::Error>;
+
+    async fn create_group(
+        &self,
+        identity: &UserInformation,
+        group: DeviceGroup,
+    ) -> Result<(), Self::Error>;
+
+    async fn get_group(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+    ) -> Result<Option<DeviceGroup>, Self::Error>;
+
+    /// List all groups of an application, sorted by name.
+    async fn list_groups(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+    ) -> Result<Vec<DeviceGroup>, Self::Error>;
+
+    async fn update_group(
+        &self,
+        identity: &UserInformation,
+        group: DeviceGroup,
+    ) -> Result<(), Self::Error>;
+
+    /// Apply a patch to the current state of a group, and store the result.
+    async fn patch_group(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error>;
+
+    /// Delete a group.
+    ///
+    /// Fails with a conflict if the group still has nested groups, or member devices.
+    async fn delete_group(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+        params: DeleteParams,
+    ) -> Result<(), Self::Error>;
+
+    async fn create_profile(
+        &self,
+        identity: &UserInformation,
+        profile: DeviceProfile,
+    ) -> Result<(), Self::Error>;
+
+    async fn get_profile(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+    ) -> Result<Option<DeviceProfile>, Self::Error>;
+
+    /// List all profiles of an application, sorted by name.
+    async fn list_profiles(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+    ) -> Result<Vec<DeviceProfile>, Self::Error>;
+
+    /// Update a profile.
+    ///
+    /// Changing the spec of a profile creates change events for all devices referencing it.
+    async fn update_profile(
+        &self,
+        identity: &UserInformation,
+        profile: DeviceProfile,
+    ) -> Result<(), Self::Error>;
+
+    /// Apply a patch to the current state of a profile, and store the result.
+    async fn patch_profile(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error>;
+
+    /// Delete a profile.
+    ///
+    /// Fails with a conflict if the profile is still referenced by devices.
+    async fn delete_profile(
+        &self,
+        identity: &UserInformation,
+        app: &str,
+        name: &str,
+        params: DeleteParams,
+    ) -> Result<(), Self::Error>;
 }
 
 #[async_trait]
@@ -141,11 +320,11 @@ where
                 _ => err,
             })?;
 
-        let events = Event::new_app(self.instance.clone(), name, uid, generation, vec![]);
+        let mut events = Event::new_app(self.instance.clone(), name, uid, generation, vec![]);
 
         // send events to outbox
 
-        Self::send_to_outbox(&t, &events).await?;
+        Self::send_to_outbox(&t, &mut events).await?;
 
         // commit
 
@@ -230,7 +409,62 @@ where
         let mut c = self.pool.get().await?;
         let t = c.build_transaction().start().await?;
 
-        let events = self
+        let mut events = self
+            .perform_update_app(
+                &t,
+                Some(identity),
+                app,
+                Some(aliases),
+                expected_uid,
+                expected_resource_version,
+            )
+            .await?;
+
+        Self::send_to_outbox(&t, &mut events).await?;
+
+        t.commit().await?;
+
+        // send events
+
+        events.send_with(&self.sender).await?;
+
+        Ok(())
+    }
+
+    async fn patch_app(
+        &self,
+        identity: &UserInformation,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        // get the current state, locking it until we are done
+
+        let current = PostgresApplicationAccessor::new(&t)
+            .get(name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        ensure(&current, identity, Permission::Write)?;
+
+        // apply the patch
+
+        let application = patch.apply(registry::v1::Application::from(current))?;
+
+        if application.metadata.name != name {
+            return Err(ServiceError::BadRequest("Changing the name is not allowed".into()).into());
+        }
+
+        // then continue like a normal update
+
+        let expected_uid = application.metadata.uid.clone();
+        let expected_resource_version = application.metadata.resource_version.clone();
+
+        let (app, aliases) = Self::app_to_entity(application)?;
+
+        let mut events = self
             .perform_update_app(
                 &t,
                 Some(identity),
@@ -241,7 +475,7 @@ where
             )
             .await?;
 
-        Self::send_to_outbox(&t, &events).await?;
+        Self::send_to_outbox(&t, &mut events).await?;
 
         t.commit().await?;
 
@@ -318,11 +552,11 @@ where
 
         // create events
 
-        let events = Event::new_app(self.instance.clone(), id, uid, revision, paths);
+        let mut events = Event::new_app(self.instance.clone(), id, uid, revision, paths);
 
         // send events to outbox
 
-        Self::send_to_outbox(&t, &events).await?;
+        Self::send_to_outbox(&t, &mut events).await?;
 
         // commit
 
@@ -342,11 +576,7 @@ where
         identity: &UserInformation,
         device: registry::v1::Device,
     ) -> Result<(), Self::Error> {
-        let (mut device, aliases) = Self::device_to_entity(device)?;
-
-        let generation = device.generation;
-
-        let application = device.application.clone();
+        let application = device.metadata.application.clone();
 
         let mut c = self.pool.get().await?;
         let t = c.build_transaction().start().await?;
@@ -367,6 +597,13 @@ where
             ServiceError::ReferenceNotFound
         })?;
 
+        let (mut device, aliases) = Self::device_to_entity(device)?;
+
+        let generation = device.generation;
+
+        Self::check_group(&t, &device).await?;
+        Self::check_profile(&t, &device).await?;
+
         let name = device.name.clone();
         // assign a new UID
         let uid = Uuid::new_v4();
@@ -389,7 +626,7 @@ where
 
         // create and persist events
 
-        let events = Event::new_device(
+        let mut events = Event::new_device(
             self.instance.clone(),
             application,
             name,
@@ -400,7 +637,7 @@ where
 
         // send events to outbox
 
-        Self::send_to_outbox(&t, &events).await?;
+        Self::send_to_outbox(&t, &mut events).await?;
 
         t.commit().await?;
 
@@ -418,6 +655,7 @@ where
         identity: &UserInformation,
         app_id: &str,
         device_id: &str,
+        effective: bool,
     ) -> Result<Option<registry::v1::Device>, Self::Error> {
         let c = self.pool.get().await?;
 
@@ -433,7 +671,11 @@ where
             .get(app_id, device_id, Lock::None)
             .await?;
 
-        Ok(device.map(Into::into))
+        Ok(match device {
+            Some(device) if effective => Some(effective_device(&c, device.into()).await?),
+            Some(device) => Some(device.into()),
+            None => None,
+        })
     }
 
     async fn list_devices(
@@ -441,8 +683,7 @@ where
         identity: UserInformation,
         app_id: &str,
         labels: LabelSelector,
-        limit: Option<usize>,
-        offset: Option<usize>,
+        options: ListOptions,
     ) -> Result<
         Pin<Box<dyn Stream<Item = Result<registry::v1::Device, Self::Error>> + Send>>,
         Self::Error,
@@ -457,35 +698,95 @@ where
         // ensure we have access, but don't confirm the device if we don't
         ensure_with(&app, &identity, Permission::Read, || ServiceError::NotFound)?;
 
+        // for the effective spec, resolve the profiles and groups of all of the application
+        let (app_profiles, app_groups): (HashMap<_, _>, HashMap<_, _>) = if options.effective {
+            (
+                PostgresProfileAccessor::new(&c)
+                    .list(app_id)
+                    .await?
+                    .into_iter()
+                    .map(|profile| (profile.name.clone(), DeviceProfile::from(profile)))
+                    .collect(),
+                PostgresGroupAccessor::new(&c)
+                    .list(app_id)
+                    .await?
+                    .into_iter()
+                    .map(|group| (group.name.clone(), DeviceGroup::from(group)))
+                    .collect(),
+            )
+        } else {
+            Default::default()
+        };
+        let effective = options.effective;
+
         Ok(Box::pin(
             PostgresDeviceAccessor::new(&c)
-                .list(app_id, None, labels, limit, offset, Lock::None)
+                .list_page(
+                    app_id,
+                    labels,
+                    options.fields,
+                    options.sort,
+                    options.cursor,
+                    options.limit,
+                    options.offset,
+                )
                 .await?
-                .map_ok(|device| device.into())
+                .map_ok(move |device| {
+                    let mut device: registry::v1::Device = device.into();
+                    if !effective {
+                        return device;
+                    }
+                    if let Ok(Some(profile)) = profiles::device_profile(&device.spec) {
+                        if let Some(profile) = app_profiles.get(profile) {
+                            profiles::apply_profile(&mut device.spec, &profile.spec);
+                        }
+                    }
+                    if let Ok(Some(group)) = groups::device_group(&device.spec) {
+                        let groups = groups::ancestors(&app_groups, group);
+                        groups::apply_groups(&mut device.spec, &groups);
+                    }
+                    device
+                })
                 .map_err(PostgresManagementServiceError::Service)
                 .into_stream(),
         ))
     }
 
-    async fn update_device(
+    async fn count_devices(
         &self,
         identity: &UserInformation,
-        device: registry::v1::Device,
-    ) -> Result<(), Self::Error> {
-        let expected_resource_version = device.metadata.resource_version.clone();
-        let expected_uid = device.metadata.uid.clone();
+        app_id: &str,
+        labels: LabelSelector,
+        fields: FieldSelector,
+    ) -> Result<u64, Self::Error> {
+        let c = self.pool.get().await?;
 
-        let (mut device, aliases) = Self::device_to_entity(device)?;
+        let app = PostgresApplicationAccessor::new(&c)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
 
-        let application = device.application.clone();
-        let name = device.name.clone();
+        ensure_with(&app, identity, Permission::Read, || ServiceError::NotFound)?;
+
+        Ok(PostgresDeviceAccessor::new(&c)
+            .count(app_id, labels, fields)
+            .await?)
+    }
 
+    async fn update_device(
+        &self,
+        identity: &UserInformation,
+        device: registry::v1::Device,
+    ) -> Result<(), Self::Error> {
         let mut c = self.pool.get().await?;
         let t = c.build_transaction().start().await?;
 
         let accessor = PostgresApplicationAccessor::new(&t);
 
-        let current = match accessor.get(&application, Lock::None).await? {
+        let current = match accessor
+            .get(&device.metadata.application, Lock::None)
+            .await?
+        {
             Some(device) => Ok(device),
             None => Err(ServiceError::NotFound),
         }?;
@@ -495,76 +796,139 @@ where
             ServiceError::NotFound
         })?;
 
-        let accessor = PostgresDeviceAccessor::new(&t);
+        let mut events = self.perform_update_device(&t, device).await?;
 
-        // get current state for diffing
-        let current = match accessor.get(&application, &name, Lock::ForUpdate).await? {
-            Some(device) => Ok(device),
-            None => Err(ServiceError::NotFound),
-        }?;
+        Self::send_to_outbox(&t, &mut events).await?;
 
-        // pre-check versions
-        utils::check_versions(expected_uid, expected_resource_version, &current)?;
+        // commit
 
-        // we simply copy over the deletion timestamp
-        device.deletion_timestamp = current.deletion_timestamp;
+        t.commit().await?;
 
-        if device.deletion_timestamp.is_some() && device.finalizers.is_empty() {
-            // delete, but don't send any event
-            accessor.delete(&application, &name).await?;
+        // send change event
 
-            // check with the application
-            self.check_clean_app(&t, &application).await?;
+        events.send_with(&self.sender).await?;
 
-            t.commit().await?;
-        } else {
-            // check which paths changed
-            let paths = diff_paths(&current, &device);
-            if paths.is_empty() {
-                // there was no change
-                return Ok(());
-            }
+        // done
 
-            let revision = device.advance_from(&paths, &current)?;
-            let uid = current.uid;
+        Ok(())
+    }
 
-            accessor
-                .update(device, Some(aliases))
-                .await
-                .map_err(|err| match err.sql_state() {
-                    Some(state) if state == &SqlState::UNIQUE_VIOLATION => {
-                        ServiceError::Conflict("Unique key violation".to_string())
-                    }
-                    _ => err,
-                })?;
+    async fn patch_device(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        device_id: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the device if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        // get the current state, locking it until we are done
+
+        let current = PostgresDeviceAccessor::new(&t)
+            .get(app_id, device_id, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // apply the patch, to the device as stored
+
+        let device = patch.apply(registry::v1::Device::from(current))?;
+
+        if device.metadata.application != app_id || device.metadata.name != device_id {
+            return Err(ServiceError::BadRequest(
+                "Changing the application or name is not allowed".into(),
+            )
+            .into());
+        }
+
+        // then continue like a normal update
+
+        let mut events = self.perform_update_device(&t, device).await?;
+
+        Self::send_to_outbox(&t, &mut events).await?;
+
+        t.commit().await?;
+
+        events.send_with(&self.sender).await?;
+
+        Ok(())
+    }
+
+    async fn import_devices(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        devices: Vec<registry::v1::Device>,
+        options: ImportOptions,
+    ) -> Result<Vec<Result<ImportOutcome, Self::Error>>, Self::Error> {
+        let mut c = self.pool.get().await?;
+        let mut t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(app_id, Lock::ForShare)
+            .await?;
+
+        // if there is no entry, or it is marked for deletion, we don't allow adding new devices
+
+        let app = match app {
+            Some(app) if app.deletion_timestamp.is_none() => app,
+            _ => return Err(ServiceError::ReferenceNotFound.into()),
+        };
+
+        ensure_with(&app, identity, Permission::Write, || {
+            ServiceError::ReferenceNotFound
+        })?;
 
-            // create events
+        let mut results = Vec::with_capacity(devices.len());
+        let mut events = Vec::new();
 
-            let events = Event::new_device(
-                self.instance.clone(),
-                application,
-                name,
-                uid,
-                revision,
-                paths,
-            );
+        for mut device in devices {
+            if device.metadata.application.is_empty() {
+                device.metadata.application = app_id.to_string();
+            } else if device.metadata.application != app_id {
+                results.push(Err(ServiceError::BadRequest(
+                    "Device belongs to a different application".into(),
+                )
+                .into()));
+                continue;
+            }
 
-            // send events to outbox
+            // use a savepoint, so that a failing device doesn't abort the whole transaction
 
-            Self::send_to_outbox(&t, &events).await?;
+            let s = t.savepoint("import_device").await?;
 
-            // commit
+            match self.import_device(&s, device, options.upsert).await {
+                Ok((outcome, device_events)) => {
+                    s.commit().await?;
+                    events.extend(device_events);
+                    results.push(Ok(outcome));
+                }
+                Err(err) => {
+                    s.rollback().await?;
+                    results.push(Err(err));
+                }
+            }
+        }
 
+        if options.dry_run {
+            t.rollback().await?;
+        } else {
             t.commit().await?;
 
-            // send change event
+            // send change events
 
             events.send_with(&self.sender).await?;
         }
 
-        // done
-
-        Ok(())
+        Ok(results)
     }
 
     async fn delete_device(
@@ -632,7 +996,7 @@ where
 
         // create events
 
-        let events = Event::new_device(
+        let mut events = Event::new_device(
             self.instance.clone(),
             application,
             device,
@@ -643,7 +1007,7 @@ where
 
         // send events to outbox
 
-        Self::send_to_outbox(&t, &events).await?;
+        Self::send_to_outbox(&t, &mut events).await?;
 
         // commit
 
@@ -657,4 +1021,415 @@ where
 
         Ok(())
     }
+
+    async fn create_group(
+        &self,
+        identity: &UserInformation,
+        group: DeviceGroup,
+    ) -> Result<(), Self::Error> {
+        let mut group = Self::group_to_entity(group)?;
+
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(&group.application, Lock::ForShare)
+            .await?;
+
+        // if there is no entry, or it is marked for deletion, we don't allow adding a new group
+
+        let app = match app {
+            Some(app) if app.deletion_timestamp.is_none() => app,
+            _ => return Err(ServiceError::ReferenceNotFound.into()),
+        };
+
+        ensure_with(&app, identity, Permission::Write, || {
+            ServiceError::ReferenceNotFound
+        })?;
+
+        Self::check_group_parent(&t, &group).await?;
+
+        // a new group has no members yet, so there are no devices to notify
+
+        // assign a new UID
+        group.uid = Uuid::new_v4();
+
+        PostgresGroupAccessor::new(&t)
+            .create(group)
+            .await
+            .map_err(Self::map_violation)?;
+
+        t.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_group(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        name: &str,
+    ) -> Result<Option<DeviceGroup>, Self::Error> {
+        let c = self.pool.get().await?;
+
+        let app = PostgresApplicationAccessor::new(&c)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the group if we don't
+        ensure_with(&app, identity, Permission::Read, || ServiceError::NotFound)?;
+
+        let group = PostgresGroupAccessor::new(&c)
+            .get(app_id, name, Lock::None)
+            .await?;
+
+        Ok(group.map(Into::into))
+    }
+
+    async fn list_groups(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+    ) -> Result<Vec<DeviceGroup>, Self::Error> {
+        let c = self.pool.get().await?;
+
+        let app = PostgresApplicationAccessor::new(&c)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        ensure_with(&app, identity, Permission::Read, || ServiceError::NotFound)?;
+
+        Ok(PostgresGroupAccessor::new(&c)
+            .list(app_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn update_group(
+        &self,
+        identity: &UserInformation,
+        group: DeviceGroup,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(&group.metadata.application, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the group if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        let mut events = self.perform_update_group(&t, group).await?;
+
+        Self::send_to_outbox(&t, &mut events).await?;
+
+        t.commit().await?;
+
+        // send change events
+
+        events.send_with(&self.sender).await?;
+
+        Ok(())
+    }
+
+    async fn patch_group(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the group if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        // get the current state, locking it until we are done
+
+        let current = PostgresGroupAccessor::new(&t)
+            .get(app_id, name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // apply the patch
+
+        let group = patch.apply(DeviceGroup::from(current))?;
+
+        if group.metadata.application != app_id || group.metadata.name != name {
+            return Err(ServiceError::BadRequest(
+                "Changing the application or name is not allowed".into(),
+            )
+            .into());
+        }
+
+        // then continue like a normal update
+
+        let mut events = self.perform_update_group(&t, group).await?;
+
+        Self::send_to_outbox(&t, &mut events).await?;
+
+        t.commit().await?;
+
+        // send change events
+
+        events.send_with(&self.sender).await?;
+
+        Ok(())
+    }
+
+    async fn delete_group(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        name: &str,
+        params: DeleteParams,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the group if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        let accessor = PostgresGroupAccessor::new(&t);
+
+        // lock the group, so that no devices or groups can be added to it
+        let current = accessor
+            .get(app_id, name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        utils::check_preconditions(&params.preconditions, &current)?;
+
+        if accessor.count_children(app_id, name).await? > 0 {
+            return Err(ServiceError::Conflict("Group still has nested groups".into()).into());
+        }
+        if accessor.count_devices(app_id, name).await? > 0 {
+            return Err(ServiceError::Conflict("Group still has devices".into()).into());
+        }
+
+        // without members, or nested groups, there are no devices to notify
+
+        accessor.delete(app_id, name).await?;
+
+        t.commit().await?;
+
+        Ok(())
+    }
+
+    async fn create_profile(
+        &self,
+        identity: &UserInformation,
+        profile: DeviceProfile,
+    ) -> Result<(), Self::Error> {
+        let mut profile = Self::profile_to_entity(profile)?;
+
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(&profile.application, Lock::ForShare)
+            .await?;
+
+        // if there is no entry, or it is marked for deletion, we don't allow adding a new profile
+
+        let app = match app {
+            Some(app) if app.deletion_timestamp.is_none() => app,
+            _ => return Err(ServiceError::ReferenceNotFound.into()),
+        };
+
+        ensure_with(&app, identity, Permission::Write, || {
+            ServiceError::ReferenceNotFound
+        })?;
+
+        // assign a new UID
+        profile.uid = Uuid::new_v4();
+
+        PostgresProfileAccessor::new(&t)
+            .create(profile)
+            .await
+            .map_err(Self::map_violation)?;
+
+        t.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_profile(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        name: &str,
+    ) -> Result<Option<DeviceProfile>, Self::Error> {
+        let c = self.pool.get().await?;
+
+        let app = PostgresApplicationAccessor::new(&c)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the profile if we don't
+        ensure_with(&app, identity, Permission::Read, || ServiceError::NotFound)?;
+
+        let profile = PostgresProfileAccessor::new(&c)
+            .get(app_id, name, Lock::None)
+            .await?;
+
+        Ok(profile.map(Into::into))
+    }
+
+    async fn list_profiles(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+    ) -> Result<Vec<DeviceProfile>, Self::Error> {
+        let c = self.pool.get().await?;
+
+        let app = PostgresApplicationAccessor::new(&c)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        ensure_with(&app, identity, Permission::Read, || ServiceError::NotFound)?;
+
+        Ok(PostgresProfileAccessor::new(&c)
+            .list(app_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn update_profile(
+        &self,
+        identity: &UserInformation,
+        profile: DeviceProfile,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(&profile.metadata.application, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the profile if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        let mut events = self.perform_update_profile(&t, profile).await?;
+
+        Self::send_to_outbox(&t, &mut events).await?;
+
+        t.commit().await?;
+
+        // send change events
+
+        events.send_with(&self.sender).await?;
+
+        Ok(())
+    }
+
+    async fn patch_profile(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        name: &str,
+        patch: Patch,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the profile if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        // get the current state, locking it until we are done
+
+        let current = PostgresProfileAccessor::new(&t)
+            .get(app_id, name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // apply the patch
+
+        let profile = patch.apply(DeviceProfile::from(current))?;
+
+        if profile.metadata.application != app_id || profile.metadata.name != name {
+            return Err(ServiceError::BadRequest(
+                "Changing the application or name is not allowed".into(),
+            )
+            .into());
+        }
+
+        // then continue like a normal update
+
+        let mut events = self.perform_update_profile(&t, profile).await?;
+
+        Self::send_to_outbox(&t, &mut events).await?;
+
+        t.commit().await?;
+
+        events.send_with(&self.sender).await?;
+
+        Ok(())
+    }
+
+    async fn delete_profile(
+        &self,
+        identity: &UserInformation,
+        app_id: &str,
+        name: &str,
+        params: DeleteParams,
+    ) -> Result<(), Self::Error> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let app = PostgresApplicationAccessor::new(&t)
+            .get(app_id, Lock::None)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // ensure we have access, but don't confirm the profile if we don't
+        ensure_with(&app, identity, Permission::Write, || ServiceError::NotFound)?;
+
+        let accessor = PostgresProfileAccessor::new(&t);
+
+        // lock the profile, so that no devices can start referencing it
+        let current = accessor
+            .get(app_id, name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        utils::check_preconditions(&params.preconditions, &current)?;
+
+        if accessor.count_devices(app_id, name).await? > 0 {
+            return Err(ServiceError::Conflict("Profile is still used by devices".into()).into());
+        }
+
+        accessor.delete(app_id, name).await?;
+
+        t.commit().await?;
+
+        Ok(())
+    }
 }
@@ -1,10 +1,15 @@
 pub mod admin;
 mod error;
 pub mod management;
+pub mod patch;
 mod utils;
+pub mod watch;
 mod x509;
 
-use crate::{service::error::PostgresManagementServiceError, utils::epoch};
+use crate::{
+    service::{error::PostgresManagementServiceError, management::ImportOutcome},
+    utils::epoch,
+};
 use deadpool_postgres::{Pool, Transaction};
 use drogue_client::{registry, user::v1::authz::Permission, Translator};
 use drogue_cloud_database_common::{
@@ -15,15 +20,19 @@ use drogue_cloud_database_common::{
         app::{ApplicationAccessor, PostgresApplicationAccessor},
         device::{DeviceAccessor, PostgresDeviceAccessor},
         diff::diff_paths,
-        outbox::PostgresOutboxAccessor,
+        group::{GroupAccessor, PostgresGroupAccessor},
+        outbox::{OutboxAccessor, PostgresOutboxAccessor},
+        profile::{PostgresProfileAccessor, ProfileAccessor},
         Advance, Lock, TypedAlias,
     },
     postgres, Client, DatabaseService,
 };
-use drogue_cloud_registry_events::{Event, EventSender, EventSenderError, SendEvent};
+use drogue_cloud_registry_events::{Event, EventSender};
 use drogue_cloud_service_api::{
     auth::user::UserInformation,
+    groups::{self, DeviceGroup, MAX_GROUP_DEPTH},
     health::{HealthCheckError, HealthChecked},
+    profiles::{self, DeviceProfile},
 };
 use drogue_cloud_service_common::keycloak::KeycloakClient;
 use serde::Deserialize;
@@ -209,6 +218,152 @@ where
         Ok((device, aliases))
     }
 
+    fn group_to_entity(
+        group: DeviceGroup,
+    ) -> Result<models::group::Group, PostgresManagementServiceError<S::Error>> {
+        if group
+            .spec
+            .defaults
+            .contains_key(groups::DEVICE_GROUP_SECTION)
+        {
+            return Err(ServiceError::BadRequest(format!(
+                "Defaults must not contain the '{}' section",
+                groups::DEVICE_GROUP_SECTION
+            ))
+            .into());
+        }
+
+        Ok(models::group::Group {
+            application: group.metadata.application,
+            name: group.metadata.name,
+            uid: Uuid::nil(), // will be set internally
+            parent: group.spec.parent,
+            labels: group.metadata.labels,
+            annotations: group.metadata.annotations,
+            creation_timestamp: epoch(),   // will be set internally
+            generation: 0,                 // will be set internally
+            resource_version: Uuid::nil(), // will be set internally
+            data: json!({
+                "spec": {
+                    "gateways": group.spec.gateways,
+                    "defaults": group.spec.defaults,
+                }
+            }),
+        })
+    }
+
+    /// Check that the group a device references exists.
+    ///
+    /// The group is locked, so that it can't be deleted before the transaction is complete.
+    async fn check_group<C: Client>(
+        t: &C,
+        device: &models::device::Device,
+    ) -> Result<(), PostgresManagementServiceError<S::Error>> {
+        let spec = match device.data.get("spec") {
+            Some(serde_json::Value::Object(spec)) => spec,
+            _ => return Ok(()),
+        };
+
+        let group = groups::device_group(spec).map_err(ServiceError::BadRequest)?;
+
+        if let Some(group) = group {
+            PostgresGroupAccessor::new(t)
+                .get(&device.application, group, Lock::ForShare)
+                .await?
+                .ok_or(ServiceError::ReferenceNotFound)?;
+        }
+
+        Ok(())
+    }
+
+    fn profile_to_entity(
+        profile: DeviceProfile,
+    ) -> Result<models::profile::Profile, PostgresManagementServiceError<S::Error>> {
+        if let Some(section) = profiles::RESERVED_SECTIONS
+            .iter()
+            .find(|section| profile.spec.contains_key(**section))
+        {
+            return Err(ServiceError::BadRequest(format!(
+                "Profiles must not contain the '{section}' section"
+            ))
+            .into());
+        }
+
+        Ok(models::profile::Profile {
+            application: profile.metadata.application,
+            name: profile.metadata.name,
+            uid: Uuid::nil(), // will be set internally
+            labels: profile.metadata.labels,
+            annotations: profile.metadata.annotations,
+            creation_timestamp: epoch(),   // will be set internally
+            generation: 0,                 // will be set internally
+            resource_version: Uuid::nil(), // will be set internally
+            data: json!({
+                "spec": profile.spec,
+            }),
+        })
+    }
+
+    /// Check that the profile a device references exists.
+    ///
+    /// The profile is locked, so that it can't be deleted before the transaction is complete.
+    async fn check_profile<C: Client>(
+        t: &C,
+        device: &models::device::Device,
+    ) -> Result<(), PostgresManagementServiceError<S::Error>> {
+        let spec = match device.data.get("spec") {
+            Some(serde_json::Value::Object(spec)) => spec,
+            _ => return Ok(()),
+        };
+
+        let profile = profiles::device_profile(spec).map_err(ServiceError::BadRequest)?;
+
+        if let Some(profile) = profile {
+            PostgresProfileAccessor::new(t)
+                .get(&device.application, profile, Lock::ForShare)
+                .await?
+                .ok_or(ServiceError::ReferenceNotFound)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that the parent of a group exists, and doesn't introduce a cycle.
+    async fn check_group_parent<C: Client>(
+        t: &C,
+        group: &models::group::Group,
+    ) -> Result<(), PostgresManagementServiceError<S::Error>> {
+        let parent = match &group.parent {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+
+        let ancestors = PostgresGroupAccessor::new(t)
+            .ancestors(&group.application, parent)
+            .await?;
+
+        if ancestors.is_empty() {
+            return Err(ServiceError::ReferenceNotFound.into());
+        }
+
+        if ancestors.iter().any(|g| g.name == group.name) {
+            return Err(ServiceError::BadRequest(
+                "The parent must not be the group itself, or one of its nested groups".into(),
+            )
+            .into());
+        }
+
+        // the group itself adds another level
+        if ancestors.len() >= MAX_GROUP_DEPTH {
+            return Err(ServiceError::BadRequest(format!(
+                "Groups must not be nested deeper than {MAX_GROUP_DEPTH} levels"
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
     /// Perform the operation of updating an application
     async fn perform_update_app<S1, S2>(
         &self,
@@ -284,6 +439,228 @@ where
         }
     }
 
+    /// Perform the operation of updating a device.
+    ///
+    /// The caller must ensure access to the application of the device. The returned events
+    /// still need to be written to the outbox.
+    async fn perform_update_device(
+        &self,
+        t: &Transaction<'_>,
+        device: registry::v1::Device,
+    ) -> Result<Vec<Event>, PostgresManagementServiceError<S::Error>> {
+        let expected_resource_version = device.metadata.resource_version.clone();
+        let expected_uid = device.metadata.uid.clone();
+
+        let (mut device, aliases) = Self::device_to_entity(device)?;
+
+        Self::check_group(t, &device).await?;
+        Self::check_profile(t, &device).await?;
+
+        let application = device.application.clone();
+        let name = device.name.clone();
+
+        let accessor = PostgresDeviceAccessor::new(t);
+
+        // get current state for diffing
+        let current = match accessor.get(&application, &name, Lock::ForUpdate).await? {
+            Some(device) => Ok(device),
+            None => Err(ServiceError::NotFound),
+        }?;
+
+        // pre-check versions
+        utils::check_versions(expected_uid, expected_resource_version, &current)?;
+
+        // we simply copy over the deletion timestamp
+        device.deletion_timestamp = current.deletion_timestamp;
+
+        if device.deletion_timestamp.is_some() && device.finalizers.is_empty() {
+            // delete, but don't send any event
+            accessor.delete(&application, &name).await?;
+
+            // check with the application
+            self.check_clean_app(t, &application).await?;
+
+            Ok(vec![])
+        } else {
+            // check which paths changed
+            let paths = diff_paths(&current, &device);
+            if paths.is_empty() {
+                // there was no change
+                return Ok(vec![]);
+            }
+
+            let revision = device.advance_from(&paths, &current)?;
+            let uid = current.uid;
+
+            accessor
+                .update(device, Some(aliases))
+                .await
+                .map_err(|err| match err.sql_state() {
+                    Some(state) if state == &SqlState::UNIQUE_VIOLATION => {
+                        ServiceError::Conflict("Unique key violation".to_string())
+                    }
+                    _ => err,
+                })?;
+
+            // create events
+
+            Ok(Event::new_device(
+                self.instance.clone(),
+                application,
+                name,
+                uid,
+                revision,
+                paths,
+            ))
+        }
+    }
+
+    /// Perform the operation of updating a device group.
+    ///
+    /// The caller must ensure access to the application of the group. When the spec changes, all
+    /// member devices, including those of nested groups, are advanced, so that their effective
+    /// spec gets reconciled. The returned events still need to be written to the outbox.
+    async fn perform_update_group(
+        &self,
+        t: &Transaction<'_>,
+        group: DeviceGroup,
+    ) -> Result<Vec<Event>, PostgresManagementServiceError<S::Error>> {
+        let expected_resource_version = group.metadata.resource_version.clone();
+        let expected_uid = group.metadata.uid.clone();
+
+        let mut group = Self::group_to_entity(group)?;
+
+        let accessor = PostgresGroupAccessor::new(t);
+
+        // get current state for diffing
+        let current = accessor
+            .get(&group.application, &group.name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // pre-check versions
+        utils::check_versions(expected_uid, expected_resource_version, &current)?;
+
+        let spec_changed = group.parent != current.parent || group.data != current.data;
+        if !spec_changed
+            && group.labels == current.labels
+            && group.annotations == current.annotations
+        {
+            // there was no change
+            return Ok(vec![]);
+        }
+
+        if spec_changed {
+            Self::check_group_parent(t, &group).await?;
+        }
+
+        let application = group.application.clone();
+        let name = group.name.clone();
+
+        group.uid = current.uid;
+        group.creation_timestamp = current.creation_timestamp;
+        group.generation = if spec_changed {
+            current.generation + 1
+        } else {
+            current.generation
+        };
+
+        accessor.update(group).await.map_err(Self::map_violation)?;
+
+        if !spec_changed {
+            return Ok(vec![]);
+        }
+
+        // advance all members, as their effective spec changed
+
+        Ok(accessor
+            .advance_devices(&application, &name)
+            .await?
+            .into_iter()
+            .flat_map(|device| {
+                Event::new_device(
+                    self.instance.clone(),
+                    &application,
+                    device.name,
+                    device.uid,
+                    device.revision,
+                    vec![".spec".into()],
+                )
+            })
+            .collect())
+    }
+
+    /// Perform the operation of updating a device profile.
+    ///
+    /// The caller must ensure access to the application of the profile. When the spec changes,
+    /// all devices referencing the profile are advanced, so that their effective spec gets
+    /// reconciled. The returned events still need to be written to the outbox.
+    async fn perform_update_profile(
+        &self,
+        t: &Transaction<'_>,
+        profile: DeviceProfile,
+    ) -> Result<Vec<Event>, PostgresManagementServiceError<S::Error>> {
+        let expected_resource_version = profile.metadata.resource_version.clone();
+        let expected_uid = profile.metadata.uid.clone();
+
+        let mut profile = Self::profile_to_entity(profile)?;
+
+        let accessor = PostgresProfileAccessor::new(t);
+
+        // get current state for diffing
+        let current = accessor
+            .get(&profile.application, &profile.name, Lock::ForUpdate)
+            .await?
+            .ok_or(ServiceError::NotFound)?;
+
+        // pre-check versions
+        utils::check_versions(expected_uid, expected_resource_version, &current)?;
+
+        let spec_changed = profile.data != current.data;
+        if !spec_changed
+            && profile.labels == current.labels
+            && profile.annotations == current.annotations
+        {
+            // there was no change
+            return Ok(vec![]);
+        }
+
+        profile.uid = current.uid;
+        profile.creation_timestamp = current.creation_timestamp;
+        profile.generation = if spec_changed {
+            current.generation + 1
+        } else {
+            current.generation
+        };
+
+        let application = profile.application.clone();
+        let name = profile.name.clone();
+
+        accessor.update(profile).await?;
+
+        if !spec_changed {
+            return Ok(vec![]);
+        }
+
+        // advance all devices, as their effective spec changed
+
+        Ok(accessor
+            .advance_devices(&application, &name)
+            .await?
+            .into_iter()
+            .flat_map(|device| {
+                Event::new_device(
+                    self.instance.clone(),
+                    &application,
+                    device.name,
+                    device.uid,
+                    device.revision,
+                    vec![".spec".into()],
+                )
+            })
+            .collect())
+    }
+
     /// Called when a device was deleted, so check if the application can be garbage collected.
     async fn check_clean_app(
         &self,
@@ -323,34 +700,198 @@ where
         Ok(())
     }
 
-    fn outbox_err<E>(err: EventSenderError<ServiceError>) -> PostgresManagementServiceError<E>
-    where
-        E: std::error::Error + std::fmt::Debug + 'static,
-    {
-        match err {
-            EventSenderError::Sender(err) => PostgresManagementServiceError::Service(err),
-            EventSenderError::CloudEvent(err) => {
-                PostgresManagementServiceError::EventSender(EventSenderError::CloudEvent(err))
+    /// Create, or update, a single device as part of an import.
+    ///
+    /// The events are already written to the outbox, but still need to be sent.
+    async fn import_device<C: Client>(
+        &self,
+        t: &C,
+        mut device: registry::v1::Device,
+        upsert: bool,
+    ) -> Result<(ImportOutcome, Vec<Event>), PostgresManagementServiceError<S::Error>> {
+        let accessor = PostgresDeviceAccessor::new(t);
+
+        let current = accessor
+            .get(
+                &device.metadata.application,
+                &device.metadata.name,
+                Lock::ForUpdate,
+            )
+            .await?;
+
+        if let Some(current) = &current {
+            Self::merge_import(current, &mut device);
+        }
+
+        let (mut device, aliases) = Self::device_to_entity(device)?;
+
+        Self::check_group(t, &device).await?;
+        Self::check_profile(t, &device).await?;
+
+        let application = device.application.clone();
+        let name = device.name.clone();
+
+        let (outcome, mut events) = match current {
+            None => {
+                let generation = device.generation;
+                let uid = Uuid::new_v4();
+                device.uid = uid;
+
+                accessor
+                    .create(device, aliases)
+                    .await
+                    .map_err(Self::map_violation)?;
+
+                let events = Event::new_device(
+                    self.instance.clone(),
+                    application,
+                    name,
+                    uid,
+                    generation,
+                    vec![],
+                );
+
+                (ImportOutcome::Created, events)
+            }
+            Some(_) if !upsert => {
+                return Err(ServiceError::Conflict("Device already exists".into()).into());
+            }
+            Some(current) if current.deletion_timestamp.is_some() => {
+                return Err(ServiceError::Conflict("Device is being deleted".into()).into());
+            }
+            Some(current) => {
+                let paths = diff_paths(&current, &device);
+                if paths.is_empty() {
+                    return Ok((ImportOutcome::Unchanged, vec![]));
+                }
+
+                let revision = device.advance_from(&paths, &current)?;
+                let uid = current.uid;
+
+                accessor
+                    .update(device, Some(aliases))
+                    .await
+                    .map_err(Self::map_violation)?;
+
+                let events = Event::new_device(
+                    self.instance.clone(),
+                    application,
+                    name,
+                    uid,
+                    revision,
+                    paths,
+                );
+
+                (ImportOutcome::Updated, events)
             }
-            EventSenderError::Event(err) => {
-                PostgresManagementServiceError::EventSender(EventSenderError::Event(err))
+        };
+
+        Self::send_to_outbox(t, &mut events).await?;
+
+        Ok((outcome, events))
+    }
+
+    /// Merge an imported device into the current one.
+    ///
+    /// The import only carries what its format can express. Labels, annotations and sections of
+    /// the current device, which are not part of the import, are kept. So are its finalizers,
+    /// which are owned by the controllers.
+    fn merge_import(current: &models::device::Device, device: &mut registry::v1::Device) {
+        for (k, v) in &current.labels {
+            device
+                .metadata
+                .labels
+                .entry(k.clone())
+                .or_insert_with(|| v.clone());
+        }
+        for (k, v) in &current.annotations {
+            device
+                .metadata
+                .annotations
+                .entry(k.clone())
+                .or_insert_with(|| v.clone());
+        }
+        device.metadata.finalizers = current.finalizers.clone();
+
+        for (section, target) in [("spec", &mut device.spec), ("status", &mut device.status)] {
+            if let Some(serde_json::Value::Object(sections)) = current.data.get(section) {
+                for (k, v) in sections {
+                    target.entry(k.clone()).or_insert_with(|| v.clone());
+                }
+            }
+        }
+    }
+
+    /// Map constraint violations to their service errors.
+    fn map_violation(err: ServiceError) -> ServiceError {
+        match err.sql_state() {
+            Some(state) if state == &SqlState::UNIQUE_VIOLATION => {
+                ServiceError::Conflict("Unique key violation".to_string())
+            }
+            Some(state) if state == &SqlState::FOREIGN_KEY_VIOLATION => {
+                ServiceError::ReferenceNotFound
             }
+            _ => err,
         }
     }
 
+    /// Write the events to the outbox, recording their position in the outbox.
     async fn send_to_outbox<'c, C: Client, E>(
         client: &C,
-        events: &[Event],
+        events: &mut [Event],
     ) -> Result<(), PostgresManagementServiceError<E>>
     where
         E: std::error::Error + std::fmt::Debug + 'static,
     {
         // send events to outbox
 
-        events
-            .to_vec()
-            .send_with(&PostgresOutboxAccessor::new(client))
-            .await
-            .map_err(Self::outbox_err)
+        let outbox = PostgresOutboxAccessor::new(client);
+        for event in events {
+            let sequence = outbox.create(event.clone().into()).await?;
+            event.set_sequence(sequence);
+        }
+
+        Ok(())
     }
 }
+
+/// Get the groups of a device, starting with its group, followed by its parents.
+async fn device_groups<C: Client>(
+    client: &C,
+    application: &str,
+    spec: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<DeviceGroup>, ServiceError> {
+    Ok(match groups::device_group(spec) {
+        Ok(Some(group)) => PostgresGroupAccessor::new(client)
+            .ancestors(application, group)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        // an invalid group is rejected when storing the device
+        Ok(None) | Err(_) => vec![],
+    })
+}
+
+/// Apply what a device inherits from its profile and groups, resulting in its effective spec.
+///
+/// The sections of the device take precedence over those of its profile, which take precedence
+/// over the defaults of its groups.
+async fn effective_device<C: Client>(
+    client: &C,
+    mut device: registry::v1::Device,
+) -> Result<registry::v1::Device, ServiceError> {
+    let application = &device.metadata.application;
+
+    let profile = PostgresProfileAccessor::new(client)
+        .device_profile(application, &device.spec)
+        .await?;
+    let groups = device_groups(client, application, &device.spec).await?;
+
+    if let Some(profile) = profile {
+        profiles::apply_profile(&mut device.spec, &profile);
+    }
+    groups::apply_groups(&mut device.spec, &groups);
+
+    Ok(device)
+}
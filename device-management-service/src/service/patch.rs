@@ -0,0 +1,160 @@
+//! Patching resources, using JSON patch or JSON merge patch.
+
+use drogue_cloud_database_common::error::ServiceError;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// The content type of a JSON patch (RFC 6902).
+pub const CONTENT_TYPE_JSON_PATCH: &str = "application/json-patch+json";
+/// The content type of a JSON merge patch (RFC 7386).
+pub const CONTENT_TYPE_MERGE_PATCH: &str = "application/merge-patch+json";
+
+/// A patch to a resource.
+#[derive(Clone, Debug)]
+pub enum Patch {
+    /// A JSON patch, as defined by RFC 6902.
+    Json(json_patch::Patch),
+    /// A JSON merge patch, as defined by RFC 7386.
+    Merge(Value),
+}
+
+impl Patch {
+    /// Parse a patch, using the content type to detect its format.
+    ///
+    /// Returns `Ok(None)` if the content type is not supported.
+    pub fn parse(content_type: &str, data: &[u8]) -> Result<Option<Self>, serde_json::Error> {
+        Ok(match content_type {
+            CONTENT_TYPE_JSON_PATCH => Some(Self::Json(serde_json::from_slice(data)?)),
+            CONTENT_TYPE_MERGE_PATCH => Some(Self::Merge(serde_json::from_slice(data)?)),
+            _ => None,
+        })
+    }
+
+    /// Apply the patch to a resource.
+    ///
+    /// A failing "test" operation results in [`ServiceError::OptimisticLockFailed`], as it is
+    /// intended to be used for optimistic locking.
+    pub fn apply<T>(&self, resource: T) -> Result<T, ServiceError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut value = serde_json::to_value(resource)
+            .map_err(|err| ServiceError::Internal(err.to_string()))?;
+
+        match self {
+            Self::Json(patch) => {
+                json_patch::patch(&mut value, patch).map_err(|err| match err {
+                    json_patch::PatchError::TestFailed => ServiceError::OptimisticLockFailed,
+                    err => ServiceError::BadRequest(format!("Failed to apply patch: {err}")),
+                })?;
+            }
+            Self::Merge(patch) => json_patch::merge(&mut value, patch),
+        }
+
+        serde_json::from_value(value)
+            .map_err(|err| ServiceError::BadRequest(format!("Invalid patched resource: {err}")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use drogue_client::registry;
+    use serde_json::json;
+
+    fn device() -> registry::v1::Device {
+        serde_json::from_value(json!({
+            "metadata": {
+                "application": "app1",
+                "name": "device1",
+                "resourceVersion": "1",
+                "labels": {"foo": "bar"},
+            },
+            "spec": {
+                "alias": ["alias1"],
+                "core": {"disabled": false},
+            },
+        }))
+        .unwrap()
+    }
+
+    fn json_patch(patch: Value) -> Patch {
+        Patch::parse(CONTENT_TYPE_JSON_PATCH, patch.to_string().as_bytes())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_unsupported() {
+        assert!(Patch::parse("application/json", b"{}").unwrap().is_none());
+        assert!(Patch::parse(CONTENT_TYPE_JSON_PATCH, b"{}").is_err());
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let patch = json_patch(json!([
+            {"op": "test", "path": "/metadata/resourceVersion", "value": "1"},
+            {"op": "replace", "path": "/spec/core/disabled", "value": true},
+            {"op": "add", "path": "/spec/alias/-", "value": "alias2"},
+            {"op": "remove", "path": "/metadata/labels/foo"},
+        ]));
+
+        let device = patch.apply(device()).unwrap();
+
+        assert_eq!(device.spec["core"], json!({"disabled": true}));
+        assert_eq!(device.spec["alias"], json!(["alias1", "alias2"]));
+        assert!(device.metadata.labels.is_empty());
+    }
+
+    #[test]
+    fn test_json_patch_test_failed() {
+        let patch = json_patch(json!([
+            {"op": "test", "path": "/metadata/resourceVersion", "value": "2"},
+            {"op": "replace", "path": "/spec/core/disabled", "value": true},
+        ]));
+
+        assert!(matches!(
+            patch.apply(device()),
+            Err(ServiceError::OptimisticLockFailed)
+        ));
+    }
+
+    #[test]
+    fn test_json_patch_invalid_pointer() {
+        let patch = json_patch(json!([
+            {"op": "remove", "path": "/spec/foo/bar"},
+        ]));
+
+        assert!(matches!(
+            patch.apply(device()),
+            Err(ServiceError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let patch = Patch::parse(
+            CONTENT_TYPE_MERGE_PATCH,
+            json!({
+                "metadata": {"labels": {"foo": null, "bar": "baz"}},
+                "spec": {"core": null, "credentials": {"credentials": [{"pass": "foo"}]}},
+            })
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap()
+        .unwrap();
+
+        let device = patch.apply(device()).unwrap();
+
+        assert_eq!(device.metadata.labels.get("bar"), Some(&"baz".to_string()));
+        assert_eq!(device.metadata.labels.get("foo"), None);
+        assert_eq!(device.metadata.resource_version, "1");
+        assert!(device.spec.get("core").is_none());
+        assert_eq!(device.spec["alias"], json!(["alias1"]));
+        assert_eq!(
+            device.spec["credentials"],
+            json!({"credentials": [{"pass": "foo"}]})
+        );
+    }
+}
@@ -0,0 +1,781 @@
+//! Watching applications and devices for changes.
+
+use crate::service::{
+    effective_device,
+    error::PostgresManagementServiceError,
+    management::{ListOptions, ManagementService},
+    PostgresManagementService,
+};
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+use drogue_client::{error::ErrorInformation, registry, user::v1::authz::Permission};
+use drogue_cloud_database_common::{
+    auth::{ensure, Resource},
+    error::ServiceError,
+    models::{
+        app::{self, ApplicationAccessor, MemberEntry, PostgresApplicationAccessor},
+        device::{DeviceAccessor, PostgresDeviceAccessor},
+        Lock,
+    },
+};
+use drogue_cloud_registry_events::{
+    stream::{EventHandler, KafkaEventStream},
+    Event, EventSender,
+};
+use drogue_cloud_service_api::{
+    auth::user::UserInformation,
+    kafka::KafkaConfig,
+    labels::LabelSelector,
+    webapp::{HttpResponse, ResponseError},
+};
+use drogue_cloud_service_common::keycloak::KeycloakClient;
+use futures::{Stream, TryStreamExt};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WatchConfig {
+    /// The topic of the registry events.
+    pub kafka: KafkaConfig,
+    /// The number of events kept for resuming watches.
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+}
+
+const fn default_buffer_size() -> usize {
+    1000
+}
+
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("Unable to resume from revision: {0}")]
+    Gone(String),
+}
+
+impl ResponseError for WatchError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::Gone(_) => HttpResponse::Gone().json(ErrorInformation {
+                error: "Gone".into(),
+                message: self.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum WatchEventType {
+    Added,
+    Modified,
+    Deleted,
+    /// The watch failed, and will be closed. The object contains the error information.
+    Error,
+}
+
+/// An event, sent to the client of a watch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchEvent {
+    pub r#type: WatchEventType,
+    /// The revision of the watch, which can be used to resume it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    pub object: Value,
+}
+
+impl WatchEvent {
+    fn error<E: ToString>(error: &str, message: E) -> Self {
+        Self {
+            r#type: WatchEventType::Error,
+            revision: None,
+            object: json!({"error": error, "message": message.to_string()}),
+        }
+    }
+}
+
+/// What to watch.
+#[derive(Clone, Debug)]
+pub enum WatchTarget {
+    Applications,
+    Devices {
+        application: String,
+        /// Report the effective spec of the devices.
+        effective: bool,
+    },
+}
+
+/// The state of an object, as seen by a watch.
+#[derive(Debug)]
+struct Object {
+    name: String,
+    uid: String,
+    resource_version: String,
+    labels: HashMap<String, String>,
+    value: Value,
+    /// The object with its effective spec, if it differs from the stored one.
+    effective: Option<Value>,
+}
+
+impl Object {
+    fn from_app(app: registry::v1::Application) -> Self {
+        Self {
+            name: app.metadata.name.clone(),
+            uid: app.metadata.uid.clone(),
+            resource_version: app.metadata.resource_version.clone(),
+            labels: app.metadata.labels.clone(),
+            value: serde_json::to_value(app).unwrap_or_default(),
+            effective: None,
+        }
+    }
+
+    fn from_device(device: registry::v1::Device) -> Self {
+        Self {
+            name: device.metadata.name.clone(),
+            uid: device.metadata.uid.clone(),
+            resource_version: device.metadata.resource_version.clone(),
+            labels: device.metadata.labels.clone(),
+            value: serde_json::to_value(device).unwrap_or_default(),
+            effective: None,
+        }
+    }
+
+    /// The value reported to a watch.
+    fn value(&self, effective: bool) -> &Value {
+        match &self.effective {
+            Some(value) if effective => value,
+            _ => &self.value,
+        }
+    }
+}
+
+/// The access information of an application, for authorizing watches.
+#[derive(Debug)]
+struct Access {
+    owner: Option<String>,
+    members: IndexMap<String, MemberEntry>,
+}
+
+impl Access {
+    fn take(app: &mut app::Application) -> Self {
+        Self {
+            owner: app.owner.take(),
+            members: std::mem::take(&mut app.members),
+        }
+    }
+}
+
+impl Resource for Access {
+    fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    fn members(&self) -> &IndexMap<String, MemberEntry> {
+        &self.members
+    }
+}
+
+/// The current state of a changed object.
+#[derive(Debug, Default)]
+struct Current {
+    /// Access to the application the object belongs to, `None` if the application is gone.
+    access: Option<Access>,
+    /// The object, `None` if it is gone.
+    object: Option<Object>,
+}
+
+impl Current {
+    /// Look up the state of the object changed by the event.
+    async fn lookup(pool: &Pool, event: &Event) -> Result<Self, ServiceError> {
+        let c = pool.get().await?;
+
+        let (application, device) = match event {
+            Event::Application { application, .. } => (application, None),
+            Event::Device {
+                application,
+                device,
+                ..
+            } => (application, Some(device)),
+        };
+
+        let mut app = match PostgresApplicationAccessor::new(&c)
+            .get(application, Lock::None)
+            .await?
+        {
+            Some(app) => app,
+            None => return Ok(Self::default()),
+        };
+        let access = Some(Access::take(&mut app));
+
+        let object = match device {
+            None => Some(Object::from_app(app.into())),
+            Some(device) => {
+                match PostgresDeviceAccessor::new(&c)
+                    .get(application, device, Lock::None)
+                    .await?
+                {
+                    Some(device) => {
+                        let device: registry::v1::Device = device.into();
+                        let effective = effective_device(&c, device.clone()).await?;
+                        Some(Object {
+                            effective: Some(serde_json::to_value(effective).unwrap_or_default()),
+                            ..Object::from_device(device)
+                        })
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        Ok(Self { access, object })
+    }
+}
+
+/// A change, distributed to all watches.
+#[derive(Clone, Debug)]
+struct Change {
+    event: Event,
+    /// The current state, looked up once for all watches.
+    ///
+    /// If missing, the watch needs to look it up by itself.
+    current: Option<Arc<Result<Current, String>>>,
+}
+
+/// Recent events, for resuming watches.
+#[derive(Debug)]
+struct State {
+    buffer_size: usize,
+    /// The highest sequence number seen so far.
+    last: u64,
+    /// The oldest revision a watch can be resumed from, `None` if no event was seen yet.
+    floor: Option<u64>,
+    /// Recent events, ordered by arrival.
+    buffer: VecDeque<Event>,
+}
+
+impl State {
+    fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            last: 0,
+            floor: None,
+            buffer: VecDeque::with_capacity(buffer_size),
+        }
+    }
+
+    /// Record an event, evicting the oldest one if the buffer is full.
+    fn record(&mut self, event: &Event) {
+        // events which are not part of the outbox can't be resumed from
+        let seq = match event.sequence() {
+            Some(seq) => seq,
+            None => return,
+        };
+
+        // we can't know about any event before the first one we received
+        if self.floor.is_none() {
+            self.floor = Some(seq.saturating_sub(1));
+        }
+        self.last = self.last.max(seq);
+
+        if self.buffer.len() >= self.buffer_size {
+            match self.buffer.pop_front() {
+                Some(evicted) => self.floor = self.floor.max(evicted.sequence()),
+                // not buffering at all, only resuming from the latest revision is possible
+                None => self.floor = Some(self.last),
+            }
+        }
+        if self.buffer_size > 0 {
+            self.buffer.push_back(event.clone());
+        }
+    }
+
+    /// Get the events following the revision.
+    fn resume(&self, revision: &str) -> Result<(u64, Vec<Event>), WatchError> {
+        let seq = revision
+            .parse::<u64>()
+            .map_err(|_| WatchError::Gone(revision.to_string()))?;
+
+        match self.floor {
+            Some(floor) if seq >= floor => Ok((
+                seq,
+                self.buffer
+                    .iter()
+                    .filter(|event| event.sequence().unwrap_or_default() > seq)
+                    .cloned()
+                    .collect(),
+            )),
+            _ => Err(WatchError::Gone(revision.to_string())),
+        }
+    }
+}
+
+/// Distributes registry events to all active watches.
+///
+/// The revision of a watch is the sequence number the outbox assigned to a change. As all
+/// instances receive the same events, a watch can be resumed on any instance which received all
+/// events following the revision, and still has them buffered.
+///
+/// The state of a changed object is looked up once, and shared by all watches.
+#[derive(Clone)]
+pub struct Watcher {
+    pool: Pool,
+    state: Arc<Mutex<State>>,
+    sender: broadcast::Sender<Change>,
+}
+
+/// A subscription to the registry events.
+struct Subscription {
+    /// The revision the subscription starts from.
+    revision: u64,
+    /// Events which happened after the requested revision.
+    replay: Vec<Event>,
+    receiver: broadcast::Receiver<Change>,
+}
+
+impl Watcher {
+    pub fn new(buffer_size: usize, pool: Pool) -> Self {
+        let (sender, _) = broadcast::channel(buffer_size.max(1));
+        Self {
+            pool,
+            state: Arc::new(Mutex::new(State::new(buffer_size))),
+            sender,
+        }
+    }
+
+    /// Consume the registry events from Kafka, forwarding them to all watches.
+    pub async fn run(self, config: KafkaConfig) -> anyhow::Result<()> {
+        KafkaEventStream::new_anonymous(config)?.run(self).await
+    }
+
+    /// The number of active watches.
+    pub fn watches(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// Record a new event, and notify all watches.
+    pub async fn notify(&self, event: Event) {
+        // only look up the object if someone is watching
+        let current = match self.watches() {
+            0 => None,
+            _ => Some(Arc::new(
+                Current::lookup(&self.pool, &event)
+                    .await
+                    .map_err(|err| err.to_string()),
+            )),
+        };
+
+        self.state.lock().unwrap().record(&event);
+
+        // sending only fails if there are no watches
+        let _ = self.sender.send(Change { event, current });
+    }
+
+    /// Subscribe to events, replaying buffered events after the revision, if present.
+    fn subscribe(&self, revision: Option<&str>) -> Result<Subscription, WatchError> {
+        // hold the lock, so that we don't miss an event between replaying and subscribing
+        let state = self.state.lock().unwrap();
+
+        let (revision, replay) = match revision {
+            Some(revision) => state.resume(revision)?,
+            None => (state.last, vec![]),
+        };
+
+        Ok(Subscription {
+            revision,
+            replay,
+            receiver: self.sender.subscribe(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventHandler for Watcher {
+    type Event = Event;
+    type Error = Infallible;
+
+    async fn handle(&self, event: &Self::Event) -> Result<(), Self::Error> {
+        self.notify(event.clone()).await;
+        Ok(())
+    }
+}
+
+/// A single watch, processing events for one client.
+struct Watch<S, K>
+where
+    S: EventSender + Clone,
+    K: KeycloakClient + Send + Sync,
+{
+    service: PostgresManagementService<S, K>,
+    pool: Pool,
+    identity: UserInformation,
+    target: WatchTarget,
+    labels: LabelSelector,
+    /// Whether the watch was resumed, in which case we don't know what the client has seen.
+    resumed: bool,
+    /// The revision reported to the client.
+    revision: u64,
+    /// Objects reported to the client, mapping the name to uid and resource version.
+    known: HashMap<String, (String, String)>,
+    tx: mpsc::Sender<WatchEvent>,
+}
+
+impl<S, K> Watch<S, K>
+where
+    S: EventSender + Clone + 'static,
+    K: KeycloakClient + Send + Sync + 'static,
+{
+    async fn run(mut self, subscription: Subscription) -> Result<(), WatchFailure> {
+        let Subscription {
+            replay,
+            mut receiver,
+            ..
+        } = subscription;
+
+        if !self.resumed {
+            self.initial().await?;
+        }
+
+        for event in replay {
+            self.handle(Change {
+                event,
+                current: None,
+            })
+            .await?;
+        }
+
+        loop {
+            let change = tokio::select! {
+                _ = self.tx.closed() => return Err(WatchFailure::Disconnected),
+                change = receiver.recv() => change,
+            };
+
+            match change {
+                Ok(change) => self.handle(change).await?,
+                Err(RecvError::Lagged(_)) => return Err(WatchFailure::Lagged),
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    async fn send(&self, event: WatchEvent) -> Result<(), WatchFailure> {
+        self.tx
+            .send(event)
+            .await
+            .map_err(|_| WatchFailure::Disconnected)
+    }
+
+    /// Report all current objects as added.
+    async fn initial(&mut self) -> Result<(), WatchFailure> {
+        let revision = Some(self.revision.to_string());
+
+        let mut objects: std::pin::Pin<Box<dyn Stream<Item = _> + Send>> = match &self.target {
+            WatchTarget::Applications => Box::pin(
+                self.service
+                    .list_apps(self.identity.clone(), self.labels.clone(), None, None)
+                    .await?
+                    .map_ok(Object::from_app),
+            ),
+            WatchTarget::Devices {
+                application,
+                effective,
+            } => Box::pin(
+                self.service
+                    .list_devices(
+                        self.identity.clone(),
+                        application,
+                        self.labels.clone(),
+                        ListOptions {
+                            effective: *effective,
+                            ..Default::default()
+                        },
+                    )
+                    .await?
+                    .map_ok(Object::from_device),
+            ),
+        };
+
+        while let Some(object) = objects.try_next().await? {
+            self.known.insert(
+                object.name.clone(),
+                (object.uid.clone(), object.resource_version.clone()),
+            );
+            self.send(WatchEvent {
+                r#type: WatchEventType::Added,
+                revision: revision.clone(),
+                object: object.value,
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a registry event, reporting the change of the object, if any.
+    async fn handle(&mut self, change: Change) -> Result<(), WatchFailure> {
+        let (name, uid) = match (&self.target, &change.event) {
+            (
+                WatchTarget::Applications,
+                Event::Application {
+                    application, uid, ..
+                },
+            ) => (application.clone(), uid.clone()),
+            (
+                WatchTarget::Devices { application, .. },
+                Event::Device {
+                    application: app,
+                    device,
+                    uid,
+                    ..
+                },
+            ) if application == app => (device.clone(), uid.clone()),
+            _ => return Ok(()),
+        };
+
+        if let Some(seq) = change.event.sequence() {
+            if seq <= self.revision {
+                // already seen by the client
+                return Ok(());
+            }
+            self.revision = seq;
+        }
+        let revision = Some(self.revision.to_string());
+
+        let current = match change.current {
+            Some(current) => current,
+            None => Arc::new(
+                Current::lookup(&self.pool, &change.event)
+                    .await
+                    .map_err(|err| err.to_string()),
+            ),
+        };
+        let current = match &*current {
+            Ok(current) => current,
+            Err(err) => return Err(WatchFailure::Service(err.clone())),
+        };
+
+        let object = match (&current.access, &current.object) {
+            (Some(access), Some(object))
+                if ensure(access, &self.identity, Permission::Read).is_ok()
+                    && self.labels.matches(&object.labels) =>
+            {
+                object
+            }
+            (_, object) => {
+                // the object is gone, no longer accessible, or no longer matches
+                let known = self.known.remove(&name);
+                let uid = known.map(|(uid, _)| uid).or_else(|| {
+                    // after resuming, we report a deletion, even if we never saw the object
+                    (self.resumed && object.is_none()).then_some(uid)
+                });
+                if let Some(uid) = uid {
+                    self.send(WatchEvent {
+                        r#type: WatchEventType::Deleted,
+                        revision,
+                        object: self.deleted(&name, &uid),
+                    })
+                    .await?;
+                }
+                return Ok(());
+            }
+        };
+
+        let r#type = match self.known.insert(
+            name.clone(),
+            (object.uid.clone(), object.resource_version.clone()),
+        ) {
+            // already reported, multiple events are sent for a single change
+            Some((uid, resource_version))
+                if uid == object.uid && resource_version == object.resource_version =>
+            {
+                return Ok(());
+            }
+            Some((uid, _)) if uid != object.uid => {
+                // the object was re-created, with the same name
+                self.send(WatchEvent {
+                    r#type: WatchEventType::Deleted,
+                    revision: revision.clone(),
+                    object: self.deleted(&name, &uid),
+                })
+                .await?;
+                WatchEventType::Added
+            }
+            Some(_) => WatchEventType::Modified,
+            None if self.resumed => WatchEventType::Modified,
+            None => WatchEventType::Added,
+        };
+
+        let effective = matches!(
+            self.target,
+            WatchTarget::Devices {
+                effective: true,
+                ..
+            }
+        );
+        self.send(WatchEvent {
+            r#type,
+            revision,
+            object: object.value(effective).clone(),
+        })
+        .await
+    }
+
+    /// The object reported for a deletion, only containing the identifying metadata.
+    fn deleted(&self, name: &str, uid: &str) -> Value {
+        match &self.target {
+            WatchTarget::Applications => json!({"metadata": {"name": name, "uid": uid}}),
+            WatchTarget::Devices { application, .. } => {
+                json!({"metadata": {"application": application, "name": name, "uid": uid}})
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+enum WatchFailure {
+    #[error("{0}")]
+    Service(String),
+    #[error("Watch fell behind, resume from the last revision")]
+    Lagged,
+    #[error("Client disconnected")]
+    Disconnected,
+}
+
+impl<E> From<PostgresManagementServiceError<E>> for WatchFailure
+where
+    E: std::error::Error + std::fmt::Debug + 'static,
+{
+    fn from(err: PostgresManagementServiceError<E>) -> Self {
+        Self::Service(err.to_string())
+    }
+}
+
+/// Start watching for changes.
+///
+/// Unless the watch is resumed from a revision, all current objects are reported as added first.
+/// The watch runs until the client disconnects, or the watch fails.
+pub fn watch<S, K>(
+    service: PostgresManagementService<S, K>,
+    watcher: &Watcher,
+    identity: UserInformation,
+    target: WatchTarget,
+    labels: LabelSelector,
+    revision: Option<&str>,
+) -> Result<impl Stream<Item = WatchEvent>, WatchError>
+where
+    S: EventSender + Clone + 'static,
+    K: KeycloakClient + Send + Sync + 'static,
+{
+    let subscription = watcher.subscribe(revision)?;
+    let (tx, rx) = mpsc::channel(16);
+
+    let watch = Watch {
+        service,
+        pool: watcher.pool.clone(),
+        identity,
+        target,
+        labels,
+        resumed: revision.is_some(),
+        revision: subscription.revision,
+        known: HashMap::new(),
+        tx: tx.clone(),
+    };
+
+    tokio::spawn(async move {
+        let event = match watch.run(subscription).await {
+            Ok(()) | Err(WatchFailure::Disconnected) => return,
+            Err(err @ WatchFailure::Lagged) => WatchEvent::error("Gone", err),
+            Err(err @ WatchFailure::Service(_)) => WatchEvent::error("ServiceError", err),
+        };
+        let _ = tx.send(event).await;
+    });
+
+    Ok(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (event, rx))
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(application: &str, sequence: u64) -> Event {
+        Event::Application {
+            instance: "drogue".into(),
+            application: application.into(),
+            uid: "uid".into(),
+            path: ".".into(),
+            revision: 1,
+            sequence: Some(sequence),
+        }
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut state = State::new(2);
+
+        // nothing seen yet
+        assert!(matches!(state.resume("0"), Err(WatchError::Gone(_))));
+
+        state.record(&event("app1", 10));
+        assert_eq!(state.last, 10);
+        assert_eq!(state.resume("9").unwrap(), (9, vec![event("app1", 10)]));
+        // we may have missed events before the first one
+        assert!(state.resume("8").is_err());
+
+        // sequence numbers may have gaps
+        state.record(&event("app2", 12));
+        state.record(&event("app3", 15));
+
+        // the first event was evicted
+        assert!(state.resume("9").is_err());
+
+        assert_eq!(
+            state.resume("10").unwrap(),
+            (10, vec![event("app2", 12), event("app3", 15)])
+        );
+        assert_eq!(state.resume("13").unwrap(), (13, vec![event("app3", 15)]));
+        assert_eq!(state.resume("15").unwrap(), (15, vec![]));
+
+        // another instance may be ahead of us
+        assert_eq!(state.resume("16").unwrap(), (16, vec![]));
+
+        // invalid revisions
+        assert!(state.resume("foo").is_err());
+        assert!(state.resume("abc.1").is_err());
+    }
+
+    #[test]
+    fn test_resume_unbuffered() {
+        let mut state = State::new(0);
+
+        state.record(&event("app1", 1));
+        state.record(&event("app2", 2));
+
+        assert!(state.resume("1").is_err());
+        assert_eq!(state.resume("2").unwrap(), (2, vec![]));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let event = WatchEvent {
+            r#type: WatchEventType::Added,
+            revision: Some("1".into()),
+            object: json!({"metadata": {"name": "app1"}}),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({"type": "ADDED", "revision": "1", "object": {"metadata": {"name": "app1"}}})
+        );
+    }
+}
@@ -48,6 +48,7 @@ async fn test_create_app() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
     })
 }
@@ -127,6 +128,7 @@ async fn test_crud_app() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist
@@ -170,6 +172,7 @@ async fn test_crud_app() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".spec.core".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist
@@ -211,6 +214,7 @@ async fn test_crud_app() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // try read, must not exist
@@ -255,6 +259,7 @@ async fn test_app_labels() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist
@@ -306,6 +311,7 @@ async fn test_app_labels() -> anyhow::Result<()> {
                 uid: "".into(),
                 path: ".metadata".into(),
                 revision: 0,
+                sequence: None,
             },
             Event::Application {
                 instance: "drogue-instance".into(),
@@ -313,6 +319,7 @@ async fn test_app_labels() -> anyhow::Result<()> {
                 uid: "".into(),
                 path: ".spec.core".into(),
                 revision: 0,
+                sequence: None,
             }
         ]);
 
@@ -372,6 +379,7 @@ async fn test_create_duplicate_app() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
 
@@ -417,6 +425,7 @@ async fn test_app_trust_anchor() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist, with cert
@@ -476,12 +485,14 @@ async fn test_app_trust_anchor() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".spec.trustAnchors".into(),
             revision: 0,
+            sequence: None,
         }, Event::Application {
             instance: "drogue-instance".into(),
             application: "app1".into(),
             uid: "".into(),
             path: ".status.trustAnchors".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist, but no cert
@@ -531,6 +542,7 @@ async fn test_delete_finalizer() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1").send_request(&app).await;
@@ -544,6 +556,7 @@ async fn test_delete_finalizer() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".metadata".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist
@@ -584,6 +597,7 @@ async fn test_delete_finalizer() -> anyhow::Result<()> {
                 uid: "".into(),
                 path: ".metadata".into(),
                 revision: 0,
+                sequence: None,
             },
         ]);
 
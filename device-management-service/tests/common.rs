@@ -27,7 +27,10 @@ pub fn init() {
 
 #[macro_export]
 macro_rules! test {
-    (($app:ident, $sender:ident, $outbox:ident) => $($code:tt)*) => {{
+    (($app:ident, $sender:ident, $outbox:ident) => $($code:tt)*) => {
+        test!(($app, $sender, $outbox, _watcher) => $($code)*)
+    };
+    (($app:ident, $sender:ident, $outbox:ident, $watcher:ident) => $($code:tt)*) => {{
         init();
 
         let cli = client();
@@ -57,6 +60,7 @@ macro_rules! test {
 
         let mut $sender = sender;
         let $outbox = outbox;
+        let $watcher = service::watch::Watcher::new(16, pool.clone());
 
         let $app = drogue_cloud_service_api::webapp::test::init_service(
             {
@@ -65,6 +69,7 @@ macro_rules! test {
                     app!(cfg, MockEventSender, KeycloakAdminMock, auth)
                     // for the management service
                     .app_data(data.clone())
+                    .app_data(web::Data::new($watcher.clone()))
                     // for the admin service
                     .app_data(web::Data::new(apps::WebData{
                         service: service.clone(),
@@ -96,11 +101,14 @@ macro_rules! test {
 
 /// Assert if events are as expected.
 ///
-/// This will ignore differences in the "generation", as they are not predictable.
+/// This will ignore differences in the "generation" and the outbox sequence, as they are not
+/// predictable.
 #[allow(irrefutable_let_patterns)]
 pub fn assert_events(actual: Vec<Vec<Event>>, mut expected: Vec<Event>) {
     for (n, actual) in actual.into_iter().enumerate() {
         for i in actual.iter().zip(expected.iter_mut()) {
+            i.1.set_sequence(i.0.sequence());
+
             // this if could be reworked when we have: https://github.com/rust-lang/rust/issues/54883
             if let Event::Application {
                 revision: actual_revision,
@@ -255,6 +263,7 @@ mod test {
             application: "app".to_string(),
             path: ".".to_string(),
             revision: 0,
+            sequence: None,
             uid: "a".to_string(),
         }];
         let actual = vec![Event::Application {
@@ -262,6 +271,7 @@ mod test {
             application: "app".to_string(),
             path: ".".to_string(),
             revision: 12345,
+            sequence: None,
             uid: "b".to_string(),
         }];
         assert_events(vec![actual], expected);
@@ -52,6 +52,7 @@ async fn test_create_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
@@ -80,11 +81,117 @@ async fn test_create_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
     })
 }
 
+#[actix_rt::test]
+#[serial]
+async fn test_import_devices() -> anyhow::Result<()> {
+    test!((app, sender, outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        sender.retrieve()?;
+        outbox_retrieve(&outbox).await?;
+
+        let body = concat!(
+            r#"{"metadata": {"application": "app1", "name": "device1", "annotations": {"foo": "bar"}}, "spec": {"alias": ["foo"], "custom": {"foo": "bar"}}}"#, "\n",
+            "\n",
+            "{\n",
+            r#"{"metadata": {"application": "app2", "name": "device2"}}"#, "\n",
+            r#"{"metadata": {"application": "app1", "name": "device3"}}"#,
+        );
+
+        // dry run
+
+        let resp = TestRequest::post()
+            .uri("/api/registry/v1alpha1/apps/app1/import?dryRun=true")
+            .insert_header((header::CONTENT_TYPE, "application/x-ndjson"))
+            .set_payload(body)
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(result["created"], json!(2));
+        assert_eq!(result["failed"], json!(2));
+        assert_eq!(result["errors"][0]["line"], json!(3));
+        assert_eq!(result["errors"][1]["device"], json!("device2"));
+
+        assert_eq!(sender.retrieve()?, vec![]);
+        assert_eq!(outbox_retrieve(&outbox).await?, vec![]);
+
+        // import
+
+        let resp = TestRequest::post()
+            .uri("/api/registry/v1alpha1/apps/app1/import")
+            .set_payload(body)
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(result["created"], json!(2));
+
+        assert_events(vec![sender.retrieve()?, outbox_retrieve(&outbox).await?], vec![Event::Device {
+            instance: "drogue-instance".into(),
+            application: "app1".into(),
+            device: "device1".into(),
+            uid: "".into(),
+            path: ".".into(),
+            revision: 0,
+            sequence: None,
+        }, Event::Device {
+            instance: "drogue-instance".into(),
+            application: "app1".into(),
+            device: "device3".into(),
+            uid: "".into(),
+            path: ".".into(),
+            revision: 0,
+            sequence: None,
+        }]);
+
+        // import again, existing devices fail without upsert
+
+        let resp = TestRequest::post()
+            .uri("/api/registry/v1alpha1/apps/app1/import?format=csv")
+            .set_payload("name,aliases\ndevice1,foo;bar\ndevice3,\n")
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(result["failed"], json!(2));
+
+        let resp = TestRequest::post()
+            .uri("/api/registry/v1alpha1/apps/app1/import?format=csv&upsert=true")
+            .set_payload("name,aliases\ndevice1,foo;bar\ndevice3,\n")
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(result["updated"], json!(1));
+        assert_eq!(result["unchanged"], json!(1));
+
+        // the upsert is merged into the existing device
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(result["metadata"]["annotations"], json!({"foo": "bar"}));
+        assert_eq!(result["spec"]["alias"], json!(["foo", "bar"]));
+        assert_eq!(result["spec"]["custom"], json!({"foo": "bar"}));
+
+        // export
+
+        let resp = TestRequest::get()
+            .uri("/api/registry/v1alpha1/apps/app1/export?format=csv")
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = actix_web::test::read_body(resp).await;
+        assert_eq!(body, "name,labels,aliases,username,password\ndevice1,,foo;bar,,\ndevice3,,,,\n");
+    })
+}
+
 #[actix_rt::test]
 #[serial]
 async fn test_create_device_no_app() -> anyhow::Result<()> {
@@ -177,6 +284,7 @@ async fn test_create_duplicate_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
@@ -247,6 +355,7 @@ async fn test_crud_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must exist now
@@ -307,6 +416,7 @@ async fn test_crud_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".spec.alias".into(),
             revision: 0,
+            sequence: None,
         },
         Event::Device {
             instance: "drogue-instance".into(),
@@ -315,6 +425,7 @@ async fn test_crud_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".spec.credentials".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must have changed
@@ -363,6 +474,7 @@ async fn test_crud_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read, must no longer not exist
@@ -421,6 +533,7 @@ async fn test_delete_app_deletes_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // create device
@@ -441,6 +554,7 @@ async fn test_delete_app_deletes_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // delete application, must succeed
@@ -455,6 +569,7 @@ async fn test_delete_app_deletes_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read device, must no longer not exist
@@ -485,6 +600,7 @@ async fn test_delete_app_finalizer_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // create device
@@ -506,6 +622,7 @@ async fn test_delete_app_finalizer_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // delete application, must succeed
@@ -519,6 +636,7 @@ async fn test_delete_app_finalizer_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".metadata".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // the application must still exist
@@ -585,6 +703,7 @@ async fn test_delete_app_finalizer_device() -> anyhow::Result<()> {
             uid: "".into(),
             path: ".metadata".into(),
             revision: 0,
+            sequence: None,
         }]);
 
         // read device, must still exist
@@ -800,6 +919,140 @@ async fn test_lock_device_uid() -> anyhow::Result<()> {
     })
 }
 
+#[actix_rt::test]
+#[serial]
+async fn test_patch_device() -> anyhow::Result<()> {
+    test!((app, sender, outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device1",
+                "application": "app1",
+                "labels": {
+                    "foo": "bar",
+                },
+            },
+            "spec": {
+                "credentials": {
+                    "credentials": [
+                        { "pass": "foo" }
+                    ]
+                }
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        sender.retrieve()?;
+        outbox_retrieve(&outbox).await?;
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        let resource_version = result["metadata"]["resourceVersion"].clone();
+
+        // unsupported content type
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device1")
+            .set_json(&json!({"spec": {"alias": ["baz"]}}))
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        // merge patch
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"spec": {"alias": ["baz"]}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        assert_events(vec![sender.retrieve()?, outbox_retrieve(&outbox).await?], vec![Event::Device {
+            instance: "drogue-instance".into(),
+            application: "app1".into(),
+            device: "device1".into(),
+            uid: "".into(),
+            path: ".spec.alias".into(),
+            revision: 0,
+            sequence: None,
+        }]);
+
+        // JSON patch, with an outdated version
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device1")
+            .insert_header((header::CONTENT_TYPE, "application/json-patch+json"))
+            .set_payload(json!([
+                {"op": "test", "path": "/metadata/resourceVersion", "value": resource_version},
+                {"op": "remove", "path": "/metadata/labels/foo"},
+            ]).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // JSON patch, with the current version
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        let result: serde_json::Value = read_body_json(resp).await;
+        let resource_version = result["metadata"]["resourceVersion"].clone();
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device1")
+            .insert_header((header::CONTENT_TYPE, "application/json-patch+json"))
+            .set_payload(json!([
+                {"op": "test", "path": "/metadata/resourceVersion", "value": resource_version},
+                {"op": "remove", "path": "/metadata/labels/foo"},
+            ]).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        assert_events(vec![sender.retrieve()?, outbox_retrieve(&outbox).await?], vec![Event::Device {
+            instance: "drogue-instance".into(),
+            application: "app1".into(),
+            device: "device1".into(),
+            uid: "".into(),
+            path: ".metadata".into(),
+            revision: 0,
+            sequence: None,
+        }]);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(result["metadata"]["labels"], json!(null));
+        assert_eq!(result["spec"], json!({
+            "credentials": {
+                "credentials": [
+                    {"pass": "foo"},
+                ]
+            },
+            "alias": ["baz"]
+        }));
+
+        // renaming is not allowed
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"metadata": {"name": "device2"}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // patching a non existing device
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device2")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"spec": {"alias": ["baz"]}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    })
+}
+
 #[actix_rt::test]
 #[serial]
 async fn test_search_devices() -> anyhow::Result<()> {
@@ -846,6 +1099,127 @@ async fn test_search_devices() -> anyhow::Result<()> {
 
         assert_devices(&app, &foo, "my-app", Some("floor"), Some(2), None, &["device-2", "device-3"]).await?;
         assert_devices(&app, &foo, "my-app", Some("floor"), Some(2), Some(1), &[ "device-3", "device-4"]).await?;
+
+        assert_devices(&app, &foo, "my-app", Some("room>101"), None, None, &["device-3", "device-4", "device-5"]).await?;
+        assert_devices(&app, &foo, "my-app", Some("room<102,floor>0.5"), None, None, &["device-2"]).await?;
+        assert_devices(&app, &foo, "my-app", Some("important<1"), None, None, &[]).await?;
+        assert_devices(&app, &foo, "my-app", Some("room^=10"), None, None, &["device-2", "device-3", "device-4"]).await?;
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_search_devices_fields() -> anyhow::Result<()> {
+    test!((app, _sender, _outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        for (name, spec) in [
+            ("device1", json!({"credentials": {"credentials": [{"pass": "foo"}]}})),
+            ("device2", json!({})),
+            ("device3", json!({"gatewaySelector": {"matchNames": ["device1"]}})),
+        ] {
+            let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+                "metadata": {
+                    "name": name,
+                    "application": "app1",
+                },
+                "spec": spec,
+            })).send_request(&app).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        let result: serde_json::Value = read_body_json(resp).await;
+        let created = result["metadata"]["creationTimestamp"].as_str().unwrap().to_string();
+        let created_after = format!("metadata.creationTimestamp>{created},!spec.credentials");
+        let created_before = format!("metadata.creationTimestamp<{created}");
+
+        for (fields, outcome) in [
+            ("spec.credentials", vec!["device1"]),
+            ("!spec.credentials", vec!["device2", "device3"]),
+            ("spec.gatewaySelector", vec!["device3"]),
+            ("!metadata.deletionTimestamp", vec!["device1", "device2", "device3"]),
+            ("metadata.deletionTimestamp", vec![]),
+            (created_after.as_str(), vec!["device2", "device3"]),
+            (created_before.as_str(), vec![]),
+        ] {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .append_pair("fieldSelector", fields)
+                .finish();
+            let resp = TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps/app1/devices?{query}")).send_request(&app).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let result: serde_json::Value = read_body_json(resp).await;
+            assert_resources(result, &outcome);
+        }
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices?fieldSelector=metadata.name").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_list_devices_continue() -> anyhow::Result<()> {
+    test!((app, _sender, _outbox) => {
+
+        let foo = user("foo");
+
+        create_app(&app, &foo, "my-app", hashmap!(
+        )).await?;
+
+        for name in ["device-1", "device-2", "device-3"] {
+            create_device(&app, &foo, "my-app", name, hashmap!(
+            )).await?;
+        }
+
+        // first page
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri("/api/registry/v1alpha1/apps/my-app/devices?limit=2&count=true&exclude=spec")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("X-Total-Count"), Some(&HeaderValue::from_static("3")));
+        let token = resp.headers().get("X-Continue-Token").expect("Continue token").to_str()?.to_string();
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_resources(result, &["device-1", "device-2"]);
+
+        // second, and last, page
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps/my-app/devices?limit=2&continue={}", token))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("X-Continue-Token").is_none());
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_resources(result, &["device-3"]);
+
+        // a limit, which can't be exceeded
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps/my-app/devices?limit={}&offset={}", usize::MAX, usize::MAX))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("X-Continue-Token").is_none());
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_resources(result, &[]);
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps/my-app/devices?limit={}", usize::MAX))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: serde_json::Value = read_body_json(resp).await;
+        assert_resources(result, &["device-1", "device-2", "device-3"]);
+
+        // invalid requests
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps/my-app/devices?continue={}&offset=1", token))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps/my-app/devices?continue={}&sort=creationTimestamp", token))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri("/api/registry/v1alpha1/apps/my-app/devices?continue=foo")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = call_http(&app, &foo, TestRequest::get().uri("/api/registry/v1alpha1/apps/my-app/devices?exclude=metadata")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     })
 }
 
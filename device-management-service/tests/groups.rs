@@ -0,0 +1,404 @@
+mod common;
+
+use crate::common::{assert_events, init, outbox_retrieve};
+use actix_cors::Cors;
+use actix_web::{
+    http::StatusCode,
+    test::{read_body_json, TestRequest},
+    web, App,
+};
+use drogue_cloud_admin_service::apps;
+use drogue_cloud_device_management_service::{
+    app, crud, endpoints,
+    service::{self},
+    WebData,
+};
+use drogue_cloud_registry_events::{mock::MockEventSender, Event};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_common::keycloak::{
+    mock::KeycloakAdminMock, KeycloakAdminClientConfig, KeycloakClient,
+};
+use drogue_cloud_test_common::{client, db};
+use http::{header, HeaderValue};
+use serde_json::{json, Value};
+use serial_test::serial;
+
+#[actix_rt::test]
+#[serial]
+async fn test_crud_group() -> anyhow::Result<()> {
+    test!((app, sender, outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        sender.retrieve()?;
+        outbox_retrieve(&outbox).await?;
+
+        // create
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/groups").set_json(&json!({
+            "metadata": {
+                "name": "building-1",
+                "application": "app1",
+            },
+            "spec": {
+                "gateways": ["gateway1"],
+                "defaults": {
+                    "foo": "bar",
+                },
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get(header::LOCATION), Some(&HeaderValue::from_static("http://localhost:8080/api/registry/v1alpha1/apps/app1/groups/building-1")));
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/groups").set_json(&json!({
+            "metadata": {
+                "name": "floor-1",
+                "application": "app1",
+            },
+            "spec": {
+                "parent": "building-1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // read
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/groups/building-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], json!({
+            "gateways": ["gateway1"],
+            "defaults": {
+                "foo": "bar",
+            },
+        }));
+        assert_eq!(result["metadata"]["generation"], json!(0));
+
+        // list
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/groups").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: Value = read_body_json(resp).await;
+        let names: Vec<_> = result.as_array().unwrap().iter().map(|g| g["metadata"]["name"].clone()).collect();
+        assert_eq!(names, vec![json!("building-1"), json!("floor-1")]);
+
+        // update
+
+        let resp = TestRequest::put().uri("/api/registry/v1alpha1/apps/app1/groups/building-1").set_json(&json!({
+            "metadata": {
+                "name": "building-1",
+                "application": "app1",
+            },
+            "spec": {
+                "gateways": ["gateway1", "gateway2"],
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/groups/building-1").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], json!({
+            "gateways": ["gateway1", "gateway2"],
+        }));
+        assert_eq!(result["metadata"]["generation"], json!(1));
+
+        // patch
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/groups/floor-1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"metadata": {"labels": {"floor": "1"}}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/groups/floor-1").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["metadata"]["labels"], json!({"floor": "1"}));
+        assert_eq!(result["spec"], json!({"parent": "building-1"}));
+        assert_eq!(result["metadata"]["generation"], json!(0));
+
+        // delete
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/groups/floor-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/groups/floor-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/groups/floor-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // groups without members don't send events
+
+        assert!(sender.retrieve()?.is_empty());
+        assert!(outbox_retrieve(&outbox).await?.is_empty());
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_group_references() -> anyhow::Result<()> {
+    test!((app, _sender, _outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // parent must exist
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/groups").set_json(&json!({
+            "metadata": {
+                "name": "floor-1",
+                "application": "app1",
+            },
+            "spec": {
+                "parent": "building-1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        for (name, parent) in [("building-1", None), ("floor-1", Some("building-1")), ("room-1", Some("floor-1"))] {
+            let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/groups").set_json(&json!({
+                "metadata": {
+                    "name": name,
+                    "application": "app1",
+                },
+                "spec": {
+                    "parent": parent,
+                },
+            })).send_request(&app).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        // defaults must not contain a group
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/groups/room-1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"spec": {"defaults": {"group": "floor-1"}}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // no cycles
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/groups/building-1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"spec": {"parent": "room-1"}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/groups/building-1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"spec": {"parent": "building-1"}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // devices must reference an existing group
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device1",
+                "application": "app1",
+            },
+            "spec": {
+                "group": "room-2",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device1",
+                "application": "app1",
+            },
+            "spec": {
+                "group": {"name": "room-1"},
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device1",
+                "application": "app1",
+            },
+            "spec": {
+                "group": "room-1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // groups with members or nested groups can't be deleted
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/groups/room-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/groups/floor-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // move the device to a different group
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device1")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"spec": {"group": "floor-1"}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/groups/room-1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_group_members() -> anyhow::Result<()> {
+    test!((app, sender, outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        for (name, spec) in [
+            ("building-1", json!({"gateways": ["gateway1"], "defaults": {"foo": "bar"}})),
+            ("floor-1", json!({"parent": "building-1"})),
+        ] {
+            let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/groups").set_json(&json!({
+                "metadata": {
+                    "name": name,
+                    "application": "app1",
+                },
+                "spec": spec,
+            })).send_request(&app).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device1",
+                "application": "app1",
+            },
+            "spec": {
+                "group": "floor-1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // the device is returned as stored, unless the effective spec is requested
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let device: Value = read_body_json(resp).await;
+        assert_eq!(device["spec"], json!({"group": "floor-1"}));
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result[0]["spec"], device["spec"]);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1?effective=true").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let effective: Value = read_body_json(resp).await;
+        assert_eq!(effective["spec"], json!({
+            "group": "floor-1",
+            "foo": "bar",
+            "gatewaySelector": {"matchNames": ["gateway1"]},
+        }));
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices?effective=true").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result[0]["spec"], effective["spec"]);
+
+        sender.retrieve()?;
+        outbox_retrieve(&outbox).await?;
+
+        // changing the spec of a parent creates events for all members
+
+        let resp = TestRequest::put().uri("/api/registry/v1alpha1/apps/app1/groups/building-1").set_json(&json!({
+            "metadata": {
+                "name": "building-1",
+                "application": "app1",
+            },
+            "spec": {
+                "gateways": ["gateway2"],
+                "defaults": {"foo": "baz"},
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        assert_events(vec![sender.retrieve()?, outbox_retrieve(&outbox).await?], vec![Event::Device {
+            instance: "drogue-instance".into(),
+            application: "app1".into(),
+            device: "device1".into(),
+            uid: "".into(),
+            path: ".spec".into(),
+            revision: 0,
+            sequence: None,
+        }]);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1?effective=true").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], json!({
+            "group": "floor-1",
+            "foo": "baz",
+            "gatewaySelector": {"matchNames": ["gateway2"]},
+        }));
+        assert_eq!(result["metadata"]["generation"], json!(1));
+
+        // sections equal to the inherited ones are stored with the device, as submitted
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        let mut device: Value = read_body_json(resp).await;
+        assert_eq!(device["spec"], json!({"group": "floor-1"}));
+
+        device["spec"] = json!({
+            "group": "floor-1",
+            "foo": "baz",
+            "gatewaySelector": {"matchNames": ["gateway2"]},
+        });
+        let resp = TestRequest::put().uri("/api/registry/v1alpha1/apps/app1/devices/device1").set_json(&device).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], device["spec"]);
+
+        // patches apply to the device as stored, and their result is stored as it is
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device2",
+                "application": "app1",
+            },
+            "spec": {
+                "group": "floor-1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/devices/device2")
+            .insert_header((header::CONTENT_TYPE, "application/json-patch+json"))
+            .set_payload(json!([
+                {"op": "test", "path": "/spec", "value": {"group": "floor-1"}},
+                {"op": "add", "path": "/spec/foo", "value": "baz"},
+            ]).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device2").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], json!({"group": "floor-1", "foo": "baz"}));
+    })
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use crate::common::{assert_events, init, outbox_retrieve};
+use actix_cors::Cors;
+use actix_web::{
+    http::StatusCode,
+    test::{read_body_json, TestRequest},
+    web, App,
+};
+use drogue_cloud_admin_service::apps;
+use drogue_cloud_device_management_service::{
+    app, crud, endpoints,
+    service::{self},
+    WebData,
+};
+use drogue_cloud_registry_events::{mock::MockEventSender, Event};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_common::keycloak::{
+    mock::KeycloakAdminMock, KeycloakAdminClientConfig, KeycloakClient,
+};
+use drogue_cloud_test_common::{client, db};
+use http::{header, HeaderValue};
+use serde_json::{json, Value};
+use serial_test::serial;
+
+#[actix_rt::test]
+#[serial]
+async fn test_crud_profile() -> anyhow::Result<()> {
+    test!((app, sender, outbox) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // create
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/profiles").set_json(&json!({
+            "metadata": {
+                "name": "sensor",
+                "application": "app1",
+            },
+            "spec": {
+                "ttn": {
+                    "app": "foo",
+                },
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get(header::LOCATION), Some(&HeaderValue::from_static("http://localhost:8080/api/registry/v1alpha1/apps/app1/profiles/sensor")));
+
+        // reserved sections
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/profiles").set_json(&json!({
+            "metadata": {
+                "name": "secret",
+                "application": "app1",
+            },
+            "spec": {
+                "credentials": {
+                    "credentials": [{"pass": "foo"}],
+                },
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // read and list
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/profiles/sensor").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], json!({"ttn": {"app": "foo"}}));
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/profiles").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result.as_array().map(|profiles| profiles.len()), Some(1));
+
+        // devices
+
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+            "metadata": {
+                "name": "device1",
+                "application": "app1",
+            },
+            "spec": {
+                "profile": "unknown",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        for name in ["device1", "device2"] {
+            let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps/app1/devices").set_json(&json!({
+                "metadata": {
+                    "name": name,
+                    "application": "app1",
+                },
+                "spec": {
+                    "profile": "sensor",
+                },
+            })).send_request(&app).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        // the management API returns the device as stored, unless the effective spec is requested
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1").send_request(&app).await;
+        let device: Value = read_body_json(resp).await;
+        assert_eq!(device["spec"], json!({"profile": "sensor"}));
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1?effective=true").send_request(&app).await;
+        let effective: Value = read_body_json(resp).await;
+        assert_eq!(effective["spec"], json!({"profile": "sensor", "ttn": {"app": "foo"}}));
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices?effective=true").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result[0]["spec"], effective["spec"]);
+
+        // storing a section equal to the one of the profile keeps it with the device
+
+        let resp = TestRequest::put().uri("/api/registry/v1alpha1/apps/app1/devices/device2").set_json(&json!({
+            "metadata": {
+                "name": "device2",
+                "application": "app1",
+            },
+            "spec": {
+                "profile": "sensor",
+                "ttn": {"app": "foo"},
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        sender.retrieve()?;
+        outbox_retrieve(&outbox).await?;
+
+        // changing the labels doesn't affect the devices
+
+        let resp = TestRequest::patch()
+            .uri("/api/registry/v1alpha1/apps/app1/profiles/sensor")
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(json!({"metadata": {"labels": {"foo": "bar"}}}).to_string())
+            .send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        assert!(sender.retrieve()?.is_empty());
+        assert!(outbox_retrieve(&outbox).await?.is_empty());
+
+        // changing the spec creates events for all devices
+
+        let resp = TestRequest::put().uri("/api/registry/v1alpha1/apps/app1/profiles/sensor").set_json(&json!({
+            "metadata": {
+                "name": "sensor",
+                "application": "app1",
+            },
+            "spec": {
+                "ttn": {
+                    "app": "bar",
+                },
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let expected = ["device1", "device2"].iter().map(|device| Event::Device {
+            instance: "drogue-instance".into(),
+            application: "app1".into(),
+            device: device.to_string(),
+            uid: "".into(),
+            path: ".spec".into(),
+            revision: 0,
+            sequence: None,
+        }).collect();
+        assert_events(vec![sender.retrieve()?, outbox_retrieve(&outbox).await?], expected);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device1?effective=true").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["metadata"]["generation"], json!(1));
+        assert_eq!(result["spec"], json!({"profile": "sensor", "ttn": {"app": "bar"}}));
+
+        // the section of the device still takes precedence
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/devices/device2?effective=true").send_request(&app).await;
+        let result: Value = read_body_json(resp).await;
+        assert_eq!(result["spec"], json!({"profile": "sensor", "ttn": {"app": "foo"}}));
+
+        // delete
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/profiles/sensor").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        for name in ["device1", "device2"] {
+            let resp = TestRequest::delete().uri(&format!("/api/registry/v1alpha1/apps/app1/devices/{name}")).send_request(&app).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+
+        let resp = TestRequest::delete().uri("/api/registry/v1alpha1/apps/app1/profiles/sensor").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps/app1/profiles/sensor").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    })
+}
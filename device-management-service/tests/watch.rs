@@ -0,0 +1,112 @@
+mod common;
+
+use crate::common::init;
+use actix_cors::Cors;
+use actix_web::{body::MessageBody, http::StatusCode, test::TestRequest, web, App};
+use drogue_cloud_admin_service::apps;
+use drogue_cloud_device_management_service::{
+    app, crud, endpoints,
+    service::{self, watch::Watcher},
+    WebData,
+};
+use drogue_cloud_registry_events::{mock::MockEventSender, Event};
+use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_common::keycloak::{
+    mock::KeycloakAdminMock, KeycloakAdminClientConfig, KeycloakClient,
+};
+use drogue_cloud_test_common::{client, db};
+use serde_json::{json, Value};
+use serial_test::serial;
+use std::{pin::Pin, time::Duration};
+use tokio::time::{sleep, timeout, Instant};
+
+/// Forward events to the watcher, as the Kafka stream would.
+async fn notify(watcher: &Watcher, events: Vec<Event>) {
+    for event in events {
+        watcher.notify(event).await;
+    }
+}
+
+/// Read the next event of a watch.
+async fn next<B: MessageBody>(body: &mut Pin<Box<B>>) -> Value {
+    let chunk = timeout(
+        Duration::from_secs(10),
+        futures::future::poll_fn(|cx| body.as_mut().poll_next(cx)),
+    )
+    .await
+    .expect("Timeout waiting for watch event")
+    .expect("Watch must not end");
+
+    match chunk {
+        Ok(chunk) => serde_json::from_slice(&chunk).expect("Event must be valid JSON"),
+        Err(_) => panic!("Failed to read watch event"),
+    }
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_watch_apps() -> anyhow::Result<()> {
+    test!((app, sender, _outbox, watcher) => {
+        let resp = TestRequest::post().uri("/api/registry/v1alpha1/apps").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        notify(&watcher, sender.retrieve()?).await;
+
+        // start watching, the existing application is reported first
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps?watch=true").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut watch = Box::pin(resp.into_body());
+
+        let event = next(&mut watch).await;
+        assert_eq!(event["type"], "ADDED");
+        assert_eq!(event["object"]["metadata"]["name"], "app1");
+        let created = event["revision"].as_str().expect("Revision must be present").to_string();
+        assert_eq!(watcher.watches(), 1);
+
+        // modify the application
+        let resp = TestRequest::put().uri("/api/registry/v1alpha1/apps/app1").set_json(&json!({
+            "metadata": {
+                "name": "app1",
+                "labels": {
+                    "foo": "bar",
+                },
+            },
+        })).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        notify(&watcher, sender.retrieve()?).await;
+
+        let event = next(&mut watch).await;
+        assert_eq!(event["type"], "MODIFIED");
+        assert_eq!(event["object"]["metadata"]["labels"]["foo"], "bar");
+        let modified = event["revision"].as_str().expect("Revision must be present").to_string();
+        // the revision is the sequence number of the outbox
+        assert!(modified.parse::<u64>()? > created.parse::<u64>()?);
+
+        // resume from the first revision, replaying the change
+        let resp = TestRequest::get().uri(&format!("/api/registry/v1alpha1/apps?watch=true&revision={created}")).send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut resumed = Box::pin(resp.into_body());
+
+        let event = next(&mut resumed).await;
+        assert_eq!(event["type"], "MODIFIED");
+        assert_eq!(event["revision"], modified.as_str());
+        assert_eq!(watcher.watches(), 2);
+
+        // an unknown revision can't be resumed from
+        let resp = TestRequest::get().uri("/api/registry/v1alpha1/apps?watch=true&revision=foo").send_request(&app).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+
+        // disconnecting the clients stops the watches
+        drop(watch);
+        drop(resumed);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while watcher.watches() > 0 {
+            assert!(Instant::now() < deadline, "Watches must stop after disconnecting");
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+}
@@ -1,6 +1,6 @@
 use crate::service::DeviceStateService;
 use drogue_cloud_service_api::{
-    services::device_state::*,
+    services::{device_state::*, twin::*},
     webapp::{web, *},
 };
 
@@ -48,6 +48,97 @@ pub async fn get(
     })
 }
 
+pub async fn list(
+    service: web::Data<dyn DeviceStateService>,
+    application: web::Path<String>,
+    query: web::Query<ConnectedDevicesRequest>,
+) -> Result<HttpResponse, Error> {
+    let response = service
+        .list(application.into_inner(), query.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn history(
+    service: web::Data<dyn DeviceStateService>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ConnectionHistoryRequest>,
+) -> Result<HttpResponse, Error> {
+    let (application, device) = path.into_inner();
+    let response = service
+        .history(application, device, query.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn get_twin(
+    service: web::Data<dyn DeviceStateService>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (application, device) = path.into_inner();
+    let response = service.get_twin(application, device).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn replace_desired(
+    service: web::Data<dyn DeviceStateService>,
+    path: web::Path<(String, String)>,
+    body: web::Json<TwinUpdate>,
+) -> Result<HttpResponse, Error> {
+    let (application, device) = path.into_inner();
+    let response = service
+        .update_desired(application, device, body.into_inner(), false)
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn merge_desired(
+    service: web::Data<dyn DeviceStateService>,
+    path: web::Path<(String, String)>,
+    body: web::Json<TwinUpdate>,
+) -> Result<HttpResponse, Error> {
+    let (application, device) = path.into_inner();
+    let response = service
+        .update_desired(application, device, body.into_inner(), true)
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn update_reported(
+    service: web::Data<dyn DeviceStateService>,
+    path: web::Path<(String, String)>,
+    body: web::Json<TwinUpdate>,
+) -> Result<HttpResponse, Error> {
+    let (application, device) = path.into_inner();
+    let response = service
+        .update_reported(application, device, body.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub async fn record_value(
+    service: web::Data<dyn DeviceStateService>,
+    path: web::Path<(String, String)>,
+    body: web::Json<RecordValueRequest>,
+) -> Result<HttpResponse, Error> {
+    let (application, device) = path.into_inner();
+    service
+        .record_value(application, device, body.into_inner())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn last_values(
+    service: web::Data<dyn DeviceStateService>,
+    application: web::Path<String>,
+    query: web::Query<LastValuesRequest>,
+) -> Result<HttpResponse, Error> {
+    let response = service
+        .last_values(application.into_inner(), query.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
 pub async fn ping(
     service: web::Data<dyn DeviceStateService>,
     instance: web::Path<String>,
@@ -3,19 +3,27 @@ pub mod service;
 
 use crate::service::{postgres::PostgresServiceConfiguration, DeviceStateService};
 use actix_web::web;
-use drogue_client::registry;
+use drogue_client::{
+    registry,
+    user::{self, v1::authz::Permission},
+};
 use drogue_cloud_endpoint_common::{
-    sender::{DownstreamSender, ExternalClientPoolConfig},
+    sender::{DownstreamSender, ExternalClientPoolConfig, UpstreamSender},
     sink::KafkaSink,
 };
+use drogue_cloud_registry_events::stream::KafkaStreamConfig;
 use drogue_cloud_service_api::{
     kafka::KafkaClientConfig,
     webapp::{self as actix_web},
 };
 use drogue_cloud_service_common::{
     actix::http::{HttpBuilder, HttpConfig},
+    actix_auth::{authentication::AuthN, authorization::ApplicationAuthorizer},
     app::{Startup, StartupExt},
-    auth::openid::{Authenticator, AuthenticatorConfig},
+    auth::{
+        openid::{Authenticator, AuthenticatorConfig},
+        pat,
+    },
     client::ClientConfig,
     defaults, openid_auth,
 };
@@ -33,10 +41,19 @@ pub struct Config {
     #[serde(default = "defaults::check_kafka_topic_ready")]
     pub check_kafka_topic_ready: bool,
     pub kafka_downstream_config: KafkaClientConfig,
+    /// Send deltas of device twins as commands.
+    #[serde(default)]
+    pub command_kafka_sink: Option<KafkaClientConfig>,
     #[serde(default)]
     pub endpoint_pool: ExternalClientPoolConfig,
 
     pub registry: ClientConfig,
+    /// Consume the registry events, to delete the twins of deleted devices.
+    #[serde(default)]
+    pub kafka_source: Option<KafkaStreamConfig>,
+
+    #[serde(default)]
+    pub user_auth: Option<ClientConfig>,
 
     #[serde(default)]
     pub http: HttpConfig,
@@ -44,26 +61,65 @@ pub struct Config {
 
 #[macro_export]
 macro_rules! app {
-    ($cfg:expr, $data:expr, $auth: expr) => {{
+    ($cfg:expr, $data:expr, $auth: expr, $user_auth: expr, $reader: expr, $writer: expr) => {{
         use $crate::endpoints;
 
-        $cfg.app_data($data.clone()).service(
-            web::scope("/api/state/v1alpha1")
-                .wrap($auth)
-                .service(
-                    web::resource("/states/{application}/{device}")
-                        .route(web::get().to(endpoints::get)),
-                )
-                .service(web::resource("/sessions").route(web::put().to(endpoints::init)))
-                .service(
-                    web::resource("/sessions/{session}").route(web::post().to(endpoints::ping)),
-                )
-                .service(
-                    web::resource("/sessions/{session}/states/{application}/{device}")
-                        .route(web::put().to(endpoints::create))
-                        .route(web::delete().to(endpoints::delete)),
-                ),
-        )
+        $cfg.app_data($data.clone())
+            // for users, authorized like the registry
+            .service(
+                web::scope("/api/state/v1alpha1/apps/{application}")
+                    .wrap($reader)
+                    .wrap($user_auth)
+                    .service(web::resource("/states").route(web::get().to(endpoints::list)))
+                    .service(
+                        web::resource("/states/{device}/history")
+                            .route(web::get().to(endpoints::history)),
+                    )
+                    .service(web::resource("/values").route(web::get().to(endpoints::last_values)))
+                    .service(
+                        web::resource("/twins/{device}").route(web::get().to(endpoints::get_twin)),
+                    )
+                    .service(
+                        web::resource("/twins/{device}/desired")
+                            .wrap($writer)
+                            .route(web::put().to(endpoints::replace_desired))
+                            .route(web::patch().to(endpoints::merge_desired)),
+                    ),
+            )
+            // for endpoints
+            .service(
+                web::scope("/api/state/v1alpha1")
+                    .wrap($auth)
+                    .service(
+                        web::resource("/states/{application}/{device}")
+                            .route(web::get().to(endpoints::get)),
+                    )
+                    .service(
+                        web::resource("/states/{application}/{device}/history")
+                            .route(web::get().to(endpoints::history)),
+                    )
+                    .service(
+                        web::resource("/twins/{application}/{device}/reported")
+                            .route(web::patch().to(endpoints::update_reported)),
+                    )
+                    .service(
+                        web::resource("/values/{application}")
+                            .route(web::get().to(endpoints::last_values)),
+                    )
+                    .service(
+                        web::resource("/values/{application}/{device}")
+                            .route(web::post().to(endpoints::record_value)),
+                    )
+                    .service(web::resource("/sessions").route(web::put().to(endpoints::init)))
+                    .service(
+                        web::resource("/sessions/{session}").route(web::post().to(endpoints::ping)),
+                    )
+                    .service(
+                        web::resource("/sessions/{session}/states/{application}/{device}")
+                            .route(web::put().to(endpoints::create))
+                            .route(web::delete().to(endpoints::delete)),
+                    ),
+            )
     }};
 }
 
@@ -72,6 +128,12 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     let authenticator = config.oauth.into_client().await?;
     log::info!("Authenticator: {authenticator:?}");
+    let user_auth = if let Some(user_auth) = config.user_auth {
+        Some(user_auth.into_client::<user::v1::Client>().await?)
+    } else {
+        None
+    };
+    let user_authenticator = authenticator.clone();
     let authenticator = authenticator.map(web::Data::new);
 
     // set up registry client
@@ -84,17 +146,27 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
             config.kafka_downstream_config,
             config.check_kafka_topic_ready,
         )?,
-        config.instance,
-        config.endpoint_pool,
+        config.instance.clone(),
+        config.endpoint_pool.clone(),
     )?;
 
     // service
 
-    let service =
+    let mut service =
         service::postgres::PostgresDeviceStateService::new(config.service, sender, registry)?;
+    if let Some(command_kafka_sink) = config.command_kafka_sink {
+        service = service.with_commands(UpstreamSender::new(
+            config.instance,
+            KafkaSink::from_config(command_kafka_sink, config.check_kafka_topic_ready)?,
+            config.endpoint_pool,
+        )?);
+    }
     startup.check(service.clone());
 
     let pruner = service::postgres::run_pruner(service.clone()).boxed();
+    let cleanup = config
+        .kafka_source
+        .map(|kafka_source| service::postgres::run_cleanup(service.clone(), kafka_source).boxed());
 
     let service: Arc<dyn DeviceStateService> = Arc::new(service);
     let service: web::Data<dyn DeviceStateService> = web::Data::from(service);
@@ -108,7 +180,13 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
             req
                 .app_data::<web::Data<Authenticator>>().as_ref().map(|s|s.get_ref())
         });
-        let mut app = app!(cfg, service, auth);
+        let user_authn = AuthN::from((
+            user_authenticator.clone(),
+            user_auth.clone().map(pat::Authenticator::new),
+        ));
+        let reader = ApplicationAuthorizer::wrapping(user_auth.clone(), Permission::Read);
+        let writer = ApplicationAuthorizer::wrapping(user_auth.clone(), Permission::Write);
+        let mut app = app!(cfg, service, auth, user_authn, reader, writer);
 
         if let Some(auth) = &authenticator {
             app = app.app_data(auth.clone())
@@ -122,6 +200,9 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     startup.spawn(main);
     startup.spawn(pruner);
+    if let Some(cleanup) = cleanup {
+        startup.spawn(cleanup);
+    }
 
     // exiting
 
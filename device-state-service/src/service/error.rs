@@ -8,6 +8,10 @@ pub enum ServiceError {
     NotInitialized,
     #[error("application not found")]
     ApplicationNotFound,
+    #[error("device not found")]
+    DeviceNotFound,
+    #[error("version mismatch")]
+    VersionMismatch,
     #[error("internal error: {0}")]
     Internal(String),
     #[error("connection pool error: {0}")]
@@ -33,6 +37,14 @@ impl ResponseError for ServiceError {
                 error: "ApplicationNotFound".into(),
                 message: self.to_string(),
             }),
+            Self::DeviceNotFound => HttpResponse::NotFound().json(ErrorInformation {
+                error: "DeviceNotFound".into(),
+                message: self.to_string(),
+            }),
+            Self::VersionMismatch => HttpResponse::Conflict().json(ErrorInformation {
+                error: "VersionMismatch".into(),
+                message: self.to_string(),
+            }),
             Self::Internal(_) => HttpResponse::InternalServerError().json(ErrorInformation {
                 error: "Internal".into(),
                 message: self.to_string(),
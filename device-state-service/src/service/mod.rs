@@ -6,8 +6,9 @@ pub use error::*;
 
 use async_trait::async_trait;
 use drogue_client::{error::ClientError, registry};
-use drogue_cloud_service_api::services::device_state::*;
+use drogue_cloud_service_api::services::{device_state::*, twin::*};
 use serde::Deserialize;
+use std::collections::HashMap;
 
 #[async_trait]
 pub trait DeviceStateService: Send + Sync {
@@ -57,32 +58,147 @@ pub trait DeviceStateService: Send + Sync {
         application: String,
         device: String,
     ) -> Result<Option<DeviceStateResponse>, ServiceError>;
+
+    /// List the connected devices of an application.
+    async fn list(
+        &self,
+        application: String,
+        request: ConnectedDevicesRequest,
+    ) -> Result<ConnectedDevicesResponse, ServiceError>;
+
+    /// Get the connection history of a device.
+    async fn history(
+        &self,
+        application: String,
+        device: String,
+        request: ConnectionHistoryRequest,
+    ) -> Result<ConnectionHistoryResponse, ServiceError>;
+
+    /// Get the twin of a device.
+    ///
+    /// If the twin doesn't exist yet, an empty twin will be returned. Twins only exist for devices
+    /// of the registry, and are reset when a device is re-created.
+    async fn get_twin(
+        &self,
+        application: String,
+        device: String,
+    ) -> Result<DeviceTwin, ServiceError>;
+
+    /// Update the desired properties of a device twin.
+    ///
+    /// If `merge` is `true`, the properties will be applied as a JSON merge patch, otherwise they
+    /// replace the current properties. If the update results in a delta, it will be sent to the
+    /// device.
+    async fn update_desired(
+        &self,
+        application: String,
+        device: String,
+        update: TwinUpdate,
+        merge: bool,
+    ) -> Result<DeviceTwin, ServiceError>;
+
+    /// Merge the properties reported by a device into its twin.
+    async fn update_reported(
+        &self,
+        application: String,
+        device: String,
+        update: TwinUpdate,
+    ) -> Result<DeviceTwin, ServiceError>;
+
+    /// Record the last event of a device on a channel.
+    ///
+    /// If a newer event was already recorded, this becomes a no-op.
+    async fn record_value(
+        &self,
+        application: String,
+        device: String,
+        request: RecordValueRequest,
+    ) -> Result<(), ServiceError>;
+
+    /// Get the last events of the devices of an application.
+    async fn last_values(
+        &self,
+        application: String,
+        request: LastValuesRequest,
+    ) -> Result<LastValuesResponse, ServiceError>;
 }
 
 #[async_trait]
-pub trait ApplicationLookup: Send + Sync {
+pub trait RegistryLookup: Send + Sync {
     async fn lookup(
         &self,
         application: &str,
     ) -> Result<Option<registry::v1::Application>, ClientError>;
+
+    async fn lookup_device(
+        &self,
+        application: &str,
+        device: &str,
+    ) -> Result<Option<registry::v1::Device>, ClientError>;
 }
 
 #[async_trait]
-impl ApplicationLookup for registry::v1::Client {
+impl RegistryLookup for registry::v1::Client {
     async fn lookup(
         &self,
         application: &str,
     ) -> Result<Option<registry::v1::Application>, ClientError> {
         self.get_app(application).await
     }
+
+    async fn lookup_device(
+        &self,
+        application: &str,
+        device: &str,
+    ) -> Result<Option<registry::v1::Device>, ClientError> {
+        self.get_device(application, device).await
+    }
 }
 
+/// A map of applications, without any devices.
 #[async_trait]
-impl ApplicationLookup for std::collections::HashMap<String, registry::v1::Application> {
+impl RegistryLookup for HashMap<String, registry::v1::Application> {
     async fn lookup(
         &self,
         application: &str,
     ) -> Result<Option<registry::v1::Application>, ClientError> {
         Ok(self.get(application).cloned())
     }
+
+    async fn lookup_device(
+        &self,
+        _application: &str,
+        _device: &str,
+    ) -> Result<Option<registry::v1::Device>, ClientError> {
+        Ok(None)
+    }
+}
+
+/// A static registry, e.g. for testing.
+#[derive(Clone, Debug, Default)]
+pub struct StaticRegistry {
+    pub applications: HashMap<String, registry::v1::Application>,
+    pub devices: Vec<registry::v1::Device>,
+}
+
+#[async_trait]
+impl RegistryLookup for StaticRegistry {
+    async fn lookup(
+        &self,
+        application: &str,
+    ) -> Result<Option<registry::v1::Application>, ClientError> {
+        self.applications.lookup(application).await
+    }
+
+    async fn lookup_device(
+        &self,
+        application: &str,
+        device: &str,
+    ) -> Result<Option<registry::v1::Device>, ClientError> {
+        Ok(self
+            .devices
+            .iter()
+            .find(|d| d.metadata.application == application && d.metadata.name == device)
+            .cloned())
+    }
 }
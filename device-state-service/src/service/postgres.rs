@@ -1,14 +1,19 @@
 use super::*;
 
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use deadpool_postgres::{Pool, Transaction};
-use drogue_client::registry::v1::Application;
+use drogue_client::{registry::v1::Application, Translator};
 use drogue_cloud_database_common::{postgres, Client, DatabaseService};
 use drogue_cloud_endpoint_common::sender::{
     DownstreamSender, Publish, PublishError, PublishId, PublishOptions, PublishOutcome, Publisher,
+    ToPublishId, UpstreamSender,
 };
-use drogue_cloud_service_api::health::HealthChecked;
+use drogue_cloud_registry_events::{
+    stream::{EventHandler, KafkaEventStream, KafkaStreamConfig},
+    Event,
+};
+use drogue_cloud_service_api::{health::HealthChecked, services::twin};
 use futures::StreamExt;
 use serde_json::Value;
 use std::{sync::Arc, time::Duration};
@@ -23,6 +28,9 @@ use uuid::Uuid;
 pub struct PostgresServiceConfiguration {
     #[serde(with = "humantime_serde", default = "default_session_timeout")]
     pub session_timeout: Duration,
+    /// How long to keep the connection history, after a connection ended.
+    #[serde(with = "humantime_serde", default = "default_history_retention")]
+    pub history_retention: Duration,
     pub pg: postgres::Config,
 }
 
@@ -30,31 +38,67 @@ const fn default_session_timeout() -> Duration {
     Duration::from_secs(10)
 }
 
+const fn default_history_retention() -> Duration {
+    Duration::from_secs(7 * 24 * 60 * 60)
+}
+
+/// Number of entries returned, if the request doesn't provide a limit.
+const DEFAULT_LIMIT: u32 = 100;
+/// Maximum number of entries returned.
+const MAX_LIMIT: u32 = 1000;
+
 #[derive(Clone)]
 pub struct PostgresDeviceStateService {
     pool: Pool,
     sender: DownstreamSender,
-    registry: Arc<dyn ApplicationLookup>,
+    registry: Arc<dyn RegistryLookup>,
+    commands: Option<UpstreamSender>,
     timeout: chrono::Duration,
+    retention: chrono::Duration,
+}
+
+/// One side of a device twin.
+#[derive(Clone, Copy, Debug)]
+enum TwinSide {
+    Desired,
+    Reported,
+}
+
+impl TwinSide {
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Desired => "DESIRED",
+            Self::Reported => "REPORTED",
+        }
+    }
 }
 
 impl PostgresDeviceStateService {
     pub fn new(
         config: PostgresServiceConfiguration,
         sender: DownstreamSender,
-        registry: impl ApplicationLookup + 'static,
+        registry: impl RegistryLookup + 'static,
     ) -> anyhow::Result<Self> {
         let pool = config.pg.create_pool()?;
 
         let timeout = chrono::Duration::from_std(config.session_timeout)?;
+        let retention = chrono::Duration::from_std(config.history_retention)?;
 
         Ok(Self {
             pool,
             sender,
             registry: Arc::new(registry),
+            commands: None,
             timeout,
+            retention,
         })
     }
+
+    /// Send deltas of device twins as commands, using the provided sender.
+    pub fn with_commands(mut self, commands: UpstreamSender) -> Self {
+        self.commands = Some(commands);
+        self
+    }
 }
 
 impl DatabaseService for PostgresDeviceStateService {
@@ -151,16 +195,39 @@ RETURNING
                 let lost: bool = row.try_get("LOST")?;
                 Ok(match lost {
                     false => {
-                        self.send_connection_event(
-                            &app,
-                            PublishId {
-                                name: device,
-                                uid: Some(state.device_uid),
-                            },
-                            true,
+                        t.execute(
+                            r#"
+INSERT INTO
+    state_history
+(
+    APPLICATION,
+    DEVICE,
+    SESSION,
+    ENDPOINT,
+    CONNECTED
+) VALUES (
+    $1,
+    $2,
+    $3::text::uuid,
+    $4,
+    $5
+)"#,
+                            &[&application, &device, &session, &state.endpoint, &now],
                         )
                         .await?;
+                        let device = PublishId {
+                            name: device,
+                            uid: Some(state.device_uid.clone()),
+                        };
+                        self.send_connection_event(&app, device.clone(), true)
+                            .await?;
+                        let twin =
+                            Self::read_twin(&t, &application, &device.name, &state.device_uid)
+                                .await?;
                         t.commit().await?;
+                        if let Err(err) = self.send_delta(&app, &device.name, &twin).await {
+                            log::warn!("Failed to send twin delta: {err}");
+                        }
                         CreateResponse::Created
                     }
                     true => {
@@ -198,7 +265,7 @@ WHERE
     AND
         TOKEN = $4
 RETURNING
-    APPLICATION, DEVICE, DATA
+    APPLICATION, DEVICE, CREATED, LOST, DATA
 "#,
                 &[&session, &application, &device, &token],
             )
@@ -207,6 +274,11 @@ RETURNING
         log::debug!("Delete result: {row:?}");
 
         if let Some(row) = row {
+            let cause = match opts.skip_lwt {
+                true => DisconnectCause::Disconnected,
+                false => DisconnectCause::LastWill,
+            };
+            Self::close_history(&t, &row, cause).await?;
             self.send_disconnect_from_delete(row, opts).await?;
         }
 
@@ -327,9 +399,458 @@ WHERE
             }
         }
     }
+
+    async fn list(
+        &self,
+        application: String,
+        request: ConnectedDevicesRequest,
+    ) -> Result<ConnectedDevicesResponse, ServiceError> {
+        let c = self.pool.get().await?;
+
+        // states marked as lost are about to be taken over, and are not reported by get() either
+        const FILTER: &str = r#"
+WHERE
+        APPLICATION = $1
+    AND
+        LOST = false
+    AND
+        ( $2::varchar IS NULL OR DATA ->> 'endpointType' = $2 )
+    AND
+        ( $3::varchar IS NULL OR DATA ->> 'endpoint' = $3 )
+"#;
+
+        let stmt = c
+            .prepare_typed(
+                &format!("SELECT COUNT(*) AS TOTAL FROM states {FILTER}"),
+                &[Type::VARCHAR, Type::VARCHAR, Type::VARCHAR],
+            )
+            .await?;
+
+        let total = c
+            .query_one(
+                &stmt,
+                &[&application, &request.endpoint_type, &request.endpoint],
+            )
+            .await?
+            .try_get::<_, i64>("TOTAL")? as u64;
+
+        let limit = request.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as i64;
+        let offset = request.offset.unwrap_or_default() as i64;
+
+        let stmt = c
+            .prepare_typed(
+                &format!(
+                    r#"
+SELECT
+    DEVICE,
+    CREATED,
+    DATA
+FROM
+    states
+{FILTER}
+ORDER BY
+    DEVICE ASC
+LIMIT
+    $4
+OFFSET
+    $5
+"#
+                ),
+                &[
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::INT8,
+                    Type::INT8,
+                ],
+            )
+            .await?;
+
+        let rows = c
+            .query(
+                &stmt,
+                &[
+                    &application,
+                    &request.endpoint_type,
+                    &request.endpoint,
+                    &limit,
+                    &offset,
+                ],
+            )
+            .await?;
+
+        let mut items = Vec::with_capacity(rows.len());
+        for row in rows {
+            let device: String = row.try_get("DEVICE")?;
+            match row.try_get::<_, Option<Json<DeviceState>>>("DATA") {
+                Ok(Some(Json(state))) => items.push(ConnectedDevice {
+                    device,
+                    created: row.try_get("CREATED")?,
+                    state,
+                }),
+                Ok(None) => {}
+                Err(err) => {
+                    log::warn!("Failed to decode data of {application}/{device}: {err}");
+                }
+            }
+        }
+
+        Ok(ConnectedDevicesResponse { total, items })
+    }
+
+    async fn get_twin(
+        &self,
+        application: String,
+        device: String,
+    ) -> Result<DeviceTwin, ServiceError> {
+        let uid = self.device_uid(&application, &device).await?;
+        let c = self.pool.get().await?;
+        Self::read_twin(&c, &application, &device, &uid).await
+    }
+
+    async fn update_desired(
+        &self,
+        application: String,
+        device: String,
+        update: TwinUpdate,
+        merge: bool,
+    ) -> Result<DeviceTwin, ServiceError> {
+        let app = match self.registry.lookup(&application).await? {
+            Some(app) => app,
+            None => return Err(ServiceError::ApplicationNotFound),
+        };
+        let uid = self.device_uid(&application, &device).await?;
+
+        let twin = self
+            .update_twin(
+                &application,
+                &device,
+                &uid,
+                TwinSide::Desired,
+                update,
+                merge,
+            )
+            .await?;
+
+        if let Err(err) = self.send_delta(&app, &device, &twin).await {
+            log::warn!("Failed to send twin delta: {err}");
+        }
+
+        Ok(twin)
+    }
+
+    async fn update_reported(
+        &self,
+        application: String,
+        device: String,
+        update: TwinUpdate,
+    ) -> Result<DeviceTwin, ServiceError> {
+        let uid = self.device_uid(&application, &device).await?;
+        self.update_twin(
+            &application,
+            &device,
+            &uid,
+            TwinSide::Reported,
+            update,
+            true,
+        )
+        .await
+    }
+
+    async fn history(
+        &self,
+        application: String,
+        device: String,
+        request: ConnectionHistoryRequest,
+    ) -> Result<ConnectionHistoryResponse, ServiceError> {
+        let c = self.pool.get().await?;
+
+        // entries which are still open, or ended after "since"
+        const FILTER: &str = r#"
+WHERE
+        APPLICATION = $1
+    AND
+        DEVICE = $2
+    AND
+        ( $3::timestamptz IS NULL OR DISCONNECTED IS NULL OR DISCONNECTED >= $3 )
+"#;
+
+        let stmt = c
+            .prepare_typed(
+                &format!(
+                    r#"
+SELECT
+    COUNT(*) AS CONNECTS,
+    MAX(CONNECTED) AS LAST_CONNECTED,
+    MAX(DISCONNECTED) AS LAST_DISCONNECTED,
+    BOOL_OR(DISCONNECTED IS NULL) AS OPEN
+FROM
+    state_history
+{FILTER}
+"#
+                ),
+                &[Type::VARCHAR, Type::VARCHAR, Type::TIMESTAMPTZ],
+            )
+            .await?;
+
+        let summary = c
+            .query_one(&stmt, &[&application, &device, &request.since])
+            .await?;
+
+        let limit = request.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as i64;
+
+        let stmt = c
+            .prepare_typed(
+                &format!(
+                    r#"
+SELECT
+    SESSION,
+    ENDPOINT,
+    CONNECTED,
+    DISCONNECTED,
+    CAUSE
+FROM
+    state_history
+{FILTER}
+ORDER BY
+    CONNECTED DESC
+LIMIT
+    $4
+"#
+                ),
+                &[Type::VARCHAR, Type::VARCHAR, Type::TIMESTAMPTZ, Type::INT8],
+            )
+            .await?;
+
+        let entries = c
+            .query(&stmt, &[&application, &device, &request.since, &limit])
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(ConnectionHistoryEntry {
+                    session: row.try_get::<_, Uuid>("SESSION")?.to_string(),
+                    endpoint: row.try_get("ENDPOINT")?,
+                    connected: row.try_get("CONNECTED")?,
+                    disconnected: row.try_get("DISCONNECTED")?,
+                    cause: row
+                        .try_get::<_, Option<String>>("CAUSE")?
+                        .as_deref()
+                        .and_then(cause_from_str),
+                })
+            })
+            .collect::<Result<_, tokio_postgres::Error>>()?;
+
+        Ok(ConnectionHistoryResponse {
+            connected: summary
+                .try_get::<_, Option<bool>>("OPEN")?
+                .unwrap_or_default(),
+            connects: summary.try_get::<_, i64>("CONNECTS")? as u64,
+            last_connected: summary.try_get("LAST_CONNECTED")?,
+            last_disconnected: summary.try_get("LAST_DISCONNECTED")?,
+            entries,
+        })
+    }
+
+    async fn record_value(
+        &self,
+        application: String,
+        device: String,
+        request: RecordValueRequest,
+    ) -> Result<(), ServiceError> {
+        let c = self.pool.get().await?;
+
+        // events may be recorded out of order, keep the newest one
+        let stmt = c
+            .prepare_typed(
+                r#"
+INSERT INTO last_values (
+    APPLICATION,
+    DEVICE,
+    CHANNEL,
+    TIME,
+    EVENT
+) VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5
+)
+ON CONFLICT (APPLICATION, DEVICE, CHANNEL) DO UPDATE SET
+    TIME = EXCLUDED.TIME,
+    EVENT = EXCLUDED.EVENT
+WHERE
+    last_values.TIME <= EXCLUDED.TIME
+"#,
+                &[
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::TIMESTAMPTZ,
+                    Type::JSONB,
+                ],
+            )
+            .await?;
+
+        c.execute(
+            &stmt,
+            &[
+                &application,
+                &device,
+                &request.channel,
+                &request.time,
+                &Json(&request.event),
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn last_values(
+        &self,
+        application: String,
+        request: LastValuesRequest,
+    ) -> Result<LastValuesResponse, ServiceError> {
+        let c = self.pool.get().await?;
+
+        const FILTER: &str = r#"
+WHERE
+        APPLICATION = $1
+    AND
+        ( $2::varchar IS NULL OR DEVICE = $2 )
+    AND
+        ( $3::varchar IS NULL OR CHANNEL = $3 )
+"#;
+
+        let stmt = c
+            .prepare_typed(
+                &format!("SELECT COUNT(*) AS TOTAL FROM last_values {FILTER}"),
+                &[Type::VARCHAR, Type::VARCHAR, Type::VARCHAR],
+            )
+            .await?;
+
+        let total = c
+            .query_one(&stmt, &[&application, &request.device, &request.channel])
+            .await?
+            .try_get::<_, i64>("TOTAL")? as u64;
+
+        let limit = request.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as i64;
+        let offset = request.offset.unwrap_or_default() as i64;
+
+        let stmt = c
+            .prepare_typed(
+                &format!(
+                    r#"
+SELECT
+    DEVICE,
+    CHANNEL,
+    TIME,
+    EVENT
+FROM
+    last_values
+{FILTER}
+ORDER BY
+    DEVICE ASC,
+    CHANNEL ASC
+LIMIT
+    $4
+OFFSET
+    $5
+"#
+                ),
+                &[
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::VARCHAR,
+                    Type::INT8,
+                    Type::INT8,
+                ],
+            )
+            .await?;
+
+        let items = c
+            .query(
+                &stmt,
+                &[
+                    &application,
+                    &request.device,
+                    &request.channel,
+                    &limit,
+                    &offset,
+                ],
+            )
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(LastValue {
+                    device: row.try_get("DEVICE")?,
+                    channel: row.try_get("CHANNEL")?,
+                    time: row.try_get("TIME")?,
+                    event: row.try_get::<_, Json<Value>>("EVENT")?.0,
+                })
+            })
+            .collect::<Result<_, tokio_postgres::Error>>()?;
+
+        Ok(LastValuesResponse { total, items })
+    }
 }
 
 impl PostgresDeviceStateService {
+    /// Delete the twins of a device, unless they belong to the device currently in the registry.
+    pub async fn cleanup_device(
+        &self,
+        application: &str,
+        device: &str,
+    ) -> Result<(), ServiceError> {
+        let uid = self
+            .registry
+            .lookup_device(application, device)
+            .await?
+            .map(|device| device.metadata.uid);
+
+        let c = self.pool.get().await?;
+        let deleted = c
+            .execute(
+                r#"
+DELETE FROM
+    twins
+WHERE
+        APPLICATION = $1
+    AND
+        DEVICE = $2
+    AND
+        ( $3::varchar IS NULL OR DEVICE_UID <> $3 )
+"#,
+                &[&application, &device, &uid],
+            )
+            .await?;
+
+        if deleted > 0 {
+            log::debug!("Deleted twin of {application}/{device}");
+        }
+
+        Ok(())
+    }
+
+    /// Delete all twins of an application, if it no longer exists in the registry.
+    pub async fn cleanup_application(&self, application: &str) -> Result<(), ServiceError> {
+        if self.registry.lookup(application).await?.is_some() {
+            return Ok(());
+        }
+
+        let c = self.pool.get().await?;
+        let deleted = c
+            .execute("DELETE FROM twins WHERE APPLICATION = $1", &[&application])
+            .await?;
+
+        if deleted > 0 {
+            log::debug!("Deleted {deleted} twins of {application}");
+        }
+
+        Ok(())
+    }
+
     pub async fn prune(&self) -> Result<(), ServiceError> {
         log::info!("Start pruning sessions");
 
@@ -367,6 +888,23 @@ FOR UPDATE SKIP LOCKED
             self.prune_session(t, next.try_get("ID")?).await?;
         }
 
+        let pruned = c
+            .execute(
+                r#"
+DELETE FROM
+    state_history
+WHERE
+    DISCONNECTED <= $2 - $1::text::interval
+"#,
+                &[
+                    &format!("{}ms", self.retention.num_milliseconds()),
+                    &Utc::now(),
+                ],
+            )
+            .await?;
+
+        log::debug!("Pruned {pruned} history entries");
+
         Ok(())
     }
 
@@ -381,7 +919,7 @@ DELETE FROM
 WHERE
     SESSION = $1
 RETURNING
-    APPLICATION, DEVICE, DATA
+    APPLICATION, DEVICE, CREATED, LOST, DATA
 "#,
                 &[&id],
             )
@@ -390,6 +928,7 @@ RETURNING
         let mut deleted = Box::pin(deleted);
 
         while let Some(row) = deleted.next().await.transpose()? {
+            Self::close_history(&t, &row, DisconnectCause::Expired).await?;
             self.send_disconnect_from_delete(row, DeleteOptions { skip_lwt: false })
                 .await?;
         }
@@ -410,6 +949,232 @@ WHERE
         Ok(())
     }
 
+    /// Read the twin of a device, returning an empty twin if it doesn't exist.
+    async fn read_twin<C: Client>(
+        c: &C,
+        application: &str,
+        device: &str,
+        uid: &str,
+    ) -> Result<DeviceTwin, ServiceError> {
+        let stmt = c
+            .prepare_typed(SELECT_TWIN, &[Type::VARCHAR, Type::VARCHAR, Type::VARCHAR])
+            .await?;
+
+        Ok(
+            match c.query_opt(&stmt, &[&application, &device, &uid]).await? {
+                Some(row) => {
+                    let (desired, reported) = twin_from_row(&row)?;
+                    DeviceTwin::new(desired, reported)
+                }
+                None => DeviceTwin::default(),
+            },
+        )
+    }
+
+    /// Look up the UID of a device in the registry.
+    async fn device_uid(&self, application: &str, device: &str) -> Result<String, ServiceError> {
+        match self.registry.lookup_device(application, device).await? {
+            Some(device) => Ok(device.metadata.uid),
+            None => Err(ServiceError::DeviceNotFound),
+        }
+    }
+
+    /// Update one side of a twin, creating the twin if necessary.
+    ///
+    /// A twin left over from a previous device with the same name gets reset first.
+    async fn update_twin(
+        &self,
+        application: &str,
+        device: &str,
+        uid: &str,
+        side: TwinSide,
+        update: TwinUpdate,
+        merge: bool,
+    ) -> Result<DeviceTwin, ServiceError> {
+        let mut c = self.pool.get().await?;
+        let t = c.transaction().await?;
+
+        // ensure we have a row we can lock
+        t.execute(
+            r#"
+INSERT INTO
+    twins
+(
+    APPLICATION,
+    DEVICE,
+    DEVICE_UID
+) VALUES (
+    $1,
+    $2,
+    $3
+)
+ON CONFLICT (APPLICATION, DEVICE)
+    DO UPDATE
+        SET
+            DEVICE_UID = $3,
+            DESIRED = '{}',
+            DESIRED_VERSION = 0,
+            DESIRED_UPDATED = NULL,
+            REPORTED = '{}',
+            REPORTED_VERSION = 0,
+            REPORTED_UPDATED = NULL
+        WHERE
+            twins.DEVICE_UID <> $3
+"#,
+            &[&application, &device, &uid],
+        )
+        .await?;
+
+        let row = t
+            .query_one(
+                &format!("{SELECT_TWIN}\nFOR UPDATE"),
+                &[&application, &device, &uid],
+            )
+            .await?;
+        let (mut desired, mut reported) = twin_from_row(&row)?;
+
+        let properties = match side {
+            TwinSide::Desired => &mut desired,
+            TwinSide::Reported => &mut reported,
+        };
+
+        if let Some(version) = update.version {
+            if version != properties.version {
+                return Err(ServiceError::VersionMismatch);
+            }
+        }
+
+        match merge {
+            true => twin::merge(&mut properties.properties, &update.properties),
+            false => properties.properties = update.properties,
+        }
+        properties.version += 1;
+        properties.updated = Some(Utc::now());
+
+        let column = side.column();
+        t.execute(
+            &format!(
+                r#"
+UPDATE
+    twins
+SET
+    {column} = $3,
+    {column}_VERSION = $4,
+    {column}_UPDATED = $5
+WHERE
+        APPLICATION = $1
+    AND
+        DEVICE = $2
+"#
+            ),
+            &[
+                &application,
+                &device,
+                &Json(&properties.properties),
+                &(properties.version as i64),
+                &properties.updated,
+            ],
+        )
+        .await?;
+
+        t.commit().await?;
+
+        Ok(DeviceTwin::new(desired, reported))
+    }
+
+    /// Send the delta of a twin to the device, if there is one.
+    ///
+    /// This is a no-op if the application has no twin section, or no command sender is
+    /// configured.
+    async fn send_delta(
+        &self,
+        application: &Application,
+        device: &str,
+        twin: &DeviceTwin,
+    ) -> Result<(), ServiceError> {
+        let commands = match &self.commands {
+            Some(commands) => commands,
+            None => return Ok(()),
+        };
+        let spec = match application.section::<TwinSpec>() {
+            Some(Ok(spec)) => spec,
+            _ => return Ok(()),
+        };
+
+        if twin.delta.is_empty() {
+            return Ok(());
+        }
+
+        let outcome = commands
+            .publish(
+                Publish {
+                    application,
+                    device: device.to_id(),
+                    sender: device.to_id(),
+                    channel: spec.channel,
+                    options: PublishOptions {
+                        content_type: Some("application/json".to_string()),
+                        ..Default::default()
+                    },
+                },
+                serde_json::to_vec(&TwinDelta {
+                    version: twin.desired.version,
+                    delta: twin.delta.clone(),
+                })?,
+            )
+            .await;
+
+        handle_outcome(outcome)
+    }
+
+    /// Close the history entry of a deleted state.
+    ///
+    /// The provided row must contain the following fields: APPLICATION, DEVICE, CREATED, LOST. If
+    /// the state was marked as lost, the cause will always be [`DisconnectCause::Takeover`].
+    async fn close_history(
+        t: &Transaction<'_>,
+        row: &Row,
+        cause: DisconnectCause,
+    ) -> Result<(), ServiceError> {
+        let application: String = row.try_get("APPLICATION")?;
+        let device: String = row.try_get("DEVICE")?;
+        let created: DateTime<Utc> = row.try_get("CREATED")?;
+        let lost: bool = row.try_get("LOST")?;
+
+        let cause = match lost {
+            true => DisconnectCause::Takeover,
+            false => cause,
+        };
+
+        t.execute(
+            r#"
+UPDATE
+    state_history
+SET
+    DISCONNECTED = $4,
+    CAUSE = $5
+WHERE
+        APPLICATION = $1
+    AND
+        DEVICE = $2
+    AND
+        CONNECTED = $3
+    AND
+        DISCONNECTED IS NULL
+"#,
+            &[
+                &application,
+                &device,
+                &created,
+                &Utc::now(),
+                &cause_to_str(cause),
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
     /// Send a disconnected event, from a delete operation.
     ///
     /// The provided row must contain the following fields: APPLICATION, DEVICE, DATA.
@@ -520,22 +1285,104 @@ WHERE
     {
         let outcome = self.sender.publish(publish, body).await;
 
-        log::debug!("Publish outcome: {outcome:?}");
+        handle_outcome(outcome)
+    }
+}
 
-        match outcome {
-            Err(PublishError::Spec(err)) => {
-                log::debug!("Failed to publish event due to misconfigured spec section: {err}");
-                Ok(())
-            }
-            Err(err) => Err(ServiceError::Publish(err)),
-            Ok(PublishOutcome::Accepted) => Ok(()),
-            Ok(PublishOutcome::Rejected | PublishOutcome::QueueFull) => Err(
-                ServiceError::Internal(format!("Unable to send event: {outcome:?}")),
-            ),
+fn handle_outcome(outcome: Result<PublishOutcome, PublishError>) -> Result<(), ServiceError> {
+    log::debug!("Publish outcome: {outcome:?}");
+
+    match outcome {
+        Err(PublishError::Spec(err)) => {
+            log::debug!("Failed to publish event due to misconfigured spec section: {err}");
+            Ok(())
+        }
+        Err(err) => Err(ServiceError::Publish(err)),
+        Ok(PublishOutcome::Accepted) => Ok(()),
+        Ok(PublishOutcome::Rejected | PublishOutcome::QueueFull) => Err(ServiceError::Internal(
+            format!("Unable to send event: {outcome:?}"),
+        )),
+    }
+}
+
+const SELECT_TWIN: &str = r#"
+SELECT
+    DESIRED,
+    DESIRED_VERSION,
+    DESIRED_UPDATED,
+    REPORTED,
+    REPORTED_VERSION,
+    REPORTED_UPDATED
+FROM
+    twins
+WHERE
+        APPLICATION = $1
+    AND
+        DEVICE = $2
+    AND
+        DEVICE_UID = $3"#;
+
+fn twin_from_row(row: &Row) -> Result<(TwinProperties, TwinProperties), ServiceError> {
+    let side = |side: TwinSide| -> Result<TwinProperties, ServiceError> {
+        let column = side.column();
+        let properties = match row.try_get::<_, Json<Value>>(column)?.0 {
+            Value::Object(properties) => properties,
+            _ => Default::default(),
+        };
+        Ok(TwinProperties {
+            properties,
+            version: row.try_get::<_, i64>(format!("{column}_VERSION").as_str())? as u64,
+            updated: row.try_get(format!("{column}_UPDATED").as_str())?,
+        })
+    };
+
+    Ok((side(TwinSide::Desired)?, side(TwinSide::Reported)?))
+}
+
+fn cause_to_str(cause: DisconnectCause) -> &'static str {
+    match cause {
+        DisconnectCause::Disconnected => "disconnected",
+        DisconnectCause::LastWill => "lastWill",
+        DisconnectCause::Takeover => "takeover",
+        DisconnectCause::Expired => "expired",
+    }
+}
+
+fn cause_from_str(cause: &str) -> Option<DisconnectCause> {
+    match cause {
+        "disconnected" => Some(DisconnectCause::Disconnected),
+        "lastWill" => Some(DisconnectCause::LastWill),
+        "takeover" => Some(DisconnectCause::Takeover),
+        "expired" => Some(DisconnectCause::Expired),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl EventHandler for PostgresDeviceStateService {
+    type Event = Event;
+    type Error = ServiceError;
+
+    async fn handle(&self, event: &Self::Event) -> Result<(), Self::Error> {
+        match event {
+            Event::Application { application, .. } => self.cleanup_application(application).await,
+            Event::Device {
+                application,
+                device,
+                ..
+            } => self.cleanup_device(application, device).await,
         }
     }
 }
 
+/// Delete the twins of deleted devices, consuming the registry events.
+pub async fn run_cleanup(
+    service: PostgresDeviceStateService,
+    config: KafkaStreamConfig,
+) -> anyhow::Result<()> {
+    KafkaEventStream::new(config)?.run(service).await
+}
+
 pub async fn run_pruner(service: PostgresDeviceStateService) -> anyhow::Result<()> {
     let period = service.timeout.to_std()?;
 
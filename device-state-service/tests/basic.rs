@@ -107,6 +107,7 @@ async fn test_create() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -140,6 +141,7 @@ async fn test_create_and_delete() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -200,6 +202,7 @@ async fn test_lost() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -212,6 +215,7 @@ async fn test_lost() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -231,6 +235,7 @@ async fn test_lost() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -265,6 +270,7 @@ async fn test_lost() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -298,6 +304,7 @@ async fn test_timeout_lost() -> anyhow::Result<()> {
                 state: DeviceState{
                     device_uid: "device_uid".into(),
                     endpoint: "pod1".into(),
+                    endpoint_type: None,
                     lwt: None,
                 }
             })
@@ -14,7 +14,7 @@ macro_rules! test {
         use drogue_cloud_service_api::webapp::*;
         use drogue_cloud_device_state_service::service::{self, DeviceStateService};
         use std::sync::Arc;
-        use drogue_cloud_endpoint_common::sender::DownstreamSender;
+        use drogue_cloud_endpoint_common::sender::{DownstreamSender, UpstreamSender};
 
         common::init();
 
@@ -23,6 +23,7 @@ macro_rules! test {
         let db = drogue_cloud_test_common::db(&cli, |pg| service::postgres::PostgresServiceConfiguration {
             pg,
             session_timeout: std::time::Duration::from_secs(10),
+            history_retention: std::time::Duration::from_secs(60 * 60),
         })?;
 
         let $pool = db.config.pg.create_pool()?;
@@ -32,7 +33,10 @@ macro_rules! test {
         let $sink = drogue_cloud_test_common::sink::MockSink::new();
         let sender = DownstreamSender::new($sink.clone(), "drogue".to_string(), Default::default()).unwrap();
 
-        let service = service::postgres::PostgresDeviceStateService::new(db.config.clone(), sender, $registry)?;
+        let commands = UpstreamSender::new("drogue", $sink.clone(), Default::default()).unwrap();
+
+        let service = service::postgres::PostgresDeviceStateService::new(db.config.clone(), sender, $registry)?
+            .with_commands(commands);
         let $service = service.clone();
 
         let s: Arc<dyn DeviceStateService> = Arc::new(service);
@@ -43,7 +47,12 @@ macro_rules! test {
                 let app = App::new();
                 app
                     .configure(move |cfg|{
-                        app!(cfg, s, auth);
+                        let user_auth = drogue_cloud_service_common::mock_auth!();
+                        use drogue_cloud_service_common::actix_auth::authorization::ApplicationAuthorizer;
+                        use drogue_client::user::v1::authz::Permission;
+                        let reader = ApplicationAuthorizer::wrapping(None, Permission::Read);
+                        let writer = ApplicationAuthorizer::wrapping(None, Permission::Write);
+                        app!(cfg, s, auth, user_auth, reader, writer);
                     })
                     .wrap_fn(|req, srv|{
                         log::warn!("Running test-user middleware");
@@ -0,0 +1,176 @@
+mod common;
+
+use chrono::SecondsFormat;
+use drogue_client::registry;
+use drogue_cloud_device_state_service::app;
+use drogue_cloud_service_api::{
+    services::device_state::*,
+    webapp::test::{read_body_json, TestRequest},
+};
+use drogue_cloud_test_common::call::{call_http, user};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serial_test::serial;
+use std::collections::HashMap;
+use tokio::time::sleep;
+
+lazy_static! {
+    static ref REGISTRY: HashMap<String, registry::v1::Application> = {
+        let mut m = HashMap::new();
+        m.insert(
+            "app1".into(),
+            registry::v1::Application {
+                ..Default::default()
+            },
+        );
+        m
+    };
+}
+
+fn create_request() -> CreateRequest {
+    CreateRequest {
+        token: "token".into(),
+        state: DeviceState {
+            device_uid: "device_uid".into(),
+            endpoint: "pod1".into(),
+            endpoint_type: None,
+            lwt: None,
+        },
+    }
+}
+
+fn delete_request(skip_lwt: bool) -> DeleteRequest {
+    DeleteRequest {
+        token: "token".into(),
+        options: DeleteOptions { skip_lwt },
+    }
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_history() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, _service, _pool, _sink) => {
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri("/api/state/v1alpha1/sessions")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: InitResponse = read_body_json(resp).await;
+        let session = response.session;
+
+        let state = format!("/api/state/v1alpha1/sessions/{}/states/app1/device1", session);
+        let history = "/api/state/v1alpha1/states/app1/device1/history";
+
+        // no history yet
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri(history)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert!(!response.connected);
+        assert_eq!(response.connects, 0);
+        assert!(response.entries.is_empty());
+
+        // connect and cleanly disconnect
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri(&state).set_json(create_request())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri(history)).await;
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert!(response.connected);
+        assert_eq!(response.entries.len(), 1);
+        assert_eq!(response.entries[0].endpoint, "pod1");
+        assert_eq!(response.entries[0].session, session);
+        assert_eq!(response.entries[0].disconnected, None);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::delete().uri(&state).set_json(delete_request(true))).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // connect and drop the connection
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri(&state).set_json(create_request())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = call_http(&app, &user("foo"), TestRequest::delete().uri(&state).set_json(delete_request(false))).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // connect and get taken over
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri(&state).set_json(create_request())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri(&state).set_json(create_request())).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = call_http(&app, &user("foo"), TestRequest::delete().uri(&state).set_json(delete_request(true))).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // check history, latest first
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri(history)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert!(!response.connected);
+        assert_eq!(response.connects, 3);
+        assert_eq!(response.last_connected, Some(response.entries[0].connected));
+        assert_eq!(response.last_disconnected, response.entries[0].disconnected);
+
+        let causes: Vec<_> = response.entries.iter().map(|entry| entry.cause).collect();
+        assert_eq!(causes, vec![
+            Some(DisconnectCause::Takeover),
+            Some(DisconnectCause::LastWill),
+            Some(DisconnectCause::Disconnected),
+        ]);
+
+        // limit
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri(&format!("{}?limit=1", history))).await;
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert_eq!(response.connects, 3);
+        assert_eq!(response.entries.len(), 1);
+        assert_eq!(response.entries[0].cause, Some(DisconnectCause::Takeover));
+
+        // since
+
+        let since = response.entries[0].disconnected.unwrap();
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri(&format!("{}?since={}", history, since.to_rfc3339_opts(SecondsFormat::Micros, true)))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert_eq!(response.connects, 1);
+        assert_eq!(response.entries.len(), 1);
+
+        // other devices are not affected
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/states/app1/device2/history")).await;
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert_eq!(response.connects, 0);
+
+        // users query the history of the devices of their applications
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/states/device1/history")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert_eq!(response.connects, 3);
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_history_expired() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, service, _pool, _sink) => {
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri("/api/state/v1alpha1/sessions")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: InitResponse = read_body_json(resp).await;
+        let session = response.session;
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri(&format!("/api/state/v1alpha1/sessions/{}/states/app1/device1", session)).set_json(create_request())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        drop(resp);
+
+        // now sleep, to time out
+        sleep(std::time::Duration::from_secs(10)).await;
+
+        service.prune().await?;
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/states/app1/device1/history")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectionHistoryResponse = read_body_json(resp).await;
+        assert!(!response.connected);
+        assert_eq!(response.entries.len(), 1);
+        assert_eq!(response.entries[0].cause, Some(DisconnectCause::Expired));
+    })
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use drogue_client::registry;
+use drogue_cloud_device_state_service::app;
+use drogue_cloud_service_api::{
+    services::device_state::*,
+    webapp::test::{read_body_json, TestRequest},
+};
+use drogue_cloud_test_common::call::{call_http, user};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serial_test::serial;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref REGISTRY: HashMap<String, registry::v1::Application> = {
+        let mut m = HashMap::new();
+        for app in ["app1", "app2"] {
+            m.insert(
+                app.into(),
+                registry::v1::Application {
+                    ..Default::default()
+                },
+            );
+        }
+        m
+    };
+}
+
+fn create_request(endpoint: &str, endpoint_type: &str) -> CreateRequest {
+    CreateRequest {
+        token: "token".into(),
+        state: DeviceState {
+            device_uid: "device_uid".into(),
+            endpoint: endpoint.into(),
+            endpoint_type: Some(endpoint_type.into()),
+            lwt: None,
+        },
+    }
+}
+
+fn names(response: &ConnectedDevicesResponse) -> Vec<&str> {
+    response
+        .items
+        .iter()
+        .map(|item| item.device.as_str())
+        .collect()
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_list() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, _service, _pool, _sink) => {
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri("/api/state/v1alpha1/sessions")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: InitResponse = read_body_json(resp).await;
+        let session = response.session;
+
+        for (application, device, endpoint, endpoint_type) in [
+            ("app1", "device1", "pod1", "mqtt"),
+            ("app1", "device2", "pod2", "mqtt"),
+            ("app1", "device3", "pod3", "coap"),
+            ("app2", "device1", "pod1", "mqtt"),
+        ] {
+            let resp = call_http(&app, &user("foo"), TestRequest::put()
+                .uri(&format!("/api/state/v1alpha1/sessions/{}/states/{}/{}", session, application, device))
+                .set_json(create_request(endpoint, endpoint_type))
+            ).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        // all devices of the application
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/states")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectedDevicesResponse = read_body_json(resp).await;
+        assert_eq!(response.total, 3);
+        assert_eq!(names(&response), vec!["device1", "device2", "device3"]);
+        assert_eq!(response.items[2].state.endpoint, "pod3");
+        assert_eq!(response.items[2].state.endpoint_type.as_deref(), Some("coap"));
+
+        // filters
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/states?endpointType=mqtt")).await;
+        let response: ConnectedDevicesResponse = read_body_json(resp).await;
+        assert_eq!(response.total, 2);
+        assert_eq!(names(&response), vec!["device1", "device2"]);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/states?endpointType=mqtt&endpoint=pod2")).await;
+        let response: ConnectedDevicesResponse = read_body_json(resp).await;
+        assert_eq!(response.total, 1);
+        assert_eq!(names(&response), vec!["device2"]);
+
+        // paging keeps the total
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/states?offset=1&limit=1")).await;
+        let response: ConnectedDevicesResponse = read_body_json(resp).await;
+        assert_eq!(response.total, 3);
+        assert_eq!(names(&response), vec!["device2"]);
+
+        // lost states are no longer connected
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put()
+            .uri(&format!("/api/state/v1alpha1/sessions/{}/states/app1/device1", session))
+            .set_json(create_request("pod2", "mqtt"))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/states")).await;
+        let response: ConnectedDevicesResponse = read_body_json(resp).await;
+        assert_eq!(response.total, 2);
+        assert_eq!(names(&response), vec!["device2", "device3"]);
+
+        // unknown applications have no connected devices
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app3/states")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: ConnectedDevicesResponse = read_body_json(resp).await;
+        assert_eq!(response.total, 0);
+        assert!(response.items.is_empty());
+    })
+}
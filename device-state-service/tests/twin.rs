@@ -0,0 +1,243 @@
+mod common;
+
+use cloudevents::{AttributesReader, Data};
+use drogue_client::{meta, registry};
+use drogue_cloud_device_state_service::{app, service::StaticRegistry};
+use drogue_cloud_service_api::{
+    services::{device_state::*, twin::*},
+    webapp::test::{read_body_json, TestRequest},
+};
+use drogue_cloud_test_common::call::{call_http, user};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use serial_test::serial;
+use std::collections::HashMap;
+use tokio_postgres::types::Json;
+
+fn device(application: &str, name: &str, uid: &str) -> registry::v1::Device {
+    registry::v1::Device {
+        metadata: meta::v1::ScopedMetadata {
+            application: application.into(),
+            name: name.into(),
+            uid: uid.into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: StaticRegistry = {
+        let mut m = HashMap::new();
+        m.insert(
+            "app1".into(),
+            registry::v1::Application {
+                spec: json!({"twin": {}}).as_object().cloned().unwrap(),
+                ..Default::default()
+            },
+        );
+        m.insert(
+            "app2".into(),
+            registry::v1::Application {
+                ..Default::default()
+            },
+        );
+        StaticRegistry {
+            applications: m,
+            devices: vec![
+                device("app1", "device1", "device_uid"),
+                device("app2", "device1", "device_uid"),
+            ],
+        }
+    };
+}
+
+fn delta(event: &cloudevents::Event) -> TwinDelta {
+    match event.data() {
+        Some(Data::Json(value)) => serde_json::from_value(value.clone()).unwrap(),
+        Some(Data::Binary(data)) => serde_json::from_slice(data).unwrap(),
+        data => panic!("Unexpected payload: {data:?}"),
+    }
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_twin() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, _service, _pool, sink) => {
+        // empty twin
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/twins/device1")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin, DeviceTwin::default());
+
+        // set the desired properties
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put()
+            .uri("/api/state/v1alpha1/apps/app1/twins/device1/desired")
+            .set_json(json!({"properties": {"interval": 10, "led": {"color": "red"}}}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin.desired.version, 1);
+        assert_eq!(Value::Object(twin.delta), json!({"interval": 10, "led": {"color": "red"}}));
+
+        let commands = sink.commands().await;
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].subject(), Some("twin"));
+        let command = delta(&commands[0]);
+        assert_eq!(command.version, 1);
+        assert_eq!(Value::Object(command.delta), json!({"interval": 10, "led": {"color": "red"}}));
+
+        // report some properties
+
+        let resp = call_http(&app, &user("foo"), TestRequest::patch()
+            .uri("/api/state/v1alpha1/twins/app1/device1/reported")
+            .set_json(json!({"properties": {"interval": 10, "led": {"color": "green"}, "temp": 21}}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin.reported.version, 1);
+        assert_eq!(Value::Object(twin.delta), json!({"led": {"color": "red"}}));
+
+        // reporting doesn't send a delta
+        assert_eq!(sink.commands().await.len(), 1);
+
+        // merge the desired properties
+
+        let resp = call_http(&app, &user("foo"), TestRequest::patch()
+            .uri("/api/state/v1alpha1/apps/app1/twins/device1/desired")
+            .set_json(json!({"properties": {"led": null}, "version": 1}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin.desired.version, 2);
+        assert_eq!(Value::Object(twin.desired.properties), json!({"interval": 10}));
+        assert!(twin.delta.is_empty());
+
+        // nothing to do for the device
+        assert_eq!(sink.commands().await.len(), 1);
+
+        // outdated version
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put()
+            .uri("/api/state/v1alpha1/apps/app1/twins/device1/desired")
+            .set_json(json!({"properties": {"interval": 20}, "version": 1}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/twins/device1")).await;
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin.desired.version, 2);
+        assert_eq!(Value::Object(twin.desired.properties), json!({"interval": 10}));
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_twin_delta_on_connect() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, _service, _pool, sink) => {
+        for application in ["app1", "app2"] {
+            let resp = call_http(&app, &user("foo"), TestRequest::put()
+                .uri(&format!("/api/state/v1alpha1/apps/{application}/twins/device1/desired"))
+                .set_json(json!({"properties": {"interval": 10}}))
+            ).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // only the application with a twin section gets a delta
+        assert_eq!(sink.commands().await.len(), 1);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put().uri("/api/state/v1alpha1/sessions")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: InitResponse = read_body_json(resp).await;
+        let session = response.session;
+
+        for application in ["app1", "app2"] {
+            let resp = call_http(&app, &user("foo"), TestRequest::put()
+                .uri(&format!("/api/state/v1alpha1/sessions/{session}/states/{application}/device1"))
+                .set_json(CreateRequest {
+                    token: "token".into(),
+                    state: DeviceState {
+                        device_uid: "device_uid".into(),
+                        endpoint: "pod1".into(),
+                        endpoint_type: None,
+                        lwt: None,
+                    },
+                })
+            ).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+
+        let commands = sink.commands().await;
+        assert_eq!(commands.len(), 2);
+        let command = delta(&commands[1]);
+        assert_eq!(command.version, 1);
+        assert_eq!(Value::Object(command.delta), json!({"interval": 10}));
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_twin_unknown_device() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, _service, _pool, sink) => {
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/twins/device2")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::put()
+            .uri("/api/state/v1alpha1/apps/app1/twins/device2/desired")
+            .set_json(json!({"properties": {"interval": 10}}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::patch()
+            .uri("/api/state/v1alpha1/twins/app1/device2/reported")
+            .set_json(json!({"properties": {"interval": 10}}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        assert!(sink.commands().await.is_empty());
+    })
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_twin_of_previous_device() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, service, pool, _sink) => {
+        // twins left over from deleted devices
+        let c = pool.get().await?;
+        for (device, uid) in [("device1", "previous_uid"), ("device2", "previous_uid")] {
+            c.execute(
+                "INSERT INTO twins (APPLICATION, DEVICE, DEVICE_UID, DESIRED, DESIRED_VERSION) VALUES ($1, $2, $3, $4, 1)",
+                &[&"app1", &device, &uid, &Json(json!({"interval": 10}))],
+            ).await?;
+        }
+
+        // the re-created device doesn't inherit the twin
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/twins/device1")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin, DeviceTwin::default());
+
+        let resp = call_http(&app, &user("foo"), TestRequest::patch()
+            .uri("/api/state/v1alpha1/apps/app1/twins/device1/desired")
+            .set_json(json!({"properties": {"led": "on"}}))
+        ).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let twin: DeviceTwin = read_body_json(resp).await;
+        assert_eq!(twin.desired.version, 1);
+        assert_eq!(Value::Object(twin.desired.properties), json!({"led": "on"}));
+
+        // cleaning up removes the twin of the deleted device only
+
+        service.cleanup_device("app1", "device1").await?;
+        service.cleanup_device("app1", "device2").await?;
+
+        let rows = c.query("SELECT DEVICE, DEVICE_UID FROM twins", &[]).await?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<_, String>("DEVICE"), "device1");
+        assert_eq!(rows[0].get::<_, String>("DEVICE_UID"), "device_uid");
+    })
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use drogue_client::registry;
+use drogue_cloud_device_state_service::app;
+use drogue_cloud_service_api::{
+    services::device_state::*,
+    webapp::test::{read_body_json, TestRequest},
+};
+use drogue_cloud_test_common::call::{call_http, user};
+use http::StatusCode;
+use lazy_static::lazy_static;
+use serde_json::json;
+use serial_test::serial;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref REGISTRY: HashMap<String, registry::v1::Application> = {
+        let mut m = HashMap::new();
+        for app in ["app1", "app2"] {
+            m.insert(
+                app.into(),
+                registry::v1::Application {
+                    ..Default::default()
+                },
+            );
+        }
+        m
+    };
+}
+
+fn keys(response: &LastValuesResponse) -> Vec<(&str, &str)> {
+    response
+        .items
+        .iter()
+        .map(|item| (item.device.as_str(), item.channel.as_str()))
+        .collect()
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_last_values() -> anyhow::Result<()> {
+    test!((REGISTRY.clone() => app, _service, _pool, _sink) => {
+        let now = Utc::now();
+
+        for (application, device, channel, value) in [
+            ("app1", "device2", "temp", 1),
+            ("app1", "device1", "temp", 2),
+            ("app1", "device1", "state", 3),
+            ("app2", "device1", "temp", 4),
+        ] {
+            let resp = call_http(&app, &user("foo"), TestRequest::post()
+                .uri(&format!("/api/state/v1alpha1/values/{application}/{device}"))
+                .set_json(RecordValueRequest {
+                    channel: channel.into(),
+                    time: now,
+                    event: json!({"data": value}),
+                })
+            ).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+
+        // all values of the application
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/values")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: LastValuesResponse = read_body_json(resp).await;
+        assert_eq!(keys(&response), vec![("device1", "state"), ("device1", "temp"), ("device2", "temp")]);
+        assert_eq!(response.items[1].event, json!({"data": 2}));
+        assert_eq!(response.total, 3);
+
+        // paging through the values
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/values?limit=2")).await;
+        let response: LastValuesResponse = read_body_json(resp).await;
+        assert_eq!(keys(&response), vec![("device1", "state"), ("device1", "temp")]);
+        assert_eq!(response.total, 3);
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/values?limit=2&offset=2")).await;
+        let response: LastValuesResponse = read_body_json(resp).await;
+        assert_eq!(keys(&response), vec![("device2", "temp")]);
+        assert_eq!(response.total, 3);
+
+        // newer events replace older ones, but not the other way round
+
+        for (time, value) in [(now + Duration::seconds(1), 5), (now - Duration::seconds(1), 6)] {
+            let resp = call_http(&app, &user("foo"), TestRequest::post()
+                .uri("/api/state/v1alpha1/values/app1/device1")
+                .set_json(RecordValueRequest {
+                    channel: "temp".into(),
+                    time,
+                    event: json!({"data": value}),
+                })
+            ).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+
+        // filter by device and channel, using the endpoint API
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/values/app1?device=device1&channel=temp")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: LastValuesResponse = read_body_json(resp).await;
+        assert_eq!(keys(&response), vec![("device1", "temp")]);
+        assert_eq!(response.items[0].event, json!({"data": 5}));
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app1/values?channel=temp")).await;
+        let response: LastValuesResponse = read_body_json(resp).await;
+        assert_eq!(keys(&response), vec![("device1", "temp"), ("device2", "temp")]);
+
+        // other application
+
+        let resp = call_http(&app, &user("foo"), TestRequest::get().uri("/api/state/v1alpha1/apps/app2/values")).await;
+        let response: LastValuesResponse = read_body_json(resp).await;
+        assert_eq!(keys(&response), vec![("device1", "temp")]);
+        assert_eq!(response.items[0].event, json!({"data": 4}));
+    })
+}
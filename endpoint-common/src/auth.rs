@@ -18,7 +18,7 @@ use futures::future::{err, ok, Ready};
 use http::HeaderValue;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{fmt::Debug, net::IpAddr};
 use tracing::instrument;
 use x509_parser::prelude::X509Certificate;
 
@@ -109,6 +109,7 @@ impl DeviceAuthenticator {
         device: D,
         credential: Credential,
         r#as: Option<String>,
+        source: Option<IpAddr>,
     ) -> AuthResult<AuthenticationResponse>
     where
         A: ToString + Debug,
@@ -120,6 +121,7 @@ impl DeviceAuthenticator {
                 device: device.to_string(),
                 credential,
                 r#as,
+                source,
             })
             .await
     }
@@ -131,10 +133,17 @@ impl DeviceAuthenticator {
     pub async fn authenticate_cert(
         &self,
         certs: Vec<Vec<u8>>,
+        source: Option<IpAddr>,
     ) -> AuthResult<AuthenticationResponse> {
         let (app_id, device_id) = Self::ids_from_cert(&certs)?;
-        self.authenticate(app_id, device_id, Credential::Certificate(certs), None)
-            .await
+        self.authenticate(
+            app_id,
+            device_id,
+            Credential::Certificate(certs),
+            None,
+            source,
+        )
+        .await
     }
 
     /// Authenticate a device based on a verified identity. The identity has previously been verified using pre-shared key
@@ -160,6 +169,7 @@ impl DeviceAuthenticator {
         auth: Option<&HeaderValue>,
         certs: Option<ClientCertificateChain>,
         verified_identity: Option<VerifiedIdentity>,
+        source: Option<IpAddr>,
     ) -> AuthResult<AuthenticationResponse>
     where
         T: AsRef<str> + Debug,
@@ -183,8 +193,14 @@ impl DeviceAuthenticator {
                 None,
                 None,
             ) => {
-                self.authenticate(&scope, &device, Credential::Password(password), None)
-                    .await
+                self.authenticate(
+                    &scope,
+                    &device,
+                    Credential::Password(password),
+                    None,
+                    source,
+                )
+                .await
             }
             // POST /<channel>?tenant=<tenant> -> basic auth `<device>` / `<password>` -> Password(<password>)
             (Some(scope), None, Some(AuthValue::Basic { username, password }), None, None) => {
@@ -193,6 +209,7 @@ impl DeviceAuthenticator {
                     username.into_string(),
                     Credential::Password(password),
                     None,
+                    source,
                 )
                 .await
             }
@@ -212,6 +229,7 @@ impl DeviceAuthenticator {
                         password,
                     },
                     None,
+                    source,
                 )
                 .await
             }
@@ -235,10 +253,11 @@ impl DeviceAuthenticator {
                     device.as_ref(),
                     Credential::UsernamePassword { username, password },
                     None,
+                    source,
                 )
                 .await
             }
-            (None, None, None, Some(certs), None) => self.authenticate_cert(certs.0).await,
+            (None, None, None, Some(certs), None) => self.authenticate_cert(certs.0, source).await,
             (None, None, None, None, Some(verified_identity)) => {
                 self.authenticate_verified_identity(verified_identity)
             }
@@ -256,6 +275,7 @@ impl DeviceAuthenticator {
         client_id: C,
         certs: Option<ClientCertificateChain>,
         verified_identity: Option<VerifiedIdentity>,
+        source: Option<IpAddr>,
     ) -> AuthResult<AuthenticationResponse>
     where
         U: AsRef<str> + Debug,
@@ -279,8 +299,14 @@ impl DeviceAuthenticator {
         ) {
             // Username/password <device>@<tenant> / <password>, Client ID: ???
             (Some(Username::Scoped { scope, device }), Some(password), _, None, None) => {
-                self.authenticate(&scope, &device, Credential::Password(password.into()), None)
-                    .await
+                self.authenticate(
+                    &scope,
+                    &device,
+                    Credential::Password(password.into()),
+                    None,
+                    source,
+                )
+                .await
             }
             // Username/password <username> / <password>, Client ID: <device>@<tenant>
             (
@@ -298,11 +324,12 @@ impl DeviceAuthenticator {
                         password: password.into(),
                     },
                     None,
+                    source,
                 )
                 .await
             }
             // Client cert only
-            (None, None, _, Some(certs), None) => self.authenticate_cert(certs.0).await,
+            (None, None, _, Some(certs), None) => self.authenticate_cert(certs.0, source).await,
             // TLS-PSK verified identity
             (None, None, _, None, Some(verified_identity)) => {
                 self.authenticate_verified_identity(verified_identity)
@@ -329,6 +356,7 @@ impl DeviceAuthenticator {
         certs: Option<Vec<Vec<u8>>>,
         verified_identity: Option<VerifiedIdentity>,
         r#as: Option<String>,
+        source: Option<IpAddr>,
     ) -> AuthResult<AuthenticationResponse>
     where
         T: AsRef<str> + Debug,
@@ -352,8 +380,14 @@ impl DeviceAuthenticator {
                 None,
                 None,
             ) => {
-                self.authenticate(&scope, &device, Credential::Password(password), r#as)
-                    .await
+                self.authenticate(
+                    &scope,
+                    &device,
+                    Credential::Password(password),
+                    r#as,
+                    source,
+                )
+                .await
             }
             // POST /<channel>?application=<application> -> basic auth `<device>` / `<password>` -> Password(<password>)
             (Some(scope), None, Some(AuthValue::Basic { username, password }), None, None) => {
@@ -362,6 +396,7 @@ impl DeviceAuthenticator {
                     username.into_string(),
                     Credential::Password(password),
                     r#as,
+                    source,
                 )
                 .await
             }
@@ -381,6 +416,7 @@ impl DeviceAuthenticator {
                         password,
                     },
                     r#as,
+                    source,
                 )
                 .await
             }
@@ -404,12 +440,13 @@ impl DeviceAuthenticator {
                     device.as_ref(),
                     Credential::UsernamePassword { username, password },
                     r#as,
+                    source,
                 )
                 .await
             }
 
             // X.509 client certificate -> all information from the cert
-            (None, None, None, Some(certs), None) => self.authenticate_cert(certs).await,
+            (None, None, None, Some(certs), None) => self.authenticate_cert(certs, source).await,
             (None, None, None, None, Some(verified_identity)) => {
                 self.authenticate_verified_identity(verified_identity)
             }
@@ -1,7 +1,9 @@
 use crate::command::{
-    Command, CommandAddress, CommandDispatcher, CommandNameFilter, CommandTarget,
+    Command, CommandAddress, CommandDispatcher, CommandNameFilter, CommandQueue, CommandStatus,
+    CommandStatusHandler, CommandTarget,
 };
 use async_trait::async_trait;
+use chrono::Utc;
 use drogue_cloud_service_common::Id;
 use std::{
     collections::{hash_map::Entry, HashMap},
@@ -10,7 +12,7 @@ use std::{
     sync::Arc,
 };
 use tokio::sync::{
-    mpsc::{channel, Receiver},
+    mpsc::{channel, error::TrySendError, Receiver, Sender},
     Mutex,
 };
 
@@ -89,6 +91,8 @@ type CommandMap<T> = Arc<Mutex<HashMap<T, HashMap<usize, CommandTarget>>>>;
 pub struct Commands {
     devices: CommandMap<CommandAddress>,
     wildcards: CommandMap<Id>,
+    queue: Option<CommandQueue>,
+    status: Option<Arc<dyn CommandStatusHandler>>,
 }
 
 impl Default for Commands {
@@ -114,6 +118,39 @@ impl Commands {
         Self {
             devices: Arc::new(Mutex::new(HashMap::new())),
             wildcards: Arc::new(Mutex::new(HashMap::new())),
+            queue: None,
+            status: None,
+        }
+    }
+
+    /// Queue commands which could not be delivered, as no device was subscribed.
+    pub fn with_queue<Q>(self, queue: Q) -> Self
+    where
+        Q: Into<Option<CommandQueue>>,
+    {
+        Self {
+            queue: queue.into(),
+            ..self
+        }
+    }
+
+    /// Report the delivery status of commands.
+    ///
+    /// Status changes of queued commands must be reported by the queue itself.
+    pub fn with_status_handler<H>(self, status: H) -> Self
+    where
+        H: Into<Option<Arc<dyn CommandStatusHandler>>>,
+    {
+        Self {
+            status: status.into(),
+            ..self
+        }
+    }
+
+    /// Report a status change of a command, if a handler is configured.
+    pub async fn report_status(&self, command: &Command, status: CommandStatus) {
+        if let Some(handler) = &self.status {
+            handler.report(command, status, None).await;
         }
     }
 
@@ -139,6 +176,9 @@ impl Commands {
             _ => filter,
         };
 
+        // keep a sender for delivering queued commands, once we are subscribed
+        let queue_tx = self.queue.as_ref().map(|_| tx.clone());
+
         let id = match filter.device.clone() {
             Some(device) => {
                 let mut devices = self.devices.lock().await;
@@ -169,6 +209,10 @@ impl Commands {
             }
         };
 
+        if let (Some(queue), Some(tx)) = (&self.queue, queue_tx) {
+            Self::deliver_queued(queue, &filter, tx, self.status.as_deref()).await;
+        }
+
         Subscription {
             receiver: rx,
             handle: SubscriptionHandle { id, filter },
@@ -212,6 +256,86 @@ impl Commands {
         }
     }
 
+    /// Return unprocessed commands of an ended subscription to the queue.
+    ///
+    /// If no queue is configured, the commands get dropped.
+    pub async fn requeue(&self, mut receiver: Receiver<Command>) {
+        if let Some(queue) = &self.queue {
+            receiver.close();
+            while let Ok(command) = receiver.try_recv() {
+                queue.requeue(command).await;
+            }
+        }
+    }
+
+    /// Return a single command, which could not be delivered, to the queue.
+    ///
+    /// If no queue is configured, the command gets dropped.
+    pub async fn requeue_command(&self, command: Command) {
+        if let Some(queue) = &self.queue {
+            queue.requeue(command).await;
+        }
+    }
+
+    /// Report an expired command, instead of sending it.
+    ///
+    /// Like a delivered command, the command is reported by the instance a device session is
+    /// subscribed to. Otherwise, it is handed to the queue, which removes and reports it right
+    /// away, so that only the instance queuing the command reports it.
+    async fn expired(&self, msg: Command) {
+        log::debug!(
+            "Dropping expired command {:?} for {:?}",
+            msg.command,
+            msg.address
+        );
+
+        if self.is_subscribed(&msg.address).await {
+            self.report_status(&msg, CommandStatus::Expired).await;
+        } else if let Some(queue) = &self.queue {
+            queue.push(msg).await;
+        }
+    }
+
+    /// Check if a device session of this instance is subscribed to commands for the address.
+    async fn is_subscribed(&self, address: &CommandAddress) -> bool {
+        self.devices.lock().await.contains_key(address)
+            || self
+                .wildcards
+                .lock()
+                .await
+                .contains_key(&Id::new(address.app_id.clone(), address.gateway_id.clone()))
+    }
+
+    async fn deliver_queued(
+        queue: &CommandQueue,
+        filter: &CommandFilter,
+        tx: Sender<Command>,
+        status: Option<&dyn CommandStatusHandler>,
+    ) {
+        let mut commands = queue.take(filter).await.into_iter();
+
+        for command in commands.by_ref() {
+            // only clone when we need to report
+            let delivered = status.map(|_| command.clone());
+            if let Err(TrySendError::Full(command) | TrySendError::Closed(command)) =
+                tx.try_send(command)
+            {
+                log::debug!("Subscription unable to receive, keeping remaining commands queued");
+                queue.requeue(command).await;
+                break;
+            }
+            if let (Some(status), Some(command)) = (status, delivered) {
+                status
+                    .report(&command, CommandStatus::Delivered, None)
+                    .await;
+            }
+        }
+
+        for command in commands {
+            queue.requeue(command).await;
+        }
+    }
+
     fn add_entry<K, V>(map: &mut HashMap<K, HashMap<usize, V>>, key: K, value: V) -> usize
     where
         K: Eq + Hash + Debug,
@@ -263,7 +387,13 @@ impl CommandDispatcher for Commands {
 
         log::debug!("Dispatching command to {:?}", msg.address);
 
+        if msg.is_expired(Utc::now()) {
+            self.expired(msg).await;
+            return;
+        }
+
         let mut num: usize = 0;
+        let mut filtered: usize = 0;
 
         if let Some(senders) = self.devices.lock().await.get(&msg.address) {
             log::debug!(
@@ -271,7 +401,9 @@ impl CommandDispatcher for Commands {
                 msg.command,
                 msg.address
             );
-            num += dispatch_command(senders.values(), &msg).await;
+            let (sent, skipped) = dispatch_command(senders.values(), &msg).await;
+            num += sent;
+            filtered += skipped;
         }
 
         if let Some(senders) = self.wildcards.lock().await.get(&Id::new(
@@ -283,22 +415,58 @@ impl CommandDispatcher for Commands {
                 msg.command,
                 msg.address
             );
-            num += dispatch_command(senders.values(), &msg).await;
+            let (sent, skipped) = dispatch_command(senders.values(), &msg).await;
+            num += sent;
+            filtered += skipped;
         }
 
         log::debug!("Sent to {} receivers", num);
+
+        if num > 0 {
+            if let Some(queue) = &self.queue {
+                // prevent other instances from queuing the command
+                queue.delivered(&msg).await;
+            }
+            self.report_status(&msg, CommandStatus::Delivered).await;
+            return;
+        }
+
+        if filtered > 0 {
+            // the device is connected to this instance, but not interested in the command
+            self.report_status(&msg, CommandStatus::Filtered).await;
+            return;
+        }
+
+        // All instances receive the command, but only the instance queuing the command reports
+        // it. Without a queue, there is no instance which knows that no device session is
+        // subscribed at all.
+        if let Some(queue) = &self.queue {
+            let reported = self.status.as_ref().map(|_| msg.clone());
+            if queue.push(msg).await {
+                if let (Some(handler), Some(msg)) = (&self.status, reported) {
+                    handler
+                        .report(&msg, CommandStatus::NoSubscriber, Some("queued".into()))
+                        .await;
+                }
+            }
+        }
     }
 }
 
 /// Dispatch a command to a list of senders/devices.
-async fn dispatch_command<'a, I>(senders: I, msg: &Command) -> usize
+///
+/// Returns the number of senders the command was sent to, and the number of senders which
+/// filtered out the command.
+async fn dispatch_command<'a, I>(senders: I, msg: &Command) -> (usize, usize)
 where
     I: IntoIterator<Item = &'a CommandTarget>,
 {
     let mut num = 0;
+    let mut filtered = 0;
 
     for sender in senders {
         if !sender.filter.matches(&msg.command) {
+            filtered += 1;
             continue;
         }
 
@@ -313,13 +481,13 @@ where
         }
     }
 
-    num
+    (num, filtered)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::command::CommandAddress;
+    use crate::command::{CommandAddress, MemoryQueueStore};
     use futures::future::select_all;
     use tokio::{
         task::JoinHandle,
@@ -414,6 +582,146 @@ mod test {
         handle.await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_queued() {
+        let _ = env_logger::try_init();
+
+        let address = CommandAddress::new("test-queued", "test", "test");
+        let filter = CommandFilter::device("test-queued", "test");
+
+        let commands = Commands::new().with_queue(CommandQueue::new(
+            &Default::default(),
+            MemoryQueueStore::default(),
+        ));
+
+        // no subscriber, so the command must be queued
+
+        commands
+            .send(Command::new(address.clone(), "test0".to_string(), None))
+            .await;
+
+        // subscribing delivers the queued command
+
+        let Subscription {
+            mut receiver,
+            handle,
+        } = commands.subscribe(filter.clone()).await;
+
+        let cmd = timeout(Duration::from_secs(1), receiver.recv()).await;
+        assert_eq!(cmd.unwrap().unwrap().command, "test0");
+
+        // an unprocessed command gets returned to the queue
+
+        commands
+            .send(Command::new(address.clone(), "test1".to_string(), None))
+            .await;
+        commands.unsubscribe(handle).await;
+        commands.requeue(receiver).await;
+
+        let Subscription { mut receiver, .. } = commands.subscribe(filter).await;
+
+        let cmd = timeout(Duration::from_secs(1), receiver.recv()).await;
+        assert_eq!(cmd.unwrap().unwrap().command, "test1");
+    }
+
+    #[derive(Debug, Default)]
+    struct MockStatusHandler(std::sync::Mutex<Vec<(String, CommandStatus, Option<String>)>>);
+
+    #[async_trait]
+    impl CommandStatusHandler for MockStatusHandler {
+        async fn report(&self, command: &Command, status: CommandStatus, reason: Option<String>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((command.command.clone(), status, reason));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let _ = env_logger::try_init();
+
+        let address = CommandAddress::new("test-status", "test", "test");
+        let filter = CommandFilter::device("test-status", "test");
+
+        let status = Arc::new(MockStatusHandler::default());
+        let commands = Commands::new()
+            .with_status_handler(Some(status.clone() as Arc<dyn CommandStatusHandler>));
+
+        // without a queue, another instance might have a subscriber, so nothing gets reported
+        commands
+            .send(Command::new(address.clone(), "test0".to_string(), None))
+            .await;
+
+        let Subscription { mut receiver, .. } = commands
+            .subscribe(filter.with_filter("test1".to_string()))
+            .await;
+
+        commands
+            .send(Command::new(address.clone(), "test1".to_string(), None))
+            .await;
+        commands
+            .send(Command::new(address.clone(), "test2".to_string(), None))
+            .await;
+
+        let cmd = timeout(Duration::from_secs(1), receiver.recv()).await;
+        assert_eq!(cmd.unwrap().unwrap().command, "test1");
+
+        assert_eq!(
+            *status.0.lock().unwrap(),
+            vec![
+                ("test1".to_string(), CommandStatus::Delivered, None),
+                ("test2".to_string(), CommandStatus::Filtered, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_expired() {
+        let _ = env_logger::try_init();
+
+        let address = CommandAddress::new("test-status-expired", "test", "test");
+        let filter = CommandFilter::device("test-status-expired", "test");
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        let status = Arc::new(MockStatusHandler::default());
+        let commands = Commands::new()
+            .with_queue(CommandQueue::new(
+                &Default::default(),
+                MemoryQueueStore::default(),
+            ))
+            .with_status_handler(Some(status.clone() as Arc<dyn CommandStatusHandler>));
+
+        // without a subscriber, the queue reports the command
+        commands
+            .send(Command::new(address.clone(), "test0".to_string(), None).with_expires(expired))
+            .await;
+
+        // with a subscriber, the command is reported, but not delivered
+        let Subscription { mut receiver, .. } = commands.subscribe(filter).await;
+        commands
+            .send(Command::new(address.clone(), "test1".to_string(), None).with_expires(expired))
+            .await;
+
+        let cmd = timeout(Duration::from_millis(100), receiver.recv()).await;
+        assert!(
+            cmd.is_err(),
+            "Expired command must not be delivered: {cmd:?}"
+        );
+
+        assert_eq!(
+            *status.0.lock().unwrap(),
+            vec![
+                (
+                    "test0".to_string(),
+                    CommandStatus::Expired,
+                    Some("expired".to_string())
+                ),
+                ("test1".to_string(), CommandStatus::Expired, None),
+            ]
+        );
+    }
+
     #[derive(Default)]
     struct MockReceiverResult {
         finished: bool,
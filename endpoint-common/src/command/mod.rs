@@ -1,14 +1,23 @@
 mod commands;
+mod queue;
+mod reply;
 mod source;
+mod status;
 mod target;
 
 pub use commands::*;
+pub use queue::*;
+pub use reply::*;
 pub use source::*;
+pub use status::*;
 pub use target::*;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use cloudevents::{event::ExtensionValue, AttributesReader, Event};
-use drogue_cloud_service_api::{EXT_APPLICATION, EXT_DEVICE, EXT_SENDER};
+use drogue_cloud_service_api::{
+    EXT_APPLICATION, EXT_CORRELATION_ID, EXT_DEVICE, EXT_EXPIRES, EXT_SENDER,
+};
 use std::convert::{TryFrom, TryInto};
 use thiserror::Error;
 
@@ -58,9 +67,17 @@ impl CommandAddress {
 /// Represents command
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Command {
+    /// The ID of the command, if known.
+    ///
+    /// This is the ID of the event carrying the command, and is the same on all endpoints.
+    pub id: Option<String>,
     pub address: CommandAddress,
     pub command: String,
     pub payload: Option<Vec<u8>>,
+    /// The correlation ID, which a device must use when replying to the command.
+    pub correlation_id: Option<String>,
+    /// The point in time the command expires, if it could not be delivered until then.
+    pub expires: Option<DateTime<Utc>>,
 }
 
 impl Command {
@@ -71,11 +88,51 @@ impl Command {
         payload: Option<Vec<u8>>,
     ) -> Self {
         Self {
+            id: None,
             address,
             command: command.into(),
             payload,
+            correlation_id: None,
+            expires: None,
         }
     }
+
+    /// Set the ID of the command.
+    pub fn with_id<I>(self, id: I) -> Self
+    where
+        I: Into<Option<String>>,
+    {
+        Self {
+            id: id.into(),
+            ..self
+        }
+    }
+
+    /// Set the correlation ID of the command.
+    pub fn with_correlation_id<C>(self, correlation_id: C) -> Self
+    where
+        C: Into<Option<String>>,
+    {
+        Self {
+            correlation_id: correlation_id.into(),
+            ..self
+        }
+    }
+
+    /// Set the point in time the command expires.
+    pub fn with_expires<E>(self, expires: E) -> Self
+    where
+        E: Into<Option<DateTime<Utc>>>,
+    {
+        Self {
+            expires: expires.into(),
+            ..self
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
 }
 
 #[derive(Clone, Debug, Error)]
@@ -103,7 +160,22 @@ impl TryFrom<Event> for Command {
             .subject()
             .ok_or(ParseCommandError::Missing("Command"))?;
 
-        Ok(Command::new(address, command, payload))
+        let correlation_id = match event.extension(EXT_CORRELATION_ID) {
+            Some(ExtensionValue::String(correlation_id)) => Some(correlation_id.clone()),
+            _ => None,
+        };
+
+        let expires = match event.extension(EXT_EXPIRES) {
+            Some(ExtensionValue::String(expires)) => DateTime::parse_from_rfc3339(expires)
+                .ok()
+                .map(|expires| expires.with_timezone(&Utc)),
+            _ => None,
+        };
+
+        Ok(Command::new(address, command, payload)
+            .with_id(event.id().to_string())
+            .with_correlation_id(correlation_id)
+            .with_expires(expires))
     }
 }
 
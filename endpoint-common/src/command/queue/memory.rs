@@ -0,0 +1,176 @@
+use super::{ExpiryReason, QueueStore, QueuedCommand, Removed};
+use crate::command::{Command, CommandFilter, CommandNameFilter};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use drogue_cloud_database_common::error::ServiceError;
+use drogue_cloud_service_common::Id;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// Per gateway queues, by device.
+type DeviceQueues = HashMap<String, VecDeque<QueuedCommand>>;
+
+#[derive(Debug, Default)]
+struct State {
+    queues: HashMap<Id, DeviceQueues>,
+    /// IDs of commands which got taken, or delivered, with their expiry.
+    done: HashMap<String, DateTime<Utc>>,
+}
+
+/// A queue store, keeping commands in memory.
+///
+/// The queue is lost when the process ends, and not shared with other instances. This is only
+/// intended for testing.
+#[derive(Debug, Default)]
+pub struct MemoryQueueStore {
+    state: Mutex<State>,
+}
+
+impl MemoryQueueStore {
+    fn retain(queue: &mut VecDeque<QueuedCommand>, now: DateTime<Utc>, removed: &mut Removed) {
+        // commands may expire before the time to live of the queue, so check all entries
+        let (expired, remaining): (Vec<_>, VecDeque<_>) =
+            queue.drain(..).partition(|entry| entry.is_expired(now));
+        *queue = remaining;
+        removed.extend(
+            expired
+                .into_iter()
+                .map(|entry| (entry, ExpiryReason::Expired)),
+        );
+    }
+}
+
+#[async_trait]
+impl QueueStore for MemoryQueueStore {
+    async fn push(
+        &self,
+        entry: QueuedCommand,
+        max_per_device: usize,
+        requeue: bool,
+        now: DateTime<Utc>,
+    ) -> Result<(bool, Removed), ServiceError> {
+        let mut removed = vec![];
+
+        let mut state = self.state.lock().unwrap();
+        let State { queues, done } = &mut *state;
+
+        let address = &entry.command.address;
+        let queue = queues
+            .entry(Id::new(address.app_id.clone(), address.gateway_id.clone()))
+            .or_default()
+            .entry(address.device_id.clone())
+            .or_default();
+
+        if let Some(id) = &entry.command.id {
+            let queued = queue
+                .iter()
+                .any(|queued| queued.command.id.as_ref() == Some(id));
+            if queued || (!requeue && done.contains_key(id)) {
+                return Ok((false, removed));
+            }
+            done.remove(id);
+        }
+
+        // drop expired commands, including the new one, then evict the oldest commands
+
+        queue.push_back(entry);
+        Self::retain(queue, now, &mut removed);
+        while queue.len() > max_per_device {
+            if let Some(evicted) = queue.pop_front() {
+                removed.push((evicted, ExpiryReason::Evicted));
+            }
+        }
+
+        Ok((true, removed))
+    }
+
+    async fn take(
+        &self,
+        filter: &CommandFilter,
+        now: DateTime<Utc>,
+    ) -> Result<(Vec<Command>, Removed), ServiceError> {
+        let command_filter = CommandNameFilter::from(&filter.command_filter);
+        let mut removed = vec![];
+        let mut result = vec![];
+
+        let mut state = self.state.lock().unwrap();
+        let State { queues, done } = &mut *state;
+        let id = Id::new(filter.application.clone(), filter.gateway.clone());
+
+        if let Some(devices) = queues.get_mut(&id) {
+            for (device, queue) in devices.iter_mut() {
+                if matches!(&filter.device, Some(filter_device) if filter_device != device) {
+                    continue;
+                }
+
+                Self::retain(queue, now, &mut removed);
+
+                let (matching, remaining): (Vec<_>, VecDeque<_>) = queue
+                    .drain(..)
+                    .partition(|entry| command_filter.matches(&entry.command.command));
+                *queue = remaining;
+
+                for entry in matching {
+                    if let Some(id) = &entry.command.id {
+                        done.insert(id.clone(), entry.expires);
+                    }
+                    result.push(entry.command);
+                }
+            }
+
+            devices.retain(|_, queue| !queue.is_empty());
+            if devices.is_empty() {
+                queues.remove(&id);
+            }
+        }
+
+        Ok((result, removed))
+    }
+
+    async fn delivered(
+        &self,
+        command: &Command,
+        expires: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let id = match &command.id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        let key = Id::new(
+            command.address.app_id.clone(),
+            command.address.gateway_id.clone(),
+        );
+        if let Some(queue) = state
+            .queues
+            .get_mut(&key)
+            .and_then(|devices| devices.get_mut(&command.address.device_id))
+        {
+            queue.retain(|entry| entry.command.id.as_ref() != Some(id));
+        }
+
+        state.done.insert(id.clone(), expires);
+
+        Ok(())
+    }
+
+    async fn prune(&self, now: DateTime<Utc>) -> Result<Removed, ServiceError> {
+        let mut removed = vec![];
+
+        let mut state = self.state.lock().unwrap();
+        for devices in state.queues.values_mut() {
+            for queue in devices.values_mut() {
+                Self::retain(queue, now, &mut removed);
+            }
+            devices.retain(|_, queue| !queue.is_empty());
+        }
+        state.queues.retain(|_, devices| !devices.is_empty());
+        state.done.retain(|_, expires| *expires > now);
+
+        Ok(removed)
+    }
+}
@@ -0,0 +1,710 @@
+mod memory;
+mod postgres;
+
+pub use memory::*;
+pub use postgres::*;
+
+use crate::{
+    command::{Command, CommandFilter, CommandStatus, CommandStatusHandler},
+    sender::{DownstreamSender, Publish, PublishId, PublishOptions, PublishOutcome, Publisher},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use drogue_client::registry;
+use drogue_cloud_database_common::{error::ServiceError, postgres as pg};
+use drogue_cloud_service_common::client::ClientConfig;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, sync::Arc, time::Duration};
+use tokio::time::sleep;
+
+lazy_static! {
+    pub static ref QUEUED_COMMANDS_COUNTER: IntCounter =
+        register_int_counter!("drogue_queued_commands", "Commands added to the queue").unwrap();
+    pub static ref EXPIRED_COMMANDS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "drogue_expired_commands",
+        "Commands removed from the queue without being delivered",
+        &["reason"],
+    )
+    .unwrap();
+}
+
+pub const COMMAND_EXPIRED_TYPE_EVENT: &str = "io.drogue.command.expired.v1";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CommandQueueConfig {
+    /// Queue commands for devices which currently have no active subscription.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The database holding the queue, required when the queue is enabled.
+    ///
+    /// All instances of an endpoint must share the same database, so that a device can receive
+    /// its commands from any instance.
+    #[serde(default)]
+    pub pg: Option<pg::Config>,
+    /// The time a command is kept in the queue, before it expires.
+    #[serde(with = "humantime_serde", default = "default_ttl")]
+    pub ttl: Duration,
+    /// The maximum number of commands queued for a single device.
+    ///
+    /// When the limit is reached, the oldest command will be evicted.
+    #[serde(default = "default_max_per_device")]
+    pub max_per_device: usize,
+    /// The period in which the queue will be checked for expired commands.
+    #[serde(with = "humantime_serde", default = "default_prune_period")]
+    pub prune_period: Duration,
+    /// Access to the registry, required for sending expiry events.
+    ///
+    /// If not configured, expired commands will only be logged.
+    #[serde(default)]
+    pub registry: Option<ClientConfig>,
+}
+
+const fn default_ttl() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+const fn default_max_per_device() -> usize {
+    16
+}
+
+const fn default_prune_period() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl Default for CommandQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pg: None,
+            ttl: default_ttl(),
+            max_per_device: default_max_per_device(),
+            prune_period: default_prune_period(),
+            registry: None,
+        }
+    }
+}
+
+/// The reason a command got removed from the queue, without being delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExpiryReason {
+    /// The command exceeded its time to live.
+    Expired,
+    /// The command was evicted, as the queue of the device was full.
+    Evicted,
+}
+
+impl ExpiryReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Expired => "Expired",
+            Self::Evicted => "Evicted",
+        }
+    }
+}
+
+/// The payload of an expiry event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandExpiredEvent {
+    pub command: String,
+    pub reason: ExpiryReason,
+    pub queued: DateTime<Utc>,
+}
+
+/// Handle commands which got removed from the queue without being delivered.
+#[async_trait]
+pub trait ExpiryHandler: Debug + Send + Sync {
+    async fn expired(&self, command: Command, queued: DateTime<Utc>, reason: ExpiryReason);
+}
+
+/// A source of the current time.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A command, held by the queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueuedCommand {
+    pub command: Command,
+    pub queued: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+}
+
+impl QueuedCommand {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires <= now
+    }
+}
+
+/// Commands removed from the queue without being delivered.
+pub type Removed = Vec<(QueuedCommand, ExpiryReason)>;
+
+/// Storage of queued commands.
+///
+/// All instances of an endpoint receive all commands. Commands carrying an ID are therefore only
+/// queued once, and are remembered as done once they got taken, or delivered by any instance,
+/// until they expire.
+#[async_trait]
+pub trait QueueStore: Debug + Send + Sync {
+    /// Add a command to the queue of its device.
+    ///
+    /// This removes expired commands of the device, and evicts the oldest commands, keeping at
+    /// most `max_per_device` commands. Returns `false` if the command was already queued or done.
+    ///
+    /// If `requeue` is set, a command which is already done will be queued again.
+    async fn push(
+        &self,
+        entry: QueuedCommand,
+        max_per_device: usize,
+        requeue: bool,
+        now: DateTime<Utc>,
+    ) -> Result<(bool, Removed), ServiceError>;
+
+    /// Take all non-expired commands matching the filter, in the order they got queued.
+    async fn take(
+        &self,
+        filter: &CommandFilter,
+        now: DateTime<Utc>,
+    ) -> Result<(Vec<Command>, Removed), ServiceError>;
+
+    /// Mark a command as done, as it was delivered directly.
+    async fn delivered(
+        &self,
+        command: &Command,
+        expires: DateTime<Utc>,
+    ) -> Result<(), ServiceError>;
+
+    /// Remove all expired commands.
+    async fn prune(&self, now: DateTime<Utc>) -> Result<Removed, ServiceError>;
+}
+
+/// A queue, holding commands for devices which are currently not connected.
+#[derive(Clone, Debug)]
+pub struct CommandQueue {
+    ttl: Duration,
+    max_per_device: usize,
+    prune_period: Duration,
+    store: Arc<dyn QueueStore>,
+    clock: Arc<dyn Clock>,
+    expiry: Option<Arc<dyn ExpiryHandler>>,
+    status: Option<Arc<dyn CommandStatusHandler>>,
+}
+
+impl CommandQueue {
+    pub fn new<S>(config: &CommandQueueConfig, store: S) -> Self
+    where
+        S: QueueStore + 'static,
+    {
+        Self {
+            ttl: config.ttl,
+            max_per_device: config.max_per_device.max(1),
+            prune_period: config.prune_period,
+            store: Arc::new(store),
+            clock: Arc::new(SystemClock),
+            expiry: None,
+            status: None,
+        }
+    }
+
+    /// Create a new queue from the configuration, returns `None` if queuing is disabled.
+    ///
+    /// If the configuration contains registry access, expired commands will be reported as
+    /// events, using the provided sender.
+    pub async fn from_config(
+        config: CommandQueueConfig,
+        sender: DownstreamSender,
+    ) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let store = match &config.pg {
+            Some(pg) => PostgresQueueStore::new(pg.create_pool()?),
+            None => anyhow::bail!("The command queue requires a database configuration"),
+        };
+
+        let queue = Self::new(&config, store);
+
+        Ok(Some(match config.registry {
+            Some(registry) => queue.with_expiry_handler(ExpiryEventSender {
+                sender,
+                registry: registry.into_client().await?,
+            }),
+            None => queue,
+        }))
+    }
+
+    pub fn with_expiry_handler<H>(self, handler: H) -> Self
+    where
+        H: ExpiryHandler + 'static,
+    {
+        Self {
+            expiry: Some(Arc::new(handler)),
+            ..self
+        }
+    }
+
+    /// Report commands removed from the queue as expired.
+    pub fn with_status_handler<H>(self, status: H) -> Self
+    where
+        H: Into<Option<Arc<dyn CommandStatusHandler>>>,
+    {
+        Self {
+            status: status.into(),
+            ..self
+        }
+    }
+
+    /// Use a different clock, instead of the system clock.
+    pub fn with_clock<C>(self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        Self {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Add a new command to the queue.
+    ///
+    /// Returns `false` if the command was not queued, as it is already queued, or was already
+    /// taken care of by another instance.
+    pub async fn push(&self, command: Command) -> bool {
+        self.insert(command, false).await
+    }
+
+    /// Return a command, which could not be delivered, to the queue.
+    pub async fn requeue(&self, command: Command) {
+        self.insert(command, true).await;
+    }
+
+    async fn insert(&self, command: Command, requeue: bool) -> bool {
+        let now = self.clock.now();
+
+        log::debug!(
+            "Queuing command {:?} for {:?}",
+            command.command,
+            command.address
+        );
+
+        // commands may expire before the time to live of the queue
+        let expires = match command.expires {
+            Some(expires) => expires.min(self.expires(now)),
+            None => self.expires(now),
+        };
+
+        let entry = QueuedCommand {
+            command,
+            queued: now,
+            expires,
+        };
+
+        match self
+            .store
+            .push(entry, self.max_per_device, requeue, now)
+            .await
+        {
+            Ok((queued, removed)) => {
+                if queued {
+                    QUEUED_COMMANDS_COUNTER.inc();
+                }
+                self.report(removed).await;
+                queued
+            }
+            Err(err) => {
+                log::warn!("Failed to queue command: {err}");
+                false
+            }
+        }
+    }
+
+    /// Take all non-expired commands matching the filter from the queue.
+    pub async fn take(&self, filter: &CommandFilter) -> Vec<Command> {
+        match self.store.take(filter, self.clock.now()).await {
+            Ok((result, removed)) => {
+                self.report(removed).await;
+                result
+            }
+            Err(err) => {
+                log::warn!("Failed to take commands from queue: {err}");
+                vec![]
+            }
+        }
+    }
+
+    /// Record a command as delivered, so that it will not be queued by other instances.
+    pub async fn delivered(&self, command: &Command) {
+        if command.id.is_none() {
+            return;
+        }
+        let expires = self.expires(self.clock.now());
+        if let Err(err) = self.store.delivered(command, expires).await {
+            log::warn!("Failed to record delivered command: {err}");
+        }
+    }
+
+    /// Remove all expired commands.
+    pub async fn prune(&self) {
+        match self.store.prune(self.clock.now()).await {
+            Ok(removed) => {
+                if !removed.is_empty() {
+                    log::debug!("Pruned {} commands", removed.len());
+                }
+                self.report(removed).await;
+            }
+            Err(err) => {
+                log::warn!("Failed to prune command queue: {err}");
+            }
+        }
+    }
+
+    /// Periodically prune the queue.
+    pub async fn run_pruner(self) -> anyhow::Result<()> {
+        loop {
+            sleep(self.prune_period).await;
+            self.prune().await;
+        }
+    }
+
+    /// The time a command expires, when being queued, or delivered, now.
+    fn expires(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        chrono::Duration::from_std(self.ttl)
+            .ok()
+            .and_then(|ttl| now.checked_add_signed(ttl))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    async fn report(&self, removed: Removed) {
+        for (entry, reason) in removed {
+            log::debug!(
+                "Command {:?} for {:?} removed from queue: {:?}",
+                entry.command.command,
+                entry.command.address,
+                reason
+            );
+            EXPIRED_COMMANDS_COUNTER
+                .with_label_values(&[reason.as_str()])
+                .inc();
+            if let Some(status) = &self.status {
+                status
+                    .report(
+                        &entry.command,
+                        CommandStatus::Expired,
+                        Some(reason.as_str().to_lowercase()),
+                    )
+                    .await;
+            }
+            if let Some(expiry) = &self.expiry {
+                expiry.expired(entry.command, entry.queued, reason).await;
+            }
+        }
+    }
+}
+
+/// Send expiry events to the event stream of the application.
+#[derive(Clone, Debug)]
+pub struct ExpiryEventSender {
+    sender: DownstreamSender,
+    registry: registry::v1::Client,
+}
+
+impl ExpiryEventSender {
+    pub fn new(sender: DownstreamSender, registry: registry::v1::Client) -> Self {
+        Self { sender, registry }
+    }
+}
+
+#[async_trait]
+impl ExpiryHandler for ExpiryEventSender {
+    async fn expired(&self, command: Command, queued: DateTime<Utc>, reason: ExpiryReason) {
+        let application = match self.registry.get_app(&command.address.app_id).await {
+            Ok(Some(application)) => application,
+            Ok(None) => {
+                log::debug!("Application no longer found: {}", command.address.app_id);
+                return;
+            }
+            Err(err) => {
+                log::info!("Failed to look up application for expiry event: {err}");
+                return;
+            }
+        };
+
+        let body = match serde_json::to_vec(&CommandExpiredEvent {
+            command: command.command.clone(),
+            reason,
+            queued,
+        }) {
+            Ok(body) => body,
+            Err(err) => {
+                log::info!("Failed to encode expiry event: {err}");
+                return;
+            }
+        };
+
+        let outcome = self
+            .sender
+            .publish(
+                Publish {
+                    application: &application,
+                    device: PublishId {
+                        name: command.address.device_id,
+                        uid: None,
+                    },
+                    sender: PublishId {
+                        name: command.address.gateway_id,
+                        uid: None,
+                    },
+                    channel: command.command,
+                    options: PublishOptions {
+                        r#type: Some(COMMAND_EXPIRED_TYPE_EVENT.to_string()),
+                        content_type: Some("application/json".to_string()),
+                        ..Default::default()
+                    },
+                },
+                body,
+            )
+            .await;
+
+        match outcome {
+            Ok(PublishOutcome::Accepted) => {}
+            outcome => {
+                log::info!("Failed to send expiry event: {outcome:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::CommandAddress;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    const APP: &str = "test-app";
+
+    /// A clock, which only advances when being told so.
+    #[derive(Clone, Debug)]
+    struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self(Arc::new(Mutex::new(Utc.timestamp_millis(1_000_000))))
+        }
+
+        fn advance(&self, duration: Duration) {
+            let mut now = self.0.lock().unwrap();
+            *now = *now + chrono::Duration::from_std(duration).unwrap();
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// Record expired commands.
+    #[derive(Clone, Debug, Default)]
+    struct Recorder(Arc<Mutex<Vec<(String, ExpiryReason)>>>);
+
+    #[async_trait]
+    impl ExpiryHandler for Recorder {
+        async fn expired(&self, command: Command, _: DateTime<Utc>, reason: ExpiryReason) {
+            self.0.lock().unwrap().push((command.command, reason));
+        }
+    }
+
+    fn queue(ttl: Duration, max_per_device: usize) -> CommandQueue {
+        CommandQueue::new(
+            &CommandQueueConfig {
+                enabled: true,
+                ttl,
+                max_per_device,
+                ..Default::default()
+            },
+            MemoryQueueStore::default(),
+        )
+    }
+
+    fn cmd(gateway: &str, device: &str, command: &str) -> Command {
+        Command::new(CommandAddress::new(APP, gateway, device), command, None)
+    }
+
+    #[tokio::test]
+    async fn test_take() {
+        let queue = queue(Duration::from_secs(60), 10);
+
+        queue.push(cmd("d1", "d1", "c1")).await;
+        queue.push(cmd("d2", "d2", "c2")).await;
+        queue.push(cmd("d1", "d1", "c3")).await;
+
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![cmd("d1", "d1", "c1"), cmd("d1", "d1", "c3")]
+        );
+        // already taken
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![]
+        );
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d2")).await,
+            vec![cmd("d2", "d2", "c2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_take_filtered() {
+        let queue = queue(Duration::from_secs(60), 10);
+
+        queue.push(cmd("gw1", "d1", "foo")).await;
+        queue.push(cmd("gw1", "d2", "foo")).await;
+        queue.push(cmd("gw1", "d1", "bar")).await;
+
+        assert_eq!(
+            queue
+                .take(
+                    &CommandFilter::proxied_device(APP, "gw1", "d1").with_filter("bar".to_string())
+                )
+                .await,
+            vec![cmd("gw1", "d1", "bar")]
+        );
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "gw1")).await,
+            vec![cmd("gw1", "d1", "foo"), cmd("gw1", "d2", "foo")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_per_device() {
+        let evicted = Recorder::default();
+        let queue = queue(Duration::from_secs(60), 2).with_expiry_handler(evicted.clone());
+
+        queue.push(cmd("d1", "d1", "c1")).await;
+        queue.push(cmd("d1", "d1", "c2")).await;
+        queue.push(cmd("d1", "d1", "c3")).await;
+
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![cmd("d1", "d1", "c2"), cmd("d1", "d1", "c3")]
+        );
+        assert_eq!(
+            *evicted.0.lock().unwrap(),
+            vec![("c1".to_string(), ExpiryReason::Evicted)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired() {
+        let clock = ManualClock::new();
+        let queue = queue(Duration::from_secs(10), 10).with_clock(clock.clone());
+
+        queue.push(cmd("d1", "d1", "c1")).await;
+        clock.advance(Duration::from_secs(10));
+        queue.push(cmd("d1", "d1", "c2")).await;
+
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![cmd("d1", "d1", "c2")]
+        );
+
+        queue.push(cmd("d1", "d1", "c3")).await;
+        clock.advance(Duration::from_secs(9));
+        queue.prune().await;
+        clock.advance(Duration::from_secs(1));
+        queue.push(cmd("d1", "d1", "c4")).await;
+
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![cmd("d1", "d1", "c4")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_command_expires() {
+        let clock = ManualClock::new();
+        let queue = queue(Duration::from_secs(60), 10).with_clock(clock.clone());
+
+        // expires before the time to live of the queue
+        let expires = clock.now() + chrono::Duration::seconds(5);
+        queue
+            .push(cmd("d1", "d1", "c1").with_expires(expires))
+            .await;
+        queue.push(cmd("d1", "d1", "c2")).await;
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![cmd("d1", "d1", "c2")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pruned() {
+        let clock = ManualClock::new();
+        let expired = Recorder::default();
+        let queue = queue(Duration::from_secs(10), 10)
+            .with_clock(clock.clone())
+            .with_expiry_handler(expired.clone());
+
+        queue.push(cmd("d1", "d1", "c1")).await;
+        clock.advance(Duration::from_secs(9));
+        queue.prune().await;
+        assert!(expired.0.lock().unwrap().is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        queue.prune().await;
+        assert_eq!(
+            *expired.0.lock().unwrap(),
+            vec![("c1".to_string(), ExpiryReason::Expired)]
+        );
+
+        // pruned only once
+        queue.prune().await;
+        assert_eq!(expired.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deduplicate() {
+        let queue = queue(Duration::from_secs(60), 10);
+        let command = cmd("d1", "d1", "c1").with_id("id1".to_string());
+
+        // queued only once
+        assert!(queue.push(command.clone()).await);
+        assert!(!queue.push(command.clone()).await);
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![command.clone()]
+        );
+
+        // not queued again, once taken
+        assert!(!queue.push(command.clone()).await);
+
+        // unless being returned to the queue
+        queue.requeue(command.clone()).await;
+        assert_eq!(
+            queue.take(&CommandFilter::wildcard(APP, "d1")).await,
+            vec![command]
+        );
+
+        // not queued, once delivered by another instance
+        let command = cmd("d1", "d1", "c2").with_id("id2".to_string());
+        queue.delivered(&command).await;
+        assert!(!queue.push(command).await);
+    }
+}
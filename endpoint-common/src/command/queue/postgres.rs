@@ -0,0 +1,346 @@
+use super::{ExpiryReason, QueueStore, QueuedCommand, Removed};
+use crate::command::{Command, CommandAddress, CommandFilter, CommandNameFilter};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use drogue_cloud_database_common::error::ServiceError;
+use tokio_postgres::Row;
+
+const COLUMNS: &str = r#"
+    ID,
+    COMMAND_ID,
+    APPLICATION,
+    GATEWAY,
+    DEVICE,
+    COMMAND,
+    PAYLOAD,
+    CORRELATION_ID,
+    QUEUED,
+    EXPIRES
+"#;
+
+/// A queue store, backed by PostgreSQL.
+///
+/// Commands are kept in the `command_queue` table. Commands which got taken, or delivered, are
+/// marked as done, and kept until they expire. This prevents other instances from queuing the
+/// same command again.
+#[derive(Clone, Debug)]
+pub struct PostgresQueueStore {
+    pool: Pool,
+}
+
+impl PostgresQueueStore {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    fn from_row(row: &Row) -> Result<(i64, QueuedCommand), ServiceError> {
+        let address = CommandAddress::new(
+            row.try_get::<_, String>("APPLICATION")?,
+            row.try_get::<_, String>("GATEWAY")?,
+            row.try_get::<_, String>("DEVICE")?,
+        );
+
+        let command = Command::new(
+            address,
+            row.try_get::<_, String>("COMMAND")?,
+            row.try_get("PAYLOAD")?,
+        )
+        .with_id(row.try_get::<_, Option<String>>("COMMAND_ID")?)
+        .with_correlation_id(row.try_get::<_, Option<String>>("CORRELATION_ID")?);
+
+        Ok((
+            row.try_get("ID")?,
+            QueuedCommand {
+                command,
+                queued: row.try_get("QUEUED")?,
+                expires: row.try_get("EXPIRES")?,
+            },
+        ))
+    }
+
+    /// Convert rows, returned in any order, to entries ordered by insertion.
+    fn from_rows(rows: Vec<Row>, reason: ExpiryReason) -> Result<Removed, ServiceError> {
+        let mut entries = rows
+            .iter()
+            .map(Self::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|(id, _)| *id);
+
+        Ok(entries
+            .into_iter()
+            .map(|(_, entry)| (entry, reason))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl QueueStore for PostgresQueueStore {
+    async fn push(
+        &self,
+        entry: QueuedCommand,
+        max_per_device: usize,
+        requeue: bool,
+        now: DateTime<Utc>,
+    ) -> Result<(bool, Removed), ServiceError> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let command = &entry.command;
+        let address = &command.address;
+
+        // serialize changes to the queue of a device, for evicting commands
+
+        t.execute(
+            "SELECT pg_advisory_xact_lock(hashtext($1 || '/' || $2 || '/' || $3))",
+            &[&address.app_id, &address.gateway_id, &address.device_id],
+        )
+        .await?;
+
+        let on_conflict = match requeue {
+            true => "DO UPDATE SET DONE = FALSE, QUEUED = EXCLUDED.QUEUED, EXPIRES = EXCLUDED.EXPIRES WHERE command_queue.DONE",
+            false => "DO NOTHING",
+        };
+
+        let inserted = t
+            .execute(
+                &format!(
+                    r#"
+INSERT INTO command_queue (
+    COMMAND_ID,
+    APPLICATION,
+    GATEWAY,
+    DEVICE,
+    COMMAND,
+    PAYLOAD,
+    CORRELATION_ID,
+    QUEUED,
+    EXPIRES
+) VALUES (
+    $1, $2, $3, $4, $5, $6, $7, $8, $9
+)
+ON CONFLICT (COMMAND_ID) {on_conflict}
+"#
+                ),
+                &[
+                    &command.id,
+                    &address.app_id,
+                    &address.gateway_id,
+                    &address.device_id,
+                    &command.command,
+                    &command.payload,
+                    &command.correlation_id,
+                    &entry.queued,
+                    &entry.expires,
+                ],
+            )
+            .await?;
+
+        if inserted == 0 {
+            t.commit().await?;
+            return Ok((false, vec![]));
+        }
+
+        let expired = t
+            .query(
+                &format!(
+                    r#"
+UPDATE command_queue SET
+    DONE = TRUE
+WHERE
+        APPLICATION = $1
+    AND
+        GATEWAY = $2
+    AND
+        DEVICE = $3
+    AND
+        NOT DONE
+    AND
+        EXPIRES <= $4
+RETURNING {COLUMNS}
+"#
+                ),
+                &[
+                    &address.app_id,
+                    &address.gateway_id,
+                    &address.device_id,
+                    &now,
+                ],
+            )
+            .await?;
+
+        let evicted = t
+            .query(
+                &format!(
+                    r#"
+UPDATE command_queue SET
+    DONE = TRUE
+WHERE ID IN (
+    SELECT ID FROM command_queue
+    WHERE
+            APPLICATION = $1
+        AND
+            GATEWAY = $2
+        AND
+            DEVICE = $3
+        AND
+            NOT DONE
+    ORDER BY ID DESC
+    OFFSET $4
+)
+RETURNING {COLUMNS}
+"#
+                ),
+                &[
+                    &address.app_id,
+                    &address.gateway_id,
+                    &address.device_id,
+                    &(max_per_device as i64),
+                ],
+            )
+            .await?;
+
+        t.commit().await?;
+
+        let mut removed = Self::from_rows(expired, ExpiryReason::Expired)?;
+        removed.extend(Self::from_rows(evicted, ExpiryReason::Evicted)?);
+
+        Ok((true, removed))
+    }
+
+    async fn take(
+        &self,
+        filter: &CommandFilter,
+        now: DateTime<Utc>,
+    ) -> Result<(Vec<Command>, Removed), ServiceError> {
+        let command_filter = CommandNameFilter::from(&filter.command_filter);
+
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let rows = t
+            .query(
+                &format!(
+                    r#"
+SELECT {COLUMNS}
+FROM command_queue
+WHERE
+        APPLICATION = $1
+    AND
+        GATEWAY = $2
+    AND
+        ($3::VARCHAR IS NULL OR DEVICE = $3)
+    AND
+        NOT DONE
+ORDER BY ID ASC
+FOR UPDATE SKIP LOCKED
+"#
+                ),
+                &[&filter.application, &filter.gateway, &filter.device],
+            )
+            .await?;
+
+        let mut ids = vec![];
+        let mut result = vec![];
+        let mut removed = vec![];
+
+        for row in rows {
+            let (id, entry) = Self::from_row(&row)?;
+            if entry.is_expired(now) {
+                ids.push(id);
+                removed.push((entry, ExpiryReason::Expired));
+            } else if command_filter.matches(&entry.command.command) {
+                ids.push(id);
+                result.push(entry.command);
+            }
+        }
+
+        if !ids.is_empty() {
+            t.execute(
+                "UPDATE command_queue SET DONE = TRUE WHERE ID = ANY($1)",
+                &[&ids],
+            )
+            .await?;
+        }
+
+        t.commit().await?;
+
+        Ok((result, removed))
+    }
+
+    async fn delivered(
+        &self,
+        command: &Command,
+        expires: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        if command.id.is_none() {
+            return Ok(());
+        }
+
+        let c = self.pool.get().await?;
+        let address = &command.address;
+
+        // record the command as done, even if it was never queued
+
+        c.execute(
+            r#"
+INSERT INTO command_queue (
+    COMMAND_ID,
+    APPLICATION,
+    GATEWAY,
+    DEVICE,
+    COMMAND,
+    QUEUED,
+    EXPIRES,
+    DONE
+) VALUES (
+    $1, $2, $3, $4, $5, NOW(), $6, TRUE
+)
+ON CONFLICT (COMMAND_ID) DO UPDATE SET DONE = TRUE
+"#,
+            &[
+                &command.id,
+                &address.app_id,
+                &address.gateway_id,
+                &address.device_id,
+                &command.command,
+                &expires,
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn prune(&self, now: DateTime<Utc>) -> Result<Removed, ServiceError> {
+        let mut c = self.pool.get().await?;
+        let t = c.build_transaction().start().await?;
+
+        let expired = t
+            .query(
+                &format!(
+                    r#"
+UPDATE command_queue SET
+    DONE = TRUE
+WHERE
+        NOT DONE
+    AND
+        EXPIRES <= $1
+RETURNING {COLUMNS}
+"#
+                ),
+                &[&now],
+            )
+            .await?;
+
+        t.execute(
+            "DELETE FROM command_queue WHERE DONE AND EXPIRES <= $1",
+            &[&now],
+        )
+        .await?;
+
+        t.commit().await?;
+
+        Self::from_rows(expired, ExpiryReason::Expired)
+    }
+}
@@ -0,0 +1,42 @@
+/// The reserved channel, devices use for replying to commands.
+///
+/// When publishing, the correlation ID of the command must be appended, separated by a colon:
+/// `$reply:<correlation-id>`. The event will carry the correlation ID as extension, and use
+/// this channel as subject.
+pub const REPLY_CHANNEL: &str = "$reply";
+
+/// The event type of replies to commands.
+pub const COMMAND_REPLY_TYPE_EVENT: &str = "io.drogue.command.reply.v1";
+
+/// Create the channel name for replying to a command.
+pub fn reply_channel(correlation_id: &str) -> String {
+    format!("{REPLY_CHANNEL}:{correlation_id}")
+}
+
+/// Parse a channel name, returning the correlation ID in case it is a reply channel.
+pub fn parse_reply_channel(channel: &str) -> Option<&str> {
+    match channel.split_once(':') {
+        Some((REPLY_CHANNEL, correlation_id)) if !correlation_id.is_empty() => Some(correlation_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_reply_channel("$reply:1234"), Some("1234"));
+        assert_eq!(parse_reply_channel("$reply:12:34"), Some("12:34"));
+        assert_eq!(parse_reply_channel("$reply:"), None);
+        assert_eq!(parse_reply_channel("$reply"), None);
+        assert_eq!(parse_reply_channel("reply:1234"), None);
+        assert_eq!(parse_reply_channel("foo"), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        assert_eq!(parse_reply_channel(&reply_channel("1234")), Some("1234"));
+    }
+}
@@ -1,13 +1,18 @@
 use crate::command::{Command, CommandDispatcher};
 use async_trait::async_trait;
-use drogue_cloud_event_common::stream::{
-    AutoAck, EventStream, EventStreamConfig, EventStreamError,
+use cloudevents::Event;
+use drogue_cloud_event_common::{
+    mqtt::MqttClientConfig,
+    stream::{
+        AutoAck, EventStream, EventStreamConfig, EventStreamError, MqttEventStream,
+        MqttEventStreamConfig,
+    },
 };
 use drogue_cloud_service_api::{
     health::{HealthCheckError, HealthChecked},
     kafka::{KafkaClientConfig, KafkaConfig},
 };
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use serde::Deserialize;
 use std::{
     convert::TryFrom,
@@ -38,7 +43,7 @@ impl KafkaCommandSource {
     where
         D: CommandDispatcher + Send + Sync + 'static,
     {
-        let mut source = EventStream::<AutoAck>::new(EventStreamConfig {
+        let source = EventStream::<AutoAck>::new(EventStreamConfig {
             kafka: KafkaConfig {
                 topic: config.topic,
                 client: kafka_client,
@@ -46,27 +51,7 @@ impl KafkaCommandSource {
             consumer_group: Some(config.consumer_group),
         })?;
 
-        let alive = Arc::new(AtomicBool::new(true));
-        let a = alive.clone();
-
-        let handle = tokio::spawn(async move {
-            while let Some(event) = source.next().await {
-                log::debug!("Command event: {:?}", event);
-                match event {
-                    Ok(event) => match Command::try_from(event) {
-                        Ok(command) => dispatcher.send(command).await,
-                        Err(_) => {
-                            log::info!("Failed to convert event to command");
-                        }
-                    },
-                    Err(err) => {
-                        log::info!("Failed to read next event: {}", err);
-                    }
-                }
-            }
-            log::info!("Exiting event loop!");
-            a.store(false, Ordering::Relaxed);
-        });
+        let (handle, alive) = dispatch(dispatcher, source);
 
         Ok(Self { handle, alive })
     }
@@ -88,3 +73,110 @@ impl HealthChecked for KafkaCommandSource {
         }
     }
 }
+
+/// A command source, consuming commands from an MQTT broker.
+///
+/// This subscribes to the command topics of all applications.
+pub struct MqttCommandSource {
+    handle: JoinHandle<()>,
+    alive: Arc<AtomicBool>,
+}
+
+impl MqttCommandSource {
+    pub fn new<D>(dispatcher: D, mqtt: MqttClientConfig, group: Option<String>) -> Self
+    where
+        D: CommandDispatcher + Send + Sync + 'static,
+    {
+        let topic = format!("{}/+/commands", mqtt.topic_prefix);
+        let source = MqttEventStream::new(MqttEventStreamConfig { mqtt, topic, group });
+
+        let (handle, alive) = dispatch(dispatcher, source);
+
+        Self { handle, alive }
+    }
+}
+
+impl Drop for MqttCommandSource {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[async_trait]
+impl HealthChecked for MqttCommandSource {
+    async fn is_alive(&self) -> Result<(), HealthCheckError> {
+        if self.alive.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            HealthCheckError::nok("Event loop is not alive")
+        }
+    }
+}
+
+/// A command source, using an MQTT broker if configured, Kafka otherwise.
+pub enum CommandSource {
+    Kafka(KafkaCommandSource),
+    Mqtt(MqttCommandSource),
+}
+
+impl CommandSource {
+    pub fn new<D>(
+        dispatcher: D,
+        kafka_client: KafkaClientConfig,
+        config: KafkaCommandSourceConfig,
+        mqtt: Option<MqttClientConfig>,
+    ) -> Result<Self, EventStreamError>
+    where
+        D: CommandDispatcher + Send + Sync + 'static,
+    {
+        Ok(match mqtt {
+            Some(mqtt) => Self::Mqtt(MqttCommandSource::new(
+                dispatcher,
+                mqtt,
+                Some(config.consumer_group),
+            )),
+            None => Self::Kafka(KafkaCommandSource::new(dispatcher, kafka_client, config)?),
+        })
+    }
+}
+
+#[async_trait]
+impl HealthChecked for CommandSource {
+    async fn is_alive(&self) -> Result<(), HealthCheckError> {
+        match self {
+            Self::Kafka(source) => source.is_alive().await,
+            Self::Mqtt(source) => source.is_alive().await,
+        }
+    }
+}
+
+/// Dispatch the commands of a stream of events.
+fn dispatch<D, S>(dispatcher: D, mut source: S) -> (JoinHandle<()>, Arc<AtomicBool>)
+where
+    D: CommandDispatcher + Send + Sync + 'static,
+    S: Stream<Item = Result<Event, EventStreamError>> + Send + Unpin + 'static,
+{
+    let alive = Arc::new(AtomicBool::new(true));
+    let a = alive.clone();
+
+    let handle = tokio::spawn(async move {
+        while let Some(event) = source.next().await {
+            log::debug!("Command event: {:?}", event);
+            match event {
+                Ok(event) => match Command::try_from(event) {
+                    Ok(command) => dispatcher.send(command).await,
+                    Err(_) => {
+                        log::info!("Failed to convert event to command");
+                    }
+                },
+                Err(err) => {
+                    log::info!("Failed to read next event: {}", err);
+                }
+            }
+        }
+        log::info!("Exiting event loop!");
+        a.store(false, Ordering::Relaxed);
+    });
+
+    (handle, alive)
+}
@@ -0,0 +1,323 @@
+use crate::{
+    command::Command,
+    sender::{
+        DownstreamSender, Publish, PublishError, PublishId, PublishOptions, PublishOutcome,
+        Publisher,
+    },
+};
+use async_trait::async_trait;
+use drogue_client::registry;
+use drogue_cloud_service_common::client::ClientConfig;
+use lazy_static::lazy_static;
+use lru::LruCache;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+lazy_static! {
+    pub static ref COMMAND_STATUS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "drogue_command_status",
+        "Reported delivery status of commands",
+        &["status"],
+    )
+    .unwrap();
+    pub static ref COMMAND_STATUS_DROPPED_COUNTER: IntCounter = register_int_counter!(
+        "drogue_command_status_dropped",
+        "Status reports of commands which got dropped, as sending fell behind"
+    )
+    .unwrap();
+}
+
+/// The channel, status events of commands are published on.
+pub const COMMAND_STATUS_CHANNEL: &str = "$command-status";
+
+/// The event type of status events of commands.
+pub const COMMAND_STATUS_TYPE_EVENT: &str = "io.drogue.command.status.v1";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CommandStatusConfig {
+    /// Report the delivery status of commands as events.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Access to the registry, required for sending status events.
+    #[serde(default)]
+    pub registry: Option<ClientConfig>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CommandStatus {
+    /// The command was handed over to a device session of the endpoint.
+    Delivered,
+    /// The device acknowledged the command, e.g. using an MQTT PUBACK.
+    Acknowledged,
+    /// The command expired, before it could be delivered.
+    Expired,
+    /// No device session was subscribed to the command, so the command got queued.
+    NoSubscriber,
+    /// A device session was subscribed, but filtered out the command by its name.
+    Filtered,
+}
+
+impl CommandStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Delivered => "Delivered",
+            Self::Acknowledged => "Acknowledged",
+            Self::Expired => "Expired",
+            Self::NoSubscriber => "NoSubscriber",
+            Self::Filtered => "Filtered",
+        }
+    }
+}
+
+/// The payload of a command status event.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandStatusEvent {
+    /// The name of the command.
+    pub command: String,
+    pub status: CommandStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl CommandStatusEvent {
+    pub fn new<C: Into<String>>(command: C, status: CommandStatus) -> Self {
+        Self {
+            command: command.into(),
+            status,
+            correlation_id: None,
+            reason: None,
+        }
+    }
+}
+
+/// Publish the status of a command, on the event stream of the application.
+pub async fn publish_status(
+    downstream: &DownstreamSender,
+    application: &registry::v1::Application,
+    device: PublishId,
+    sender: PublishId,
+    status: &CommandStatusEvent,
+) -> Result<PublishOutcome, PublishError> {
+    COMMAND_STATUS_COUNTER
+        .with_label_values(&[status.status.as_str()])
+        .inc();
+
+    downstream
+        .publish(
+            Publish {
+                application,
+                device,
+                sender,
+                channel: COMMAND_STATUS_CHANNEL.to_string(),
+                options: PublishOptions {
+                    r#type: Some(COMMAND_STATUS_TYPE_EVENT.to_string()),
+                    content_type: Some(mime::APPLICATION_JSON.to_string()),
+                    ..Default::default()
+                },
+            },
+            serde_json::to_vec(status).map_err(PublishError::Spec)?,
+        )
+        .await
+}
+
+/// Handle status changes of commands, processed by an endpoint.
+#[async_trait]
+pub trait CommandStatusHandler: Debug + Send + Sync {
+    async fn report(&self, command: &Command, status: CommandStatus, reason: Option<String>);
+}
+
+/// Number of reports buffered for sending, further reports get dropped.
+const REPORT_QUEUE_SIZE: usize = 1024;
+/// Number of applications cached for sending status events.
+const APPLICATION_CACHE_SIZE: NonZeroUsize = match NonZeroUsize::new(1024) {
+    Some(size) => size,
+    None => panic!(),
+};
+/// Time an application is cached, before looking it up again.
+const APPLICATION_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Report {
+    command: Command,
+    status: CommandStatus,
+    reason: Option<String>,
+}
+
+/// Send status events to the event stream of the application.
+///
+/// Events are sent from a background task, so that reporting doesn't delay the delivery of
+/// commands. If the task falls behind, reports get dropped.
+#[derive(Clone, Debug)]
+pub struct CommandStatusEventSender {
+    tx: mpsc::Sender<Report>,
+}
+
+impl CommandStatusEventSender {
+    /// Create a new sender, starting the background task.
+    ///
+    /// This must be called in the context of a Tokio runtime.
+    pub fn new(sender: DownstreamSender, registry: registry::v1::Client) -> Self {
+        let (tx, rx) = mpsc::channel(REPORT_QUEUE_SIZE);
+
+        tokio::spawn(
+            Reporter {
+                sender,
+                registry,
+                applications: LruCache::new(APPLICATION_CACHE_SIZE),
+            }
+            .run(rx),
+        );
+
+        Self { tx }
+    }
+
+    /// Create a new handler from the configuration, returns `None` if reporting is disabled.
+    pub async fn from_config(
+        config: CommandStatusConfig,
+        sender: DownstreamSender,
+    ) -> anyhow::Result<Option<Arc<dyn CommandStatusHandler>>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let registry = config.registry.ok_or_else(|| {
+            anyhow::anyhow!("Reporting the status of commands requires access to the registry")
+        })?;
+
+        Ok(Some(Arc::new(Self::new(
+            sender,
+            registry.into_client().await?,
+        ))))
+    }
+}
+
+#[async_trait]
+impl CommandStatusHandler for CommandStatusEventSender {
+    async fn report(&self, command: &Command, status: CommandStatus, reason: Option<String>) {
+        let report = Report {
+            command: command.clone(),
+            status,
+            reason,
+        };
+
+        if let Err(err) = self.tx.try_send(report) {
+            log::debug!("Dropping status report: {err}");
+            COMMAND_STATUS_DROPPED_COUNTER.inc();
+        }
+    }
+}
+
+struct Reporter {
+    sender: DownstreamSender,
+    registry: registry::v1::Client,
+    applications: LruCache<String, (Instant, registry::v1::Application)>,
+}
+
+impl Reporter {
+    async fn run(mut self, mut rx: mpsc::Receiver<Report>) {
+        while let Some(report) = rx.recv().await {
+            self.report(report).await;
+        }
+    }
+
+    /// Look up the application, using the cache.
+    async fn application(&mut self, name: &str) -> Option<registry::v1::Application> {
+        if let Some((fetched, application)) = self.applications.get(name) {
+            if fetched.elapsed() < APPLICATION_CACHE_TTL {
+                return Some(application.clone());
+            }
+        }
+
+        match self.registry.get_app(name).await {
+            Ok(Some(application)) => {
+                self.applications
+                    .put(name.to_string(), (Instant::now(), application.clone()));
+                Some(application)
+            }
+            Ok(None) => {
+                log::debug!("Application no longer found: {}", name);
+                self.applications.pop(name);
+                None
+            }
+            Err(err) => {
+                log::info!("Failed to look up application for status event: {err}");
+                None
+            }
+        }
+    }
+
+    async fn report(&mut self, report: Report) {
+        let Report {
+            command,
+            status,
+            reason,
+        } = report;
+
+        let application = match self.application(&command.address.app_id).await {
+            Some(application) => application,
+            None => return,
+        };
+
+        let event = CommandStatusEvent {
+            command: command.command,
+            status,
+            correlation_id: command.correlation_id,
+            reason,
+        };
+
+        let outcome = publish_status(
+            &self.sender,
+            &application,
+            PublishId {
+                name: command.address.device_id,
+                uid: None,
+            },
+            PublishId {
+                name: command.address.gateway_id,
+                uid: None,
+            },
+            &event,
+        )
+        .await;
+
+        match outcome {
+            Ok(PublishOutcome::Accepted) => {}
+            outcome => {
+                log::info!("Failed to send status event: {outcome:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_serialize() {
+        let mut status = CommandStatusEvent::new("reboot", CommandStatus::Expired);
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            json!({"command": "reboot", "status": "expired"})
+        );
+
+        status.status = CommandStatus::NoSubscriber;
+        status.correlation_id = Some("1234".into());
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            json!({"command": "reboot", "status": "noSubscriber", "correlationId": "1234"})
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use chrono::{DateTime, Utc};
 use drogue_client::error::ClientError;
 use drogue_cloud_service_api::webapp::{
-    error::PayloadError, http::StatusCode, HttpResponse, ResponseError,
+    error::PayloadError,
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
 };
 use serde::{Deserialize, Serialize};
 use std::fmt::Formatter;
@@ -19,6 +22,9 @@ pub enum EndpointError {
     /// The authentication process successfully evaluated that the access is denied.
     #[error("Authentication failed")]
     AuthenticationError,
+    /// The device is temporarily locked out, due to too many failed authentication attempts.
+    #[error("Authentication locked until: {}", until)]
+    AuthenticationLocked { until: DateTime<Utc> },
 }
 
 impl EndpointError {
@@ -29,6 +35,19 @@ impl EndpointError {
             EndpointError::ConfigurationError { .. } => "ConfigurationError",
             EndpointError::AuthenticationServiceError { .. } => "AuthenticationServiceError",
             EndpointError::AuthenticationError { .. } => "AuthenticationError",
+            EndpointError::AuthenticationLocked { .. } => "AuthenticationLocked",
+        }
+    }
+
+    /// The number of seconds a client should wait, before retrying the request.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            EndpointError::AuthenticationLocked { until } => {
+                // round up, so that the client doesn't retry too early
+                let millis = (*until - Utc::now()).num_milliseconds().max(0) as u64;
+                Some((millis + 999) / 1000)
+            }
+            _ => None,
         }
     }
 }
@@ -65,6 +84,7 @@ impl ResponseError for HttpEndpointError {
             EndpointError::ConfigurationError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             EndpointError::AuthenticationServiceError { .. } => StatusCode::SERVICE_UNAVAILABLE,
             EndpointError::AuthenticationError { .. } => StatusCode::FORBIDDEN,
+            EndpointError::AuthenticationLocked { .. } => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 
@@ -75,7 +95,11 @@ impl ResponseError for HttpEndpointError {
             message: self.to_string(),
             error: self.0.name().into(),
         };
-        HttpResponse::build(status_code).json(error_response)
+        let mut response = HttpResponse::build(status_code);
+        if let Some(retry_after) = self.0.retry_after() {
+            response.insert_header((header::RETRY_AFTER, retry_after));
+        }
+        response.json(error_response)
     }
 }
 
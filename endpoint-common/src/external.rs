@@ -0,0 +1,333 @@
+//! Authentication and TLS options for calling external endpoints.
+
+use reqwest::{Certificate, ClientBuilder, Identity, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+/// Time before the expiration of a token, when it gets refreshed.
+const REFRESH_BEFORE: Duration = Duration::from_secs(30);
+/// Timeout for connecting to the token endpoint.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Timeout for requesting a token, including connecting.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Token request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Token endpoint responded with: {0}")]
+    Response(reqwest::StatusCode),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Authentication {
+    #[default]
+    None,
+    Basic {
+        username: String,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+    /// OAuth2 client credentials flow.
+    #[serde(rename = "oauth2")]
+    OAuth2(ClientCredentials),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCredentials {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsOptions {
+    #[serde(default)]
+    pub insecure: bool,
+    /// Trusted root certificates, PEM encoded.
+    #[serde(default)]
+    pub certificate: Option<String>,
+    /// Client certificate, used for mTLS authentication.
+    #[serde(default)]
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCertificate {
+    /// Certificate chain, PEM encoded.
+    pub certificate: String,
+    /// Private key, PKCS#8 PEM encoded.
+    pub key: String,
+}
+
+impl TlsOptions {
+    /// Apply the options to a client builder.
+    pub fn apply(&self, mut client: ClientBuilder) -> Result<ClientBuilder, reqwest::Error> {
+        if self.insecure {
+            client = client
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        if let Some(cert) = &self.certificate {
+            client = client
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(Certificate::from_pem(cert.as_bytes())?);
+        }
+        if let Some(identity) = &self.client_certificate {
+            client = client.identity(Identity::from_pkcs8_pem(
+                identity.certificate.as_bytes(),
+                identity.key.as_bytes(),
+            )?);
+        }
+
+        Ok(client)
+    }
+}
+
+impl Authentication {
+    /// Apply the authentication to a request.
+    pub async fn apply(
+        &self,
+        request: RequestBuilder,
+        tokens: &TokenCache,
+    ) -> Result<RequestBuilder, AuthError> {
+        Ok(match self {
+            Self::None => request,
+            Self::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Self::Bearer { token } => request.bearer_auth(token),
+            Self::OAuth2(credentials) => request.bearer_auth(tokens.token(credentials).await?),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    access_token: String,
+    expires: Option<Instant>,
+}
+
+impl Token {
+    fn is_valid(&self, now: Instant) -> bool {
+        match self.expires {
+            Some(expires) => now + REFRESH_BEFORE < expires,
+            None => true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// The token of a set of client credentials.
+///
+/// Refreshing the token holds the lock, so that concurrent callers wait for the same request.
+type TokenSlot = Arc<Mutex<Option<Token>>>;
+
+/// Cache of OAuth2 access tokens, refreshing them before they expire.
+#[derive(Clone, Debug)]
+pub struct TokenCache {
+    client: reqwest::Client,
+    tokens: Arc<std::sync::Mutex<HashMap<ClientCredentials, TokenSlot>>>,
+}
+
+impl Default for TokenCache {
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            client,
+            tokens: Default::default(),
+        }
+    }
+}
+
+impl TokenCache {
+    /// Get a valid access token, requesting a new one if required.
+    pub async fn token(&self, credentials: &ClientCredentials) -> Result<String, AuthError> {
+        let slot = self
+            .tokens
+            .lock()
+            .unwrap()
+            .entry(credentials.clone())
+            .or_default()
+            .clone();
+
+        let mut token = slot.lock().await;
+        if let Some(token) = token.as_ref() {
+            if token.is_valid(Instant::now()) {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let new_token = self.request(credentials).await?;
+        let access_token = new_token.access_token.clone();
+        *token = Some(new_token);
+
+        Ok(access_token)
+    }
+
+    async fn request(&self, credentials: &ClientCredentials) -> Result<Token, AuthError> {
+        let mut form = vec![("grant_type", "client_credentials".to_string())];
+        if !credentials.scopes.is_empty() {
+            form.push(("scope", credentials.scopes.join(" ")));
+        }
+
+        let start = Instant::now();
+        let response = self
+            .client
+            .post(&credentials.token_url)
+            .basic_auth(&credentials.client_id, Some(&credentials.client_secret))
+            .form(&form)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(AuthError::Response(response.status()));
+        }
+
+        let response: TokenResponse = response.json().await?;
+
+        Ok(Token {
+            access_token: response.access_token,
+            expires: response
+                .expires_in
+                .map(|expires_in| start + Duration::from_secs(expires_in)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn test_parse_auth() {
+        let auth: Authentication = serde_json::from_value(json!({
+            "oauth2": {
+                "tokenUrl": "https://sso/token",
+                "clientId": "id",
+                "clientSecret": "secret",
+                "scopes": ["read", "write"],
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            auth,
+            Authentication::OAuth2(ClientCredentials {
+                token_url: "https://sso/token".into(),
+                client_id: "id".into(),
+                client_secret: "secret".into(),
+                scopes: vec!["read".into(), "write".into()],
+            })
+        );
+
+        let auth: Authentication =
+            serde_json::from_value(json!({"basic": {"username": "foo"}})).unwrap();
+        assert_eq!(
+            auth,
+            Authentication::Basic {
+                username: "foo".into(),
+                password: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_valid() {
+        let now = Instant::now();
+        let token = |expires| Token {
+            access_token: "token".into(),
+            expires,
+        };
+
+        assert!(token(None).is_valid(now));
+        assert!(token(Some(now + Duration::from_secs(60))).is_valid(now));
+        assert!(!token(Some(now + Duration::from_secs(10))).is_valid(now));
+    }
+
+    /// Start a token endpoint, returning the URL and the number of requests it received.
+    async fn token_endpoint() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    // the form is small, a single read gets the whole request
+                    let mut buf = [0u8; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    // slow down, so that concurrent callers queue up
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let body = format!(r#"{{"access_token":"token{n}","expires_in":3600}}"#);
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        let (token_url, requests) = token_endpoint().await;
+        let credentials = ClientCredentials {
+            token_url,
+            client_id: "id".into(),
+            client_secret: "secret".into(),
+            scopes: vec![],
+        };
+
+        let tokens = TokenCache::default();
+        let results = futures::future::join_all((0..10).map(|_| {
+            let tokens = tokens.clone();
+            let credentials = credentials.clone();
+            async move { tokens.token(&credentials).await.unwrap() }
+        }))
+        .await;
+
+        assert!(results.iter().all(|token| token == "token0"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // cached
+        assert_eq!(tokens.token(&credentials).await.unwrap(), "token0");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
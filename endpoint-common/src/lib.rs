@@ -1,6 +1,7 @@
 pub mod auth;
 pub mod command;
 pub mod error;
+pub mod external;
 pub mod psk;
 pub mod sender;
 pub mod sink;
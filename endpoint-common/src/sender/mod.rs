@@ -1,24 +1,32 @@
 mod process;
+mod state;
 
-pub use process::ExternalClientPoolConfig;
+pub use process::{ExternalClientPool, ExternalClientPoolConfig};
+pub use state::StateRecorder;
 
 use crate::{
-    sender::process::{ExternalClientPool, Outcome},
+    command::{parse_reply_channel, COMMAND_REPLY_TYPE_EVENT, REPLY_CHANNEL},
+    sender::process::{Outcome, ScriptCache},
     sink::{Sink, SinkError, SinkTarget},
     EXT_PARTITIONKEY,
 };
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use cloudevents::{event::Data, Event, EventBuilder, EventBuilderV10};
+use cloudevents::{event::Data, AttributesReader, Event, EventBuilder, EventBuilderV10};
 use drogue_client::{
     meta::v1::{NonScopedMetadata, ScopedMetadata},
-    registry,
+    registry, Translator,
 };
 use drogue_cloud_service_api::{
-    webapp::HttpResponse, EXT_APPLICATION_UID, EXT_DEVICE_UID, EXT_INSTANCE, EXT_SENDER,
+    services::{
+        device_state::LastValuesSpec,
+        twin::{TwinSpec, TwinUpdate},
+    },
+    webapp::HttpResponse,
+    EXT_APPLICATION_UID, EXT_CORRELATION_ID, EXT_DEVICE_UID, EXT_INSTANCE, EXT_SENDER,
     EXT_SENDER_UID,
 };
-use drogue_cloud_service_common::{Id, IdInjector};
+use drogue_cloud_service_common::{client::DeviceStateClient, Id, IdInjector};
 use lazy_static::lazy_static;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use process::Processor;
@@ -187,6 +195,8 @@ pub struct PublishOptions {
     pub content_type: Option<String>,
     pub extensions: HashMap<String, String>,
     pub r#type: Option<String>,
+    /// Labels of the device, available to the processing rules.
+    pub labels: HashMap<String, String>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -199,7 +209,7 @@ pub enum PublishOutcome {
     QueueFull,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     /// Cloud to device messaging
     Upstream,
@@ -213,6 +223,7 @@ pub struct UpstreamSender {
     sink: Arc<dyn Sink>,
     instance: String,
     pool: ExternalClientPool,
+    scripts: ScriptCache,
 }
 
 impl UpstreamSender {
@@ -225,6 +236,7 @@ impl UpstreamSender {
             sink: Arc::new(sink),
             instance: instance.into(),
             pool: ExternalClientPool::new(config),
+            scripts: Default::default(),
         })
     }
 }
@@ -235,6 +247,9 @@ pub struct DownstreamSender {
     sink: Arc<dyn Sink>,
     instance: String,
     pool: ExternalClientPool,
+    scripts: ScriptCache,
+    twin: Option<StateRecorder>,
+    last_values: Option<StateRecorder>,
 }
 
 impl DownstreamSender {
@@ -247,8 +262,64 @@ impl DownstreamSender {
             sink: Arc::new(sink),
             instance,
             pool: ExternalClientPool::new(config),
+            scripts: Default::default(),
+            twin: None,
+            last_values: None,
         })
     }
+
+    /// Report events on the twin channel of an application as reported properties.
+    ///
+    /// Reports are sent in the background, this must be called in the context of a Tokio runtime.
+    pub fn with_twin(mut self, client: DeviceStateClient) -> Self {
+        self.twin = Some(StateRecorder::new(client));
+        self
+    }
+
+    /// Record the last event of each device and channel, for applications with a `lastValues`
+    /// section.
+    ///
+    /// Events are recorded in the background, this must be called in the context of a Tokio
+    /// runtime.
+    pub fn with_last_values(mut self, client: DeviceStateClient) -> Self {
+        self.last_values = Some(StateRecorder::new(client));
+        self
+    }
+
+    /// Get the ID of an event, if it should be recorded as last value.
+    fn last_value(app: &registry::v1::Application, event: &Event) -> Option<Id> {
+        match app.section::<LastValuesSpec>() {
+            Some(Ok(_)) => Id::from_event(event),
+            _ => None,
+        }
+    }
+
+    /// Extract the twin update of an event, if it was sent on the twin channel.
+    fn twin_update(app: &registry::v1::Application, event: &Event) -> Option<(Id, TwinUpdate)> {
+        let spec = match app.section::<TwinSpec>() {
+            Some(Ok(spec)) => spec,
+            _ => return None,
+        };
+
+        if event.subject() != Some(spec.channel.as_str()) {
+            return None;
+        }
+
+        let update = match event.data() {
+            Some(Data::Json(value)) => serde_json::from_value(value.clone()),
+            Some(Data::Binary(data)) => serde_json::from_slice(data),
+            Some(Data::String(data)) => serde_json::from_str(data),
+            None => return None,
+        };
+
+        match update {
+            Ok(update) => Id::from_event(event).map(|id| (id, update)),
+            Err(err) => {
+                log::info!("Ignoring invalid twin update: {err}");
+                None
+            }
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -273,6 +344,10 @@ impl Publisher for DownstreamSender {
         self.pool.clone()
     }
 
+    fn scripts(&self) -> ScriptCache {
+        self.scripts.clone()
+    }
+
     #[inline]
     fn direction() -> Direction {
         Direction::Downstream
@@ -283,7 +358,31 @@ impl Publisher for DownstreamSender {
         app: &registry::v1::Application,
         event: Event,
     ) -> Result<PublishOutcome, SinkError> {
-        self.sink.publish(SinkTarget::Events(app), event).await
+        let update = match &self.twin {
+            Some(_) => Self::twin_update(app, &event),
+            None => None,
+        };
+        let last_value = match &self.last_values {
+            Some(_) => Self::last_value(app, &event).map(|id| (id, event.clone())),
+            None => None,
+        };
+
+        let outcome = self.sink.publish(SinkTarget::Events(app), event).await?;
+
+        if let (Some(twin), Some((id, update)), PublishOutcome::Accepted) =
+            (&self.twin, update, outcome)
+        {
+            // the event was accepted, failing to update the twin doesn't change that
+            twin.twin(id, update);
+        }
+
+        if let (Some(last_values), Some((id, event)), PublishOutcome::Accepted) =
+            (&self.last_values, last_value, outcome)
+        {
+            last_values.last_value(id, event);
+        }
+
+        Ok(outcome)
     }
 }
 
@@ -297,6 +396,10 @@ impl Publisher for UpstreamSender {
         self.pool.clone()
     }
 
+    fn scripts(&self) -> ScriptCache {
+        self.scripts.clone()
+    }
+
     #[inline]
     fn direction() -> Direction {
         Direction::Upstream
@@ -317,6 +420,8 @@ pub trait Publisher {
 
     fn pool(&self) -> ExternalClientPool;
 
+    fn scripts(&self) -> ScriptCache;
+
     fn direction() -> Direction;
 
     async fn send(
@@ -354,18 +459,31 @@ pub trait Publisher {
 
         let key = format!("{}/{}", app_enc, sender_enc);
 
+        // replies to commands carry the correlation ID in the channel name
+        let (channel, correlation_id) = match parse_reply_channel(&publish.channel) {
+            Some(correlation_id) if Self::direction() == Direction::Downstream => {
+                (REPLY_CHANNEL, Some(correlation_id.to_string()))
+            }
+            _ => (publish.channel.as_str(), None),
+        };
+
+        let default_type = match correlation_id {
+            Some(_) => COMMAND_REPLY_TYPE_EVENT,
+            None => DEFAULT_TYPE_EVENT,
+        };
+
         let mut event = EventBuilderV10::new()
             .id(uuid::Uuid::new_v4().to_string())
             .ty(publish
                 .options
                 .r#type
-                .unwrap_or_else(|| DEFAULT_TYPE_EVENT.to_string()))
+                .unwrap_or_else(|| default_type.to_string()))
             // we need an "absolute" URL for the moment: until 0.4 is released
             // see: https://github.com/cloudevents/sdk-rust/issues/106
             // also, go has in issue with percent encoded segments in the start
             .source(format!("drogue://{app_id}/{device_enc}"))
             .inject(Id::new(app_id, publish.device.name))
-            .subject(&publish.channel)
+            .subject(channel)
             .time(Utc::now());
 
         event = event.extension(
@@ -384,6 +502,10 @@ pub trait Publisher {
         event = event.extension(EXT_INSTANCE, self.instance());
         event = event.extension(EXT_SENDER, publish.sender.name);
 
+        if let Some(correlation_id) = correlation_id {
+            event = event.extension(EXT_CORRELATION_ID, correlation_id);
+        }
+
         if let Some(data_schema) = publish.options.data_schema {
             event = event.extension("dataschema", data_schema);
         }
@@ -426,7 +548,9 @@ pub trait Publisher {
         // handle publish steps
 
         let processor = Processor::try_from((Self::direction(), publish.application, self.pool()))
-            .map_err(PublishError::Spec)?;
+            .map_err(PublishError::Spec)?
+            .with_labels(publish.options.labels)
+            .with_scripts(self.scripts());
         match processor.process(event).await? {
             Outcome::Rejected(reason) => {
                 // event was rejected
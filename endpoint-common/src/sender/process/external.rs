@@ -1,23 +1,44 @@
+use super::ExternalEndpoint;
+use crate::external::{AuthError, TlsOptions, TokenCache};
 use cloudevents::{
     binding::reqwest::{RequestBuilderExt, RequestSerializer},
     message::StructuredDeserializer,
     Event,
 };
-use drogue_client::registry::v1::{
-    Authentication, ContentMode, ExternalEndpoint, RequestType, TlsOptions,
-};
+use drogue_client::registry::v1::{ContentMode, RequestType};
 use drogue_cloud_service_common::reqwest::to_method;
 use http::{header::HeaderName, HeaderMap, HeaderValue, Method};
+use lazy_static::lazy_static;
 use lru::LruCache;
-use reqwest::{Certificate, Url};
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use reqwest::Url;
 use serde::Deserialize;
 use std::num::NonZeroUsize;
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use tokio::sync::Mutex;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
+lazy_static! {
+    static ref EXTERNAL_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "drogue_external_endpoint_request_duration_seconds",
+        "Duration of requests to external endpoints",
+        &["endpoint"]
+    )
+    .unwrap();
+    static ref EXTERNAL_REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "drogue_external_endpoint_errors",
+        "Failed requests to external endpoints",
+        &["endpoint", "reason"]
+    )
+    .unwrap();
+}
+
 #[derive(Debug, Error)]
 pub enum ExternalError {
     #[error("Invalid configuration: {0}")]
@@ -26,11 +47,15 @@ pub enum ExternalError {
     Request(#[from] reqwest::Error),
     #[error("Cloud event error: {0}")]
     CloudEvent(#[from] cloudevents::message::Error),
+    #[error("Authentication error: {0}")]
+    Authentication(#[from] AuthError),
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ExternalClientPoolConfig {
     pub capacity: NonZeroUsize,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
 }
 
 const DEFAULT_CAPACITY: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(8) };
@@ -39,6 +64,55 @@ impl Default for ExternalClientPoolConfig {
     fn default() -> Self {
         Self {
             capacity: DEFAULT_CAPACITY,
+            circuit_breaker: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures, opening the circuit.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Time the circuit stays open, before requests are tried again.
+    #[serde(with = "humantime_serde", default = "default_reset_timeout")]
+    pub reset_timeout: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            reset_timeout: default_reset_timeout(),
+        }
+    }
+}
+
+const fn default_failure_threshold() -> u32 {
+    5
+}
+
+const fn default_reset_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// State of the circuit breaker of a single endpoint.
+#[derive(Clone, Debug, Default)]
+struct CircuitState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitState {
+    fn is_open(&self, now: Instant) -> bool {
+        // once the timeout expired, we are "half open", and let requests pass again
+        matches!(self.open_until, Some(until) if now < until)
+    }
+
+    fn failure(&mut self, now: Instant, config: &CircuitBreakerConfig) {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= config.failure_threshold {
+            self.open_until = Some(now + config.reset_timeout);
         }
     }
 }
@@ -46,6 +120,9 @@ impl Default for ExternalClientPoolConfig {
 #[derive(Clone, Debug)]
 pub struct ExternalClientPool {
     cache: Arc<Mutex<LruCache<Option<TlsOptions>, ExternalClient>>>,
+    circuits: Arc<std::sync::Mutex<HashMap<String, CircuitState>>>,
+    circuit_breaker: CircuitBreakerConfig,
+    tokens: TokenCache,
 }
 
 impl Default for ExternalClientPool {
@@ -57,24 +134,95 @@ impl Default for ExternalClientPool {
 impl ExternalClientPool {
     pub fn new(config: ExternalClientPoolConfig) -> Self {
         let cache = Arc::new(Mutex::new(LruCache::new(config.capacity)));
-        Self { cache }
+        Self {
+            cache,
+            circuits: Default::default(),
+            circuit_breaker: config.circuit_breaker,
+            tokens: Default::default(),
+        }
+    }
+
+    /// The cache of OAuth2 tokens, shared by all clients of the pool.
+    pub fn tokens(&self) -> TokenCache {
+        self.tokens.clone()
+    }
+
+    /// Check if the circuit of the endpoint is open, rejecting requests.
+    pub fn is_open(&self, endpoint: &ExternalEndpoint) -> bool {
+        let circuits = self.circuits.lock().unwrap();
+        let open = circuits
+            .get(&endpoint.url)
+            .map_or(false, |state| state.is_open(Instant::now()));
+
+        if open {
+            EXTERNAL_REQUEST_ERRORS
+                .with_label_values(&[&endpoint_label(endpoint), "circuitOpen"])
+                .inc();
+        }
+
+        open
+    }
+
+    /// Record the outcome of a request to an endpoint.
+    ///
+    /// The error reason is `None` if the request was successful.
+    pub fn record(&self, endpoint: &ExternalEndpoint, duration: Duration, error: Option<&str>) {
+        let label = endpoint_label(endpoint);
+        EXTERNAL_REQUEST_DURATION
+            .with_label_values(&[&label])
+            .observe(duration.as_secs_f64());
+
+        let mut circuits = self.circuits.lock().unwrap();
+        match error {
+            None => {
+                // a successful request closes the circuit, we only keep track of failing endpoints
+                circuits.remove(&endpoint.url);
+            }
+            Some(reason) => {
+                EXTERNAL_REQUEST_ERRORS
+                    .with_label_values(&[&label, reason])
+                    .inc();
+                circuits
+                    .entry(endpoint.url.clone())
+                    .or_default()
+                    .failure(Instant::now(), &self.circuit_breaker);
+            }
+        }
     }
 
     pub async fn get(&self, endpoint: &ExternalEndpoint) -> Result<ExternalClient, ExternalError> {
+        Ok(self.client_for(&endpoint.tls).await?)
+    }
+
+    /// Get an HTTP client for the TLS options, cached by the pool.
+    pub async fn http_client(&self, tls: &TlsOptions) -> Result<reqwest::Client, reqwest::Error> {
+        Ok(self.client_for(&Some(tls.clone())).await?.client)
+    }
+
+    async fn client_for(&self, tls: &Option<TlsOptions>) -> Result<ExternalClient, reqwest::Error> {
         let mut cache = self.cache.lock().await;
-        if let Some(client) = cache.get(&endpoint.tls) {
+        if let Some(client) = cache.get(tls) {
             Ok(client.clone())
         } else {
-            let client = ExternalClient::new(endpoint.tls.as_ref())?;
-            cache.put(endpoint.tls.clone(), client.clone());
+            let client = ExternalClient::new(tls.as_ref(), self.tokens.clone())?;
+            cache.put(tls.clone(), client.clone());
             Ok(client)
         }
     }
 }
 
+/// The label of an endpoint for metrics, the host, to keep the cardinality low.
+fn endpoint_label(endpoint: &ExternalEndpoint) -> String {
+    Url::parse(&endpoint.url)
+        .ok()
+        .and_then(|url| url.host_str().map(ToString::to_string))
+        .unwrap_or_else(|| "invalid".to_string())
+}
+
 #[derive(Clone, Debug)]
 pub struct ExternalClient {
     client: reqwest::Client,
+    tokens: TokenCache,
 }
 
 #[derive(Clone, Debug)]
@@ -99,24 +247,16 @@ impl IntoPayload for RequestType {
 }
 
 impl ExternalClient {
-    pub fn new(tls: Option<&TlsOptions>) -> Result<Self, reqwest::Error> {
+    pub fn new(tls: Option<&TlsOptions>, tokens: TokenCache) -> Result<Self, reqwest::Error> {
         let mut client = reqwest::Client::builder();
 
         if let Some(tls) = tls {
-            if tls.insecure {
-                client = client
-                    .danger_accept_invalid_certs(true)
-                    .danger_accept_invalid_hostnames(true);
-            }
-            if let Some(cert) = &tls.certificate {
-                client = client
-                    .tls_built_in_root_certs(false)
-                    .add_root_certificate(Certificate::from_pem(cert.as_bytes())?);
-            }
+            client = tls.apply(client)?;
         }
 
         Ok(Self {
             client: client.build()?,
+            tokens,
         })
     }
 
@@ -155,15 +295,7 @@ impl ExternalClient {
             request = request.headers(headers);
         }
 
-        match &endpoint.auth {
-            Authentication::None => {}
-            Authentication::Basic { username, password } => {
-                request = request.basic_auth(username, password.as_ref());
-            }
-            Authentication::Bearer { token } => {
-                request = request.bearer_auth(token);
-            }
-        }
+        request = endpoint.auth.apply(request, &self.tokens).await?;
 
         request = request.timeout(endpoint.timeout.unwrap_or(DEFAULT_TIMEOUT));
 
@@ -196,9 +328,32 @@ impl ExternalClient {
 
 #[cfg(test)]
 mod test {
-    use drogue_client::registry::v1::TlsOptions;
+    use super::*;
     use std::collections::HashMap;
 
+    #[test]
+    fn test_circuit() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::from_secs(10),
+        };
+        let now = Instant::now();
+        let mut state = CircuitState::default();
+
+        state.failure(now, &config);
+        assert!(!state.is_open(now));
+        state.failure(now, &config);
+        assert!(state.is_open(now));
+        assert!(state.is_open(now + Duration::from_secs(5)));
+
+        // half open
+        let later = now + Duration::from_secs(11);
+        assert!(!state.is_open(later));
+        // failing again, re-opens
+        state.failure(later, &config);
+        assert!(state.is_open(later + Duration::from_secs(5)));
+    }
+
     #[test]
     fn test_key() {
         let key1a = None;
@@ -206,14 +361,17 @@ mod test {
         let key2a = Some(TlsOptions {
             insecure: false,
             certificate: None,
+            client_certificate: None,
         });
         let key2b = Some(TlsOptions {
             insecure: false,
             certificate: None,
+            client_certificate: None,
         });
         let key3 = Some(TlsOptions {
             insecure: true,
             certificate: None,
+            client_certificate: None,
         });
 
         let mut map = HashMap::new();
@@ -1,23 +1,33 @@
 mod external;
+mod rules;
+mod script;
+mod transform;
 
 pub use external::{ExternalClientPool, ExternalClientPoolConfig};
+pub use rules::*;
+pub use script::*;
+pub use transform::*;
 
 use crate::sender::{
     is_json,
     process::external::{ExternalError, IntoPayload},
     Direction,
 };
-use cloudevents::{event::ExtensionValue, AttributesReader, AttributesWriter};
+use cloudevents::{
+    event::{Data, ExtensionValue},
+    AttributesReader, AttributesWriter,
+};
 use drogue_client::{
     registry::{
         self,
-        v1::{Application, EnrichSpec, ResponseType, Rule, Step, ValidateSpec, When},
+        v1::{Application, ResponseType},
     },
     Translator,
 };
 use http::{header::CONTENT_TYPE, StatusCode};
 use reqwest::Url;
 use serde_json::Value;
+use std::{borrow::Cow, collections::HashMap, future::Future, time::Instant};
 use thiserror::Error;
 use tracing::instrument;
 
@@ -42,6 +52,21 @@ pub enum Error {
     ExternalEndpoint(#[from] ExternalError),
     #[error("External endpoint response: {0}")]
     ExternalResponse(String),
+    #[error("External endpoint unavailable: {0}")]
+    ExternalUnavailable(String),
+}
+
+impl Error {
+    /// The reason for metrics, in case this is a failure of the external endpoint.
+    fn external_failure(&self) -> Option<&'static str> {
+        match self {
+            Self::ExternalEndpoint(ExternalError::Request(_)) => Some("request"),
+            Self::ExternalEndpoint(ExternalError::CloudEvent(_)) => Some("cloudEvent"),
+            Self::ExternalEndpoint(ExternalError::Authentication(_)) => Some("authentication"),
+            Self::ExternalResponse(_) => Some("response"),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -58,19 +83,46 @@ pub enum Outcome {
 pub struct Processor {
     pool: ExternalClientPool,
     rules: Vec<Rule>,
+    labels: HashMap<String, String>,
+    scripts: ScriptCache,
+    revision: Option<Revision>,
 }
 
 impl Processor {
     #[inline]
     pub fn new(pool: ExternalClientPool, rules: Vec<Rule>) -> Self {
-        Self { pool, rules }
+        Self {
+            pool,
+            rules,
+            labels: Default::default(),
+            scripts: Default::default(),
+            revision: None,
+        }
+    }
+
+    /// Set the cache to use for compiled scripts.
+    pub fn with_scripts(mut self, scripts: ScriptCache) -> Self {
+        self.scripts = scripts;
+        self
+    }
+
+    /// Set the application revision the rules originate from, used for caching scripts.
+    pub fn with_revision(mut self, revision: Revision) -> Self {
+        self.revision = Some(revision);
+        self
+    }
+
+    /// Set the labels of the device, which can be checked by the rules.
+    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.labels = labels;
+        self
     }
 
     #[instrument(level = "debug", skip_all, err, fields(num_rules=self.rules.len()))]
     pub async fn process(&self, mut event: cloudevents::Event) -> Result<Outcome, Error> {
-        for rule in &self.rules {
-            if Self::is_when(&rule.when, &event) {
-                event = match self.handle(&rule.then, event).await? {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if self.is_when(&rule.when, &event) {
+                event = match self.handle(index, &rule.then, event).await? {
                     // continue processing
                     StepOutcome::Continue(event) => event,
                     // stop processing, return as accepted
@@ -87,19 +139,19 @@ impl Processor {
         Ok(Outcome::Accepted(event))
     }
 
-    fn is_when(when: &When, event: &cloudevents::Event) -> bool {
+    fn is_when(&self, when: &When, event: &cloudevents::Event) -> bool {
         match when {
             // matches always
             When::Always => true,
             // invert outcome
-            When::Not(when) => !Self::is_when(when, event),
+            When::Not(when) => !self.is_when(when, event),
             // matches when not empty and all children match
             When::And(when) => {
                 if when.is_empty() {
                     return false;
                 }
                 for when in when {
-                    if !Self::is_when(when, event) {
+                    if !self.is_when(when, event) {
                         return false;
                     }
                 }
@@ -109,7 +161,7 @@ impl Processor {
             When::Or(when) => {
                 let mut result = false;
                 for when in when {
-                    if Self::is_when(when, event) {
+                    if self.is_when(when, event) {
                         result = true;
                         break;
                     }
@@ -121,16 +173,69 @@ impl Processor {
                 Some(subject) => channel == subject,
                 _ => false,
             },
+            // compare the value of an attribute
+            When::Attribute(condition) => condition.comparison.matches(
+                Self::attribute(event, condition.name)
+                    .map(Value::String)
+                    .as_ref(),
+            ),
+            // compare the value of an extension
+            When::Extension(condition) => condition.comparison.matches(
+                event
+                    .extension(&condition.name)
+                    .map(|value| match value {
+                        ExtensionValue::String(value) => Value::String(value.clone()),
+                        ExtensionValue::Boolean(value) => Value::Bool(*value),
+                        ExtensionValue::Integer(value) => Value::from(*value),
+                    })
+                    .as_ref(),
+            ),
+            // compare the value of a device label
+            When::Label(condition) => condition.comparison.matches(
+                self.labels
+                    .get(&condition.name)
+                    .map(|value| Value::String(value.clone()))
+                    .as_ref(),
+            ),
+            // compare a value of the payload
+            When::Payload(condition) => match Self::payload(event) {
+                Some(payload) => condition
+                    .comparison
+                    .matches(payload.pointer(&condition.pointer)),
+                None => false,
+            },
+        }
+    }
+
+    fn attribute(event: &cloudevents::Event, attribute: Attribute) -> Option<String> {
+        match attribute {
+            Attribute::Id => Some(event.id().to_string()),
+            Attribute::Type => Some(event.ty().to_string()),
+            Attribute::Source => Some(event.source().to_string()),
+            Attribute::Subject => event.subject().map(ToString::to_string),
+            Attribute::DataContentType => event.datacontenttype().map(ToString::to_string),
+            Attribute::DataSchema => event.dataschema().map(ToString::to_string),
+        }
+    }
+
+    /// Get the payload as JSON, if it is JSON.
+    fn payload(event: &cloudevents::Event) -> Option<Cow<Value>> {
+        match event.data()? {
+            Data::Json(value) => Some(Cow::Borrowed(value)),
+            _ if !is_json(event.datacontenttype().unwrap_or_default()) => None,
+            Data::Binary(data) => serde_json::from_slice(data).ok().map(Cow::Owned),
+            Data::String(data) => serde_json::from_str(data).ok().map(Cow::Owned),
         }
     }
 
     async fn handle(
         &self,
+        rule: usize,
         then: &[Step],
         mut event: cloudevents::Event,
     ) -> Result<StepOutcome, Error> {
-        for step in then {
-            event = match self.step(step, event).await? {
+        for (index, step) in then.iter().enumerate() {
+            event = match self.step(step, (rule, index), event).await? {
                 StepOutcome::Continue(event) => event,
                 StepOutcome::Accept(event) => return Ok(StepOutcome::Accept(event)),
                 StepOutcome::Drop => return Ok(StepOutcome::Drop),
@@ -141,7 +246,12 @@ impl Processor {
         Ok(StepOutcome::Continue(event))
     }
 
-    async fn step(&self, step: &Step, mut event: cloudevents::Event) -> Result<StepOutcome, Error> {
+    async fn step(
+        &self,
+        step: &Step,
+        (rule, index): (usize, usize),
+        mut event: cloudevents::Event,
+    ) -> Result<StepOutcome, Error> {
         match step {
             Step::Drop => Ok(StepOutcome::Drop),
             Step::Break => Ok(StepOutcome::Accept(event)),
@@ -162,8 +272,79 @@ impl Processor {
                 event.remove_extension(name);
                 Ok(StepOutcome::Continue(event))
             }
-            Step::Validate(spec) => self.validate(spec, event).await,
-            Step::Enrich(spec) => self.enrich(spec, event).await,
+            Step::Validate(step) => {
+                self.external(&step.spec.endpoint, &step.policy, event, |event| {
+                    self.validate(&step.spec, event)
+                })
+                .await
+            }
+            Step::Enrich(step) => {
+                self.external(&step.spec.endpoint, &step.policy, event, |event| {
+                    self.enrich(&step.spec, event)
+                })
+                .await
+            }
+            Step::Transform(transform) => match transform.apply(event) {
+                Ok(event) => Ok(StepOutcome::Continue(event)),
+                Err(TransformError::Payload(reason)) => Ok(StepOutcome::Reject(reason)),
+                Err(TransformError::Config(reason)) => Err(Error::Config(reason)),
+            },
+            Step::Script(script) => {
+                let key = self.revision.as_ref().map(|revision| ScriptKey {
+                    revision: revision.clone(),
+                    rule,
+                    step: index,
+                });
+                script.run(&self.scripts, key, event).await
+            }
+        }
+    }
+
+    /// Call an external endpoint, applying the retry and failure policy.
+    async fn external<F, Fut>(
+        &self,
+        endpoint: &ExternalEndpoint,
+        policy: &FailurePolicy,
+        event: cloudevents::Event,
+        f: F,
+    ) -> Result<StepOutcome, Error>
+    where
+        F: Fn(cloudevents::Event) -> Fut,
+        Fut: Future<Output = Result<StepOutcome, Error>>,
+    {
+        let mut delay = policy.retry.initial_delay;
+        let mut retries = 0;
+
+        let err = loop {
+            if self.pool.is_open(endpoint) {
+                break Error::ExternalUnavailable("Circuit is open".into());
+            }
+
+            let start = Instant::now();
+            let result = f(event.clone()).await;
+            let failure = result.as_ref().err().and_then(Error::external_failure);
+            self.pool.record(endpoint, start.elapsed(), failure);
+
+            match result {
+                Err(err) if failure.is_some() => {
+                    if retries >= policy.retry.max_retries {
+                        break err;
+                    }
+                    log::debug!("External endpoint failed, retrying in {delay:?}: {err}");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(policy.retry.max_delay);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        };
+
+        log::info!("External endpoint failed: {err}");
+
+        match policy.on_failure {
+            FailureMode::Fail => Err(err),
+            FailureMode::Accept => Ok(StepOutcome::Continue(event)),
+            FailureMode::Drop => Ok(StepOutcome::Drop),
         }
     }
 
@@ -321,22 +502,29 @@ impl TryFrom<(Direction, &registry::v1::Application, ExternalClientPool)> for Pr
     type Error = serde_json::Error;
 
     fn try_from(value: (Direction, &Application, ExternalClientPool)) -> Result<Self, Self::Error> {
+        let revision = Revision {
+            application: value.1.metadata.uid.clone(),
+            resource_version: value.1.metadata.resource_version.clone(),
+            direction: value.0,
+        };
+
         Ok(Self::new(
             value.2,
             match value.0 {
                 Direction::Upstream => value
                     .1
-                    .section::<registry::v1::CommandSpec>()
+                    .section::<CommandSpec>()
                     .transpose()?
                     .map(|spec| spec.rules),
                 Direction::Downstream => value
                     .1
-                    .section::<registry::v1::PublishSpec>()
+                    .section::<PublishSpec>()
                     .transpose()?
                     .map(|spec| spec.rules),
             }
             .unwrap_or_default(),
-        ))
+        )
+        .with_revision(revision))
     }
 }
 
@@ -344,8 +532,18 @@ impl TryFrom<(Direction, &registry::v1::Application, ExternalClientPool)> for Pr
 mod test {
     use super::*;
     use cloudevents::EventBuilder;
-    use drogue_client::registry::v1::PublishSpec;
     use serde_json::json;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
 
     impl TryFrom<serde_json::Value> for Processor {
         type Error = serde_json::Error;
@@ -451,6 +649,268 @@ mod test {
         )
     }
 
+    #[tokio::test]
+    async fn test_payload_condition() {
+        let spec = json!({
+            "rules": [
+                {
+                    "when": {
+                        "and": [
+                            { "attribute": { "name": "type", "value": "type" } },
+                            { "payload": { "pointer": "/temp/value", "op": "gt", "value": 20 } },
+                        ]
+                    },
+                    "then": [
+                        { "reject": "Too hot" },
+                    ]
+                }
+            ]
+        });
+
+        assert_process(
+            spec.clone(),
+            event("id1", "type", "source", "chan1")
+                .data("application/json", json!({"temp": {"value": 21.5}}))
+                .build()
+                .unwrap(),
+            Outcome::Rejected("Too hot".into()),
+        )
+        .await;
+
+        assert_process(
+            spec.clone(),
+            event("id1", "type", "source", "chan1")
+                .data("application/json", json!({"temp": {"value": 19}}))
+                .build()
+                .unwrap(),
+            Outcome::Accepted(
+                event("id1", "type", "source", "chan1")
+                    .data("application/json", json!({"temp": {"value": 19}}))
+                    .build()
+                    .unwrap(),
+            ),
+        )
+        .await;
+
+        // JSON, but not decoded yet
+        assert_process(
+            spec.clone(),
+            event("id1", "type", "source", "chan1")
+                .data("application/json", br#"{"temp": {"value": 30}}"#.to_vec())
+                .build()
+                .unwrap(),
+            Outcome::Rejected("Too hot".into()),
+        )
+        .await;
+
+        // not JSON
+        let input = event("id1", "type", "source", "chan1")
+            .data("text/plain", br#"{"temp": {"value": 30}}"#.to_vec())
+            .build()
+            .unwrap();
+        assert_process(spec, input.clone(), Outcome::Accepted(input)).await;
+    }
+
+    #[tokio::test]
+    async fn test_extension_and_label_condition() {
+        let spec: PublishSpec = serde_json::from_value(json!({
+            "rules": [
+                {
+                    "when": {
+                        "or": [
+                            { "extension": { "name": "sender", "op": "startsWith", "value": "gw-" } },
+                            { "label": { "name": "muted", "op": "exists" } },
+                        ]
+                    },
+                    "then": ["drop"]
+                }
+            ]
+        }))
+        .unwrap();
+
+        let input = event("id1", "type", "source", "chan1")
+            .extension("sender", "device1")
+            .build()
+            .unwrap();
+
+        let processor = Processor::from(spec.rules.clone());
+        assert_eq!(
+            processor.process(input.clone()).await.unwrap(),
+            Outcome::Accepted(input.clone())
+        );
+
+        let processor = Processor::from(spec.rules.clone())
+            .with_labels([("muted".to_string(), "".to_string())].into());
+        assert_eq!(processor.process(input).await.unwrap(), Outcome::Dropped);
+
+        let processor = Processor::from(spec.rules);
+        assert_eq!(
+            processor
+                .process(
+                    event("id1", "type", "source", "chan1")
+                        .extension("sender", "gw-1")
+                        .build()
+                        .unwrap()
+                )
+                .await
+                .unwrap(),
+            Outcome::Dropped
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transform_json() {
+        assert_process(
+            json!({
+                "rules": [
+                    {
+                        "when": "always",
+                        "then": [
+                            { "transform": { "rename": { "from": "/t", "to": "/temperature/value" } } },
+                            { "transform": { "convert": {
+                                "pointer": "/temperature/value",
+                                "linear": { "factor": 0.5, "offset": -6 }
+                            } } },
+                            { "transform": { "remove": "/debug" } },
+                        ]
+                    }
+                ]
+            }),
+            event("id1", "type", "source", "chan1")
+                .data_with_schema(
+                    "application/json",
+                    "urn:my:schema",
+                    json!({"t": 212, "debug": true}),
+                )
+                .build()
+                .unwrap(),
+            Outcome::Accepted(
+                event("id1", "type", "source", "chan1")
+                    .data_with_schema(
+                        "application/json",
+                        "urn:my:schema",
+                        json!({"temperature": {"value": 100.0}}),
+                    )
+                    .build()
+                    .unwrap(),
+            ),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_transform_decode() {
+        let mut cbor = vec![];
+        ciborium::ser::into_writer(&json!({"temp": 21, "hum": 42}), &mut cbor).unwrap();
+
+        let spec = json!({
+            "rules": [
+                {
+                    "when": "always",
+                    "then": [
+                        { "transform": { "decode": "hex" } },
+                        { "transform": { "decode": "cbor" } },
+                        { "transform": { "map": [
+                            { "from": "/temp", "to": "/temperature" },
+                        ] } },
+                    ]
+                }
+            ]
+        });
+
+        assert_process(
+            spec.clone(),
+            event("id1", "type", "source", "chan1")
+                .data("text/plain", hex::encode(&cbor))
+                .build()
+                .unwrap(),
+            Outcome::Accepted(
+                event("id1", "type", "source", "chan1")
+                    .data("application/json", json!({"temperature": 21}))
+                    .build()
+                    .unwrap(),
+            ),
+        )
+        .await;
+
+        assert_process(
+            spec,
+            event("id1", "type", "source", "chan1")
+                .data("text/plain", "no hex")
+                .build()
+                .unwrap(),
+            Outcome::Rejected("Invalid character 'n' at position 0".into()),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_script() {
+        let spec = json!({
+            "rules": [
+                {
+                    "when": "always",
+                    "then": [
+                        { "script": { "rhai": r#"
+                            if event.data.temp > 50 {
+                                return reject("Too hot");
+                            }
+                            event.data.temp = event.data.temp * 2;
+                            event.extensions.processed = true;
+                            event.subject = "processed";
+                        "# } },
+                    ]
+                }
+            ]
+        });
+
+        assert_process(
+            spec.clone(),
+            event("id1", "type", "source", "chan1")
+                .data("application/json", json!({"temp": 60}))
+                .build()
+                .unwrap(),
+            Outcome::Rejected("Too hot".into()),
+        )
+        .await;
+
+        assert_process(
+            spec,
+            event("id1", "type", "source", "chan1")
+                .data("application/json", json!({"temp": 21}))
+                .build()
+                .unwrap(),
+            Outcome::Accepted(
+                event("id1", "type", "source", "processed")
+                    .extension("processed", true)
+                    .data("application/json", json!({"temp": 42}))
+                    .build()
+                    .unwrap(),
+            ),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_script_limit() {
+        let processor = Processor::try_from(json!([
+            {
+                "when": "always",
+                "then": [
+                    { "script": { "rhai": "loop { }" } },
+                ]
+            }
+        ]))
+        .unwrap();
+
+        assert!(matches!(
+            processor
+                .process(event("id1", "type", "source", "chan1").build().unwrap())
+                .await,
+            Ok(Outcome::Rejected(_))
+        ));
+    }
+
     #[tokio::test]
     async fn test_parse_1() {
         let spec = json!({
@@ -479,13 +939,179 @@ mod test {
         let spec: PublishSpec = serde_json::from_value(spec).unwrap();
         assert!(matches!(
             spec.rules[0].then[0],
-            Step::Enrich(EnrichSpec {
-                response: ResponseType::Raw,
+            Step::Enrich(ExternalStep {
+                spec: EnrichSpec {
+                    response: ResponseType::Raw,
+                    ..
+                },
                 ..
             })
         ));
     }
 
+    #[tokio::test]
+    async fn test_parse_failure_policy() {
+        let spec: PublishSpec = serde_json::from_value(json!({
+            "rules": [
+                {
+                    "when": "always",
+                    "then": [
+                        {
+                            "validate": {
+                                "endpoint": {
+                                    "url": "https://some-external-service/path/to"
+                                },
+                                "retry": {
+                                    "maxRetries": 3,
+                                    "initialDelay": "50ms",
+                                },
+                                "onFailure": "drop",
+                            }
+                        }
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+
+        match &spec.rules[0].then[0] {
+            Step::Validate(step) => {
+                assert_eq!(
+                    step.spec.endpoint.url,
+                    "https://some-external-service/path/to"
+                );
+                assert_eq!(
+                    step.policy,
+                    FailurePolicy {
+                        retry: RetryPolicy {
+                            max_retries: 3,
+                            initial_delay: Duration::from_millis(50),
+                            max_delay: Duration::from_secs(2),
+                        },
+                        on_failure: FailureMode::Drop,
+                    }
+                );
+            }
+            step => panic!("Unexpected step: {step:?}"),
+        }
+    }
+
+    /// Start an HTTP endpoint, responding to all requests with the provided status.
+    ///
+    /// Returns the URL of the endpoint, and the number of requests it received.
+    async fn mock_endpoint(status: u16) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    // consume the request, before responding
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    loop {
+                        let n = stream.read(&mut buf).await.unwrap_or_default();
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                        let request = String::from_utf8_lossy(&request).to_lowercase();
+                        if let Some((headers, body)) = request.split_once("\r\n\r\n") {
+                            let length = headers
+                                .lines()
+                                .find_map(|line| line.strip_prefix("content-length:"))
+                                .and_then(|length| length.trim().parse::<usize>().ok())
+                                .unwrap_or_default();
+                            if body.len() >= length {
+                                break;
+                            }
+                        }
+                    }
+                    let response = format!(
+                        "HTTP/1.1 {status} Mock\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_failure_mode() {
+        let (url, requests) = mock_endpoint(500).await;
+
+        let spec = json!({
+            "rules": [
+                {
+                    "when": "always",
+                    "then": [
+                        {
+                            "enrich": {
+                                "endpoint": {
+                                    "url": url,
+                                },
+                                "response": {
+                                    "type": "raw",
+                                },
+                                "retry": {
+                                    "maxRetries": 1,
+                                    "initialDelay": "1ms",
+                                },
+                                "onFailure": "accept",
+                            }
+                        },
+                        { "setExtension": { "name": "ext1", "value": "value1" } },
+                    ]
+                }
+            ]
+        });
+
+        // processing continues with the event as it was before the failed step
+        let input = event("id1", "type", "source", "chan1").build().unwrap();
+        let expected = event("id1", "type", "source", "chan1")
+            .extension("ext1", "value1")
+            .build()
+            .unwrap();
+        assert_process(spec, input, Outcome::Accepted(expected)).await;
+
+        // the first attempt, and one retry
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failure_mode_drop() {
+        let (url, requests) = mock_endpoint(503).await;
+
+        let spec = json!({
+            "rules": [
+                {
+                    "when": "always",
+                    "then": [
+                        {
+                            "validate": {
+                                "endpoint": {
+                                    "url": url,
+                                },
+                                "onFailure": "drop",
+                            }
+                        },
+                        { "reject": "Must not be reached" },
+                    ]
+                }
+            ]
+        });
+
+        let input = event("id1", "type", "source", "chan1").build().unwrap();
+        assert_process(spec, input, Outcome::Dropped).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
     async fn assert_process(spec: serde_json::Value, input: cloudevents::Event, expected: Outcome) {
         let spec: PublishSpec = serde_json::from_value(spec).unwrap();
         let processor = Processor::from(spec.rules);
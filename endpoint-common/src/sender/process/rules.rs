@@ -0,0 +1,359 @@
+//! Processing rules of an application.
+//!
+//! This mirrors the publish and command specs of the registry, but supports additional
+//! conditions when matching events, and additional processing steps.
+
+use super::{Script, Transform};
+use crate::external::{Authentication, TlsOptions};
+use drogue_client::{
+    dialect,
+    registry::v1::{RequestType, ResponseType},
+    Section,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{cmp::Ordering, time::Duration};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PublishSpec {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+dialect!(PublishSpec[Section::Spec => "publish"]);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CommandSpec {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+dialect!(CommandSpec[Section::Spec => "commands"]);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    pub when: When,
+    pub then: Vec<Step>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Step {
+    /// Drop the event.
+    Drop,
+    /// Stop processing and accept the event.
+    Break,
+    /// Reject the event, providing a reason.
+    Reject(String),
+    SetAttribute {
+        name: String,
+        value: String,
+    },
+    RemoveAttribute(String),
+    SetExtension {
+        name: String,
+        value: String,
+    },
+    RemoveExtension(String),
+    /// Validate the event using an external endpoint.
+    Validate(ExternalStep<ValidateSpec>),
+    /// Enrich the event using an external endpoint.
+    Enrich(ExternalStep<EnrichSpec>),
+    /// Transform the payload, inside the endpoint.
+    Transform(Transform),
+    /// Run a script, inside the endpoint.
+    Script(Script),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateSpec {
+    pub endpoint: ExternalEndpoint,
+    #[serde(default)]
+    pub request: RequestType,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnrichSpec {
+    pub endpoint: ExternalEndpoint,
+    #[serde(default)]
+    pub request: RequestType,
+    #[serde(default)]
+    pub response: ResponseType,
+}
+
+/// An external endpoint, supporting additional authentication methods.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalEndpoint {
+    #[serde(default)]
+    pub method: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub tls: Option<TlsOptions>,
+    #[serde(default)]
+    pub auth: Authentication,
+    #[serde(default)]
+    pub headers: Vec<Header>,
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// A step calling an external endpoint, along with the policy for handling failures.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExternalStep<T> {
+    #[serde(flatten)]
+    pub spec: T,
+    #[serde(flatten)]
+    pub policy: FailurePolicy,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailurePolicy {
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// What to do when the endpoint failed, after all retries.
+    #[serde(default)]
+    pub on_failure: FailureMode,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryPolicy {
+    /// Number of retries, in addition to the first attempt.
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled with every retry.
+    #[serde(with = "humantime_serde", default = "default_initial_delay")]
+    pub initial_delay: Duration,
+    #[serde(with = "humantime_serde", default = "default_max_delay")]
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_delay: default_initial_delay(),
+            max_delay: default_max_delay(),
+        }
+    }
+}
+
+const fn default_initial_delay() -> Duration {
+    Duration::from_millis(100)
+}
+
+const fn default_max_delay() -> Duration {
+    Duration::from_secs(2)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FailureMode {
+    /// Fail processing the event.
+    #[default]
+    Fail,
+    /// Continue processing, with the event as it was before the step.
+    Accept,
+    /// Silently drop the event.
+    Drop,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum When {
+    Always,
+    Not(Box<When>),
+    And(Vec<When>),
+    Or(Vec<When>),
+    IsChannel(String),
+    /// Compare a cloud events attribute.
+    Attribute(AttributeCondition),
+    /// Compare a cloud events extension.
+    Extension(NamedCondition),
+    /// Compare a label of the device the event originated from.
+    Label(NamedCondition),
+    /// Compare a value of the JSON payload, selected by a JSON pointer.
+    Payload(PayloadCondition),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Attribute {
+    Id,
+    Type,
+    Source,
+    Subject,
+    DataContentType,
+    DataSchema,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AttributeCondition {
+    pub name: Attribute,
+    #[serde(flatten)]
+    pub comparison: Comparison,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NamedCondition {
+    pub name: String,
+    #[serde(flatten)]
+    pub comparison: Comparison,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PayloadCondition {
+    /// A JSON pointer (RFC 6901), like `/temperature/value`.
+    pub pointer: String,
+    #[serde(flatten)]
+    pub comparison: Comparison,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Operator {
+    /// Value is present.
+    Exists,
+    /// Value is equal.
+    #[default]
+    Eq,
+    /// Value is missing or not equal.
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// String value starts with the provided prefix.
+    StartsWith,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    #[serde(default)]
+    pub op: Operator,
+    #[serde(default)]
+    pub value: Value,
+}
+
+impl Comparison {
+    /// Check if the actual value, `None` if it is missing, matches the comparison.
+    pub fn matches(&self, actual: Option<&Value>) -> bool {
+        let actual = match (self.op, actual) {
+            (Operator::Exists, actual) => return actual.is_some(),
+            (Operator::Ne, actual) => return !actual.map_or(false, |a| equals(a, &self.value)),
+            (_, None) => return false,
+            (_, Some(actual)) => actual,
+        };
+
+        match self.op {
+            Operator::Eq => equals(actual, &self.value),
+            Operator::Gt => compare(actual, &self.value) == Some(Ordering::Greater),
+            Operator::Ge => matches!(
+                compare(actual, &self.value),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Operator::Lt => compare(actual, &self.value) == Some(Ordering::Less),
+            Operator::Le => matches!(
+                compare(actual, &self.value),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Operator::StartsWith => match (actual, &self.value) {
+                (Value::String(actual), Value::String(prefix)) => actual.starts_with(prefix),
+                _ => false,
+            },
+            Operator::Exists | Operator::Ne => unreachable!("handled above"),
+        }
+    }
+}
+
+fn equals(a: &Value, b: &Value) -> bool {
+    // compare numbers by value, so that `1` equals `1.0`
+    compare(a, b) == Some(Ordering::Equal) || a == b
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn comparison(op: Operator, value: Value) -> Comparison {
+        Comparison { op, value }
+    }
+
+    #[test]
+    fn test_parse() {
+        let when: When = serde_json::from_value(json!({
+            "and": [
+                { "isChannel": "state" },
+                { "attribute": { "name": "datacontenttype", "value": "application/json" } },
+                { "extension": { "name": "sender", "op": "startsWith", "value": "gw-" } },
+                { "payload": { "pointer": "/temp", "op": "gt", "value": 20 } },
+                { "not": { "label": { "name": "muted", "op": "exists" } } },
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            when,
+            When::And(vec![
+                When::IsChannel("state".into()),
+                When::Attribute(AttributeCondition {
+                    name: Attribute::DataContentType,
+                    comparison: comparison(Operator::Eq, json!("application/json")),
+                }),
+                When::Extension(NamedCondition {
+                    name: "sender".into(),
+                    comparison: comparison(Operator::StartsWith, json!("gw-")),
+                }),
+                When::Payload(PayloadCondition {
+                    pointer: "/temp".into(),
+                    comparison: comparison(Operator::Gt, json!(20)),
+                }),
+                When::Not(Box::new(When::Label(NamedCondition {
+                    name: "muted".into(),
+                    comparison: comparison(Operator::Exists, Value::Null),
+                }))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let value = json!(20);
+
+        assert!(comparison(Operator::Eq, json!(20.0)).matches(Some(&value)));
+        assert!(comparison(Operator::Ne, json!(21)).matches(Some(&value)));
+        assert!(comparison(Operator::Ne, json!(21)).matches(None));
+        assert!(comparison(Operator::Gt, json!(19.5)).matches(Some(&value)));
+        assert!(!comparison(Operator::Gt, json!(20)).matches(Some(&value)));
+        assert!(comparison(Operator::Ge, json!(20)).matches(Some(&value)));
+        assert!(comparison(Operator::Lt, json!(21)).matches(Some(&value)));
+        assert!(comparison(Operator::Le, json!(20)).matches(Some(&value)));
+        assert!(!comparison(Operator::Lt, json!(21)).matches(None));
+        assert!(!comparison(Operator::Gt, json!("10")).matches(Some(&value)));
+
+        assert!(comparison(Operator::Exists, Value::Null).matches(Some(&Value::Null)));
+        assert!(!comparison(Operator::Exists, Value::Null).matches(None));
+
+        assert!(comparison(Operator::StartsWith, json!("foo")).matches(Some(&json!("foobar"))));
+        assert!(!comparison(Operator::StartsWith, json!("bar")).matches(Some(&json!("foobar"))));
+    }
+}
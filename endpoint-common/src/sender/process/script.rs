@@ -0,0 +1,431 @@
+use super::{Error, StepOutcome};
+use crate::sender::{is_json, Direction};
+use cloudevents::{
+    event::{Data, ExtensionValue},
+    AttributesReader, AttributesWriter,
+};
+use lru::LruCache;
+use reqwest::Url;
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Maximum number of operations a script may perform.
+const MAX_OPERATIONS: u64 = 100_000;
+/// Maximum time a script may run.
+const MAX_DURATION: Duration = Duration::from_millis(100);
+
+const DEFAULT_CAPACITY: NonZeroUsize = match NonZeroUsize::new(64) {
+    Some(capacity) => capacity,
+    None => panic!(),
+};
+
+/// A script, processing an event.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Script {
+    /// A Rhai script.
+    ///
+    /// The script has access to the event as `event` variable, and may modify it. The outcome
+    /// can be controlled by returning `accept()`, `reject(reason)`, or `drop()`. Returning
+    /// anything else continues processing with the modified event.
+    Rhai(String),
+}
+
+/// The outcome of a script.
+#[derive(Clone, Debug)]
+enum ScriptOutcome {
+    Accept,
+    Reject(String),
+    Drop,
+}
+
+/// The revision of an application, which processes events.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Revision {
+    /// The UID of the application.
+    pub application: String,
+    /// The resource version of the application.
+    pub resource_version: String,
+    /// The direction of the processing rules.
+    pub direction: Direction,
+}
+
+/// The location of a script, in the processing rules of an application revision.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ScriptKey {
+    pub revision: Revision,
+    pub rule: usize,
+    pub step: usize,
+}
+
+thread_local! {
+    /// The point in time the script, running on this thread, must complete.
+    static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// Cache of compiled scripts.
+///
+/// Scripts are cached by application revision, so that a new revision of an application
+/// compiles its scripts again. All scripts share the same engine.
+#[derive(Clone, Debug)]
+pub struct ScriptCache {
+    engine: Arc<Engine>,
+    cache: Arc<Mutex<LruCache<ScriptKey, Arc<AST>>>>,
+}
+
+impl Default for ScriptCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ScriptCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            engine: Arc::new(engine()),
+            cache: Arc::new(Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    async fn get(&self, key: Option<ScriptKey>, source: &str) -> Result<Arc<AST>, Error> {
+        if let Some(ast) = key
+            .as_ref()
+            .and_then(|key| self.cache.lock().unwrap().get(key).cloned())
+        {
+            return Ok(ast);
+        }
+
+        // compile without holding the lock, concurrent misses only compile the script twice
+
+        let engine = self.engine.clone();
+        let source = source.to_string();
+        let ast = tokio::task::spawn_blocking(move || engine.compile(source))
+            .await
+            .map_err(|err| Error::Internal(Box::new(err)))?
+            .map_err(|err| Error::Config(format!("Invalid script: {err}")))?;
+        let ast = Arc::new(ast);
+
+        if let Some(key) = key {
+            self.cache.lock().unwrap().put(key, ast.clone());
+        }
+
+        Ok(ast)
+    }
+}
+
+impl Script {
+    /// Run the script.
+    ///
+    /// The key identifies the script for caching its compiled form. Without a key, the script
+    /// will be compiled for every run.
+    pub async fn run(
+        &self,
+        cache: &ScriptCache,
+        key: Option<ScriptKey>,
+        event: cloudevents::Event,
+    ) -> Result<StepOutcome, Error> {
+        match self {
+            Self::Rhai(source) => {
+                let ast = cache.get(key, source).await?;
+                let engine = cache.engine.clone();
+                // scripts are CPU bound, don't block the executor
+                tokio::task::spawn_blocking(move || run_rhai(&engine, &ast, event))
+                    .await
+                    .map_err(|err| Error::Internal(Box::new(err)))
+            }
+        }
+    }
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1024 * 1024);
+    engine.set_max_array_size(64 * 1024);
+    engine.set_max_map_size(64 * 1024);
+
+    engine.on_progress(|_| {
+        let expired = DEADLINE
+            .with(|deadline| matches!(deadline.get(), Some(deadline) if Instant::now() > deadline));
+        match expired {
+            true => Some(Dynamic::UNIT),
+            false => None,
+        }
+    });
+
+    engine
+        .register_type_with_name::<ScriptOutcome>("Outcome")
+        .register_fn("accept", || ScriptOutcome::Accept)
+        .register_fn("reject", |reason: &str| {
+            ScriptOutcome::Reject(reason.to_string())
+        })
+        .register_fn("drop", || ScriptOutcome::Drop);
+
+    engine
+}
+
+/// Run a script, rejecting the event if the script fails.
+fn run_rhai(engine: &Engine, ast: &AST, event: cloudevents::Event) -> StepOutcome {
+    DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + MAX_DURATION)));
+    let result = eval_rhai(engine, ast, event);
+    DEADLINE.with(|deadline| deadline.set(None));
+
+    result.unwrap_or_else(|reason| {
+        log::debug!("Script failed: {reason}");
+        StepOutcome::Reject(reason)
+    })
+}
+
+fn eval_rhai(
+    engine: &Engine,
+    ast: &AST,
+    mut event: cloudevents::Event,
+) -> Result<StepOutcome, String> {
+    let mut scope = Scope::new();
+    scope.push("event", to_script(&event)?);
+
+    let result = engine
+        .eval_ast_with_scope::<Dynamic>(&mut scope, ast)
+        .map_err(|err| match *err {
+            EvalAltResult::ErrorTerminated(..) | EvalAltResult::ErrorTooManyOperations(..) => {
+                "Script exceeded its time or operation limit".to_string()
+            }
+            err => format!("Script failed: {err}"),
+        })?;
+
+    let accept = match result.try_cast::<ScriptOutcome>() {
+        Some(ScriptOutcome::Reject(reason)) => return Ok(StepOutcome::Reject(reason)),
+        Some(ScriptOutcome::Drop) => return Ok(StepOutcome::Drop),
+        Some(ScriptOutcome::Accept) => true,
+        None => false,
+    };
+
+    let script_event = scope
+        .get_value::<Map>("event")
+        .ok_or_else(|| "Script must keep 'event' as an object".to_string())?;
+    from_script(script_event, &mut event)?;
+
+    match accept {
+        true => Ok(StepOutcome::Accept(event)),
+        false => Ok(StepOutcome::Continue(event)),
+    }
+}
+
+/// Convert an event into a script object.
+fn to_script(event: &cloudevents::Event) -> Result<Map, String> {
+    let mut map = Map::new();
+
+    map.insert("id".into(), event.id().into());
+    map.insert("type".into(), event.ty().into());
+    map.insert("source".into(), event.source().to_string().into());
+    map.insert("subject".into(), optional(event.subject()));
+    map.insert("datacontenttype".into(), optional(event.datacontenttype()));
+    map.insert(
+        "dataschema".into(),
+        optional(event.dataschema().map(ToString::to_string).as_deref()),
+    );
+
+    let mut extensions = Map::new();
+    for (name, value) in event.iter_extensions() {
+        let value = match value {
+            ExtensionValue::String(value) => value.clone().into(),
+            ExtensionValue::Boolean(value) => (*value).into(),
+            ExtensionValue::Integer(value) => (*value).into(),
+        };
+        extensions.insert(name.into(), value);
+    }
+    map.insert("extensions".into(), Dynamic::from_map(extensions));
+
+    let data = match event.data() {
+        Some(Data::Json(value)) => rhai::serde::to_dynamic(value)
+            .map_err(|err| format!("Unable to convert payload: {err}"))?,
+        Some(Data::String(value)) => value.clone().into(),
+        Some(Data::Binary(value)) => Dynamic::from_blob(value.clone()),
+        None => Dynamic::UNIT,
+    };
+    map.insert("data".into(), data);
+
+    Ok(map)
+}
+
+/// Apply the changes of the script object to the event.
+fn from_script(mut map: Map, event: &mut cloudevents::Event) -> Result<(), String> {
+    let string = |value: Option<Dynamic>, name: &str| -> Result<Option<String>, String> {
+        match value {
+            None => Ok(None),
+            Some(value) if value.is_unit() => Ok(None),
+            Some(value) => value
+                .into_string()
+                .map(Some)
+                .map_err(|_| format!("'{name}' must be a string")),
+        }
+    };
+
+    let ty = string(map.remove("type"), "type")?
+        .ok_or_else(|| "'type' must not be removed".to_string())?;
+    event.set_type(ty);
+    event.set_subject(string(map.remove("subject"), "subject")?);
+    let dataschema = string(map.remove("dataschema"), "dataschema")?
+        .map(|url| Url::parse(&url))
+        .transpose()
+        .map_err(|err| format!("Invalid data schema: {err}"))?;
+
+    let extensions = map
+        .remove("extensions")
+        .and_then(|extensions| extensions.try_cast::<Map>())
+        .unwrap_or_default();
+    let existing: Vec<String> = event
+        .iter_extensions()
+        .map(|(k, _)| k.to_string())
+        .collect();
+    for name in existing {
+        event.remove_extension(&name);
+    }
+    for (name, value) in extensions {
+        let value = if value.is_bool() {
+            ExtensionValue::Boolean(value.as_bool().unwrap_or_default())
+        } else if value.is_int() {
+            ExtensionValue::Integer(value.as_int().unwrap_or_default())
+        } else {
+            ExtensionValue::String(value.to_string())
+        };
+        event.set_extension(&name, value);
+    }
+
+    let content_type = string(map.remove("datacontenttype"), "datacontenttype")?;
+    let data = map.remove("data").unwrap_or(Dynamic::UNIT);
+    let data = if data.is_unit() {
+        None
+    } else if data.is::<Blob>() {
+        Some(Data::Binary(data.cast::<Blob>()))
+    } else if data.is_string() && !is_json(content_type.as_deref().unwrap_or_default()) {
+        Some(Data::String(data.into_string().unwrap_or_default()))
+    } else {
+        Some(Data::Json(rhai::serde::from_dynamic(&data).map_err(
+            |err| format!("Invalid payload from script: {err}"),
+        )?))
+    };
+
+    match data {
+        Some(data) => {
+            let content_type = content_type.unwrap_or_else(|| match &data {
+                Data::Json(_) => mime::APPLICATION_JSON.to_string(),
+                Data::String(_) => mime::TEXT_PLAIN_UTF_8.to_string(),
+                Data::Binary(_) => mime::APPLICATION_OCTET_STREAM.to_string(),
+            });
+            event.set_data(content_type, data);
+        }
+        None => {
+            event.take_data();
+        }
+    }
+    event.set_dataschema(dataschema);
+
+    Ok(())
+}
+
+fn optional(value: Option<&str>) -> Dynamic {
+    value.map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+
+    fn key(resource_version: &str) -> ScriptKey {
+        ScriptKey {
+            revision: Revision {
+                application: "uid1".into(),
+                resource_version: resource_version.into(),
+                direction: Direction::Downstream,
+            },
+            rule: 0,
+            step: 0,
+        }
+    }
+
+    fn event() -> cloudevents::Event {
+        EventBuilderV10::new()
+            .id("id1")
+            .ty("type")
+            .source("source")
+            .subject("chan1")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cache_by_revision() {
+        let cache = ScriptCache::default();
+
+        let ast1 = cache.get(Some(key("1")), "1").await.unwrap();
+        // same revision, the compiled script is re-used
+        let ast2 = cache.get(Some(key("1")), "2").await.unwrap();
+        assert!(Arc::ptr_eq(&ast1, &ast2));
+
+        // a new revision compiles the script again
+        let ast3 = cache.get(Some(key("2")), "1").await.unwrap();
+        assert!(!Arc::ptr_eq(&ast1, &ast3));
+
+        // without a key, nothing gets cached
+        cache.get(None, "1").await.unwrap();
+        assert_eq!(cache.cache.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_script() {
+        let script = Script::Rhai("if {".into());
+        assert!(matches!(
+            script.run(&ScriptCache::default(), None, event()).await,
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_runtime_error() {
+        let cache = ScriptCache::default();
+
+        for source in [
+            r#"throw "failed""#,
+            "event.data.temp + 1",
+            "event = 42",
+            r#"event.type = ()"#,
+        ] {
+            let outcome = Script::Rhai(source.into())
+                .run(&cache, None, event())
+                .await
+                .unwrap();
+            assert!(
+                matches!(outcome, StepOutcome::Reject(_)),
+                "Script should reject: {source}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit_shared_engine() {
+        let cache = ScriptCache::default();
+
+        let outcome = Script::Rhai("loop { }".into())
+            .run(&cache, Some(key("1")), event())
+            .await
+            .unwrap();
+        assert!(matches!(outcome, StepOutcome::Reject(_)));
+
+        // the deadline of the previous run doesn't affect the next one
+        let outcome = Script::Rhai("let x = 1;".into())
+            .run(&cache, Some(key("2")), event())
+            .await
+            .unwrap();
+        assert!(matches!(outcome, StepOutcome::Continue(_)));
+    }
+}
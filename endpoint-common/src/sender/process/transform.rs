@@ -0,0 +1,378 @@
+use crate::sender::is_json;
+use cloudevents::{event::Data, AttributesWriter};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TransformError {
+    /// The payload could not be transformed.
+    #[error("Invalid payload: {0}")]
+    Payload(String),
+    /// The transformation is not valid.
+    #[error("Invalid transformation: {0}")]
+    Config(String),
+}
+
+/// A transformation of the payload, performed inside the endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Transform {
+    /// Decode a binary payload.
+    Decode(Encoding),
+    /// Move a value of a JSON payload to a different location.
+    Rename { from: String, to: String },
+    /// Replace the JSON payload with a new object, built from the mapped values.
+    Map(Vec<Mapping>),
+    /// Remove a value from the JSON payload.
+    Remove(String),
+    /// Convert a numeric value of the JSON payload.
+    Convert(ConvertSpec),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Encoding {
+    /// Base64 encoded binary data, result is binary data.
+    Base64,
+    /// Hex encoded binary data, result is binary data.
+    Hex,
+    /// CBOR encoded data, result is JSON.
+    Cbor,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mapping {
+    /// JSON pointer to the source value.
+    pub from: String,
+    /// JSON pointer to the target location.
+    pub to: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConvertSpec {
+    /// JSON pointer to the value.
+    pub pointer: String,
+    #[serde(flatten)]
+    pub conversion: Conversion,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Conversion {
+    /// Calculate `value * factor + offset`.
+    Linear {
+        factor: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// Convert between two units of the same kind.
+    Unit { from: Unit, to: Unit },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dimension {
+    Temperature,
+    Pressure,
+    Length,
+    Speed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Unit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+    Pascal,
+    Hectopascal,
+    Bar,
+    Psi,
+    Meter,
+    Kilometer,
+    Foot,
+    Mile,
+    MeterPerSecond,
+    KilometerPerHour,
+    MilePerHour,
+    Knot,
+}
+
+impl Unit {
+    /// The dimension and the linear conversion (factor, offset) into the SI base unit.
+    fn base(&self) -> (Dimension, f64, f64) {
+        match self {
+            Self::Celsius => (Dimension::Temperature, 1.0, 273.15),
+            Self::Fahrenheit => (Dimension::Temperature, 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+            Self::Kelvin => (Dimension::Temperature, 1.0, 0.0),
+            Self::Pascal => (Dimension::Pressure, 1.0, 0.0),
+            Self::Hectopascal => (Dimension::Pressure, 100.0, 0.0),
+            Self::Bar => (Dimension::Pressure, 100_000.0, 0.0),
+            Self::Psi => (Dimension::Pressure, 6_894.757_293_168, 0.0),
+            Self::Meter => (Dimension::Length, 1.0, 0.0),
+            Self::Kilometer => (Dimension::Length, 1_000.0, 0.0),
+            Self::Foot => (Dimension::Length, 0.3048, 0.0),
+            Self::Mile => (Dimension::Length, 1_609.344, 0.0),
+            Self::MeterPerSecond => (Dimension::Speed, 1.0, 0.0),
+            Self::KilometerPerHour => (Dimension::Speed, 1.0 / 3.6, 0.0),
+            Self::MilePerHour => (Dimension::Speed, 0.447_04, 0.0),
+            Self::Knot => (Dimension::Speed, 1_852.0 / 3_600.0, 0.0),
+        }
+    }
+}
+
+impl Conversion {
+    fn apply(&self, value: f64) -> Result<f64, TransformError> {
+        match self {
+            Self::Linear { factor, offset } => Ok(value * factor + offset),
+            Self::Unit { from, to } => {
+                let (from_dim, from_factor, from_offset) = from.base();
+                let (to_dim, to_factor, to_offset) = to.base();
+                if from_dim != to_dim {
+                    return Err(TransformError::Config(format!(
+                        "Unable to convert from {from:?} to {to:?}"
+                    )));
+                }
+                Ok((value * from_factor + from_offset - to_offset) / to_factor)
+            }
+        }
+    }
+}
+
+impl Transform {
+    /// Apply the transformation to the event.
+    pub fn apply(
+        &self,
+        mut event: cloudevents::Event,
+    ) -> Result<cloudevents::Event, TransformError> {
+        let (content_type, schema, data) = event.take_data();
+
+        let (content_type, data) = match self {
+            Self::Decode(encoding) => encoding.decode(data)?,
+            Self::Rename { from, to } => {
+                let mut payload = into_json(content_type, data)?;
+                let value = take(&mut payload, from)?;
+                set(&mut payload, to, value)?;
+                json_data(payload)
+            }
+            Self::Map(mappings) => {
+                let payload = into_json(content_type, data)?;
+                let mut result = Value::Object(Map::new());
+                for mapping in mappings {
+                    let value = payload.pointer(&mapping.from).cloned().ok_or_else(|| {
+                        TransformError::Payload(format!("Missing value: {}", mapping.from))
+                    })?;
+                    set(&mut result, &mapping.to, value)?;
+                }
+                json_data(result)
+            }
+            Self::Remove(pointer) => {
+                let mut payload = into_json(content_type, data)?;
+                take(&mut payload, pointer)?;
+                json_data(payload)
+            }
+            Self::Convert(spec) => {
+                let mut payload = into_json(content_type, data)?;
+                let value = payload.pointer_mut(&spec.pointer).ok_or_else(|| {
+                    TransformError::Payload(format!("Missing value: {}", spec.pointer))
+                })?;
+                let number = value.as_f64().ok_or_else(|| {
+                    TransformError::Payload(format!("Value is not a number: {}", spec.pointer))
+                })?;
+                *value = serde_json::Number::from_f64(spec.conversion.apply(number)?)
+                    .map(Value::Number)
+                    .ok_or_else(|| TransformError::Payload("Invalid conversion result".into()))?;
+                json_data(payload)
+            }
+        };
+
+        event.set_data(content_type, data);
+        // decoding changes the format, so the schema no longer applies
+        if !matches!(self, Self::Decode(_)) {
+            event.set_dataschema(schema);
+        }
+
+        Ok(event)
+    }
+}
+
+impl Encoding {
+    fn decode(&self, data: Option<Data>) -> Result<(String, Data), TransformError> {
+        match self {
+            Self::Base64 => {
+                let data = base64::decode(text(data)?.trim())
+                    .map_err(|err| TransformError::Payload(err.to_string()))?;
+                Ok((
+                    mime::APPLICATION_OCTET_STREAM.to_string(),
+                    Data::Binary(data),
+                ))
+            }
+            Self::Hex => {
+                let data = hex::decode(text(data)?.trim())
+                    .map_err(|err| TransformError::Payload(err.to_string()))?;
+                Ok((
+                    mime::APPLICATION_OCTET_STREAM.to_string(),
+                    Data::Binary(data),
+                ))
+            }
+            Self::Cbor => {
+                let data = binary(data)?;
+                let value: Value = ciborium::de::from_reader(data.as_slice())
+                    .map_err(|err| TransformError::Payload(err.to_string()))?;
+                Ok(json_data(value))
+            }
+        }
+    }
+}
+
+fn json_data(value: Value) -> (String, Data) {
+    (mime::APPLICATION_JSON.to_string(), Data::Json(value))
+}
+
+/// Get the payload as JSON.
+fn into_json(content_type: Option<String>, data: Option<Data>) -> Result<Value, TransformError> {
+    match data {
+        Some(Data::Json(value)) => Ok(value),
+        Some(Data::Binary(data)) if is_json(content_type.as_deref().unwrap_or_default()) => {
+            serde_json::from_slice(&data).map_err(|err| TransformError::Payload(err.to_string()))
+        }
+        Some(Data::String(data)) if is_json(content_type.as_deref().unwrap_or_default()) => {
+            serde_json::from_str(&data).map_err(|err| TransformError::Payload(err.to_string()))
+        }
+        _ => Err(TransformError::Payload("Payload is not JSON".into())),
+    }
+}
+
+/// Get the payload as text, a JSON payload must be a string value.
+fn text(data: Option<Data>) -> Result<String, TransformError> {
+    match data {
+        Some(Data::Json(Value::String(data))) | Some(Data::String(data)) => Ok(data),
+        Some(Data::Json(_)) => Err(TransformError::Payload(
+            "JSON payload must be a string".into(),
+        )),
+        data => {
+            String::from_utf8(binary(data)?).map_err(|err| TransformError::Payload(err.to_string()))
+        }
+    }
+}
+
+/// Get the payload as binary data.
+fn binary(data: Option<Data>) -> Result<Vec<u8>, TransformError> {
+    match data {
+        Some(Data::Binary(data)) => Ok(data),
+        Some(Data::String(data)) => Ok(data.into_bytes()),
+        Some(Data::Json(_)) => Err(TransformError::Payload("Payload is JSON".into())),
+        None => Err(TransformError::Payload("Missing payload".into())),
+    }
+}
+
+/// Split a JSON pointer into its parent pointer and the (unescaped) last token.
+fn split_pointer(pointer: &str) -> Result<(&str, String), TransformError> {
+    match pointer.rsplit_once('/') {
+        Some((parent, token)) => Ok((parent, unescape(token))),
+        None => Err(TransformError::Config(format!(
+            "Invalid JSON pointer: '{pointer}'"
+        ))),
+    }
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Remove the value the pointer points to.
+fn take(value: &mut Value, pointer: &str) -> Result<Value, TransformError> {
+    let (parent, token) = split_pointer(pointer)?;
+    let missing = || TransformError::Payload(format!("Missing value: {pointer}"));
+
+    match value.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&token).ok_or_else(missing),
+        Some(Value::Array(array)) => match token.parse::<usize>() {
+            Ok(index) if index < array.len() => Ok(array.remove(index)),
+            _ => Err(missing()),
+        },
+        _ => Err(missing()),
+    }
+}
+
+/// Set a value, creating intermediate objects if required.
+fn set(value: &mut Value, pointer: &str, new_value: Value) -> Result<(), TransformError> {
+    if pointer.is_empty() {
+        *value = new_value;
+        return Ok(());
+    }
+
+    let (parent, token) = split_pointer(pointer)?;
+    if value.pointer(parent).is_none() {
+        set(value, parent, Value::Object(Map::new()))?;
+    }
+
+    match value.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, new_value);
+            Ok(())
+        }
+        Some(Value::Array(array)) if token == "-" => {
+            array.push(new_value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => match token.parse::<usize>() {
+            Ok(index) if index < array.len() => {
+                array[index] = new_value;
+                Ok(())
+            }
+            _ => Err(TransformError::Payload(format!(
+                "Invalid array index: {pointer}"
+            ))),
+        },
+        _ => Err(TransformError::Payload(format!(
+            "Unable to set value: {pointer}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn assert_convert(value: f64, from: Unit, to: Unit, expected: f64) {
+        let result = Conversion::Unit { from, to }.apply(value).unwrap();
+        assert!(
+            (result - expected).abs() < 1e-9,
+            "{value} {from:?} -> {to:?}: expected {expected}, was {result}"
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        assert_convert(100.0, Unit::Celsius, Unit::Fahrenheit, 212.0);
+        assert_convert(32.0, Unit::Fahrenheit, Unit::Celsius, 0.0);
+        assert_convert(0.0, Unit::Celsius, Unit::Kelvin, 273.15);
+        assert_convert(1013.25, Unit::Hectopascal, Unit::Bar, 1.01325);
+        assert_convert(1.0, Unit::Mile, Unit::Kilometer, 1.609344);
+        assert_convert(36.0, Unit::KilometerPerHour, Unit::MeterPerSecond, 10.0);
+
+        assert!(Conversion::Unit {
+            from: Unit::Celsius,
+            to: Unit::Meter
+        }
+        .apply(1.0)
+        .is_err());
+    }
+
+    #[test]
+    fn test_set_and_take() {
+        let mut value = json!({"a": {"b": [1, 2]}, "c~/d": true});
+
+        assert_eq!(take(&mut value, "/c~0~1d").unwrap(), json!(true));
+        assert_eq!(take(&mut value, "/a/b/0").unwrap(), json!(1));
+        assert!(take(&mut value, "/a/x").is_err());
+
+        set(&mut value, "/x/y", json!(42)).unwrap();
+        set(&mut value, "/a/b/-", json!(3)).unwrap();
+        assert!(set(&mut value, "/a/b/5", json!(3)).is_err());
+
+        assert_eq!(value, json!({"a": {"b": [2, 3]}, "x": {"y": 42}}));
+    }
+}
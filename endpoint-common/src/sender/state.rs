@@ -0,0 +1,109 @@
+use chrono::Utc;
+use cloudevents::{AttributesReader, Event};
+use drogue_cloud_service_api::services::{device_state::RecordValueRequest, twin::TwinUpdate};
+use drogue_cloud_service_common::{client::DeviceStateClient, Id};
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter, IntCounter};
+use tokio::sync::mpsc;
+
+lazy_static! {
+    pub static ref DEVICE_STATE_DROPPED_COUNTER: IntCounter = register_int_counter!(
+        "drogue_device_state_dropped",
+        "Device state records which got dropped, as recording fell behind"
+    )
+    .unwrap();
+}
+
+/// Number of records buffered for sending, further records get dropped.
+const RECORD_QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug)]
+enum Record {
+    /// Reported properties of a twin.
+    Twin { id: Id, update: TwinUpdate },
+    /// The last event of a device on a channel.
+    LastValue { id: Id, event: Event },
+}
+
+/// Record the state of devices with the device state service.
+///
+/// Records are sent from a background task, so that recording doesn't delay publishing events.
+/// If the task falls behind, records get dropped.
+#[derive(Clone, Debug)]
+pub struct StateRecorder {
+    tx: mpsc::Sender<Record>,
+}
+
+impl StateRecorder {
+    /// Create a new recorder, starting the background task.
+    ///
+    /// This must be called in the context of a Tokio runtime.
+    pub fn new(client: DeviceStateClient) -> Self {
+        let (tx, rx) = mpsc::channel(RECORD_QUEUE_SIZE);
+
+        tokio::spawn(run(client, rx));
+
+        Self { tx }
+    }
+
+    /// Record reported properties of a twin.
+    pub fn twin(&self, id: Id, update: TwinUpdate) {
+        self.record(Record::Twin { id, update });
+    }
+
+    /// Record an event as the last value of its device and channel.
+    pub fn last_value(&self, id: Id, event: Event) {
+        self.record(Record::LastValue { id, event });
+    }
+
+    fn record(&self, record: Record) {
+        if let Err(err) = self.tx.try_send(record) {
+            log::debug!("Dropping device state record: {err}");
+            DEVICE_STATE_DROPPED_COUNTER.inc();
+        }
+    }
+}
+
+async fn run(client: DeviceStateClient, mut rx: mpsc::Receiver<Record>) {
+    while let Some(record) = rx.recv().await {
+        match record {
+            Record::Twin { id, update } => {
+                if let Err(err) = client
+                    .update_reported(&id.app_id, &id.device_id, &update)
+                    .await
+                {
+                    log::warn!("Failed to update reported twin properties: {err}");
+                }
+            }
+            Record::LastValue { id, event } => {
+                let request = match last_value(&event) {
+                    Some(request) => request,
+                    None => continue,
+                };
+                if let Err(err) = client
+                    .record_value(&id.app_id, &id.device_id, &request)
+                    .await
+                {
+                    log::warn!("Failed to record last value: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Create the request for recording an event as last value.
+fn last_value(event: &Event) -> Option<RecordValueRequest> {
+    let channel = event.subject()?.to_string();
+
+    match serde_json::to_value(event) {
+        Ok(value) => Some(RecordValueRequest {
+            channel,
+            time: event.time().cloned().unwrap_or_else(Utc::now),
+            event: value,
+        }),
+        Err(err) => {
+            log::info!("Unable to encode last value: {err}");
+            None
+        }
+    }
+}
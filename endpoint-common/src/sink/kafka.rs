@@ -10,15 +10,32 @@ use drogue_client::{core, registry, Translator};
 use drogue_cloud_service_api::kafka::{KafkaClientConfig, KafkaConfigExt, KafkaEventType};
 use drogue_cloud_service_common::config::ConfigFromEnv;
 use futures::channel::oneshot;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter, IntCounter};
 use rdkafka::{
     error::{KafkaError, RDKafkaErrorCode},
-    producer::{FutureProducer, FutureRecord},
+    producer::{future_producer::OwnedDeliveryResult, FutureProducer, FutureRecord},
     ClientConfig,
 };
-use std::fmt::Formatter;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Formatter,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::instrument;
 
+lazy_static! {
+    static ref PIPELINED_DELIVERY_FAILURES: IntCounter = register_int_counter!(
+        "drogue_kafka_sink_pipelined_delivery_failures",
+        "Pipelined publishes which failed after being accepted"
+    )
+    .unwrap();
+}
+
 #[derive(Debug, Error)]
 pub enum KafkaSinkError {
     #[error("Kafka error")]
@@ -29,16 +46,126 @@ pub enum KafkaSinkError {
     Canceled,
 }
 
+/// Options for tuning the Kafka producer of a sink.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct KafkaSinkOptions {
+    /// Accept a publish once it is queued, instead of waiting for the delivery report.
+    ///
+    /// This increases the throughput, but publishes which fail after being queued will
+    /// only be logged.
+    #[serde(default)]
+    pub pipelined: bool,
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    /// Time to wait for more messages, before sending a batch.
+    #[serde(default, with = "humantime_serde")]
+    pub linger: Option<Duration>,
+    /// Maximum size of a batch, in bytes.
+    #[serde(default)]
+    pub batch_size: Option<u32>,
+    /// Maximum number of messages in a batch.
+    #[serde(default)]
+    pub batch_num_messages: Option<u32>,
+    /// Maximum number of publishes in flight, per application.
+    ///
+    /// Publishes exceeding this limit will report the queue as full.
+    #[serde(default)]
+    pub max_in_flight_per_application: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct CompressionConfig {
+    pub codec: Compression,
+    /// The compression level, the meaning depends on the codec.
+    #[serde(default)]
+    pub level: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Snappy => "snappy",
+            Self::Lz4 => "lz4",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+impl KafkaSinkOptions {
+    /// Apply the options to the producer configuration.
+    fn apply(&self, config: &mut ClientConfig) {
+        if let Some(compression) = &self.compression {
+            config.set("compression.type", compression.codec.as_str());
+            if let Some(level) = compression.level {
+                config.set("compression.level", level.to_string());
+            }
+        }
+        if let Some(linger) = self.linger {
+            config.set("linger.ms", linger.as_millis().to_string());
+        }
+        if let Some(batch_size) = self.batch_size {
+            config.set("batch.size", batch_size.to_string());
+        }
+        if let Some(batch_num_messages) = self.batch_num_messages {
+            config.set("batch.num.messages", batch_num_messages.to_string());
+        }
+    }
+}
+
+/// Limits the number of in-flight publishes, per application.
+#[derive(Debug)]
+struct InFlightLimit {
+    max: usize,
+    permits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl InFlightLimit {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            permits: Default::default(),
+        }
+    }
+
+    /// Try to acquire a permit for the application, `None` if the limit is reached.
+    fn try_acquire(&self, application: &str) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self
+            .permits
+            .lock()
+            .unwrap()
+            .entry(application.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max)))
+            .clone();
+
+        semaphore.try_acquire_owned().ok()
+    }
+}
+
 #[derive(Clone)]
 pub struct KafkaSink {
     internal_producer: FutureProducer,
     check_ready: bool,
+    pipelined: bool,
+    in_flight: Option<Arc<InFlightLimit>>,
 }
 
 impl Debug for KafkaSink {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("KafkaSink")
             .field("check_ready", &self.check_ready)
+            .field("pipelined", &self.pipelined)
             .finish()
     }
 }
@@ -52,11 +179,24 @@ impl KafkaSink {
     }
 
     pub fn from_config(config: KafkaClientConfig, check_ready: bool) -> anyhow::Result<Self> {
-        let kafka_config: ClientConfig = config.into();
+        Self::with_options(config, KafkaSinkOptions::default(), check_ready)
+    }
+
+    pub fn with_options(
+        config: KafkaClientConfig,
+        options: KafkaSinkOptions,
+        check_ready: bool,
+    ) -> anyhow::Result<Self> {
+        let mut kafka_config: ClientConfig = config.into();
+        options.apply(&mut kafka_config);
 
         Ok(Self {
             internal_producer: kafka_config.create()?,
             check_ready,
+            pipelined: options.pipelined,
+            in_flight: options
+                .max_in_flight_per_application
+                .map(|max| Arc::new(InFlightLimit::new(max))),
         })
     }
 
@@ -66,12 +206,13 @@ impl KafkaSink {
         config.create()
     }
 
-    #[instrument(level = "debug", skip(producer, message_record))]
+    #[instrument(level = "debug", skip(self, message_record, permit))]
     async fn send_with(
-        producer: &FutureProducer,
+        &self,
         topic: String,
         key: String,
         message_record: MessageRecord,
+        permit: Option<OwnedSemaphorePermit>,
     ) -> Result<PublishOutcome, SinkError> {
         let record = FutureRecord::<String, Vec<u8>>::to(&topic)
             .key(&key)
@@ -79,28 +220,24 @@ impl KafkaSink {
 
         log::debug!("Sending record");
 
-        let scheduled = producer.send_result(record);
+        let scheduled = self.internal_producer.send_result(record);
 
         tracing::debug!("Send returned");
 
         match scheduled {
+            // accepted delivery, don't wait for the outcome
+            Ok(fut) if self.pipelined => {
+                tokio::spawn(async move {
+                    if let Err(err) = Self::delivered(fut.await) {
+                        log::warn!("Failed to deliver pipelined publish: {}", err);
+                        PIPELINED_DELIVERY_FAILURES.inc();
+                    }
+                    drop(permit);
+                });
+                Ok(PublishOutcome::Accepted)
+            }
             // accepted delivery
-            Ok(fut) => match fut.await {
-                // received outcome & outcome ok
-                Ok(Ok((partition, offset))) => {
-                    tracing::debug!(partition, offset, "Publish accepted");
-                    Ok(PublishOutcome::Accepted)
-                }
-                // received outcome & outcome failed
-                Ok(Err((err, _))) => {
-                    log::debug!("Kafka transport error: {}", err);
-                    Err(SinkError::Transport(Box::new(err)))
-                }
-                // producer closed before delivered
-                Err(oneshot::Canceled) => {
-                    Err(SinkError::Transport(Box::new(KafkaSinkError::Canceled)))
-                }
-            },
+            Ok(fut) => Self::delivered(fut.await),
             // failed to queue up
             Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
                 Ok(PublishOutcome::QueueFull)
@@ -113,18 +250,28 @@ impl KafkaSink {
         }
     }
 
-    #[instrument(level = "debug", skip_all, ret, fields(
-        application=%app.metadata.name,
-    ))]
-    fn check_ready(&self, app: &registry::v1::Application) -> bool {
-        if self.check_ready {
-            Self::is_ready(app)
-        } else {
-            true
+    /// Evaluate the outcome of a delivery.
+    fn delivered(
+        result: Result<OwnedDeliveryResult, oneshot::Canceled>,
+    ) -> Result<PublishOutcome, SinkError> {
+        match result {
+            // received outcome & outcome ok
+            Ok(Ok((partition, offset))) => {
+                tracing::debug!(partition, offset, "Publish accepted");
+                Ok(PublishOutcome::Accepted)
+            }
+            // received outcome & outcome failed
+            Ok(Err((err, _))) => {
+                log::debug!("Kafka transport error: {}", err);
+                Err(SinkError::Transport(Box::new(err)))
+            }
+            // producer closed before delivered
+            Err(oneshot::Canceled) => Err(SinkError::Transport(Box::new(KafkaSinkError::Canceled))),
         }
     }
 
-    fn is_ready(app: &registry::v1::Application) -> bool {
+    /// Check if the topics of the application are ready, as reported by the topic operator.
+    fn is_topic_ready(app: &registry::v1::Application) -> bool {
         app.section::<core::v1::Conditions>()
             .and_then(|s| {
                 if let Err(err) = &s {
@@ -155,11 +302,22 @@ impl Sink for KafkaSink {
         target: SinkTarget<'a>,
         event: Event,
     ) -> Result<PublishOutcome, SinkError> {
-        if !self.check_ready(&target) {
+        if !self.is_ready(&target) {
             log::debug!("Kafka topic is not ready yet");
             return Err(SinkError::Transport(Box::new(KafkaSinkError::NotReady)));
         }
 
+        let permit = match &self.in_flight {
+            Some(in_flight) => match in_flight.try_acquire(&target.metadata.name) {
+                Some(permit) => Some(permit),
+                None => {
+                    log::debug!("Too many publishes in flight");
+                    return Ok(PublishOutcome::QueueFull);
+                }
+            },
+            None => None,
+        };
+
         let topic = match target {
             SinkTarget::Commands(app) => app.kafka_topic(KafkaEventType::Commands),
             SinkTarget::Events(app) => app.kafka_topic(KafkaEventType::Events),
@@ -176,7 +334,14 @@ impl Sink for KafkaSink {
 
         let message_record = MessageRecord::from_event(event)?;
 
-        Self::send_with(&self.internal_producer, topic, key, message_record).await
+        self.send_with(topic, key, message_record, permit).await
+    }
+
+    #[instrument(level = "debug", skip_all, ret, fields(
+        application=%target.metadata.name,
+    ))]
+    fn is_ready(&self, target: &SinkTarget<'_>) -> bool {
+        !self.check_ready || Self::is_topic_ready(target)
     }
 }
 
@@ -190,7 +355,7 @@ mod test {
     fn test_ready() {
         let mut app = registry::v1::Application::default();
 
-        assert!(!KafkaSink::is_ready(&app));
+        assert!(!KafkaSink::is_topic_ready(&app));
 
         app.update_section(|mut conditions: Conditions| {
             conditions.update("KafkaReady", true);
@@ -198,6 +363,44 @@ mod test {
         })
         .unwrap();
 
-        assert!(KafkaSink::is_ready(&app));
+        assert!(KafkaSink::is_topic_ready(&app));
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let limit = InFlightLimit::new(2);
+
+        let first = limit.try_acquire("app1");
+        let second = limit.try_acquire("app1");
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(limit.try_acquire("app1").is_none());
+
+        // other applications are not affected
+        assert!(limit.try_acquire("app2").is_some());
+
+        drop(first);
+        assert!(limit.try_acquire("app1").is_some());
+    }
+
+    #[test]
+    fn test_options() {
+        let options: KafkaSinkOptions = serde_json::from_value(serde_json::json!({
+            "pipelined": true,
+            "compression": { "codec": "zstd", "level": 3 },
+            "linger": "5ms",
+            "batch_num_messages": 1000,
+        }))
+        .unwrap();
+
+        let mut config = ClientConfig::new();
+        options.apply(&mut config);
+
+        assert!(options.pipelined);
+        assert_eq!(config.get("compression.type"), Some("zstd"));
+        assert_eq!(config.get("compression.level"), Some("3"));
+        assert_eq!(config.get("linger.ms"), Some("5"));
+        assert_eq!(config.get("batch.num.messages"), Some("1000"));
+        assert_eq!(config.get("batch.size"), None);
     }
 }
@@ -1,17 +1,23 @@
 mod http;
 mod kafka;
+mod mqtt;
+mod spool;
 
 pub use self::http::HttpSink;
 pub use kafka::*;
+pub use mqtt::*;
+pub use spool::*;
 
 use crate::sender::PublishOutcome;
 use async_trait::async_trait;
 use cloudevents::Event;
 use drogue_client::registry;
+pub use drogue_cloud_event_common::mqtt::MqttClientConfig;
+use drogue_cloud_service_api::kafka::KafkaClientConfig;
 use std::{fmt::Debug, ops::Deref};
 use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum SinkTarget<'a> {
     Events(&'a registry::v1::Application),
     Commands(&'a registry::v1::Application),
@@ -37,6 +43,40 @@ pub trait Sink: Send + Sync + Debug + 'static {
         target: SinkTarget<'a>,
         event: Event,
     ) -> Result<PublishOutcome, SinkError>;
+
+    /// Check if the sink is ready to accept events for the target.
+    fn is_ready(&self, _target: &SinkTarget<'_>) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl Sink for Box<dyn Sink> {
+    #[allow(clippy::needless_lifetimes)]
+    async fn publish<'a>(
+        &self,
+        target: SinkTarget<'a>,
+        event: Event,
+    ) -> Result<PublishOutcome, SinkError> {
+        self.as_ref().publish(target, event).await
+    }
+
+    fn is_ready(&self, target: &SinkTarget<'_>) -> bool {
+        self.as_ref().is_ready(target)
+    }
+}
+
+/// Create a sink, using an MQTT broker if configured, Kafka otherwise.
+pub fn from_config(
+    kafka: KafkaClientConfig,
+    kafka_options: KafkaSinkOptions,
+    mqtt: Option<MqttClientConfig>,
+    check_ready: bool,
+) -> anyhow::Result<Box<dyn Sink>> {
+    Ok(match mqtt {
+        Some(mqtt) => Box::new(MqttSink::from_config(mqtt, check_ready)?),
+        None => Box::new(KafkaSink::with_options(kafka, kafka_options, check_ready)?),
+    })
 }
 
 #[derive(Error, Debug)]
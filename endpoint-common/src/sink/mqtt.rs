@@ -0,0 +1,186 @@
+use super::*;
+use async_trait::async_trait;
+use cloudevents::AttributesReader;
+use drogue_cloud_event_common::mqtt::{MqttClientConfig, MqttEventType};
+use rumqttc::{AsyncClient, ClientError, Outgoing, Packet, QoS};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Formatter,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tracing::instrument;
+
+/// Delay before re-connecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Time to wait for the broker to acknowledge an event.
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum MqttSinkError {
+    #[error("MQTT client error")]
+    Client(#[from] ClientError),
+    #[error("Not connected to the MQTT broker")]
+    NotReady,
+    #[error("MQTT client stopped before the event was acknowledged")]
+    Stopped,
+    #[error("MQTT broker didn't acknowledge the event in time")]
+    Timeout,
+}
+
+/// Events waiting for their acknowledgement by the broker.
+#[derive(Debug, Default)]
+struct Acks {
+    /// Handed over to the client, in the order they will be sent.
+    queued: VecDeque<oneshot::Sender<()>>,
+    /// Sent to the broker, by packet ID.
+    sent: HashMap<u16, oneshot::Sender<()>>,
+}
+
+/// A sink, publishing events to an MQTT broker.
+///
+/// Events are published as structured cloud events, using QoS 1, to the topic
+/// `<prefix>/<application>/events` (or `commands`). Events are only accepted once the broker
+/// acknowledged them. Unlike Kafka, topics don't need to be provisioned, so the sink is ready as
+/// long as it is connected to the broker.
+#[derive(Clone)]
+pub struct MqttSink {
+    config: MqttClientConfig,
+    client: AsyncClient,
+    connected: Arc<AtomicBool>,
+    acks: Arc<Mutex<Acks>>,
+    check_ready: bool,
+}
+
+impl Debug for MqttSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttSink")
+            .field("host", &self.config.host)
+            .field("port", &self.config.port)
+            .field("check_ready", &self.check_ready)
+            .finish()
+    }
+}
+
+impl MqttSink {
+    /// Create a new sink, connecting to the broker in the background.
+    ///
+    /// This must be called in the context of a Tokio runtime.
+    pub fn from_config(config: MqttClientConfig, check_ready: bool) -> anyhow::Result<Self> {
+        let (client, mut eventloop) = AsyncClient::new(config.to_options(), 64);
+        let connected = Arc::new(AtomicBool::new(false));
+        let acks = Arc::new(Mutex::new(Acks::default()));
+
+        {
+            let connected = connected.clone();
+            let acks = acks.clone();
+            tokio::spawn(async move {
+                loop {
+                    match eventloop.poll().await {
+                        Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                            log::info!("Connected to MQTT broker");
+                            connected.store(true, Ordering::Relaxed);
+                        }
+                        Ok(rumqttc::Event::Outgoing(Outgoing::Publish(pkid))) => {
+                            let mut acks = acks.lock().unwrap();
+                            // events which are not yet acknowledged get re-sent after
+                            // re-connecting, keeping their packet ID
+                            if !acks.sent.contains_key(&pkid) {
+                                if let Some(ack) = acks.queued.pop_front() {
+                                    acks.sent.insert(pkid, ack);
+                                }
+                            }
+                        }
+                        Ok(rumqttc::Event::Incoming(Packet::PubAck(puback))) => {
+                            let ack = acks.lock().unwrap().sent.remove(&puback.pkid);
+                            if let Some(ack) = ack {
+                                // the publisher might have timed out in the meantime
+                                let _ = ack.send(());
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            if connected.swap(false, Ordering::Relaxed) {
+                                log::warn!("Lost connection to MQTT broker: {}", err);
+                            } else {
+                                log::debug!("Failed to connect to MQTT broker: {}", err);
+                            }
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            config,
+            client,
+            connected,
+            acks,
+            check_ready,
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for MqttSink {
+    #[allow(clippy::needless_lifetimes)]
+    #[instrument(level = "debug", skip_all, fields(
+        application=%target.metadata.name,
+        id=%event.id(),
+    ))]
+    async fn publish<'a>(
+        &self,
+        target: SinkTarget<'a>,
+        event: Event,
+    ) -> Result<PublishOutcome, SinkError> {
+        if !self.is_ready(&target) {
+            log::debug!("Not connected to MQTT broker");
+            return Err(SinkError::Transport(Box::new(MqttSinkError::NotReady)));
+        }
+
+        let topic = match target {
+            SinkTarget::Commands(app) => self
+                .config
+                .topic(&app.metadata.name, MqttEventType::Commands),
+            SinkTarget::Events(app) => self.config.topic(&app.metadata.name, MqttEventType::Events),
+        };
+
+        let payload =
+            serde_json::to_vec(&event).map_err(|err| SinkError::Transport(Box::new(err)))?;
+
+        let (tx, rx) = oneshot::channel();
+
+        {
+            // hold the lock while handing over the event, so that the waiting publishers are in
+            // the same order as the events being sent
+            let mut acks = self.acks.lock().unwrap();
+            match self
+                .client
+                .try_publish(topic, QoS::AtLeastOnce, false, payload)
+            {
+                Ok(()) => acks.queued.push_back(tx),
+                Err(ClientError::TryRequest(_)) => return Ok(PublishOutcome::QueueFull),
+                Err(err) => return Err(SinkError::Transport(Box::new(MqttSinkError::Client(err)))),
+            }
+        }
+
+        let err = match tokio::time::timeout(ACK_TIMEOUT, rx).await {
+            Ok(Ok(())) => return Ok(PublishOutcome::Accepted),
+            Ok(Err(_)) => MqttSinkError::Stopped,
+            Err(_) => MqttSinkError::Timeout,
+        };
+
+        log::debug!("Failed to publish event: {err}");
+        Err(SinkError::Transport(Box::new(err)))
+    }
+
+    fn is_ready(&self, _target: &SinkTarget<'_>) -> bool {
+        !self.check_ready || self.connected.load(Ordering::Relaxed)
+    }
+}
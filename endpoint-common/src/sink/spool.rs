@@ -0,0 +1,687 @@
+use super::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+lazy_static! {
+    static ref SPOOL_EVENTS: IntGauge =
+        register_int_gauge!("drogue_spool_events", "Number of spooled events").unwrap();
+    static ref SPOOL_BYTES: IntGauge =
+        register_int_gauge!("drogue_spool_bytes", "Size of spooled events").unwrap();
+    static ref SPOOL_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "drogue_spool_dropped",
+        "Spooled events which got dropped",
+        &["reason"]
+    )
+    .unwrap();
+}
+
+/// Configuration of the on-disk spool.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct SpoolConfig {
+    /// The directory to store events in.
+    pub directory: PathBuf,
+    /// Maximum size of all spooled events, in bytes.
+    #[serde(default = "default_max_size")]
+    pub max_size: u64,
+    /// Maximum age of an event, older events get dropped.
+    #[serde(default = "default_max_age", with = "humantime_serde")]
+    pub max_age: Duration,
+    /// Interval for trying to replay spooled events.
+    #[serde(default = "default_replay_interval", with = "humantime_serde")]
+    pub replay_interval: Duration,
+}
+
+const fn default_max_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+const fn default_max_age() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+const fn default_replay_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// Wrap the sink with a spool, if configured.
+pub async fn with_spool(
+    sink: Box<dyn Sink>,
+    config: Option<SpoolConfig>,
+) -> anyhow::Result<Box<dyn Sink>> {
+    Ok(match config {
+        Some(config) => Box::new(SpoolSink::new(sink, config).await?),
+        None => sink,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum SpoolTarget {
+    Events,
+    Commands,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpoolEntry {
+    /// Time the event was spooled, in milliseconds since the epoch.
+    timestamp: u64,
+    target: SpoolTarget,
+    /// The name of the application.
+    application: String,
+    /// The UID of the application, events of a re-created application get dropped.
+    uid: String,
+    event: Event,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SpoolFile {
+    sequence: u64,
+    size: u64,
+}
+
+/// The spooled events of a single application.
+#[derive(Debug, Default)]
+struct AppSpool {
+    files: VecDeque<SpoolFile>,
+    /// The most recent state of the application, used for replaying.
+    ///
+    /// This is unknown for events recovered from disk, until the next event of the application
+    /// gets published.
+    application: Option<registry::v1::Application>,
+}
+
+#[derive(Debug, Default)]
+struct SpoolState {
+    apps: HashMap<String, AppSpool>,
+    next_sequence: u64,
+    events: usize,
+    size: u64,
+}
+
+impl SpoolState {
+    fn has_files(&self, application: &str) -> bool {
+        self.apps
+            .get(application)
+            .map(|app| !app.files.is_empty())
+            .unwrap_or_default()
+    }
+
+    fn push(&mut self, application: &str, file: SpoolFile) {
+        self.size += file.size;
+        self.events += 1;
+        self.next_sequence = self.next_sequence.max(file.sequence + 1);
+        self.apps
+            .entry(application.to_string())
+            .or_default()
+            .files
+            .push_back(file);
+        self.update_metrics();
+    }
+
+    fn pop(&mut self, application: &str) -> Option<SpoolFile> {
+        let app = self.apps.get_mut(application)?;
+        let file = app.files.pop_front()?;
+        if app.files.is_empty() {
+            self.apps.remove(application);
+        }
+        self.size -= file.size;
+        self.events -= 1;
+        self.update_metrics();
+        Some(file)
+    }
+
+    fn update_metrics(&self) {
+        SPOOL_EVENTS.set(self.events as i64);
+        SPOOL_BYTES.set(self.size as i64);
+    }
+}
+
+#[derive(Debug)]
+struct Inner<S: Sink> {
+    sink: S,
+    config: SpoolConfig,
+    state: Mutex<SpoolState>,
+    /// Held while replaying, so that events are not replayed twice.
+    replaying: Mutex<()>,
+}
+
+/// A sink, storing events on disk while the wrapped sink is unavailable.
+///
+/// Events are only spooled when the wrapped sink fails with a transport error, like when Kafka
+/// is not reachable. Events of applications which are not ready, like when their Kafka topic is
+/// not yet provisioned, are not spooled.
+///
+/// Each application has its own spool. As long as there are spooled events for an application,
+/// new events of the application get spooled too, so that they are replayed in order.
+#[derive(Debug)]
+pub struct SpoolSink<S: Sink> {
+    inner: Arc<Inner<S>>,
+    replay: JoinHandle<()>,
+}
+
+impl<S: Sink> SpoolSink<S> {
+    pub async fn new(sink: S, config: SpoolConfig) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(&config.directory).await?;
+        let state = Self::recover(&config.directory).await?;
+        if state.events > 0 {
+            log::info!(
+                "Recovered {} spooled events, of {} applications",
+                state.events,
+                state.apps.len()
+            );
+        }
+
+        let inner = Arc::new(Inner {
+            sink,
+            config,
+            state: Mutex::new(state),
+            replaying: Mutex::new(()),
+        });
+
+        let replay = {
+            let inner = inner.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(inner.config.replay_interval);
+                loop {
+                    interval.tick().await;
+                    inner.replay().await;
+                }
+            })
+        };
+
+        Ok(Self { inner, replay })
+    }
+
+    /// Recover the state from the spooled files, stored in one directory per application.
+    async fn recover(directory: &Path) -> anyhow::Result<SpoolState> {
+        let mut state = SpoolState::default();
+
+        let mut apps = tokio::fs::read_dir(directory).await?;
+        while let Some(app) = apps.next_entry().await? {
+            if !app.file_type().await?.is_dir() {
+                continue;
+            }
+            let application = match app.file_name().to_str() {
+                Some(application) => application.to_string(),
+                None => continue,
+            };
+
+            let mut files = vec![];
+            let mut entries = tokio::fs::read_dir(app.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let sequence = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".json"))
+                    .and_then(|sequence| sequence.parse::<u64>().ok());
+                if let Some(sequence) = sequence {
+                    let size = entry.metadata().await?.len();
+                    files.push(SpoolFile { sequence, size });
+                }
+            }
+
+            files.sort_unstable_by_key(|file| file.sequence);
+            for file in files {
+                state.push(&application, file);
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+impl<S: Sink> Drop for SpoolSink<S> {
+    fn drop(&mut self) {
+        self.replay.abort();
+    }
+}
+
+/// Check if the application name can safely be used as a directory name.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+/// Check if the sink failed, as the application is not ready.
+///
+/// Waiting won't help in this case, so the error is reported instead.
+fn is_not_ready(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<KafkaSinkError>(),
+        Some(KafkaSinkError::NotReady)
+    )
+}
+
+impl<S: Sink> Inner<S> {
+    fn path(&self, application: &str, sequence: u64) -> PathBuf {
+        self.config
+            .directory
+            .join(application)
+            .join(format!("{:020}.json", sequence))
+    }
+
+    /// Append an event to the spool, returning `false` if the spool is full.
+    async fn spool(
+        &self,
+        state: &mut SpoolState,
+        target: SinkTarget<'_>,
+        event: Event,
+    ) -> Result<bool, SinkError> {
+        let application = &target.metadata.name;
+        if !is_valid_name(application) {
+            log::info!("Unable to spool event of application: {:?}", application);
+            return Ok(false);
+        }
+
+        let entry = SpoolEntry {
+            timestamp: now(),
+            target: match target {
+                SinkTarget::Events(_) => SpoolTarget::Events,
+                SinkTarget::Commands(_) => SpoolTarget::Commands,
+            },
+            application: application.clone(),
+            uid: target.metadata.uid.clone(),
+            event,
+        };
+        let data = serde_json::to_vec(&entry).map_err(|err| SinkError::Transport(Box::new(err)))?;
+        let size = data.len() as u64;
+
+        if state.size + size > self.config.max_size {
+            SPOOL_DROPPED.with_label_values(&["full"]).inc();
+            return Ok(false);
+        }
+
+        let sequence = state.next_sequence;
+        let path = self.path(application, sequence);
+        let temp = path.with_extension("tmp");
+
+        // write to a temporary file first, so that we never replay a partially written event
+        tokio::fs::create_dir_all(self.config.directory.join(application))
+            .await
+            .map_err(|err| SinkError::Transport(Box::new(err)))?;
+        tokio::fs::write(&temp, data)
+            .await
+            .map_err(|err| SinkError::Transport(Box::new(err)))?;
+        tokio::fs::rename(&temp, &path)
+            .await
+            .map_err(|err| SinkError::Transport(Box::new(err)))?;
+
+        state.push(application, SpoolFile { sequence, size });
+        if let Some(app) = state.apps.get_mut(application) {
+            app.application = Some((*target).clone());
+        }
+
+        Ok(true)
+    }
+
+    /// Replay spooled events of all applications.
+    async fn replay(&self) {
+        let _replaying = self.replaying.lock().await;
+
+        let applications: Vec<String> = self.state.lock().await.apps.keys().cloned().collect();
+        for application in applications {
+            self.replay_app(&application).await;
+        }
+    }
+
+    /// Replay spooled events of an application, until its spool is empty or the sink fails.
+    ///
+    /// The state is not locked while publishing, new events get appended to the spool in the
+    /// meantime.
+    async fn replay_app(&self, application: &str) {
+        loop {
+            let (file, current) = {
+                let state = self.state.lock().await;
+                match state.apps.get(application) {
+                    Some(app) => match app.files.front() {
+                        Some(file) => (*file, app.application.clone()),
+                        None => break,
+                    },
+                    None => break,
+                }
+            };
+
+            let path = self.path(application, file.sequence);
+
+            match self.replay_file(&path, current.as_ref()).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    log::warn!("Dropping unreadable spooled event {:?}: {}", path, err);
+                    SPOOL_DROPPED.with_label_values(&["invalid"]).inc();
+                }
+            }
+
+            self.state.lock().await.pop(application);
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                log::warn!("Failed to remove spooled event {:?}: {}", path, err);
+            }
+        }
+    }
+
+    /// Replay a single file, returning `false` if the sink is (still) unavailable.
+    async fn replay_file(
+        &self,
+        path: &Path,
+        current: Option<&registry::v1::Application>,
+    ) -> anyhow::Result<bool> {
+        let entry: SpoolEntry = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+
+        if now().saturating_sub(entry.timestamp) > self.config.max_age.as_millis() as u64 {
+            log::debug!("Dropping expired spooled event: {}", entry.event.id());
+            SPOOL_DROPPED.with_label_values(&["expired"]).inc();
+            return Ok(true);
+        }
+
+        let application = match current {
+            Some(application) if application.metadata.uid == entry.uid => application,
+            Some(_) => {
+                log::debug!(
+                    "Dropping spooled event of deleted application: {}",
+                    entry.event.id()
+                );
+                SPOOL_DROPPED.with_label_values(&["deleted"]).inc();
+                return Ok(true);
+            }
+            // wait for the next event of the application, providing its current state
+            None => return Ok(false),
+        };
+
+        let target = match entry.target {
+            SpoolTarget::Events => SinkTarget::Events(application),
+            SpoolTarget::Commands => SinkTarget::Commands(application),
+        };
+
+        match self.sink.publish(target, entry.event).await {
+            Ok(PublishOutcome::QueueFull) | Err(SinkError::Transport(_)) => Ok(false),
+            Ok(outcome) => {
+                log::debug!("Replayed spooled event: {:?}", outcome);
+                Ok(true)
+            }
+            Err(err) => {
+                log::info!("Failed to replay spooled event: {}", err);
+                SPOOL_DROPPED.with_label_values(&["rejected"]).inc();
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Sink> Sink for SpoolSink<S> {
+    #[allow(clippy::needless_lifetimes)]
+    async fn publish<'a>(
+        &self,
+        target: SinkTarget<'a>,
+        event: Event,
+    ) -> Result<PublishOutcome, SinkError> {
+        {
+            let mut state = self.inner.state.lock().await;
+
+            // keep the order, as long as there are events of the application in the spool
+            if state.has_files(&target.metadata.name) {
+                return match self.inner.spool(&mut state, target, event).await? {
+                    true => Ok(PublishOutcome::Accepted),
+                    false => Ok(PublishOutcome::QueueFull),
+                };
+            }
+        }
+
+        // don't block other publishes while the sink is busy
+
+        match self.inner.sink.publish(target, event.clone()).await {
+            Err(SinkError::Transport(err)) if !is_not_ready(err.as_ref()) => {
+                log::debug!("Spooling event, sink failed: {}", err);
+                let mut state = self.inner.state.lock().await;
+                match self.inner.spool(&mut state, target, event).await? {
+                    true => Ok(PublishOutcome::Accepted),
+                    false => Err(SinkError::Transport(err)),
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn is_ready(&self, target: &SinkTarget<'_>) -> bool {
+        self.inner.sink.is_ready(target)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+    use drogue_client::meta;
+    use std::collections::HashSet;
+
+    #[derive(Debug, Default)]
+    struct MockSink {
+        /// Applications for which publishing fails.
+        unavailable: std::sync::Mutex<HashSet<String>>,
+        not_ready: std::sync::Mutex<HashSet<String>>,
+        events: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl MockSink {
+        fn set_available(&self, application: &str, available: bool) {
+            let mut unavailable = self.unavailable.lock().unwrap();
+            match available {
+                true => unavailable.remove(application),
+                false => unavailable.insert(application.to_string()),
+            };
+        }
+    }
+
+    #[async_trait]
+    impl Sink for Arc<MockSink> {
+        #[allow(clippy::needless_lifetimes)]
+        async fn publish<'a>(
+            &self,
+            target: SinkTarget<'a>,
+            event: Event,
+        ) -> Result<PublishOutcome, SinkError> {
+            let application = &target.metadata.name;
+            if self.not_ready.lock().unwrap().contains(application) {
+                Err(SinkError::Transport(Box::new(KafkaSinkError::NotReady)))
+            } else if self.unavailable.lock().unwrap().contains(application) {
+                Err(SinkError::Transport("unavailable".into()))
+            } else {
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}/{}", application, event.id()));
+                Ok(PublishOutcome::Accepted)
+            }
+        }
+    }
+
+    fn event(id: &str) -> Event {
+        EventBuilderV10::new()
+            .id(id)
+            .ty("type")
+            .source("source")
+            .build()
+            .unwrap()
+    }
+
+    fn app(name: &str, uid: &str) -> registry::v1::Application {
+        registry::v1::Application {
+            metadata: meta::v1::CommonMetadata {
+                name: name.to_string(),
+                uid: uid.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn config(directory: &Path) -> SpoolConfig {
+        SpoolConfig {
+            directory: directory.to_path_buf(),
+            max_size: default_max_size(),
+            max_age: default_max_age(),
+            replay_interval: Duration::from_secs(60 * 60),
+        }
+    }
+
+    fn directory() -> PathBuf {
+        std::env::temp_dir().join(format!("spool-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn events(mock: &MockSink) -> Vec<String> {
+        mock.events.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_spool_and_replay() {
+        let directory = directory();
+        let mock = Arc::new(MockSink::default());
+        let app = app("app1", "uid1");
+
+        let sink = SpoolSink::new(mock.clone(), config(&directory))
+            .await
+            .unwrap();
+
+        mock.set_available("app1", false);
+        for id in ["1", "2"] {
+            let outcome = sink.publish(SinkTarget::Events(&app), event(id)).await;
+            assert!(matches!(outcome, Ok(PublishOutcome::Accepted)));
+        }
+        assert_eq!(sink.inner.state.lock().await.events, 2);
+
+        // once available, new events must not overtake spooled ones
+        mock.set_available("app1", true);
+        sink.publish(SinkTarget::Events(&app), event("3"))
+            .await
+            .unwrap();
+        assert!(events(&mock).is_empty());
+
+        sink.inner.replay().await;
+        assert_eq!(events(&mock), vec!["app1/1", "app1/2", "app1/3"]);
+        assert_eq!(sink.inner.state.lock().await.events, 0);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_per_application() {
+        let directory = directory();
+        let mock = Arc::new(MockSink::default());
+        let app1 = app("app1", "uid1");
+        let app2 = app("app2", "uid2");
+
+        let sink = SpoolSink::new(mock.clone(), config(&directory))
+            .await
+            .unwrap();
+
+        mock.set_available("app1", false);
+        sink.publish(SinkTarget::Events(&app1), event("1"))
+            .await
+            .unwrap();
+
+        // events of other applications are not held back
+        sink.publish(SinkTarget::Events(&app2), event("2"))
+            .await
+            .unwrap();
+        assert_eq!(events(&mock), vec!["app2/2"]);
+
+        // the spool is only persisting the name and UID of the application
+        let entry: serde_json::Value =
+            serde_json::from_slice(&tokio::fs::read(sink.inner.path("app1", 0)).await.unwrap())
+                .unwrap();
+        assert_eq!(entry["application"], "app1");
+        assert_eq!(entry["uid"], "uid1");
+
+        // still failing, the spool of app1 is kept
+        sink.inner.replay().await;
+        assert_eq!(events(&mock), vec!["app2/2"]);
+
+        mock.set_available("app1", true);
+        sink.inner.replay().await;
+        assert_eq!(events(&mock), vec!["app2/2", "app1/1"]);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_not_ready() {
+        let directory = directory();
+        let mock = Arc::new(MockSink::default());
+        let app = app("app1", "uid1");
+
+        let sink = SpoolSink::new(mock.clone(), config(&directory))
+            .await
+            .unwrap();
+
+        mock.not_ready.lock().unwrap().insert("app1".to_string());
+        let outcome = sink.publish(SinkTarget::Events(&app), event("1")).await;
+        assert!(matches!(outcome, Err(SinkError::Transport(_))));
+        assert_eq!(sink.inner.state.lock().await.events, 0);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        let directory = directory();
+        let mock = Arc::new(MockSink::default());
+
+        {
+            let sink = SpoolSink::new(mock.clone(), config(&directory))
+                .await
+                .unwrap();
+            mock.set_available("app1", false);
+            sink.publish(SinkTarget::Events(&app("app1", "uid1")), event("1"))
+                .await
+                .unwrap();
+            mock.set_available("app2", false);
+            sink.publish(SinkTarget::Events(&app("app2", "uid2")), event("2"))
+                .await
+                .unwrap();
+        }
+
+        mock.set_available("app1", true);
+        mock.set_available("app2", true);
+
+        let sink = SpoolSink::new(mock.clone(), config(&directory))
+            .await
+            .unwrap();
+        assert_eq!(sink.inner.state.lock().await.events, 2);
+
+        // the state of the applications is unknown, until they publish again
+        sink.inner.replay().await;
+        assert!(events(&mock).is_empty());
+
+        // app1 is still the same
+        sink.publish(SinkTarget::Events(&app("app1", "uid1")), event("3"))
+            .await
+            .unwrap();
+        // app2 got re-created in the meantime
+        sink.publish(SinkTarget::Events(&app("app2", "uid3")), event("4"))
+            .await
+            .unwrap();
+
+        sink.inner.replay().await;
+        let mut result = events(&mock);
+        result.sort_unstable();
+        assert_eq!(result, vec!["app1/1", "app1/3", "app2/4"]);
+
+        tokio::fs::remove_dir_all(directory).await.unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use cloudevents::{Event, EventBuilder, EventBuilderV10};
+use drogue_client::{meta::v1::CommonMetadata, registry};
+use drogue_cloud_endpoint_common::{
+    sender::PublishOutcome,
+    sink::{MqttClientConfig, MqttSink, Sink, SinkError, SinkTarget},
+};
+use drogue_cloud_event_common::{
+    mqtt::MqttEventType,
+    stream::{MqttEventStream, MqttEventStreamConfig},
+};
+use drogue_cloud_test_common::{client, mqtt};
+use futures::StreamExt;
+use serial_test::serial;
+use std::time::Duration;
+use tokio::time::{sleep, timeout, Instant};
+
+const APP: &str = "app1";
+
+fn config(host: &str, port: u16) -> MqttClientConfig {
+    serde_json::from_value(serde_json::json!({
+        "host": host,
+        "port": port,
+    }))
+    .unwrap()
+}
+
+fn app() -> registry::v1::Application {
+    registry::v1::Application {
+        metadata: CommonMetadata {
+            name: APP.into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn event(id: usize) -> Event {
+    EventBuilderV10::new()
+        .id(id.to_string())
+        .source("drogue://test")
+        .ty("io.drogue.event.v1")
+        .build()
+        .unwrap()
+}
+
+fn stream(config: &MqttClientConfig, group: Option<&str>) -> MqttEventStream {
+    MqttEventStream::new(MqttEventStreamConfig {
+        mqtt: config.clone(),
+        topic: config.topic(APP, MqttEventType::Events),
+        group: group.map(ToString::to_string),
+    })
+}
+
+/// Wait until the condition is met, as clients connect in the background.
+async fn wait_for<F>(f: F)
+where
+    F: Fn() -> bool,
+{
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !f() {
+        assert!(Instant::now() < deadline, "Timeout waiting for condition");
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_publish() -> anyhow::Result<()> {
+    let cli = client();
+    let broker = mqtt(&cli);
+    let config = config(&broker.host, broker.port);
+    let app = app();
+
+    let sink = MqttSink::from_config(config.clone(), true)?;
+    let mut stream = stream(&config, None);
+
+    wait_for(|| sink.is_ready(&SinkTarget::Events(&app)) && stream.is_subscribed()).await;
+
+    // the outcome is only reported once the broker acknowledged the event
+    let outcome = sink.publish(SinkTarget::Events(&app), event(1)).await?;
+    assert!(matches!(outcome, PublishOutcome::Accepted));
+
+    let received = timeout(Duration::from_secs(10), stream.next())
+        .await?
+        .expect("stream must not end")?;
+    assert_eq!(received.id(), "1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_not_ready() -> anyhow::Result<()> {
+    // nothing is listening on this port
+    let sink = MqttSink::from_config(config("localhost", 1), true)?;
+    let app = app();
+
+    assert!(!sink.is_ready(&SinkTarget::Events(&app)));
+    let result = sink.publish(SinkTarget::Events(&app), event(1)).await;
+    assert!(matches!(result, Err(SinkError::Transport(_))));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn test_shared_subscription() -> anyhow::Result<()> {
+    let cli = client();
+    let broker = mqtt(&cli);
+    let config = config(&broker.host, broker.port);
+    let app = app();
+
+    let sink = MqttSink::from_config(config.clone(), true)?;
+    let mut s1 = stream(&config, Some("group1"));
+    let mut s2 = stream(&config, Some("group1"));
+
+    wait_for(|| {
+        sink.is_ready(&SinkTarget::Events(&app)) && s1.is_subscribed() && s2.is_subscribed()
+    })
+    .await;
+
+    const COUNT: usize = 10;
+    for id in 0..COUNT {
+        let outcome = sink.publish(SinkTarget::Events(&app), event(id)).await?;
+        assert!(matches!(outcome, PublishOutcome::Accepted));
+    }
+
+    // every event must be delivered to exactly one member of the group
+    let mut received = vec![];
+    while received.len() < COUNT {
+        let event = timeout(
+            Duration::from_secs(10),
+            futures::future::select(s1.next(), s2.next()),
+        )
+        .await?
+        .factor_first()
+        .0
+        .expect("stream must not end")?;
+        received.push(event.id().parse::<usize>()?);
+    }
+
+    // nothing else arrives
+    sleep(Duration::from_secs(1)).await;
+    assert!(timeout(Duration::from_millis(100), s1.next())
+        .await
+        .is_err());
+    assert!(timeout(Duration::from_millis(100), s2.next())
+        .await
+        .is_err());
+
+    received.sort_unstable();
+    assert_eq!(received, (0..COUNT).collect::<Vec<_>>());
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use chrono::{Duration, TimeZone, Utc};
+use drogue_cloud_endpoint_common::command::{
+    Command, CommandAddress, CommandFilter, ExpiryReason, PostgresQueueStore, QueueStore,
+    QueuedCommand,
+};
+use drogue_cloud_test_common::{client, db};
+use serial_test::serial;
+
+const APP: &str = "app1";
+
+fn entry(device: &str, command: &str, id: Option<&str>, queued: i64) -> QueuedCommand {
+    let queued = Utc.timestamp(queued, 0);
+    QueuedCommand {
+        command: Command::new(CommandAddress::new(APP, device, device), command, None)
+            .with_id(id.map(ToString::to_string)),
+        queued,
+        expires: queued + Duration::seconds(10),
+    }
+}
+
+fn commands(entries: Vec<(QueuedCommand, ExpiryReason)>) -> Vec<(String, ExpiryReason)> {
+    entries
+        .into_iter()
+        .map(|(entry, reason)| (entry.command.command, reason))
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn test_queue() -> anyhow::Result<()> {
+    let cli = client();
+    let db = db(&cli, |pg| pg)?;
+    let store = PostgresQueueStore::new(db.config.create_pool()?);
+    let now = Utc.timestamp(0, 0);
+
+    for (command, id) in [("c1", "id1"), ("c2", "id2"), ("c3", "id3")] {
+        let (queued, removed) = store
+            .push(entry("d1", command, Some(id), 0), 2, false, now)
+            .await?;
+        assert!(queued);
+        if command == "c3" {
+            assert_eq!(
+                commands(removed),
+                vec![("c1".to_string(), ExpiryReason::Evicted)]
+            );
+        }
+    }
+
+    // a second instance receiving the same command doesn't queue it again
+    let (queued, _) = store
+        .push(entry("d1", "c2", Some("id2"), 0), 2, false, now)
+        .await?;
+    assert!(!queued);
+
+    let (taken, removed) = store
+        .take(
+            &CommandFilter::wildcard(APP, "d1").with_filter("c3".to_string()),
+            now,
+        )
+        .await?;
+    assert_eq!(taken, vec![entry("d1", "c3", Some("id3"), 0).command]);
+    assert!(removed.is_empty());
+
+    let (taken, _) = store.take(&CommandFilter::wildcard(APP, "d1"), now).await?;
+    assert_eq!(taken, vec![entry("d1", "c2", Some("id2"), 0).command]);
+
+    // nothing left
+    let (taken, _) = store.take(&CommandFilter::wildcard(APP, "d1"), now).await?;
+    assert!(taken.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn test_delivered() -> anyhow::Result<()> {
+    let cli = client();
+    let db = db(&cli, |pg| pg)?;
+    let store = PostgresQueueStore::new(db.config.create_pool()?);
+    let now = Utc.timestamp(0, 0);
+
+    // delivered by one instance, before a second instance tries to queue it
+    let c1 = entry("d1", "c1", Some("id1"), 0);
+    store.delivered(&c1.command, c1.expires).await?;
+    let (queued, _) = store.push(c1.clone(), 10, false, now).await?;
+    assert!(!queued);
+
+    // queued by one instance, before a second instance delivers it
+    let c2 = entry("d1", "c2", Some("id2"), 0);
+    let (queued, _) = store.push(c2.clone(), 10, false, now).await?;
+    assert!(queued);
+    store.delivered(&c2.command, c2.expires).await?;
+
+    let (taken, _) = store.take(&CommandFilter::wildcard(APP, "d1"), now).await?;
+    assert!(taken.is_empty());
+
+    // returning an undelivered command to the queue
+    let (queued, _) = store.push(c1.clone(), 10, true, now).await?;
+    assert!(queued);
+    let (taken, _) = store.take(&CommandFilter::wildcard(APP, "d1"), now).await?;
+    assert_eq!(taken, vec![c1.command]);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn test_expired() -> anyhow::Result<()> {
+    let cli = client();
+    let db = db(&cli, |pg| pg)?;
+    let store = PostgresQueueStore::new(db.config.create_pool()?);
+
+    store
+        .push(entry("d1", "c1", None, 0), 10, false, Utc.timestamp(0, 0))
+        .await?;
+    store
+        .push(entry("d1", "c2", None, 5), 10, false, Utc.timestamp(5, 0))
+        .await?;
+
+    assert!(store.prune(Utc.timestamp(9, 0)).await?.is_empty());
+    assert_eq!(
+        commands(store.prune(Utc.timestamp(10, 0)).await?),
+        vec![("c1".to_string(), ExpiryReason::Expired)]
+    );
+    // reported only once
+    assert!(store.prune(Utc.timestamp(11, 0)).await?.is_empty());
+
+    // expired commands are not taken
+    let (taken, removed) = store
+        .take(&CommandFilter::wildcard(APP, "d1"), Utc.timestamp(15, 0))
+        .await?;
+    assert!(taken.is_empty());
+    assert_eq!(
+        commands(removed),
+        vec![("c2".to_string(), ExpiryReason::Expired)]
+    );
+
+    Ok(())
+}
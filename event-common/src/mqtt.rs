@@ -0,0 +1,103 @@
+//! Using an MQTT broker as message backbone, instead of Kafka.
+
+use rumqttc::{MqttOptions, Transport};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Connection to an MQTT broker, used as message backbone.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MqttClientConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// The client ID, a random one will be used if absent.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Use TLS, with the system trust anchors.
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default = "default_keep_alive", with = "humantime_serde")]
+    pub keep_alive: Duration,
+    /// The prefix of all topics.
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+}
+
+const fn default_port() -> u16 {
+    1883
+}
+
+const fn default_keep_alive() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_topic_prefix() -> String {
+    "drogue".into()
+}
+
+/// The type of events carried by a topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MqttEventType {
+    Events,
+    Commands,
+}
+
+impl MqttClientConfig {
+    /// The topic carrying events of the type for an application.
+    pub fn topic(&self, application: &str, r#type: MqttEventType) -> String {
+        let r#type = match r#type {
+            MqttEventType::Events => "events",
+            MqttEventType::Commands => "commands",
+        };
+        format!("{}/{}/{}", self.topic_prefix, application, r#type)
+    }
+
+    /// Create the options for connecting to the broker.
+    pub fn to_options(&self) -> MqttOptions {
+        let client_id = self
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("drogue-{}", Uuid::new_v4()));
+
+        let mut options = MqttOptions::new(client_id, &self.host, self.port);
+        options.set_keep_alive(self.keep_alive);
+
+        if let Some(username) = &self.username {
+            options.set_credentials(username, self.password.as_deref().unwrap_or_default());
+        }
+        if self.tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_topic() {
+        let config: MqttClientConfig = serde_json::from_value(json!({
+            "host": "localhost",
+        }))
+        .unwrap();
+
+        assert_eq!(config.port, 1883);
+        assert_eq!(
+            config.topic("app1", MqttEventType::Events),
+            "drogue/app1/events"
+        );
+        assert_eq!(
+            config.topic("app1", MqttEventType::Commands),
+            "drogue/app1/commands"
+        );
+    }
+}
@@ -9,4 +9,8 @@ pub enum EventStreamError {
     MissingMetadata,
     #[error("Cloud event error: {0}")]
     CloudEvent(#[from] cloudevents::message::Error),
+    #[error("MQTT error: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+    #[error("Failed to decode event: {0}")]
+    Decode(#[from] serde_json::Error),
 }
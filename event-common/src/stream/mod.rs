@@ -1,6 +1,8 @@
 mod error;
+mod mqtt;
 
 pub use error::*;
+pub use mqtt::*;
 
 use cloudevents::{binding::rdkafka::MessageExt, AttributesReader, AttributesWriter, Data, Event};
 use drogue_cloud_service_api::kafka::KafkaConfig;
@@ -15,7 +17,7 @@ use rdkafka::{
     error::KafkaResult,
     message::BorrowedMessage,
     util::Timeout,
-    Message, TopicPartitionList,
+    Message, Offset, TopicPartitionList,
 };
 use std::{
     fmt::{Debug, Formatter},
@@ -81,12 +83,22 @@ pub struct EventStreamConfig {
     pub consumer_group: Option<String>,
 }
 
+/// The source of events.
+enum Upstream<'s> {
+    Kafka(OwningHandle<Box<StreamConsumer>, Box<rdkafka::consumer::MessageStream<'s>>>),
+    Mqtt(MqttEventStream),
+}
+
+/// A stream of events, consumed from Kafka, or an MQTT broker.
+///
+/// Events consumed from an MQTT broker are acknowledged when being received, acknowledging
+/// them manually has no effect.
 pub struct EventStream<'s, Ack = AutoAck>
 where
     Ack: AckMode,
 {
     _marker: PhantomData<Ack>,
-    upstream: OwningHandle<Box<StreamConsumer>, Box<rdkafka::consumer::MessageStream<'s>>>,
+    upstream: Upstream<'s>,
     topic: String,
 }
 
@@ -204,22 +216,83 @@ where
         Ok(Self::wrap(cfg.kafka.topic.clone(), consumer))
     }
 
+    /// Create a new stream, consuming events from an MQTT broker.
+    ///
+    /// This must be called in the context of a Tokio runtime.
+    pub fn new_mqtt(cfg: MqttEventStreamConfig) -> Self {
+        let topic = cfg.topic.clone();
+        Self {
+            _marker: PhantomData,
+            upstream: Upstream::Mqtt(MqttEventStream::new(cfg)),
+            topic,
+        }
+    }
+
     fn wrap(topic: String, consumer: StreamConsumer) -> Self {
         Self {
             _marker: PhantomData,
-            upstream: OwningHandle::new_with_fn(Box::new(consumer), |c| {
+            upstream: Upstream::Kafka(OwningHandle::new_with_fn(Box::new(consumer), |c| {
                 Box::new(unsafe { &*c }.stream())
-            }),
+            })),
             topic,
         }
     }
 
+    /// Check if the consumer got partitions of the topic assigned.
+    ///
+    /// Events are only received once partitions are assigned, or the subscription to the MQTT
+    /// broker is active. As the assignment is performed while the stream is being polled, this
+    /// must be checked concurrently.
+    pub fn is_assigned(&self) -> bool {
+        match &self.upstream {
+            Upstream::Kafka(upstream) => upstream
+                .as_owner()
+                .assignment()
+                .map_or(false, |assignment| assignment.count() > 0),
+            Upstream::Mqtt(upstream) => upstream.is_subscribed(),
+        }
+    }
+
+    /// Check if the consumer group has committed offsets for the topic.
+    ///
+    /// In this case, the stream continues from those offsets, rather than only receiving new
+    /// events. This blocks while querying the broker. Streams consuming from an MQTT broker don't
+    /// have committed offsets.
+    pub fn has_committed_offsets(&self, timeout: Duration) -> KafkaResult<bool> {
+        let consumer = match &self.upstream {
+            Upstream::Kafka(upstream) => upstream.as_owner(),
+            Upstream::Mqtt(_) => return Ok(false),
+        };
+
+        let metadata = consumer.fetch_metadata(Some(&self.topic), timeout)?;
+
+        let mut partitions = TopicPartitionList::new();
+        for topic in metadata.topics().iter().filter(|t| t.name() == self.topic) {
+            for partition in topic.partitions() {
+                partitions.add_partition(&self.topic, partition.id());
+            }
+        }
+
+        let committed = consumer.committed_offsets(partitions, timeout)?;
+
+        Ok(committed
+            .elements()
+            .iter()
+            .any(|element| matches!(element.offset(), Offset::Offset(_))))
+    }
+
     pub fn ack<T>(&self, handle: Handle<'_, T>) -> KafkaResult<()> {
-        self.do_ack(&handle.msg)
+        match &handle.msg {
+            Some(msg) => self.do_ack(msg),
+            None => Ok(()),
+        }
     }
 
     fn do_ack(&self, msg: &BorrowedMessage) -> KafkaResult<()> {
-        self.upstream.as_owner().store_offset_from_message(msg)
+        match &self.upstream {
+            Upstream::Kafka(upstream) => upstream.as_owner().store_offset_from_message(msg),
+            Upstream::Mqtt(_) => Ok(()),
+        }
     }
 }
 
@@ -303,7 +376,8 @@ where
 #[derive(Debug)]
 pub struct Handle<'s, T> {
     event: T,
-    msg: BorrowedMessage<'s>,
+    /// The Kafka message, `None` for events received from an MQTT broker.
+    msg: Option<BorrowedMessage<'s>>,
 }
 
 impl<'s, T> Handle<'s, T> {
@@ -353,7 +427,12 @@ impl Stream for EventStream<'_, AutoAck> {
     type Item = Result<Event, EventStreamError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let next = self.upstream.poll_next_unpin(cx);
+        let upstream = match &mut self.upstream {
+            Upstream::Kafka(upstream) => upstream,
+            Upstream::Mqtt(upstream) => return upstream.poll_next_unpin(cx),
+        };
+
+        let next = upstream.poll_next_unpin(cx);
 
         match next {
             Poll::Pending => Poll::Pending,
@@ -381,7 +460,16 @@ impl<'s> Stream for EventStream<'s, CustomAck> {
     type Item = Result<Handle<'s, Event>, EventStreamError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let next = self.upstream.poll_next_unpin(cx);
+        let upstream = match &mut self.upstream {
+            Upstream::Kafka(upstream) => upstream,
+            Upstream::Mqtt(upstream) => {
+                return upstream
+                    .poll_next_unpin(cx)
+                    .map(|next| next.map(|event| event.map(|event| Handle { event, msg: None })))
+            }
+        };
+
+        let next = upstream.poll_next_unpin(cx);
 
         match next {
             Poll::Pending => Poll::Pending,
@@ -397,7 +485,10 @@ impl<'s> Stream for EventStream<'s, CustomAck> {
                     let event = msg.to_event()?;
                     let event = fixup_data_type(event);
 
-                    let event = Handle { event, msg };
+                    let event = Handle {
+                        event,
+                        msg: Some(msg),
+                    };
                     Poll::Ready(Some(Ok(event)))
                 }
             },
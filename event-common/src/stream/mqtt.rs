@@ -0,0 +1,135 @@
+use super::{fixup_data_type, EventStreamError};
+use crate::mqtt::MqttClientConfig;
+use cloudevents::Event;
+use futures::{
+    task::{Context, Poll},
+    Stream,
+};
+use rumqttc::{AsyncClient, Packet, QoS};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Delay before re-connecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct MqttEventStreamConfig {
+    pub mqtt: MqttClientConfig,
+    pub topic: String,
+    /// Share the subscription with other members of the group.
+    ///
+    /// This uses shared subscriptions (`$share/<group>/<topic>`), which must be supported by
+    /// the broker.
+    pub group: Option<String>,
+}
+
+/// A stream of events, consumed from an MQTT broker.
+///
+/// Events are expected to be encoded as structured cloud events. The subscription is
+/// re-established when the connection to the broker gets lost, events published in the
+/// meantime might be missed.
+pub struct MqttEventStream {
+    topic: String,
+    events: mpsc::Receiver<Result<Event, EventStreamError>>,
+    subscribed: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for MqttEventStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttEventStream")
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl MqttEventStream {
+    pub fn new(cfg: MqttEventStreamConfig) -> Self {
+        let (client, mut eventloop) = AsyncClient::new(cfg.mqtt.to_options(), 16);
+        let filter = match &cfg.group {
+            Some(group) => format!("$share/{}/{}", group, cfg.topic),
+            None => cfg.topic.clone(),
+        };
+
+        let (tx, events) = mpsc::channel(16);
+        let subscribed = Arc::new(AtomicBool::new(false));
+
+        let task_subscribed = subscribed.clone();
+        let task = tokio::spawn(async move {
+            let subscribed = task_subscribed;
+            loop {
+                let result = match eventloop.poll().await {
+                    Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                        log::debug!("Connected, subscribing to: {}", filter);
+                        // subscriptions don't survive a re-connect with a clean session
+                        client
+                            .try_subscribe(&filter, QoS::AtLeastOnce)
+                            .err()
+                            .map(|err| Err(err.into()))
+                    }
+                    Ok(rumqttc::Event::Incoming(Packet::SubAck(_))) => {
+                        log::debug!("Subscribed to: {}", filter);
+                        subscribed.store(true, Ordering::Relaxed);
+                        None
+                    }
+                    Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => Some(
+                        serde_json::from_slice::<Event>(&publish.payload)
+                            .map(fixup_data_type)
+                            .map_err(EventStreamError::from),
+                    ),
+                    Ok(_) => None,
+                    Err(err) => {
+                        subscribed.store(false, Ordering::Relaxed);
+                        log::info!("MQTT connection failed: {}", err);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        None
+                    }
+                };
+
+                if let Some(result) = result {
+                    if tx.send(result).await.is_err() {
+                        // stream got dropped
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            topic: cfg.topic,
+            events,
+            subscribed,
+            task,
+        }
+    }
+
+    /// Check if the subscription is currently active.
+    ///
+    /// Events are only received once subscribed. Events published before, or while the
+    /// connection to the broker is lost, are missed.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscribed.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for MqttEventStream {
+    type Item = Result<Event, EventStreamError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for MqttEventStream {
+    fn drop(&mut self) {
+        log::debug!("Stream dropped: {:?}", self);
+        self.task.abort();
+    }
+}
@@ -12,6 +12,7 @@ use std::time::Duration;
 use tracing::instrument;
 
 const HEADER_COMMAND: &str = "command";
+const HEADER_CORRELATION_ID: &str = "correlation-id";
 
 #[instrument(skip(commands))]
 pub async fn wait_for_command(
@@ -25,17 +26,21 @@ pub async fn wait_for_command(
                 mut receiver,
                 handle,
             } = commands.subscribe(filter).await;
-            match timeout(Duration::from_secs(ttd), receiver.recv()).await {
+            let result = timeout(Duration::from_secs(ttd), receiver.recv()).await;
+            commands.unsubscribe(handle).await;
+            // return commands we received, but cannot deliver anymore
+            commands.requeue(receiver).await;
+
+            match result {
                 Ok(Some(cmd)) => {
-                    commands.unsubscribe(handle).await;
-                    Ok(HttpResponse::Ok()
-                        .insert_header((HEADER_COMMAND, cmd.command))
-                        .body(cmd.payload.unwrap_or_default()))
-                }
-                _ => {
-                    commands.unsubscribe(handle).await;
-                    Ok(HttpResponse::build(http::StatusCode::ACCEPTED).finish())
+                    let mut response = HttpResponse::Ok();
+                    response.insert_header((HEADER_COMMAND, cmd.command));
+                    if let Some(correlation_id) = cmd.correlation_id {
+                        response.insert_header((HEADER_CORRELATION_ID, correlation_id));
+                    }
+                    Ok(response.body(cmd.payload.unwrap_or_default()))
                 }
+                _ => Ok(HttpResponse::build(http::StatusCode::ACCEPTED).finish()),
             }
         }
         _ => Ok(HttpResponse::build(http::StatusCode::ACCEPTED).finish()),
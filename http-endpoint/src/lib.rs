@@ -1,16 +1,21 @@
 mod command;
 mod downstream;
+mod source;
 mod telemetry;
 mod ttn;
 mod x509;
 
 use actix_web::{web, HttpResponse, Responder};
+use drogue_bazaar::core::config::CommaSeparatedVec;
 use drogue_cloud_endpoint_common::{
     auth::{AuthConfig, DeviceAuthenticator},
-    command::{Commands, KafkaCommandSource, KafkaCommandSourceConfig},
+    command::{
+        CommandQueue, CommandQueueConfig, CommandSource, CommandStatusConfig,
+        CommandStatusEventSender, Commands, KafkaCommandSourceConfig,
+    },
     psk::{set_ssl_identity, Identity, VerifiedIdentity},
     sender::{DownstreamSender, ExternalClientPoolConfig},
-    sink::KafkaSink,
+    sink::{self, KafkaSinkOptions, MqttClientConfig, SpoolConfig},
 };
 use drogue_cloud_service_api::auth::device::authn::PreSharedKeyOutcome;
 use drogue_cloud_service_api::{
@@ -20,10 +25,12 @@ use drogue_cloud_service_api::{
 use drogue_cloud_service_common::{
     actix::http::{HttpBuilder, HttpConfig},
     app::{Startup, StartupExt},
+    client::{DeviceStateClient, DeviceStateClientConfig},
     defaults,
     tls::TlsAuthConfig,
 };
 use serde::Deserialize;
+use source::TrustedProxies;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
@@ -33,6 +40,16 @@ pub struct Config {
 
     pub kafka_downstream_config: KafkaClientConfig,
     pub kafka_command_config: KafkaClientConfig,
+    #[serde(default)]
+    pub kafka_downstream_options: KafkaSinkOptions,
+
+    /// Use an MQTT broker for events and commands, instead of Kafka.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
+
+    /// Spool events on disk, while the sink is unavailable.
+    #[serde(default)]
+    pub spool: Option<SpoolConfig>,
 
     pub instance: String,
 
@@ -42,6 +59,25 @@ pub struct Config {
     #[serde(default)]
     pub endpoint_pool: ExternalClientPoolConfig,
 
+    #[serde(default)]
+    pub command_queue: CommandQueueConfig,
+
+    #[serde(default)]
+    pub command_status: CommandStatusConfig,
+
+    /// Report events on the twin channel of an application to the device state service.
+    #[serde(default)]
+    pub twin: Option<DeviceStateClientConfig>,
+
+    /// Record the last event of each device and channel in the device state service.
+    #[serde(default)]
+    pub last_values: Option<DeviceStateClientConfig>,
+
+    /// Addresses of proxies, which are trusted to report the address of the client in the
+    /// `X-Forwarded-For` header.
+    #[serde(default)]
+    pub trusted_proxies: CommaSeparatedVec,
+
     #[serde(default)]
     pub http: HttpConfig,
 }
@@ -53,19 +89,39 @@ async fn index() -> impl Responder {
 pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()> {
     log::info!("Starting HTTP service endpoint");
 
-    let sender = DownstreamSender::new(
-        KafkaSink::from_config(
-            config.kafka_downstream_config,
-            config.check_kafka_topic_ready,
-        )?,
+    let mut sender = DownstreamSender::new(
+        sink::with_spool(
+            sink::from_config(
+                config.kafka_downstream_config,
+                config.kafka_downstream_options,
+                config.mqtt_backbone.clone(),
+                config.check_kafka_topic_ready,
+            )?,
+            config.spool,
+        )
+        .await?,
         config.instance,
         config.endpoint_pool,
     )?;
-    let commands = Commands::new();
+    if let Some(twin) = config.twin {
+        sender = sender.with_twin(DeviceStateClient::from_config(twin).await?);
+    }
+    if let Some(last_values) = config.last_values {
+        sender = sender.with_last_values(DeviceStateClient::from_config(last_values).await?);
+    }
+    let status =
+        CommandStatusEventSender::from_config(config.command_status, sender.clone()).await?;
+    let queue = CommandQueue::from_config(config.command_queue, sender.clone())
+        .await?
+        .map(|queue| queue.with_status_handler(status.clone()));
+    let commands = Commands::new()
+        .with_queue(queue.clone())
+        .with_status_handler(status);
 
     let http_server_commands = commands.clone();
 
     let device_authenticator = DeviceAuthenticator::new(config.auth).await?;
+    let trusted_proxies = TrustedProxies::parse(&config.trusted_proxies.0)?;
 
     let disable_tls_psk: bool = config.http.disable_tls_psk;
     let mut tls_auth_config = TlsAuthConfig::default();
@@ -110,6 +166,7 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
         cfg.app_data(web::Data::new(sender.clone()))
             .app_data(web::Data::new(http_server_commands.clone()))
             .app_data(web::Data::new(device_authenticator.clone()))
+            .app_data(web::Data::new(trusted_proxies.clone()))
             .service(web::resource("/").route(web::get().to(index)))
             // the standard endpoint
             .service(
@@ -151,16 +208,20 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     // command source
 
-    let command_source = KafkaCommandSource::new(
+    let command_source = CommandSource::new(
         commands,
         config.kafka_command_config,
         config.command_source_kafka,
+        config.mqtt_backbone,
     )?;
 
     // spawn
 
     startup.spawn(main);
     startup.check(command_source);
+    if let Some(queue) = queue {
+        startup.spawn(queue.run_pruner());
+    }
 
     // done
 
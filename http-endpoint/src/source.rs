@@ -0,0 +1,120 @@
+use anyhow::Context;
+use drogue_cloud_service_api::webapp::{web, HttpRequest};
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+/// Proxies in front of the endpoint, which are trusted to report the address of the client.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn parse(proxies: &[String]) -> anyhow::Result<Self> {
+        Ok(Self(
+            proxies
+                .iter()
+                .map(|proxy| {
+                    proxy
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid trusted proxy address: {proxy}"))
+                })
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+/// Get the address the request originates from.
+///
+/// If the request was received from a trusted proxy, this is the last address of the
+/// `X-Forwarded-For` header which isn't a trusted proxy itself. Addresses in front of that could
+/// have been provided by the client, and are ignored.
+pub fn source(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+
+    let proxies = match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) if !proxies.0.is_empty() => proxies,
+        _ => return Some(peer),
+    };
+
+    forwarded_source(
+        peer,
+        req.headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok()),
+        &proxies.0,
+    )
+}
+
+fn forwarded_source<'a>(
+    peer: IpAddr,
+    headers: impl Iterator<Item = &'a str>,
+    proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    if !proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded = headers
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    for address in forwarded.into_iter().rev() {
+        match address.parse::<IpAddr>() {
+            Ok(address) if proxies.contains(&address) => continue,
+            Ok(address) => return Some(address),
+            // we can't tell where the request came from
+            Err(_) => return None,
+        }
+    }
+
+    // all addresses are trusted proxies
+    Some(peer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_forwarded_source() {
+        let proxies = vec![addr("10.0.0.1"), addr("10.0.0.2")];
+
+        // not a proxy, the header is ignored
+        assert_eq!(
+            forwarded_source(addr("192.0.2.1"), ["192.0.2.2"].into_iter(), &proxies),
+            Some(addr("192.0.2.1"))
+        );
+
+        // the last address which isn't a proxy
+        assert_eq!(
+            forwarded_source(
+                addr("10.0.0.1"),
+                ["192.0.2.2, 192.0.2.3", "10.0.0.2"].into_iter(),
+                &proxies
+            ),
+            Some(addr("192.0.2.3"))
+        );
+
+        // no header
+        assert_eq!(
+            forwarded_source(addr("10.0.0.1"), std::iter::empty(), &proxies),
+            Some(addr("10.0.0.1"))
+        );
+
+        // invalid address
+        assert_eq!(
+            forwarded_source(
+                addr("10.0.0.1"),
+                ["192.0.2.2, unknown"].into_iter(),
+                &proxies
+            ),
+            None
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::downstream::HttpCommandSender;
+use crate::{downstream::HttpCommandSender, source};
 use drogue_cloud_endpoint_common::{
     auth::DeviceAuthenticator,
     command::Commands,
@@ -112,12 +112,18 @@ pub async fn publish(
             certs.map(|c| c.0),
             verified_identity,
             opts.r#as.clone(),
+            source::source(&req),
         )
         .await
         .map_err(|err| HttpEndpointError(err.into()))?
         .outcome
     {
         authn::Outcome::Fail => return Err(HttpEndpointError(EndpointError::AuthenticationError)),
+        authn::Outcome::Locked { until } => {
+            return Err(HttpEndpointError(EndpointError::AuthenticationLocked {
+                until,
+            }))
+        }
         authn::Outcome::Pass {
             application,
             device,
@@ -125,6 +131,9 @@ pub async fn publish(
         } => (application, device, r#as),
     };
 
+    // labels of the device the event originates from
+    let labels = r#as.as_ref().unwrap_or(&device).metadata.labels.clone();
+
     let PublishIdPair { device, sender } = PublishIdPair::with_devices(device, r#as);
 
     // publish
@@ -142,6 +151,7 @@ pub async fn publish(
                 .get(header::CONTENT_TYPE)
                 .and_then(|v| v.to_str().ok())
                 .map(|s| s.to_string()),
+            labels,
             ..Default::default()
         },
     };
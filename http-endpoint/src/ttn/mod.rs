@@ -72,12 +72,19 @@ async fn publish_uplink(
             cert.map(|c| c.0),
             None,
             Some(device_id.clone()),
+            // requests come from the addresses of TTN, shared by all devices
+            None,
         )
         .await
         .map_err(|err| HttpEndpointError(err.into()))?
         .outcome
     {
         authn::Outcome::Fail => return Err(HttpEndpointError(EndpointError::AuthenticationError)),
+        authn::Outcome::Locked { until } => {
+            return Err(HttpEndpointError(EndpointError::AuthenticationLocked {
+                until,
+            }))
+        }
         authn::Outcome::Pass {
             application,
             device,
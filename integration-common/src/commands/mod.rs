@@ -1,5 +1,11 @@
+mod reply;
 mod sender;
+mod spec;
 
+pub use reply::*;
+pub use spec::*;
+
+use chrono::{DateTime, Utc};
 use drogue_client::{registry, Translator};
 use drogue_cloud_endpoint_common::{
     error::HttpEndpointError,
@@ -8,8 +14,12 @@ use drogue_cloud_endpoint_common::{
         UpstreamSender,
     },
 };
-use drogue_cloud_service_api::webapp::HttpResponse;
+use drogue_cloud_service_api::{
+    webapp::{http::StatusCode, HttpResponse},
+    EXT_CORRELATION_ID, EXT_EXPIRES,
+};
 use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
 
 #[derive(Deserialize)]
 pub struct CommandOptions {
@@ -18,6 +28,117 @@ pub struct CommandOptions {
 
     pub command: String,
     pub content_type: Option<String>,
+    /// The correlation ID, which the device must use for replying.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Hold back the command until this point in time.
+    ///
+    /// This is only honored when the command is handed to a scheduler. Processing a command
+    /// directly will deliver it immediately.
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    /// Discard the command, when it could not be delivered until this point in time.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CommandOptions {
+    /// Check if the command is expired at the provided point in time.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+/// Options for waiting on the reply of a device.
+#[derive(Clone, Debug)]
+pub struct ReplyOptions {
+    /// The router, consuming the replies.
+    pub router: ReplyRouter,
+    /// The time to wait for the reply.
+    pub timeout: Duration,
+}
+
+/// The outcome of sending a command and waiting for the reply.
+#[derive(Debug)]
+pub enum RequestOutcome {
+    /// The device replied.
+    Reply(Reply),
+    /// The device did not reply in time.
+    Timeout,
+    /// The command was not sent to the device in a way that allows waiting for a reply.
+    Response(HttpResponse),
+}
+
+impl From<RequestOutcome> for HttpResponse {
+    fn from(outcome: RequestOutcome) -> Self {
+        match outcome {
+            RequestOutcome::Reply(reply) => {
+                let mut response = HttpResponse::Ok();
+                if let Some(content_type) = reply.content_type {
+                    response.content_type(content_type);
+                }
+                response.body(reply.payload)
+            }
+            RequestOutcome::Timeout => HttpResponse::GatewayTimeout().finish(),
+            RequestOutcome::Response(response) => response,
+        }
+    }
+}
+
+/// Send a command and wait for the reply of the device.
+///
+/// Replies can only be awaited for commands processed internally. For commands sent to an
+/// external endpoint, the response of that endpoint is returned.
+#[allow(clippy::too_many_arguments)]
+pub async fn request_command(
+    application: registry::v1::Application,
+    device: registry::v1::Device,
+    gateways: Vec<registry::v1::Device>,
+    sender: &UpstreamSender,
+    client: reqwest::Client,
+    mut opts: CommandOptions,
+    body: bytes::Bytes,
+    reply: &ReplyOptions,
+) -> Result<RequestOutcome, HttpEndpointError> {
+    let correlation_id = opts
+        .correlation_id
+        .get_or_insert_with(|| uuid::Uuid::new_v4().to_string())
+        .clone();
+
+    // start listening before sending the command, so that we don't miss the reply
+
+    let waiter = match reply
+        .router
+        .waiter(&application, &opts.device, correlation_id)
+        .await
+    {
+        Ok(waiter) => waiter,
+        Err(err) => {
+            log::info!("Failed to wait for reply: {}", err);
+            return Ok(RequestOutcome::Response(
+                HttpResponse::ServiceUnavailable().finish(),
+            ));
+        }
+    };
+
+    let response =
+        process_command(application, device, gateways, sender, client, opts, body).await?;
+
+    if response.status() != StatusCode::ACCEPTED {
+        return Ok(RequestOutcome::Response(response));
+    }
+
+    match waiter.wait(reply.timeout).await {
+        Ok(Some(reply)) => Ok(RequestOutcome::Reply(reply)),
+        Ok(None) => Ok(RequestOutcome::Timeout),
+        Err(err) => {
+            log::info!("Failed to wait for reply: {}", err);
+            Ok(RequestOutcome::Response(
+                HttpResponse::ServiceUnavailable().finish(),
+            ))
+        }
+    }
 }
 
 /// Main entrypoint for processing commands
@@ -41,14 +162,20 @@ pub async fn process_command(
             continue;
         }
 
-        if let Some(command) = gateway.attribute::<registry::v1::Commands>().pop() {
+        let command = gateway
+            .section::<DeviceSpecCommands>()
+            .and_then(|spec| spec.ok())
+            .and_then(|mut spec| spec.commands.pop());
+
+        if let Some(command) = command {
             return match command {
-                registry::v1::Command::External(endpoint) => {
+                Command::External(endpoint) => {
                     log::debug!("Sending to external command endpoint {:?}", endpoint);
 
                     let ctx = sender::Context {
                         device_id: device.metadata.name,
                         client,
+                        pool: sender.pool(),
                     };
 
                     match sender::send_to_external(ctx, endpoint, opts, body).await {
@@ -69,6 +196,14 @@ pub async fn process_command(
 
     log::debug!("Processing command internally");
 
+    let mut extensions = HashMap::new();
+    if let Some(correlation_id) = &opts.correlation_id {
+        extensions.insert(EXT_CORRELATION_ID.to_string(), correlation_id.clone());
+    }
+    if let Some(expires_at) = &opts.expires_at {
+        extensions.insert(EXT_EXPIRES.to_string(), expires_at.to_rfc3339());
+    }
+
     for target in targets {
         log::debug!("Delivering to: {}", target);
         match sender
@@ -80,6 +215,7 @@ pub async fn process_command(
                     sender: target.into_id(),
                     options: PublishOptions {
                         content_type: opts.content_type.clone(),
+                        extensions: extensions.clone(),
                         ..Default::default()
                     },
                 },
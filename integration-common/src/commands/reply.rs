@@ -0,0 +1,478 @@
+use crate::stream::{EventStream, EventStreamConfig};
+use cloudevents::{event::ExtensionValue, AttributesReader, Data, Event};
+use drogue_client::registry;
+use drogue_cloud_endpoint_common::command::REPLY_CHANNEL;
+use drogue_cloud_event_common::{
+    mqtt::{MqttClientConfig, MqttEventType},
+    stream::EventStreamError,
+};
+use drogue_cloud_service_api::{
+    kafka::{KafkaClientConfig, KafkaConfig, KafkaConfigExt, KafkaEventType},
+    EXT_APPLICATION, EXT_CORRELATION_ID, EXT_DEVICE,
+};
+use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::sync::{oneshot, watch};
+
+#[derive(Debug, Error)]
+pub enum ReplyError {
+    #[error("Invalid configuration: {0}")]
+    Configuration(String),
+    #[error("Event stream error: {0}")]
+    Stream(#[from] EventStreamError),
+    #[error("Consumer of replies is not ready")]
+    NotReady,
+    #[error("Consumer of replies stopped")]
+    Stopped,
+}
+
+/// The reply of a device to a command.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub content_type: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+impl From<Event> for Reply {
+    fn from(mut event: Event) -> Self {
+        let (content_type, _, data) = event.take_data();
+        let payload = match data {
+            Some(Data::Binary(data)) => data,
+            Some(Data::String(data)) => data.into_bytes(),
+            Some(Data::Json(data)) => serde_json::to_vec(&data).unwrap_or_default(),
+            None => vec![],
+        };
+
+        Self {
+            content_type,
+            payload,
+        }
+    }
+}
+
+/// Identifies the reply to a command.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ReplyKey {
+    application: String,
+    device: String,
+    correlation_id: String,
+}
+
+impl ReplyKey {
+    /// Get the key of an event, in case it is a reply.
+    fn from_event(event: &Event) -> Option<Self> {
+        if event.subject() != Some(REPLY_CHANNEL) {
+            return None;
+        }
+
+        match (
+            event.extension(EXT_APPLICATION),
+            event.extension(EXT_DEVICE),
+            event.extension(EXT_CORRELATION_ID),
+        ) {
+            (
+                Some(ExtensionValue::String(application)),
+                Some(ExtensionValue::String(device)),
+                Some(ExtensionValue::String(correlation_id)),
+            ) => Some(Self {
+                application: application.clone(),
+                device: device.clone(),
+                correlation_id: correlation_id.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Requests waiting for replies, of a single topic.
+#[derive(Debug)]
+struct ReplyConsumer {
+    waiters: Mutex<HashMap<ReplyKey, oneshot::Sender<Reply>>>,
+    assigned: watch::Receiver<bool>,
+}
+
+impl ReplyConsumer {
+    /// Hand over the event to the waiting request, in case it is a reply.
+    fn dispatch(&self, event: Event) {
+        let key = match ReplyKey::from_event(&event) {
+            Some(key) => key,
+            None => return,
+        };
+
+        let waiter = self.waiters.lock().unwrap().remove(&key);
+        if let Some(waiter) = waiter {
+            // the request might have timed out in the meantime, which is ok
+            let _ = waiter.send(Reply::from(event));
+        }
+    }
+
+    /// Wait until the consumer receives events.
+    async fn wait_assigned(&self) -> Result<(), ReplyError> {
+        let mut assigned = self.assigned.clone();
+        let wait = async {
+            while !*assigned.borrow() {
+                if assigned.changed().await.is_err() {
+                    return Err(ReplyError::Stopped);
+                }
+            }
+            Ok(())
+        };
+
+        tokio::time::timeout(ASSIGNMENT_TIMEOUT, wait)
+            .await
+            .map_err(|_| ReplyError::NotReady)?
+    }
+}
+
+type Consumers = Arc<Mutex<HashMap<String, Arc<ReplyConsumer>>>>;
+
+/// The time to wait for the consumer of replies to get partitions assigned.
+const ASSIGNMENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The period of checking for the assignment, and for being idle.
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+/// The time after which a consumer without waiting requests is stopped.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Routes the replies of devices to the requests waiting for them.
+///
+/// There is a single consumer per event topic, shared by all requests of the process. Consumers
+/// are created on demand, and stopped when no request was waiting on them for a while.
+#[derive(Clone, Debug)]
+pub struct ReplyRouter {
+    kafka: KafkaClientConfig,
+    mqtt: Option<MqttClientConfig>,
+    consumers: Consumers,
+}
+
+/// The source of the replies of an application.
+#[derive(Clone, Debug)]
+enum ReplySource {
+    Kafka(KafkaConfig),
+    Mqtt {
+        mqtt: MqttClientConfig,
+        application: String,
+    },
+}
+
+impl ReplySource {
+    fn topic(&self) -> String {
+        match self {
+            Self::Kafka(kafka) => kafka.topic.clone(),
+            Self::Mqtt { mqtt, application } => mqtt.topic(application, MqttEventType::Events),
+        }
+    }
+
+    fn into_stream(self) -> Result<EventStream<'static>, EventStreamError> {
+        // no consumer group, we are only interested in what is coming next
+        match self {
+            Self::Kafka(kafka) => EventStream::new(EventStreamConfig {
+                kafka,
+                consumer_group: None,
+            }),
+            Self::Mqtt { mqtt, application } => Ok(EventStream::new_mqtt(mqtt, &application, None)),
+        }
+    }
+}
+
+impl ReplyRouter {
+    /// Create a new router, consuming replies from the provided Kafka cluster.
+    pub fn new(kafka: KafkaClientConfig) -> Self {
+        Self {
+            kafka,
+            mqtt: None,
+            consumers: Default::default(),
+        }
+    }
+
+    /// Consume replies from an MQTT broker, instead of Kafka.
+    pub fn with_mqtt(mut self, mqtt: Option<MqttClientConfig>) -> Self {
+        self.mqtt = mqtt;
+        self
+    }
+
+    /// Start waiting for the reply to a command.
+    ///
+    /// This must be called before the command gets sent, otherwise the reply might be missed. It
+    /// returns once the consumer of the replies is able to receive them.
+    pub async fn waiter<D, C>(
+        &self,
+        application: &registry::v1::Application,
+        device: D,
+        correlation_id: C,
+    ) -> Result<ReplyWaiter, ReplyError>
+    where
+        D: Into<String>,
+        C: Into<String>,
+    {
+        let source = match &self.mqtt {
+            Some(mqtt) => ReplySource::Mqtt {
+                mqtt: mqtt.clone(),
+                application: application.metadata.name.clone(),
+            },
+            None => ReplySource::Kafka(
+                application
+                    .kafka_target(KafkaEventType::Events, &self.kafka)
+                    .map_err(|err| ReplyError::Configuration(err.to_string()))?
+                    .into(),
+            ),
+        };
+        let topic = source.topic();
+
+        let key = ReplyKey {
+            application: application.metadata.name.clone(),
+            device: device.into(),
+            correlation_id: correlation_id.into(),
+        };
+        let (tx, rx) = oneshot::channel();
+
+        let consumer = {
+            let mut consumers = self.consumers.lock().unwrap();
+            let consumer = match consumers.get(&topic) {
+                Some(consumer) => consumer.clone(),
+                None => {
+                    let consumer = Self::start(self.consumers.clone(), topic.clone(), source)?;
+                    consumers.insert(topic, consumer.clone());
+                    consumer
+                }
+            };
+            // register while holding the lock, so that the consumer isn't considered idle
+            consumer.waiters.lock().unwrap().insert(key.clone(), tx);
+            consumer
+        };
+
+        let waiter = ReplyWaiter { rx, key, consumer };
+        waiter.consumer.wait_assigned().await?;
+
+        Ok(waiter)
+    }
+
+    fn start(
+        consumers: Consumers,
+        topic: String,
+        source: ReplySource,
+    ) -> Result<Arc<ReplyConsumer>, ReplyError> {
+        let mut stream = source.into_stream()?;
+
+        let (assigned_tx, assigned) = watch::channel(false);
+        let consumer = Arc::new(ReplyConsumer {
+            waiters: Default::default(),
+            assigned,
+        });
+
+        log::debug!("Starting reply consumer for: {topic}");
+
+        let task_consumer = consumer.clone();
+        tokio::spawn(async move {
+            let consumer = task_consumer;
+            let mut check = tokio::time::interval(CHECK_PERIOD);
+            let mut idle_since: Option<Instant> = None;
+
+            loop {
+                tokio::select! {
+                    event = stream.next() => match event {
+                        Some(Ok(event)) => consumer.dispatch(event),
+                        Some(Err(err)) => log::info!("Failed to receive reply: {err}"),
+                        None => break,
+                    },
+                    _ = check.tick() => {
+                        if !*assigned_tx.borrow() && stream.is_assigned() {
+                            log::debug!("Reply consumer for {topic} is assigned");
+                            let _ = assigned_tx.send(true);
+                        }
+
+                        // only the list of consumers, and this task, hold a reference
+                        if Arc::strong_count(&consumer) > 2 {
+                            idle_since = None;
+                            continue;
+                        }
+                        let idle = idle_since.get_or_insert_with(Instant::now);
+                        if idle.elapsed() < IDLE_TIMEOUT {
+                            continue;
+                        }
+
+                        // re-check while holding the lock, as no new waiter can be added then
+                        let stop = Self::remove(&consumers, &topic, &consumer, |consumer| {
+                            Arc::strong_count(consumer) <= 2
+                        });
+                        if stop {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            Self::remove(&consumers, &topic, &consumer, |_| true);
+            // fail all requests still waiting
+            consumer.waiters.lock().unwrap().clear();
+            log::debug!("Stopped reply consumer for: {topic}");
+        });
+
+        Ok(consumer)
+    }
+
+    /// Remove the consumer from the list, if it is still registered and the condition holds.
+    ///
+    /// Returns `false` if the consumer is still registered, as the condition didn't hold.
+    fn remove<F>(consumers: &Consumers, topic: &str, consumer: &Arc<ReplyConsumer>, f: F) -> bool
+    where
+        F: FnOnce(&Arc<ReplyConsumer>) -> bool,
+    {
+        let mut consumers = consumers.lock().unwrap();
+        match consumers.get(topic) {
+            Some(current) if Arc::ptr_eq(current, consumer) => {
+                if !f(consumer) {
+                    return false;
+                }
+                consumers.remove(topic);
+                true
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Wait for the reply of a device to a command.
+///
+/// Dropping the waiter stops waiting for the reply.
+#[derive(Debug)]
+pub struct ReplyWaiter {
+    rx: oneshot::Receiver<Reply>,
+    key: ReplyKey,
+    consumer: Arc<ReplyConsumer>,
+}
+
+impl ReplyWaiter {
+    /// Wait for the reply, returns `None` if no reply was received before the timeout.
+    pub async fn wait(mut self, timeout: Duration) -> Result<Option<Reply>, ReplyError> {
+        match tokio::time::timeout(timeout, &mut self.rx).await {
+            Ok(Ok(reply)) => Ok(Some(reply)),
+            Ok(Err(_)) => Err(ReplyError::Stopped),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl Drop for ReplyWaiter {
+    fn drop(&mut self) {
+        self.consumer.waiters.lock().unwrap().remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+
+    fn event(subject: &str, device: &str, correlation_id: &str) -> Event {
+        EventBuilderV10::new()
+            .id("1")
+            .ty("io.drogue.command.reply.v1")
+            .source("drogue://app/device")
+            .subject(subject)
+            .extension(EXT_APPLICATION, "app1")
+            .extension(EXT_DEVICE, device)
+            .extension(EXT_CORRELATION_ID, correlation_id)
+            .data("text/plain", "reply")
+            .build()
+            .unwrap()
+    }
+
+    fn key(device: &str, correlation_id: &str) -> ReplyKey {
+        ReplyKey {
+            application: "app1".into(),
+            device: device.into(),
+            correlation_id: correlation_id.into(),
+        }
+    }
+
+    fn consumer(assigned: bool) -> (Arc<ReplyConsumer>, watch::Sender<bool>) {
+        let (tx, assigned) = watch::channel(assigned);
+        (
+            Arc::new(ReplyConsumer {
+                waiters: Default::default(),
+                assigned,
+            }),
+            tx,
+        )
+    }
+
+    fn waiter(consumer: &Arc<ReplyConsumer>, key: ReplyKey) -> ReplyWaiter {
+        let (tx, rx) = oneshot::channel();
+        consumer.waiters.lock().unwrap().insert(key.clone(), tx);
+        ReplyWaiter {
+            rx,
+            key,
+            consumer: consumer.clone(),
+        }
+    }
+
+    #[test]
+    fn test_reply_key() {
+        assert_eq!(
+            ReplyKey::from_event(&event("$reply", "device1", "1234")),
+            Some(key("device1", "1234"))
+        );
+        assert_eq!(ReplyKey::from_event(&event("foo", "device1", "1234")), None);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let (consumer, _) = consumer(true);
+
+        let w1 = waiter(&consumer, key("device1", "1234"));
+        let w2 = waiter(&consumer, key("device1", "4321"));
+
+        // not a reply, or for a different device
+        consumer.dispatch(event("foo", "device1", "1234"));
+        consumer.dispatch(event("$reply", "device2", "1234"));
+        // the reply for the first waiter
+        consumer.dispatch(event("$reply", "device1", "1234"));
+
+        let reply = w1.wait(Duration::from_secs(1)).await.unwrap().unwrap();
+        assert_eq!(reply.payload, b"reply");
+        assert_eq!(reply.content_type.as_deref(), Some("text/plain"));
+
+        assert!(w2.wait(Duration::from_millis(10)).await.unwrap().is_none());
+        // the timed out waiter got removed
+        assert!(consumer.waiters.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drop_waiter() {
+        let (consumer, _) = consumer(true);
+
+        let w1 = waiter(&consumer, key("device1", "1234"));
+        assert_eq!(consumer.waiters.lock().unwrap().len(), 1);
+        drop(w1);
+        assert!(consumer.waiters.lock().unwrap().is_empty());
+
+        // a late reply is ignored
+        consumer.dispatch(event("$reply", "device1", "1234"));
+    }
+
+    #[tokio::test]
+    async fn test_wait_assigned() {
+        let (consumer, tx) = consumer(false);
+
+        let wait = tokio::spawn({
+            let consumer = consumer.clone();
+            async move { consumer.wait_assigned().await }
+        });
+
+        tx.send(true).unwrap();
+        assert!(wait.await.unwrap().is_ok());
+
+        // a stopped consumer
+        let (consumer, tx) = self::consumer(false);
+        drop(tx);
+        assert!(matches!(
+            consumer.wait_assigned().await,
+            Err(ReplyError::Stopped)
+        ));
+    }
+}
@@ -2,9 +2,9 @@ mod default;
 mod ttnv2;
 mod ttnv3;
 
-use crate::commands::CommandOptions;
+use crate::commands::{CommandOptions, ExternalCommandEndpoint};
 use async_trait::async_trait;
-use drogue_client::registry;
+use drogue_cloud_endpoint_common::sender::ExternalClientPool;
 use drogue_cloud_service_api::webapp::web;
 use drogue_cloud_service_common::reqwest::to_method;
 use reqwest::{
@@ -18,6 +18,8 @@ use url::Url;
 pub struct Context {
     pub device_id: String,
     pub client: reqwest::Client,
+    /// Clients for custom TLS options, and OAuth2 tokens.
+    pub pool: ExternalClientPool,
 }
 
 #[async_trait]
@@ -25,7 +27,7 @@ pub trait Sender {
     async fn send(
         &self,
         ctx: Context,
-        endpoint: registry::v1::ExternalCommandEndpoint,
+        endpoint: ExternalCommandEndpoint,
         command: CommandOptions,
         body: web::Bytes,
     ) -> Result<(), Error>;
@@ -56,7 +58,7 @@ impl std::error::Error for HttpError {}
 
 pub async fn send_to_external(
     ctx: Context,
-    endpoint: registry::v1::ExternalCommandEndpoint,
+    endpoint: ExternalCommandEndpoint,
     command: CommandOptions,
     payload: web::Bytes,
 ) -> Result<(), Error> {
@@ -81,10 +83,10 @@ pub async fn send_to_external(
 }
 
 /// Takes an external endpoint and creates an HTTP request builder from it.
-pub(crate) fn to_builder<F>(
-    client: reqwest::Client,
+pub(crate) async fn to_builder<F>(
+    ctx: Context,
     default_method: reqwest::Method,
-    endpoint: &registry::v1::ExternalCommandEndpoint,
+    endpoint: &ExternalCommandEndpoint,
     f: F,
 ) -> Result<reqwest::RequestBuilder, Error>
 where
@@ -98,6 +100,15 @@ where
 
     let url = f(url)?;
 
+    // a custom TLS configuration requires a dedicated client
+    let client =
+        match &endpoint.tls {
+            Some(tls) => ctx.pool.http_client(tls).await.map_err(|err| {
+                Error::InvalidConfiguration(format!("Invalid TLS options: {}", err))
+            })?,
+            None => ctx.client,
+        };
+
     let mut builder = client.request(method, url);
 
     for (k, v) in &endpoint.headers {
@@ -110,7 +121,11 @@ where
         builder = builder.header(key, value);
     }
 
-    Ok(builder)
+    endpoint
+        .auth
+        .apply(builder, &ctx.pool.tokens())
+        .await
+        .map_err(|err| Error::Transport(Box::new(err)))
 }
 
 pub(crate) async fn default_error(resp: Response) -> Error {
@@ -10,11 +10,11 @@ impl Sender for TtnV2Sender {
     async fn send(
         &self,
         ctx: Context,
-        endpoint: registry::v1::ExternalCommandEndpoint,
+        endpoint: ExternalCommandEndpoint,
         command: CommandOptions,
         payload: web::Bytes,
     ) -> Result<(), Error> {
-        let builder = super::to_builder(ctx.client, Method::POST, &endpoint, Ok)?;
+        let builder = super::to_builder(ctx, Method::POST, &endpoint, Ok).await?;
 
         let payload = base64::encode(payload);
 
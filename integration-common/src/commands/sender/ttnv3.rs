@@ -10,18 +10,19 @@ impl Sender for TtnV3Sender {
     async fn send(
         &self,
         ctx: Context,
-        endpoint: registry::v1::ExternalCommandEndpoint,
+        endpoint: ExternalCommandEndpoint,
         command: CommandOptions,
         payload: web::Bytes,
     ) -> Result<(), Error> {
-        let device_id = ctx.device_id;
-        let builder = super::to_builder(ctx.client, Method::POST, &endpoint, |mut url| {
+        let device_id = ctx.device_id.clone();
+        let builder = super::to_builder(ctx, Method::POST, &endpoint, |mut url| {
             url.path_segments_mut()
                 .map_err(|_| Error::Payload("Failed to extend path".into()))?
                 // TODO: we should have a way to influence "push" vs "replace"
                 .extend(&[&device_id, "down", "replace"]);
             Ok(url)
-        })?;
+        })
+        .await?;
 
         let payload = base64::encode(payload);
 
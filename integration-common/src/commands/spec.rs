@@ -0,0 +1,90 @@
+//! Command configuration of a device.
+//!
+//! This mirrors the command section of the registry, but supports additional options for
+//! authenticating with external endpoints.
+
+use drogue_client::{dialect, Section};
+use drogue_cloud_endpoint_common::external::{Authentication, TlsOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeviceSpecCommands {
+    #[serde(default)]
+    pub commands: Vec<Command>,
+}
+
+dialect!(DeviceSpecCommands[Section::Spec => "commands"]);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Command {
+    External(ExternalCommandEndpoint),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExternalCommandEndpoint {
+    #[serde(default)]
+    pub r#type: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub method: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub auth: Authentication,
+    #[serde(default)]
+    pub tls: Option<TlsOptions>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use drogue_cloud_endpoint_common::external::ClientCredentials;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let spec: DeviceSpecCommands = serde_json::from_value(json!({
+            "commands": [
+                {
+                    "external": {
+                        "type": "ttnv3",
+                        "url": "https://gateway/api",
+                        "auth": {
+                            "oauth2": {
+                                "tokenUrl": "https://sso/token",
+                                "clientId": "id",
+                                "clientSecret": "secret",
+                            }
+                        },
+                        "tls": {
+                            "clientCertificate": {
+                                "certificate": "cert",
+                                "key": "key",
+                            }
+                        }
+                    }
+                }
+            ]
+        }))
+        .unwrap();
+
+        let Command::External(endpoint) = &spec.commands[0];
+        assert_eq!(endpoint.r#type.as_deref(), Some("ttnv3"));
+        assert_eq!(
+            endpoint.auth,
+            Authentication::OAuth2(ClientCredentials {
+                token_url: "https://sso/token".into(),
+                client_id: "id".into(),
+                client_secret: "secret".into(),
+                scopes: vec![],
+            })
+        );
+        assert!(endpoint
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.client_certificate.as_ref())
+            .is_some());
+    }
+}
@@ -0,0 +1,70 @@
+use cloudevents::Event;
+use drogue_cloud_service_api::services::device_state::LastValuesRequest;
+use drogue_cloud_service_common::client::DeviceStateClient;
+
+/// Number of last values retrieved at once.
+const PAGE_SIZE: u32 = 100;
+
+/// The last event of each device and channel of an application, sent as retained events.
+///
+/// The events are retrieved page by page, while they are being sent. Events recorded in the
+/// meantime might be missed, or be sent twice.
+pub struct Retained {
+    client: DeviceStateClient,
+    application: String,
+    offset: u32,
+    done: bool,
+}
+
+impl Retained {
+    pub fn new(client: DeviceStateClient, application: impl Into<String>) -> Self {
+        Self {
+            client,
+            application: application.into(),
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Retrieve the next page of events, or `None` if all events were retrieved.
+    ///
+    /// Failing to retrieve a page ends the retained events, rather than failing the stream.
+    pub async fn next_page(&mut self) -> Option<Vec<Event>> {
+        if self.done {
+            return None;
+        }
+
+        let request = LastValuesRequest {
+            limit: Some(PAGE_SIZE),
+            offset: Some(self.offset),
+            ..Default::default()
+        };
+
+        let response = match self.client.last_values(&self.application, &request).await {
+            Ok(response) => response,
+            Err(err) => {
+                log::warn!("Failed to retrieve last values: {err}");
+                self.done = true;
+                return None;
+            }
+        };
+
+        let count = response.items.len() as u32;
+        self.offset += count;
+        self.done = count < PAGE_SIZE || self.offset as u64 >= response.total;
+
+        Some(
+            response
+                .items
+                .into_iter()
+                .filter_map(|value| match serde_json::from_value(value.event) {
+                    Ok(event) => Some(event),
+                    Err(err) => {
+                        log::info!("Ignoring invalid last value: {err}");
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+}
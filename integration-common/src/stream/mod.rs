@@ -4,7 +4,10 @@ mod actix;
 #[cfg(feature = "with_actix")]
 pub use self::actix::*;
 
-use drogue_cloud_event_common::stream::{self, AckMode, AutoAck, EventStreamError};
+use drogue_cloud_event_common::{
+    mqtt::{MqttClientConfig, MqttEventType},
+    stream::{self, AckMode, AutoAck, EventStreamError, MqttEventStreamConfig},
+};
 use drogue_cloud_service_api::kafka::KafkaConfig;
 use std::ops::{Deref, DerefMut};
 
@@ -34,6 +37,22 @@ where
 
         Ok(Self { stream })
     }
+
+    /// Create a new stream, consuming the events of an application from an MQTT broker.
+    pub fn new_mqtt(
+        mqtt: MqttClientConfig,
+        application: &str,
+        consumer_group: Option<String>,
+    ) -> Self {
+        let topic = mqtt.topic(application, MqttEventType::Events);
+        let stream = stream::EventStream::new_mqtt(MqttEventStreamConfig {
+            mqtt,
+            topic,
+            group: consumer_group,
+        });
+
+        Self { stream }
+    }
 }
 
 impl<'s, Ack> From<EventStream<'s, Ack>> for stream::EventStream<'s, Ack>
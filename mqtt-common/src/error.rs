@@ -32,6 +32,9 @@ pub enum ServerError {
     UnsupportedOperation,
     #[error("authentication failed")]
     AuthenticationFailed,
+    /// Temporarily locked out, due to too many failed authentication attempts
+    #[error("authentication locked")]
+    AuthenticationLocked,
     #[error("not authorized")]
     NotAuthorized,
     #[error("publish error {0}")]
@@ -98,6 +101,8 @@ impl<St> MqttResponse<v3::Handshake, v3::HandshakeAck<St>> for ServerError {
 
         match self {
             Self::AuthenticationFailed => ack.bad_username_or_pwd(),
+            // there is no better code in v3, telling the client to back off
+            Self::AuthenticationLocked => ack.service_unavailable(),
             Self::NotAuthorized => ack.not_authorized(),
             _ => ack.service_unavailable(),
         }
@@ -112,6 +117,7 @@ impl<St> MqttResponse<v5::Handshake, v5::HandshakeAck<St>> for ServerError {
             Self::AuthenticationFailed => {
                 ack.failed(v5::codec::ConnectAckReason::BadUserNameOrPassword)
             }
+            Self::AuthenticationLocked => ack.failed(v5::codec::ConnectAckReason::QuotaExceeded),
             Self::NotAuthorized => ack.failed(v5::codec::ConnectAckReason::NotAuthorized),
             _ => ack.failed(v5::codec::ConnectAckReason::UnspecifiedError),
         }
@@ -406,6 +406,19 @@ impl<'a> Subscription<'a> {
         }
     }
 
+    /// Whether retained messages should be sent for this subscription.
+    ///
+    /// MQTT v3.1 clients can't opt out of retained messages.
+    pub fn retained(&self) -> bool {
+        match self {
+            Self::V3(_) => true,
+            Self::V5(sub) => !matches!(
+                sub.options().retain_handling,
+                v5::codec::RetainHandling::NoAtSubscribe
+            ),
+        }
+    }
+
     pub fn fail(&mut self, reason: v5::codec::SubscribeAckReason) {
         match self {
             Self::V3(sub) => sub.fail(),
@@ -1,8 +1,12 @@
 use drogue_cloud_endpoint_common::{
-    auth::AuthConfig, command::KafkaCommandSourceConfig, sender::ExternalClientPoolConfig,
+    auth::AuthConfig,
+    command::{CommandQueueConfig, CommandStatusConfig, KafkaCommandSourceConfig},
+    sender::ExternalClientPoolConfig,
+    sink::{KafkaSinkOptions, MqttClientConfig, SpoolConfig},
 };
 use drogue_cloud_mqtt_common::server::{MqttServerOptions, TlsConfig};
 use drogue_cloud_service_api::kafka::KafkaClientConfig;
+use drogue_cloud_service_common::client::DeviceStateClientConfig;
 use drogue_cloud_service_common::defaults;
 use drogue_cloud_service_common::state::StateControllerConfiguration;
 use serde::Deserialize;
@@ -70,6 +74,16 @@ pub struct Config {
 
     pub kafka_downstream_config: KafkaClientConfig,
     pub kafka_command_config: KafkaClientConfig,
+    #[serde(default)]
+    pub kafka_downstream_options: KafkaSinkOptions,
+
+    /// Use an MQTT broker for events and commands, instead of Kafka.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
+
+    /// Spool events on disk, while the sink is unavailable.
+    #[serde(default)]
+    pub spool: Option<SpoolConfig>,
 
     pub instance: String,
 
@@ -79,6 +93,20 @@ pub struct Config {
     #[serde(default)]
     pub endpoint_pool: ExternalClientPoolConfig,
 
+    #[serde(default)]
+    pub command_queue: CommandQueueConfig,
+
+    #[serde(default)]
+    pub command_status: CommandStatusConfig,
+
+    /// Report events on the twin channel of an application to the device state service.
+    #[serde(default)]
+    pub twin: Option<DeviceStateClientConfig>,
+
+    /// Record the last event of each device and channel in the device state service.
+    #[serde(default)]
+    pub last_values: Option<DeviceStateClientConfig>,
+
     pub state: StateControllerConfiguration,
 }
 
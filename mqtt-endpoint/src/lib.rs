@@ -7,14 +7,15 @@ use drogue_cloud_service_api::auth::device::authn::{PreSharedKeyOutcome, PreShar
 
 use crate::{auth::DeviceAuthenticator, service::App};
 use drogue_cloud_endpoint_common::{
-    command::{Commands, KafkaCommandSource},
+    command::{CommandQueue, CommandSource, CommandStatusEventSender, Commands},
     psk::Identity,
     sender::DownstreamSender,
-    sink::KafkaSink,
+    sink,
 };
 use drogue_cloud_mqtt_common::server::build;
 use drogue_cloud_service_common::{
     app::{Startup, StartupExt},
+    client::DeviceStateClient,
     state::StateController,
 };
 use futures_util::TryFutureExt;
@@ -34,22 +35,51 @@ lazy_static! {
 }
 
 pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()> {
-    let commands = Commands::new();
+    let mut downstream = DownstreamSender::new(
+        sink::with_spool(
+            sink::from_config(
+                config.kafka_downstream_config.clone(),
+                config.kafka_downstream_options.clone(),
+                config.mqtt_backbone.clone(),
+                config.check_kafka_topic_ready,
+            )?,
+            config.spool.clone(),
+        )
+        .await?,
+        config.instance.clone(),
+        config.endpoint_pool.clone(),
+    )?;
+    if let Some(twin) = config.twin.clone() {
+        downstream = downstream.with_twin(DeviceStateClient::from_config(twin).await?);
+    }
+    if let Some(last_values) = config.last_values.clone() {
+        downstream =
+            downstream.with_last_values(DeviceStateClient::from_config(last_values).await?);
+    }
+
+    // command queue and status
+
+    let status =
+        CommandStatusEventSender::from_config(config.command_status.clone(), downstream.clone())
+            .await?;
+    let queue = CommandQueue::from_config(config.command_queue.clone(), downstream.clone())
+        .await?
+        .map(|queue| queue.with_status_handler(status.clone()));
+    let commands = Commands::new()
+        .with_queue(queue.clone())
+        .with_status_handler(status);
 
     // state service
 
-    let (states, runner) = StateController::new(config.state.clone()).await?;
+    let mut state = config.state.clone();
+    state
+        .endpoint_type
+        .get_or_insert_with(|| "mqtt".to_string());
+    let (states, runner) = StateController::new(state).await?;
 
     let app = App {
         config: config.endpoint.clone(),
-        downstream: DownstreamSender::new(
-            KafkaSink::from_config(
-                config.kafka_downstream_config.clone(),
-                config.check_kafka_topic_ready,
-            )?,
-            config.instance.clone(),
-            config.endpoint_pool.clone(),
-        )?,
+        downstream,
 
         authenticator: DeviceAuthenticator(
             drogue_cloud_endpoint_common::auth::DeviceAuthenticator::new(config.auth.clone())
@@ -109,10 +139,11 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     // command source
 
-    let command_source = KafkaCommandSource::new(
+    let command_source = CommandSource::new(
         commands,
         config.kafka_command_config,
         config.command_source_kafka,
+        config.mqtt_backbone,
     )?;
 
     // run
@@ -121,6 +152,9 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
     startup.spawn(srv);
     startup.spawn(runner.run());
     startup.check(command_source);
+    if let Some(queue) = queue {
+        startup.spawn(queue.run_pruner());
+    }
 
     // exiting
 
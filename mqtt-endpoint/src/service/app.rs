@@ -21,7 +21,8 @@ use drogue_cloud_service_api::{
     services::device_state::LastWillTestament,
 };
 use drogue_cloud_service_common::state::{CreateOptions, CreationOutcome, StateController};
-use std::fmt::Debug;
+use ntex::io::types::PeerAddr;
+use std::{fmt::Debug, net::IpAddr};
 use tracing::instrument;
 
 #[derive(Clone, Debug)]
@@ -44,6 +45,7 @@ impl App {
         client_id: &str,
         certs: Option<ClientCertificateChain>,
         verified_identity: Option<VerifiedIdentity>,
+        source: Option<IpAddr>,
     ) -> Result<AuthOutcome, EndpointError> {
         let password = password
             .map(|p| String::from_utf8(p.to_vec()))
@@ -55,7 +57,14 @@ impl App {
 
         Ok(self
             .authenticator
-            .authenticate_mqtt(username, password, &client_id, certs, verified_identity)
+            .authenticate_mqtt(
+                username,
+                password,
+                &client_id,
+                certs,
+                verified_identity,
+                source,
+            )
             .await
             .map_err(|err| {
                 log::debug!("Failed to call authentication service: {}", err);
@@ -214,6 +223,11 @@ impl Service<Session> for App {
                 None
             }
         };
+        let source = connect
+            .io()
+            .query::<PeerAddr>()
+            .as_ref()
+            .map(|addr| addr.0.ip());
         let (username, password) = connect.credentials();
 
         match self
@@ -223,6 +237,7 @@ impl Service<Session> for App {
                 connect.client_id().as_ref(),
                 certs,
                 verified_identity,
+                source,
             )
             .await
         {
@@ -251,6 +266,10 @@ impl Service<Session> for App {
                 })
             }
             Ok(AuthOutcome::Fail) => Err(ServerError::AuthenticationFailed),
+            Ok(AuthOutcome::Locked { until }) => {
+                log::debug!("Authentication locked until: {until}");
+                Err(ServerError::AuthenticationLocked)
+            }
             Err(_) => Err(ServerError::AuthenticationFailed),
         }
     }
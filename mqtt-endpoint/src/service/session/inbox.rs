@@ -1,9 +1,16 @@
 use crate::service::session::dialect::SubscriptionTopicEncoder;
 use drogue_cloud_endpoint_common::command::{
-    Command, CommandFilter, Commands, Subscription, SubscriptionHandle,
+    reply_channel, Command, CommandFilter, CommandStatus, Commands, Subscription,
+    SubscriptionHandle,
 };
 use drogue_cloud_mqtt_common::mqtt;
 use ntex::util::{ByteString, Bytes};
+use ntex_mqtt::types::QoS;
+use std::{future::Future, time::Duration};
+use tokio::time::timeout;
+
+/// Time to wait for a device to acknowledge a command, sent with QoS 1.
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct InboxSubscription {
     filter: CommandFilter,
@@ -28,6 +35,7 @@ impl InboxSubscription {
         commands: Commands,
         sink: mqtt::Sink,
         encoder: SubscriptionTopicEncoder,
+        qos: QoS,
     ) -> Self {
         // TODO: try to reduce cloning
 
@@ -37,16 +45,24 @@ impl InboxSubscription {
         } = commands.subscribe(filter.clone()).await;
 
         let sub_filter = filter.clone();
+        let sub_commands = commands.clone();
 
         ntex::rt::spawn(async move {
             log::debug!("Starting inbox command loop: {:?}", sub_filter);
             while let Some(cmd) = receiver.recv().await {
-                match Self::send_command(&sink, cmd, &encoder).await {
-                    Ok(_) => {
+                match Self::send_command(&sink, &cmd, &encoder, qos).await {
+                    Ok(acknowledged) => {
                         log::debug!("Command sent to device subscription {:?}", sub_filter);
+                        if acknowledged {
+                            sub_commands
+                                .report_status(&cmd, CommandStatus::Acknowledged)
+                                .await;
+                        }
                     }
                     Err(e) => {
                         log::error!("Failed to send a command to device subscription {:?}", e);
+                        // the connection is gone, keep the command for the next one
+                        sub_commands.requeue_command(cmd).await;
                     }
                 }
             }
@@ -59,31 +75,74 @@ impl InboxSubscription {
         }
     }
 
+    /// Send a command to the device.
+    ///
+    /// Returns `true` if the device acknowledged the command, which is only the case for QoS 1,
+    /// and only if the device acknowledged it in time.
     async fn send_command(
         sink: &mqtt::Sink,
-        cmd: Command,
+        cmd: &Command,
         encoder: &SubscriptionTopicEncoder,
-    ) -> Result<(), String> {
-        let topic = encoder.encode_command_topic(&cmd);
+        qos: QoS,
+    ) -> Result<bool, String> {
+        let topic = encoder.encode_command_topic(cmd);
 
         log::debug!("Topic '{topic}' for command: {cmd:?} (encoder: {encoder:?})");
 
         let topic = ByteString::from(topic);
 
-        let payload = match cmd.payload {
-            Some(payload) => Bytes::from(payload),
+        let payload = match &cmd.payload {
+            Some(payload) => Bytes::from(payload.clone()),
             None => Bytes::new(),
         };
 
         match sink {
-            mqtt::Sink::V3(sink) => match sink.publish(topic, payload).send_at_most_once() {
-                Ok(_) => Ok(()),
-                Err(e) => Err(e.to_string()),
-            },
-            mqtt::Sink::V5(sink) => match sink.publish(topic, payload).send_at_most_once() {
-                Ok(_) => Ok(()),
-                Err(e) => Err(e.to_string()),
-            },
+            mqtt::Sink::V3(sink) => {
+                let builder = sink.publish(topic, payload);
+                match qos {
+                    QoS::AtMostOnce => builder
+                        .send_at_most_once()
+                        .map(|_| false)
+                        .map_err(|e| e.to_string()),
+                    _ => Self::acknowledged(builder.send_at_least_once()).await,
+                }
+            }
+            mqtt::Sink::V5(sink) => {
+                let builder = sink.publish(topic, payload);
+                // allow the device to reply, using the MQTT v5 request/response pattern
+                let builder = match &cmd.correlation_id {
+                    Some(correlation_id) => builder.properties(|p| {
+                        p.correlation_data = Some(Bytes::from(correlation_id.clone()));
+                        p.response_topic = Some(ByteString::from(reply_channel(correlation_id)));
+                    }),
+                    None => builder,
+                };
+                match qos {
+                    QoS::AtMostOnce => builder
+                        .send_at_most_once()
+                        .map(|_| false)
+                        .map_err(|e| e.to_string()),
+                    _ => Self::acknowledged(builder.send_at_least_once()).await,
+                }
+            }
+        }
+    }
+
+    /// Wait for the device to acknowledge a command.
+    ///
+    /// A command which was not acknowledged in time is not sent again, as it might still be
+    /// processed by the device, but it is not reported as acknowledged either.
+    async fn acknowledged<F, T, E>(ack: F) -> Result<bool, String>
+    where
+        F: Future<Output = Result<T, E>>,
+        E: ToString,
+    {
+        match timeout(ACK_TIMEOUT, ack).await {
+            Ok(result) => result.map(|_| true).map_err(|e| e.to_string()),
+            Err(_) => {
+                log::info!("Device did not acknowledge command in time");
+                Ok(false)
+            }
         }
     }
 
@@ -125,6 +125,7 @@ impl Session {
         topic_filter: F,
         filter: CommandFilter,
         encoder: SubscriptionTopicEncoder,
+        qos: QoS,
     ) where
         F: Into<String>,
     {
@@ -144,6 +145,7 @@ impl Session {
                     self.commands.clone(),
                     self.sink.clone(),
                     encoder,
+                    qos,
                 )
                 .await;
                 entry.insert(subscription);
@@ -215,6 +217,7 @@ impl mqtt::Session for Session {
                     sender: self.device.metadata.to_id(),
                     options: PublishOptions {
                         content_type,
+                        labels: device.metadata.labels.clone(),
                         ..Default::default()
                     },
                 },
@@ -264,13 +267,19 @@ impl mqtt::Session for Session {
 
             match self.dialect.parse_subscribe(sub.topic()) {
                 Ok(ParsedSubscribeTopic { filter, encoder }) => {
+                    // commands are sent with QoS 1 at most, which allows tracking the PUBACK
+                    let qos = match sub.qos() {
+                        QoS::AtMostOnce => QoS::AtMostOnce,
+                        _ => QoS::AtLeastOnce,
+                    };
                     self.subscribe_inbox(
                         sub.topic().to_string(),
                         filter.into_command_filter(&self.id),
                         encoder,
+                        qos,
                     )
                     .await;
-                    sub.confirm(QoS::AtMostOnce);
+                    sub.confirm(qos);
                 }
                 Err(err) => {
                     log::info!("Subscribing to topic {:?} not allowed: {err}", sub.topic());
@@ -4,14 +4,15 @@ pub use crate::service::ServiceConfig;
 
 use drogue_cloud_endpoint_common::{
     sender::{ExternalClientPoolConfig, UpstreamSender},
-    sink::KafkaSink,
+    sink,
 };
+use drogue_cloud_integration_common::commands::ReplyRouter;
 use drogue_cloud_mqtt_common::server::{build, MqttServerOptions, TlsConfig};
 use drogue_cloud_service_api::kafka::KafkaClientConfig;
 use drogue_cloud_service_common::{
     app::{Startup, StartupExt},
     auth::openid::AuthenticatorConfig,
-    client::ClientConfig,
+    client::{ClientConfig, DeviceStateClient, DeviceStateClientConfig},
     defaults,
     reqwest::ClientFactory,
 };
@@ -70,6 +71,10 @@ pub struct Config {
 
     #[serde(default)]
     pub endpoint_pool: ExternalClientPoolConfig,
+
+    /// Send the last events of devices as retained messages, using the device state service.
+    #[serde(default)]
+    pub last_values: Option<DeviceStateClientConfig>,
 }
 
 impl TlsConfig for Config {
@@ -131,10 +136,20 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     let sender = UpstreamSender::new(
         config.instance,
-        KafkaSink::from_config(config.command_kafka_sink, config.check_kafka_topic_ready)?,
+        sink::from_config(
+            config.command_kafka_sink,
+            Default::default(),
+            config.service.mqtt_backbone.clone(),
+            config.check_kafka_topic_ready,
+        )?,
         config.endpoint_pool,
     )?;
 
+    let last_values = match config.last_values {
+        Some(last_values) => Some(DeviceStateClient::from_config(last_values).await?),
+        None => None,
+    };
+
     log::info!("Authenticator: {:?}", authenticator);
     log::info!("User auth: {:?}", user_auth);
 
@@ -147,6 +162,9 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
         sender,
         client: ClientFactory::new().build()?,
         registry,
+        last_values,
+        replies: ReplyRouter::new(config.service.kafka.clone())
+            .with_mqtt(config.service.mqtt_backbone.clone()),
     };
 
     // create server
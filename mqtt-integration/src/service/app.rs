@@ -2,9 +2,13 @@ use crate::service::{session::Session, ServiceConfig};
 use async_trait::async_trait;
 use drogue_client::{registry, user};
 use drogue_cloud_endpoint_common::sender::UpstreamSender;
+use drogue_cloud_integration_common::commands::ReplyRouter;
 use drogue_cloud_mqtt_common::{error::ServerError, mqtt::*};
 use drogue_cloud_service_api::auth::user::UserInformation;
-use drogue_cloud_service_common::auth::openid::{Authenticator, AuthenticatorError};
+use drogue_cloud_service_common::{
+    auth::openid::{Authenticator, AuthenticatorError},
+    client::DeviceStateClient,
+};
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
@@ -15,6 +19,8 @@ pub struct App {
     pub sender: UpstreamSender,
     pub client: reqwest::Client,
     pub registry: registry::v1::Client,
+    pub last_values: Option<DeviceStateClient>,
+    pub replies: ReplyRouter,
 }
 
 impl App {
@@ -119,6 +125,8 @@ impl Service<Session> for App {
                 self.sender.clone(),
                 self.client.clone(),
                 self.registry.clone(),
+                self.last_values.clone(),
+                self.replies.clone(),
                 token,
             ),
             ack: AckOptions {
@@ -4,15 +4,39 @@ mod stream;
 
 pub use app::App;
 
+use drogue_cloud_endpoint_common::sink::MqttClientConfig;
 use drogue_cloud_service_api::kafka::KafkaClientConfig;
 use serde::Deserialize;
+use std::time::Duration;
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ServiceConfig {
     #[serde(default)]
     pub kafka: KafkaClientConfig,
+    /// Use an MQTT broker for events and commands, instead of Kafka.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
     #[serde(default)]
     pub enable_username_password_auth: bool,
     #[serde(default)]
     pub disable_api_keys: bool,
+    /// The time to wait for the reply of a device, when a command is sent as MQTT v5 request.
+    #[serde(with = "humantime_serde", default = "default_reply_timeout")]
+    pub reply_timeout: Duration,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            kafka: Default::default(),
+            mqtt_backbone: None,
+            enable_username_password_auth: false,
+            disable_api_keys: false,
+            reply_timeout: default_reply_timeout(),
+        }
+    }
+}
+
+const fn default_reply_timeout() -> Duration {
+    Duration::from_secs(30)
 }
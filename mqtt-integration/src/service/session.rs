@@ -12,7 +12,8 @@ use drogue_cloud_endpoint_common::sender::UpstreamSender;
 use drogue_cloud_event_common::stream::CustomAck;
 use drogue_cloud_integration_common::{
     self,
-    commands::CommandOptions,
+    commands::{CommandOptions, ReplyOptions, ReplyRouter, RequestOutcome},
+    retained::Retained,
     stream::{EventStream, EventStreamConfig},
 };
 use drogue_cloud_mqtt_common::{
@@ -23,11 +24,17 @@ use drogue_cloud_service_api::{
     auth::user::UserInformation,
     kafka::{KafkaConfigExt, KafkaEventType},
 };
+use drogue_cloud_service_common::client::DeviceStateClient;
 use futures::lock::Mutex;
+use ntex::util::ByteString;
+use ntex_bytes::Bytes;
 use ntex_mqtt::{types::QoS, v5};
-use std::{collections::HashMap, num::NonZeroU32, sync::Arc};
+use std::{collections::HashMap, num::NonZeroU32, sync::Arc, time::Duration};
 use tokio::task::JoinHandle;
 
+/// Timeout for checking if the consumer group of a subscription has committed offsets.
+const COMMITTED_OFFSETS_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Session {
     pub config: ServiceConfig,
     pub user_auth: Option<Arc<user::v1::Client>>,
@@ -41,6 +48,8 @@ pub struct Session {
     pub sender: UpstreamSender,
     pub client: reqwest::Client,
     pub registry: registry::v1::Client,
+    pub last_values: Option<DeviceStateClient>,
+    pub replies: ReplyRouter,
 
     pub token: Option<String>,
 }
@@ -56,6 +65,8 @@ impl Session {
         sender: UpstreamSender,
         client: reqwest::Client,
         registry: registry::v1::Client,
+        last_values: Option<DeviceStateClient>,
+        replies: ReplyRouter,
         token: Option<String>,
     ) -> Self {
         CONNECTIONS_COUNTER.inc();
@@ -69,6 +80,8 @@ impl Session {
             sender,
             client,
             registry,
+            last_values,
+            replies,
             token,
         }
     }
@@ -110,22 +123,23 @@ impl Session {
         original_topic: String,
         qos: QoS,
         content_mode: ContentMode,
+        retained: bool,
     ) -> Result<QoS, v5::codec::SubscribeAckReason> {
         // split topic into path segments
         let topic = original_topic.split('/').collect::<Vec<_>>();
 
         // extract the shared named, which we use as kafka consumer group id
-        let (group_id, topic) = match topic.as_slice() {
-            ["$share", group_id, topic @ ..] => (Some(*group_id), topic),
+        let (group_id, topic, shared) = match topic.as_slice() {
+            ["$share", group_id, topic @ ..] => (Some(*group_id), topic, true),
             // keep incorrect topic prefix for a bit, to not break existing stuff
-            ["$shared", group_id, topic @ ..] => (Some(*group_id), topic),
+            ["$shared", group_id, topic @ ..] => (Some(*group_id), topic, true),
             other => {
                 let group_id = if self.client_id.is_empty() {
                     None
                 } else {
                     Some(self.client_id.as_str())
                 };
-                (group_id, other)
+                (group_id, other, false)
             }
         };
 
@@ -174,7 +188,7 @@ impl Session {
             }
         }
 
-        // find kafka info
+        // find the application, and its kafka info
 
         let app_res = self
             .registry
@@ -185,17 +199,43 @@ impl Session {
 
         // create stream
 
-        let stream_config = EventStreamConfig {
-            kafka: app_res
-                .kafka_target(KafkaEventType::Events, &self.config.kafka)
-                .map(|target| target.into())
-                .map_err(|_| v5::codec::SubscribeAckReason::UnspecifiedError)?,
-            consumer_group: group_id.map(|s| format!("{app}.{s}")),
+        let consumer_group = group_id.map(|s| format!("{app}.{s}"));
+        let event_stream = match &self.config.mqtt_backbone {
+            Some(mqtt) => EventStream::<CustomAck>::new_mqtt(mqtt.clone(), app, consumer_group),
+            None => {
+                let stream_config = EventStreamConfig {
+                    kafka: app_res
+                        .kafka_target(KafkaEventType::Events, &self.config.kafka)
+                        .map(|target| target.into())
+                        .map_err(|_| v5::codec::SubscribeAckReason::UnspecifiedError)?,
+                    consumer_group,
+                };
+                EventStream::<CustomAck>::new(stream_config).map_err(|err| {
+                    log::info!("Failed to subscribe to Kafka topic: {}", err);
+                    v5::codec::SubscribeAckReason::UnspecifiedError
+                })?
+            }
+        };
+
+        // retained events, which are not sent for shared subscriptions, or when continuing from
+        // committed offsets
+
+        let retained = match &self.last_values {
+            Some(last_values) if retained && !shared => {
+                match event_stream.has_committed_offsets(COMMITTED_OFFSETS_TIMEOUT) {
+                    Ok(false) => Some(Retained::new(last_values.clone(), *app)),
+                    Ok(true) => {
+                        log::debug!("Continuing from committed offsets, skipping retained events");
+                        None
+                    }
+                    Err(err) => {
+                        log::info!("Failed to check for committed offsets: {err}");
+                        None
+                    }
+                }
+            }
+            _ => None,
         };
-        let event_stream = EventStream::<CustomAck>::new(stream_config).map_err(|err| {
-            log::info!("Failed to subscribe to Kafka topic: {}", err);
-            v5::codec::SubscribeAckReason::UnspecifiedError
-        })?;
 
         // we started the stream, now hold on to it ...
 
@@ -205,6 +245,7 @@ impl Session {
             id,
             event_stream,
             content_mode,
+            retained,
         };
 
         self.attach_stream(stream).await;
@@ -217,6 +258,71 @@ impl Session {
         })
     }
 
+    /// Send a command and publish the reply of the device to the response topic.
+    ///
+    /// This will spawn a task waiting for the reply, as we must not block the session.
+    fn request_command(
+        &self,
+        application: registry::v1::Application,
+        device_gateways: (registry::v1::Device, Vec<registry::v1::Device>),
+        opts: CommandOptions,
+        body: bytes::Bytes,
+        response_topic: ByteString,
+        correlation_data: Option<Bytes>,
+    ) {
+        let sink = self.sink.clone();
+        let sender = self.sender.clone();
+        let client = self.client.clone();
+        let reply = ReplyOptions {
+            router: self.replies.clone(),
+            timeout: self.config.reply_timeout,
+        };
+
+        ntex_rt::spawn(async move {
+            let outcome = drogue_cloud_integration_common::commands::request_command(
+                application,
+                device_gateways.0,
+                device_gateways.1,
+                &sender,
+                client,
+                opts,
+                body,
+                &reply,
+            )
+            .await;
+
+            let reply = match outcome {
+                Ok(RequestOutcome::Reply(reply)) => reply,
+                Ok(outcome) => {
+                    log::info!("No reply for command: {:?}", outcome);
+                    return;
+                }
+                Err(err) => {
+                    log::info!("Error sending command {:?}", err);
+                    return;
+                }
+            };
+
+            match sink {
+                Sink::V5(sink) => {
+                    let result = sink
+                        .publish(response_topic, reply.payload.into())
+                        .properties(|p| {
+                            p.correlation_data = correlation_data;
+                            p.content_type = reply.content_type.map(Into::into);
+                        })
+                        .send_at_most_once();
+                    if let Err(err) = result {
+                        log::info!("Failed to send reply: {}", err);
+                    }
+                }
+                Sink::V3(_) => {
+                    // response topics are only available with MQTT v5
+                }
+            }
+        });
+    }
+
     async fn attach_stream(&self, stream: Stream<'static>) {
         let topic = stream.topic.to_string();
 
@@ -291,8 +397,28 @@ impl mqtt::Session for Session {
                         device: device.to_string(),
                         command: command.to_string(),
                         content_type: None,
+                        correlation_id: None,
+                        not_before: None,
+                        expires_at: None,
                     };
 
+                    // an MQTT v5 request, waiting for the reply of the device
+                    if let Some(response_topic) =
+                        publish.properties().and_then(|p| p.response_topic.clone())
+                    {
+                        self.request_command(
+                            application,
+                            device_gateways,
+                            opts,
+                            bytes::Bytes::from(publish.payload().to_vec()),
+                            response_topic,
+                            publish
+                                .properties()
+                                .and_then(|p| p.correlation_data.clone()),
+                        );
+                        return Ok(());
+                    }
+
                     match drogue_cloud_integration_common::commands::process_command(
                         application,
                         device_gateways.0,
@@ -353,9 +479,21 @@ impl mqtt::Session for Session {
 
         log::debug!("Content mode: {:?}", content_mode);
 
+        // retained events must be requested explicitly
+
+        let retained = user_properties
+            .and_then(|props| props.iter().find(|(k, _)| k == "retained"))
+            .map_or(false, |(_, v)| v == "true");
+
         for mut sub in subscribe {
             let res = self
-                .subscribe_to(id, sub.topic().to_string(), sub.qos(), content_mode)
+                .subscribe_to(
+                    id,
+                    sub.topic().to_string(),
+                    sub.qos(),
+                    content_mode,
+                    retained && sub.retained(),
+                )
                 .await;
             log::debug!("Subscribing to: {:?} -> {:?}", sub.topic(), res);
             match res {
@@ -1,7 +1,7 @@
 use anyhow::anyhow;
-use cloudevents::Data;
+use cloudevents::{Data, Event};
 use drogue_cloud_event_common::stream::CustomAck;
-use drogue_cloud_integration_common::{self, stream::EventStream};
+use drogue_cloud_integration_common::{self, retained::Retained, stream::EventStream};
 use drogue_cloud_mqtt_common::mqtt::Sink;
 use futures_util::StreamExt;
 use ntex::util::ByteString;
@@ -44,6 +44,8 @@ pub struct Stream<'s> {
     pub id: Option<NonZeroU32>,
     pub event_stream: EventStream<'s, CustomAck>,
     pub content_mode: ContentMode,
+    /// Events sent as retained messages, before the events of the stream.
+    pub retained: Option<Retained>,
 }
 
 impl Drop for Stream<'_> {
@@ -81,6 +83,17 @@ impl Stream<'_> {
     }
 
     pub async fn run_v3(mut self, sink: &mut v3::MqttSink) -> Result<(), anyhow::Error> {
+        if let Some(mut retained) = self.retained.take() {
+            while let Some(events) = retained.next_page().await {
+                for event in events {
+                    let event = serde_json::to_vec(&event)?;
+                    let builder = sink.publish(self.topic.clone(), event.into()).retain();
+
+                    self.qos.send_v3(builder).await?;
+                }
+            }
+        }
+
         while let Some(handle) = self.event_stream.next().await {
             log::debug!("Event: {:?}", handle);
 
@@ -100,18 +113,32 @@ impl Stream<'_> {
     pub async fn run_v5_structured(mut self, sink: &mut v5::MqttSink) -> Result<(), anyhow::Error> {
         let sub_ids = self.id.map(|id| vec![id]);
 
+        if let Some(mut retained) = self.retained.take() {
+            while let Some(events) = retained.next_page().await {
+                for event in events {
+                    let builder = Self::publish_v5_structured(
+                        sink,
+                        self.topic.clone(),
+                        &event,
+                        sub_ids.clone(),
+                    )?
+                    .retain();
+
+                    self.qos.send_v5(builder).await?;
+                }
+            }
+        }
+
         while let Some(handle) = self.event_stream.next().await {
             log::debug!("Event: {:?}", handle);
 
             let handle = handle?;
-            let event = serde_json::to_vec(handle.deref())?;
-            let builder = sink
-                .publish(self.topic.clone(), event.into())
-                .properties(|p| {
-                    p.content_type = Some("application/cloudevents+json; charset=utf-8".into());
-                    p.is_utf8_payload = Some(true);
-                    p.subscription_ids = sub_ids.clone();
-                });
+            let builder = Self::publish_v5_structured(
+                sink,
+                self.topic.clone(),
+                handle.deref(),
+                sub_ids.clone(),
+            )?;
 
             self.qos.send_v5(builder).await?;
             self.event_stream.ack(handle)?;
@@ -125,34 +152,33 @@ impl Stream<'_> {
     pub async fn run_v5_binary(mut self, sink: &mut v5::MqttSink) -> Result<(), anyhow::Error> {
         let sub_ids = self.id.map(|id| vec![id]);
 
+        if let Some(mut retained) = self.retained.take() {
+            while let Some(events) = retained.next_page().await {
+                for mut event in events {
+                    let builder = Self::publish_v5_binary(
+                        sink,
+                        self.topic.clone(),
+                        &mut event,
+                        sub_ids.clone(),
+                    )?
+                    .retain();
+
+                    self.qos.send_v5(builder).await?;
+                }
+            }
+        }
+
         while let Some(handle) = self.event_stream.next().await {
             log::debug!("Event: {:?}", handle);
 
             let mut handle = handle?;
-            let event = handle.deref_mut();
-            let topic = self.topic.clone();
-
-            let (content_type, _, data) = event.take_data();
-            let builder = match data {
-                Some(Data::Binary(data)) => sink.publish(topic, data.into()),
-                Some(Data::String(data)) => sink.publish(topic, data.into()),
-                Some(Data::Json(data)) => {
-                    sink.publish(topic.clone(), serde_json::to_vec(&data)?.into())
-                }
-                None => sink.publish(topic.clone(), Bytes::new()),
-            };
+            let builder = Self::publish_v5_binary(
+                sink,
+                self.topic.clone(),
+                handle.deref_mut(),
+                sub_ids.clone(),
+            )?;
 
-            // convert attributes and extensions ...
-
-            let builder = builder.properties(|p| {
-                for (k, v) in event.iter() {
-                    p.user_properties.push((k.into(), v.to_string().into()));
-                }
-                p.content_type = content_type.map(Into::into);
-                p.subscription_ids = sub_ids.clone();
-            });
-
-            // ... and send
             self.qos.send_v5(builder).await?;
             self.event_stream.ack(handle)?;
 
@@ -161,4 +187,43 @@ impl Stream<'_> {
 
         Ok(())
     }
+
+    fn publish_v5_structured(
+        sink: &v5::MqttSink,
+        topic: ByteString,
+        event: &Event,
+        sub_ids: Option<Vec<NonZeroU32>>,
+    ) -> Result<v5::PublishBuilder, anyhow::Error> {
+        let event = serde_json::to_vec(event)?;
+        Ok(sink.publish(topic, event.into()).properties(|p| {
+            p.content_type = Some("application/cloudevents+json; charset=utf-8".into());
+            p.is_utf8_payload = Some(true);
+            p.subscription_ids = sub_ids;
+        }))
+    }
+
+    fn publish_v5_binary(
+        sink: &v5::MqttSink,
+        topic: ByteString,
+        event: &mut Event,
+        sub_ids: Option<Vec<NonZeroU32>>,
+    ) -> Result<v5::PublishBuilder, anyhow::Error> {
+        let (content_type, _, data) = event.take_data();
+        let builder = match data {
+            Some(Data::Binary(data)) => sink.publish(topic, data.into()),
+            Some(Data::String(data)) => sink.publish(topic, data.into()),
+            Some(Data::Json(data)) => sink.publish(topic, serde_json::to_vec(&data)?.into()),
+            None => sink.publish(topic, Bytes::new()),
+        };
+
+        // convert attributes and extensions
+
+        Ok(builder.properties(|p| {
+            for (k, v) in event.iter() {
+                p.user_properties.push((k.into(), v.to_string().into()));
+            }
+            p.content_type = content_type.map(Into::into);
+            p.subscription_ids = sub_ids;
+        }))
+    }
 }
@@ -14,6 +14,7 @@ impl From<Event> for OutboxEntry {
                 uid,
                 path,
                 revision,
+                sequence,
             } => OutboxEntry {
                 instance,
                 app: application,
@@ -21,6 +22,7 @@ impl From<Event> for OutboxEntry {
                 uid,
                 path,
                 revision,
+                sequence,
             },
             Event::Device {
                 instance,
@@ -29,6 +31,7 @@ impl From<Event> for OutboxEntry {
                 uid,
                 path,
                 revision,
+                sequence,
             } => OutboxEntry {
                 instance,
                 app: application,
@@ -36,6 +39,7 @@ impl From<Event> for OutboxEntry {
                 uid,
                 path,
                 revision,
+                sequence,
             },
         }
     }
@@ -51,6 +55,7 @@ impl From<OutboxEntry> for Event {
                 path: entry.path,
                 revision: entry.revision,
                 uid: entry.uid,
+                sequence: entry.sequence,
             }
         } else {
             Event::Application {
@@ -59,6 +64,7 @@ impl From<OutboxEntry> for Event {
                 path: entry.path,
                 revision: entry.revision,
                 uid: entry.uid,
+                sequence: entry.sequence,
             }
         }
     }
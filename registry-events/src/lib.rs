@@ -30,6 +30,8 @@ pub enum Event {
         uid: String,
         path: String,
         revision: u64,
+        /// The position of the change in the outbox, if it was recorded there.
+        sequence: Option<u64>,
     },
     Device {
         instance: String,
@@ -38,6 +40,8 @@ pub enum Event {
         uid: String,
         path: String,
         revision: u64,
+        /// The position of the change in the outbox, if it was recorded there.
+        sequence: Option<u64>,
     },
 }
 
@@ -46,6 +50,8 @@ pub struct EventData {
     #[serde(alias = "generation")]
     pub revision: u64,
     pub uid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
 }
 
 #[async_trait]
@@ -68,6 +74,20 @@ impl<S: EventSender> SendEvent<S> for Vec<Event> {
 }
 
 impl Event {
+    /// The position of the change in the outbox, if it was recorded there.
+    pub fn sequence(&self) -> Option<u64> {
+        match self {
+            Self::Application { sequence, .. } | Self::Device { sequence, .. } => *sequence,
+        }
+    }
+
+    /// Set the position of the change in the outbox.
+    pub fn set_sequence(&mut self, value: Option<u64>) {
+        match self {
+            Self::Application { sequence, .. } | Self::Device { sequence, .. } => *sequence = value,
+        }
+    }
+
     fn get_data(event: &cloudevents::Event) -> Result<EventData, EventError> {
         event
             .data()
@@ -96,6 +116,7 @@ impl Event {
                 .to_string(),
             revision: data.revision,
             uid: data.uid,
+            sequence: data.sequence,
         })
     }
 
@@ -120,6 +141,7 @@ impl Event {
                 .to_string(),
             revision: data.revision,
             uid: data.uid,
+            sequence: data.sequence,
         })
     }
 
@@ -154,6 +176,7 @@ impl Event {
             uid: uid.to_string(),
             path,
             revision,
+            sequence: None,
         })
     }
 
@@ -179,6 +202,7 @@ impl Event {
             uid: uid.to_string(),
             path,
             revision,
+            sequence: None,
         })
     }
 }
@@ -235,6 +259,7 @@ impl TryFrom<Event> for cloudevents::Event {
                 uid,
                 revision,
                 path,
+                sequence,
             } => builder
                 .ty(EVENT_TYPE_APPLICATION)
                 .source(format!("drogue:/{}/{}", instance, application))
@@ -245,8 +270,12 @@ impl TryFrom<Event> for cloudevents::Event {
                 .data(
                     mime::APPLICATION_JSON.to_string(),
                     Data::Json(
-                        serde_json::to_value(&EventData { revision, uid })
-                            .map_err(EventError::PayloadEncoder)?,
+                        serde_json::to_value(&EventData {
+                            revision,
+                            uid,
+                            sequence,
+                        })
+                        .map_err(EventError::PayloadEncoder)?,
                     ),
                 ),
             Event::Device {
@@ -256,6 +285,7 @@ impl TryFrom<Event> for cloudevents::Event {
                 uid,
                 revision,
                 path,
+                sequence,
             } => builder
                 .ty(EVENT_TYPE_DEVICE)
                 .source(format!("drogue:/{}/{}/{}", instance, application, device))
@@ -270,8 +300,12 @@ impl TryFrom<Event> for cloudevents::Event {
                 .data(
                     mime::APPLICATION_JSON.to_string(),
                     Data::Json(
-                        serde_json::to_value(&EventData { revision, uid })
-                            .map_err(EventError::PayloadEncoder)?,
+                        serde_json::to_value(&EventData {
+                            revision,
+                            uid,
+                            sequence,
+                        })
+                        .map_err(EventError::PayloadEncoder)?,
                     ),
                 ),
         };
@@ -309,6 +343,7 @@ mod test {
             uid: "uid".to_string(),
             path: ".spec.core".to_string(),
             revision: 123,
+            sequence: None,
         }
         .try_into()?;
 
@@ -360,10 +395,28 @@ mod test {
                 uid: "uid".to_string(),
                 path: ".spec.credentials".to_string(),
                 revision: 321,
+                sequence: None,
             },
             event
         );
 
         Ok(())
     }
+
+    #[test]
+    fn test_sequence() -> anyhow::Result<()> {
+        let mut event = Event::new_device("instance", "app", "device", "uid", 1, vec![])
+            .pop()
+            .unwrap();
+        assert_eq!(event.sequence(), None);
+
+        event.set_sequence(Some(42));
+        let ce: cloudevents::Event = event.clone().try_into()?;
+        let decoded: Event = ce.try_into()?;
+
+        assert_eq!(decoded.sequence(), Some(42));
+        assert_eq!(decoded, event);
+
+        Ok(())
+    }
 }
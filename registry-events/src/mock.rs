@@ -99,7 +99,8 @@ mod test {
             application: "app1".into(),
             revision: 123,
             uid: "a".into(),
-            path: "spec/core".into()
+            path: "spec/core".into(),
+            sequence: None,
         }
         .send_with(&sender)
         .await
@@ -114,7 +115,8 @@ mod test {
                 application: "app1".into(),
                 revision: 123,
                 uid: "a".into(),
-                path: "spec/core".into()
+                path: "spec/core".into(),
+                sequence: None,
             }]
         );
 
@@ -130,7 +132,8 @@ mod test {
                 application: "app1".into(),
                 revision: 123,
                 uid: "a".into(),
-                path: "spec/core".into()
+                path: "spec/core".into(),
+                sequence: None,
             }]
         );
 
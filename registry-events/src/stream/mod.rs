@@ -57,6 +57,23 @@ impl<'s> KafkaEventStream<'s> {
     pub fn new(cfg: KafkaStreamConfig) -> Result<Self, KafkaStreamError> {
         Ok(EventStream::new(cfg.into()).map(Self)?)
     }
+
+    /// Create a new stream, which only receives new events, without a shared consumer group.
+    ///
+    /// This is intended for cases where every instance must see all events, like notifying
+    /// clients about changes.
+    pub fn new_anonymous(mut client: KafkaConfig) -> Result<Self, KafkaStreamError> {
+        client
+            .client
+            .properties
+            .insert("auto.offset.reset".into(), "latest".into());
+
+        Ok(EventStream::new(EventStreamConfig {
+            kafka: client,
+            consumer_group: None,
+        })
+        .map(Self)?)
+    }
 }
 
 impl KafkaEventStream<'static> {
@@ -12,7 +12,7 @@ use drogue_cloud_device_management_service::service::PostgresManagementServiceCo
 use drogue_cloud_device_state_service::service::postgres::PostgresServiceConfiguration;
 use drogue_cloud_endpoint_common::{auth::AuthConfig, command::KafkaCommandSourceConfig};
 use drogue_cloud_mqtt_common::server::{MqttServerOptions, Transport};
-use drogue_cloud_registry_events::sender::KafkaSenderConfig; //, stream::KafkaStreamConfig};
+use drogue_cloud_registry_events::{sender::KafkaSenderConfig, stream::KafkaStreamConfig};
 use drogue_cloud_service_api::{kafka::KafkaClientConfig, webapp::HttpServer};
 use drogue_cloud_service_common::{
     actix::http::{CorsConfig, HttpBuilder, HttpConfig},
@@ -438,13 +438,20 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
             oauth: oauth.clone(),
             service: PostgresServiceConfiguration {
                 session_timeout: Duration::from_secs(10),
+                history_retention: Duration::from_secs(7 * 24 * 60 * 60),
                 pg: pg.clone(),
             },
             instance: "drogue".to_string(),
             check_kafka_topic_ready: false,
-            kafka_downstream_config: kafka,
+            kafka_downstream_config: kafka.clone(),
             endpoint_pool: Default::default(),
             registry: registry.clone(),
+            kafka_source: Some(KafkaStreamConfig {
+                client: kafka_config(&kafka, "registry"),
+                consumer_group: "device_state".to_string(),
+            }),
+            user_auth: user_auth.clone(),
+            command_kafka_sink: Some(kafka),
         };
 
         drogue_cloud_device_state_service::run(config, &mut main).await?;
@@ -479,7 +486,15 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
                 ..Default::default()
             },
             oauth: oauth.clone(),
-            auth_service_config: AuthenticationServiceConfig { pg: pg.clone() },
+            auth_service_config: AuthenticationServiceConfig {
+                pg: pg.clone(),
+                lockout: Default::default(),
+            },
+            instance: "drogue".to_string(),
+            check_kafka_topic_ready: false,
+            kafka_downstream_config: Some(server.kafka.clone()),
+            mqtt_backbone: None,
+            endpoint_pool: Default::default(),
         };
 
         drogue_cloud_authentication_service::run(config, &mut main).await?;
@@ -514,6 +529,7 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
                 instance: server.database.db.to_string(),
             },
             kafka_sender: kafka_sender("registry", &server.kafka.clone()),
+            watch: None,
         };
 
         let config_command = {
@@ -527,8 +543,11 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
                 instance: "drogue".to_string(),
                 check_kafka_topic_ready: false,
                 command_kafka_sink: kafka,
+                mqtt_backbone: None,
                 user_auth,
                 endpoint_pool: Default::default(),
+                max_reply_timeout: Duration::from_secs(60),
+                scheduler: None,
             }
         };
 
@@ -545,12 +564,14 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
                 .await
                 .unwrap();
 
-        let (registry, _) =
-            drogue_cloud_device_management_service::configurator(config_device_management_service)
-                .await
-                .unwrap();
+        let (registry, _) = drogue_cloud_device_management_service::configurator(
+            config_device_management_service,
+            &mut main,
+        )
+        .await
+        .unwrap();
 
-        let (command, _) = drogue_cloud_command_endpoint::configurator(config_command)
+        let (command, _) = drogue_cloud_command_endpoint::configurator(config_command, &mut main)
             .await
             .unwrap();
 
@@ -586,8 +607,16 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
             instance: "drogue".to_string(),
             kafka_downstream_config: kafka.clone(),
             kafka_command_config: kafka,
+            kafka_downstream_options: Default::default(),
+            mqtt_backbone: None,
+            spool: None,
             check_kafka_topic_ready: false,
             endpoint_pool: Default::default(),
+            command_queue: Default::default(),
+            command_status: Default::default(),
+            twin: Some(state.client.clone()),
+            last_values: Some(state.client.clone()),
+            trusted_proxies: Default::default(),
         };
 
         drogue_cloud_http_endpoint::run(config, &mut main).await?;
@@ -613,8 +642,15 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
             oauth: oauth.clone(),
 
             registry: registry.clone(),
-            kafka,
+            kafka: kafka.clone(),
             user_auth,
+            command_kafka_sink: Some(kafka),
+            mqtt_backbone: None,
+            last_values: Some(state.client.clone()),
+            check_kafka_topic_ready: false,
+            instance: "drogue".to_string(),
+            endpoint_pool: Default::default(),
+            max_reply_timeout: Duration::from_secs(60),
         };
 
         // The websocket integration uses the actix actors, so for now, that must run
@@ -682,8 +718,15 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
                 command_source_kafka,
                 kafka_downstream_config: kafka.clone(),
                 kafka_command_config: kafka,
+                kafka_downstream_options: Default::default(),
+                mqtt_backbone: None,
+                spool: None,
                 check_kafka_topic_ready: false,
                 endpoint_pool: Default::default(),
+                command_queue: Default::default(),
+                command_status: Default::default(),
+                twin: Some(state.client.clone()),
+                last_values: Some(state.client.clone()),
                 state: state.clone(),
             };
 
@@ -725,12 +768,14 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
                     kafka: kafka.clone(),
                     enable_username_password_auth: false,
                     disable_api_keys: false,
+                    ..Default::default()
                 },
                 check_kafka_topic_ready: false,
                 user_auth,
                 instance: "drogue".to_string(),
                 command_kafka_sink: kafka,
                 endpoint_pool: Default::default(),
+                last_values: Some(state.client.clone()),
             };
 
             // tasks.push(Box::pin(drogue_cloud_mqtt_integration::run(config.clone())));
@@ -792,8 +837,15 @@ async fn cmd_run(matches: &ArgMatches) -> anyhow::Result<()> {
             command_source_kafka,
             kafka_downstream_config: kafka.clone(),
             kafka_command_config: kafka,
+            kafka_downstream_options: Default::default(),
+            mqtt_backbone: None,
+            spool: None,
             check_kafka_topic_ready: false,
             endpoint_pool: Default::default(),
+            command_queue: Default::default(),
+            command_status: Default::default(),
+            twin: Some(state.client.clone()),
+            last_values: Some(state.client.clone()),
             disable_dtls: !(key_file.is_some() && cert_bundle_file.is_some()),
             disable_client_certificates: false,
             disable_psk: false,
@@ -1,9 +1,14 @@
+use chrono::{DateTime, Utc};
 use core::fmt::{self, Formatter};
 use drogue_client::{
     metrics::{AsPassFail, PassFail},
     registry,
 };
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// The type of event sent when a device was locked out, due to failed authentication attempts.
+pub const AUTH_FAILED_TYPE_EVENT: &str = "io.drogue.auth.failed";
 
 /// Authenticate a device.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -13,6 +18,9 @@ pub struct AuthenticationRequest {
     pub credential: Credential,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub r#as: Option<String>,
+    /// The address of the client, as seen by the endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
 }
 
 /// Requesting pre-shared keys for a device.
@@ -80,6 +88,10 @@ pub enum Outcome {
     /// The authentication request failed. The device is not authenticated, and the device's
     /// request must be rejected.
     Fail,
+    /// The device, or the address it connects from, is temporarily locked out due to too many
+    /// failed attempts. The device's request must be rejected, and it should not retry before
+    /// the lock expires.
+    Locked { until: DateTime<Utc> },
 }
 
 /// The result of an authentication request.
@@ -93,7 +105,7 @@ impl AsPassFail for AuthenticationResponse {
     fn as_pass_fail(&self) -> PassFail {
         match &self.outcome {
             Outcome::Pass { .. } => PassFail::Pass,
-            Outcome::Fail => PassFail::Fail,
+            Outcome::Fail | Outcome::Locked { .. } => PassFail::Fail,
         }
     }
 }
@@ -106,6 +118,19 @@ impl AuthenticationResponse {
     }
 }
 
+/// The payload of an [`AUTH_FAILED_TYPE_EVENT`] event.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthFailedEvent {
+    /// The number of failed attempts.
+    pub failures: u32,
+    /// The time until the device is locked out.
+    pub locked_until: DateTime<Utc>,
+    /// The address of the last failed attempt, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
+}
+
 /// The result of a key request
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PreSharedKeyResponse {
@@ -185,6 +210,20 @@ mod test {
         assert_eq!(String::from(r#"{"outcome":"fail"}"#), str.unwrap());
     }
 
+    #[test]
+    fn test_encode_locked() {
+        let str = serde_json::to_string(&AuthenticationResponse {
+            outcome: Outcome::Locked {
+                until: Utc.timestamp_millis(1000),
+            },
+        });
+        assert!(str.is_ok());
+        assert_eq!(
+            String::from(r#"{"outcome":{"locked":{"until":"1970-01-01T00:00:01Z"}}}"#),
+            str.unwrap()
+        );
+    }
+
     #[test]
     fn test_encode_pass() {
         let str = serde_json::to_string(&AuthenticationResponse {
@@ -0,0 +1,53 @@
+#[cfg(feature = "nom")]
+mod parser;
+
+#[cfg(feature = "nom")]
+pub use parser::*;
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "nom")]
+use std::convert::TryFrom;
+
+/// Select resources based on their fields, rather than their labels.
+#[derive(Clone, Debug, Default)]
+pub struct FieldSelector(pub Vec<FieldOperation>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldOperation {
+    /// Created after the timestamp (`metadata.creationTimestamp>…`).
+    CreatedAfter(DateTime<Utc>),
+    /// Created before the timestamp (`metadata.creationTimestamp<…`).
+    CreatedBefore(DateTime<Utc>),
+    /// Marked for deletion (`metadata.deletionTimestamp`).
+    Deleted,
+    /// Not marked for deletion (`!metadata.deletionTimestamp`).
+    NotDeleted,
+    /// Has a section in the spec (e.g. `spec.gatewaySelector`).
+    SpecExists(String),
+    /// Has no section in the spec (e.g. `!spec.credentials`).
+    SpecNotExists(String),
+}
+
+impl FieldSelector {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(feature = "nom")]
+impl TryFrom<&str> for FieldSelector {
+    type Error = ParserError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(FieldSelector(parser::parse_from(value)?))
+    }
+}
+
+#[cfg(feature = "nom")]
+impl TryFrom<String> for FieldSelector {
+    type Error = ParserError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(FieldSelector(parser::parse_from(&value)?))
+    }
+}
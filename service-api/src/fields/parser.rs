@@ -0,0 +1,140 @@
+use super::FieldOperation;
+use crate::labels::ParserError;
+use chrono::{DateTime, Utc};
+use nom::{
+    branch::*, bytes::complete::*, character::complete::*, combinator::*, multi::*, sequence::*,
+    AsChar, IResult,
+};
+
+pub fn parse_from(value: &str) -> Result<Vec<FieldOperation>, ParserError> {
+    let (remain, ops) = parse(value).map_err(|err| ParserError::new(err.to_string()))?;
+
+    if !remain.is_empty() {
+        Err(ParserError::new(format!(
+            "Unparsable remaining content: '{}'",
+            remain
+        )))
+    } else {
+        Ok(ops)
+    }
+}
+
+fn parse(input: &str) -> IResult<&str, Vec<FieldOperation>> {
+    separated_list0(tag(","), parse_one)(input)
+}
+
+fn parse_one(input: &str) -> IResult<&str, FieldOperation> {
+    delimited(
+        space0,
+        alt((
+            parse_created_after,
+            parse_created_before,
+            parse_deleted,
+            parse_not_deleted,
+            parse_spec_exists,
+            parse_spec_not_exists,
+        )),
+        space0,
+    )(input)
+}
+
+fn parse_created_after(input: &str) -> IResult<&str, FieldOperation> {
+    map(
+        preceded(
+            tuple((tag("metadata.creationTimestamp"), space0, tag(">"), space0)),
+            parse_timestamp,
+        ),
+        FieldOperation::CreatedAfter,
+    )(input)
+}
+
+fn parse_created_before(input: &str) -> IResult<&str, FieldOperation> {
+    map(
+        preceded(
+            tuple((tag("metadata.creationTimestamp"), space0, tag("<"), space0)),
+            parse_timestamp,
+        ),
+        FieldOperation::CreatedBefore,
+    )(input)
+}
+
+fn parse_deleted(input: &str) -> IResult<&str, FieldOperation> {
+    value(FieldOperation::Deleted, tag("metadata.deletionTimestamp"))(input)
+}
+
+fn parse_not_deleted(input: &str) -> IResult<&str, FieldOperation> {
+    value(
+        FieldOperation::NotDeleted,
+        tag("!metadata.deletionTimestamp"),
+    )(input)
+}
+
+fn parse_spec_exists(input: &str) -> IResult<&str, FieldOperation> {
+    map(preceded(tag("spec."), parse_section), |section| {
+        FieldOperation::SpecExists(section.into())
+    })(input)
+}
+
+fn parse_spec_not_exists(input: &str) -> IResult<&str, FieldOperation> {
+    map(preceded(tag("!spec."), parse_section), |section| {
+        FieldOperation::SpecNotExists(section.into())
+    })(input)
+}
+
+fn parse_section(input: &str) -> IResult<&str, &str> {
+    recognize(preceded(
+        alpha1,
+        many0(satisfy(|c| c.is_alphanum() || c == '_' || c == '-')),
+    ))(input)
+}
+
+fn parse_timestamp(input: &str) -> IResult<&str, DateTime<Utc>> {
+    map_res(
+        take_while1(|c: char| c.is_alphanum() || c == ':' || c == '.' || c == '-' || c == '+'),
+        |value: &str| DateTime::parse_from_rfc3339(value).map(|ts| ts.with_timezone(&Utc)),
+    )(input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_0() {
+        assert_eq!(parse_from(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_from(
+                "metadata.creationTimestamp>2022-01-01T00:00:00Z, !spec.credentials,spec.gatewaySelector"
+            ),
+            Ok(vec![
+                FieldOperation::CreatedAfter(Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)),
+                FieldOperation::SpecNotExists("credentials".into()),
+                FieldOperation::SpecExists("gatewaySelector".into()),
+            ])
+        );
+        assert_eq!(
+            parse_from("metadata.creationTimestamp < 2022-01-01T01:00:00+01:00,!metadata.deletionTimestamp"),
+            Ok(vec![
+                FieldOperation::CreatedBefore(Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)),
+                FieldOperation::NotDeleted,
+            ])
+        );
+        assert_eq!(
+            parse_from("metadata.deletionTimestamp"),
+            Ok(vec![FieldOperation::Deleted])
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse_from("metadata.creationTimestamp>yesterday").is_err());
+        assert!(parse_from("metadata.name").is_err());
+        assert!(parse_from("spec.").is_err());
+        assert!(parse_from("spec.foo.bar").is_err());
+    }
+}
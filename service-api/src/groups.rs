@@ -0,0 +1,276 @@
+//! Groups of devices.
+//!
+//! Groups live inside an application and may be nested. A device references its group using the
+//! `group` section of its spec, and inherits the default spec sections of the group and all of
+//! its parents. The gateways of the groups are added to the `gatewaySelector` section of the
+//! effective spec of the device.
+
+use drogue_client::meta;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// The spec section of a device, containing the name of its group.
+pub const DEVICE_GROUP_SECTION: &str = "group";
+
+/// The spec section of a device, containing the gateways which may act on its behalf.
+pub const GATEWAY_SELECTOR_SECTION: &str = "gatewaySelector";
+
+/// The field of the gateway selector, containing the names of the gateways.
+const MATCH_NAMES: &str = "matchNames";
+
+/// The maximum number of nested group levels.
+pub const MAX_GROUP_DEPTH: usize = 16;
+
+/// A group of devices.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeviceGroup {
+    pub metadata: meta::v1::ScopedMetadata,
+    #[serde(default)]
+    pub spec: DeviceGroupSpec,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceGroupSpec {
+    /// The name of the parent group, if the group is nested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Gateways, which may act on behalf of all devices of the group, and its nested groups.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gateways: Vec<String>,
+    /// Spec sections, inherited by devices which don't provide their own.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub defaults: Map<String, Value>,
+}
+
+/// Get the name of the group a device belongs to.
+///
+/// Returns an error if the section is present, but doesn't contain a name.
+pub fn device_group(spec: &Map<String, Value>) -> Result<Option<&str>, String> {
+    match spec.get(DEVICE_GROUP_SECTION) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(group)) if !group.is_empty() => Ok(Some(group)),
+        Some(_) => Err(format!(
+            "The '{DEVICE_GROUP_SECTION}' section must contain the name of a group"
+        )),
+    }
+}
+
+/// Apply the defaults of the groups to the spec of a device.
+///
+/// The groups must be ordered starting with the group of the device, followed by its parents.
+/// Sections of the device take precedence over the defaults, and defaults of nested groups take
+/// precedence over the defaults of their parents.
+pub fn apply_defaults(spec: &mut Map<String, Value>, groups: &[DeviceGroup]) {
+    for group in groups {
+        for (section, value) in &group.spec.defaults {
+            if !spec.contains_key(section) {
+                spec.insert(section.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Check if a gateway may act on behalf of the devices of the groups.
+pub fn is_group_gateway(gateway: &str, groups: &[DeviceGroup]) -> bool {
+    groups
+        .iter()
+        .any(|group| group.spec.gateways.iter().any(|name| name == gateway))
+}
+
+/// Get a group, followed by all of its parents, from the groups of an application.
+///
+/// The result is empty if the group doesn't exist.
+pub fn ancestors(groups: &HashMap<String, DeviceGroup>, name: &str) -> Vec<DeviceGroup> {
+    let mut result = Vec::new();
+    let mut next = Some(name);
+
+    // the depth limit protects against cycles, which the registry should prevent anyway
+    while let Some(group) = next.and_then(|name| groups.get(name)) {
+        if result.len() >= MAX_GROUP_DEPTH {
+            break;
+        }
+        result.push(group.clone());
+        next = group.spec.parent.as_deref();
+    }
+
+    result
+}
+
+/// Apply the groups to the spec of a device, resulting in its effective spec.
+///
+/// Applies the defaults of the groups, and adds the gateways of the groups to the gateway
+/// selector of the device.
+pub fn apply_groups(spec: &mut Map<String, Value>, groups: &[DeviceGroup]) {
+    apply_defaults(spec, groups);
+
+    let mut gateways = groups
+        .iter()
+        .flat_map(|group| &group.spec.gateways)
+        .peekable();
+    if gateways.peek().is_none() {
+        return;
+    }
+
+    let selector = spec
+        .entry(GATEWAY_SELECTOR_SECTION)
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(selector) = selector {
+        let names = selector
+            .entry(MATCH_NAMES)
+            .or_insert_with(|| Value::Array(vec![]));
+        if let Value::Array(names) = names {
+            for gateway in gateways {
+                let gateway = Value::String(gateway.clone());
+                if !names.contains(&gateway) {
+                    names.push(gateway);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn group(name: &str, spec: Value) -> DeviceGroup {
+        let mut group = DeviceGroup {
+            spec: serde_json::from_value(spec).unwrap(),
+            ..Default::default()
+        };
+        group.metadata.name = name.into();
+        group
+    }
+
+    #[test]
+    fn test_apply_defaults() {
+        let groups = vec![
+            group(
+                "floor-1",
+                json!({"parent": "building-1", "defaults": {"core": {"disabled": true}}}),
+            ),
+            group(
+                "building-1",
+                json!({"defaults": {"core": {"disabled": false}, "foo": "bar"}}),
+            ),
+        ];
+
+        let mut spec = json!({"group": "floor-1", "foo": "baz"})
+            .as_object()
+            .cloned()
+            .unwrap();
+        apply_defaults(&mut spec, &groups);
+
+        assert_eq!(
+            Value::Object(spec),
+            json!({"group": "floor-1", "foo": "baz", "core": {"disabled": true}})
+        );
+    }
+
+    #[test]
+    fn test_gateways() {
+        let groups = vec![
+            group("floor-1", json!({"parent": "building-1"})),
+            group("building-1", json!({"gateways": ["gw-1"]})),
+        ];
+
+        assert!(is_group_gateway("gw-1", &groups));
+        assert!(!is_group_gateway("gw-2", &groups));
+        assert!(!is_group_gateway("gw-1", &[]));
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let groups: HashMap<_, _> = [
+            group("room-1", json!({"parent": "floor-1"})),
+            group("floor-1", json!({"parent": "building-1"})),
+            group("building-1", json!({})),
+        ]
+        .into_iter()
+        .map(|group| (group.metadata.name.clone(), group))
+        .collect();
+
+        let names = |name| {
+            ancestors(&groups, name)
+                .into_iter()
+                .map(|group| group.metadata.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("room-1"), vec!["room-1", "floor-1", "building-1"]);
+        assert_eq!(names("building-1"), vec!["building-1"]);
+        assert!(names("room-2").is_empty());
+    }
+
+    #[test]
+    fn test_ancestors_limit() {
+        // "group-0" is nested deepest, one level more than the limit allows
+        let groups: HashMap<_, _> = (0..=MAX_GROUP_DEPTH)
+            .map(|i| {
+                let parent = format!("group-{}", i + 1);
+                group(&format!("group-{i}"), json!({ "parent": parent }))
+            })
+            .map(|group| (group.metadata.name.clone(), group))
+            .collect();
+
+        let result = ancestors(&groups, "group-0");
+        assert_eq!(result.len(), MAX_GROUP_DEPTH);
+        assert_eq!(
+            result.last().map(|group| group.metadata.name.as_str()),
+            Some("group-15")
+        );
+
+        // a group nested up to the limit gets all of its ancestors
+        let result = ancestors(&groups, "group-1");
+        assert_eq!(result.len(), MAX_GROUP_DEPTH);
+        assert_eq!(
+            result.last().map(|group| group.metadata.name.as_str()),
+            Some("group-16")
+        );
+    }
+
+    #[test]
+    fn test_apply_groups() {
+        let groups = vec![
+            group(
+                "floor-1",
+                json!({"parent": "building-1", "gateways": ["gw-2"], "defaults": {"foo": "bar"}}),
+            ),
+            group(
+                "building-1",
+                json!({"gateways": ["gw-1", "gw-2"], "defaults": {"foo": "baz"}}),
+            ),
+        ];
+
+        let mut spec = json!({"group": "floor-1", "gatewaySelector": {"matchNames": ["gw-0"]}})
+            .as_object()
+            .cloned()
+            .unwrap();
+        apply_groups(&mut spec, &groups);
+
+        assert_eq!(
+            Value::Object(spec),
+            json!({
+                "group": "floor-1",
+                "foo": "bar",
+                "gatewaySelector": {"matchNames": ["gw-0", "gw-2", "gw-1"]},
+            })
+        );
+    }
+
+    #[test]
+    fn test_device_group() {
+        let spec = |value: Value| value.as_object().cloned().unwrap();
+
+        assert_eq!(device_group(&spec(json!({}))), Ok(None));
+        assert_eq!(
+            device_group(&spec(json!({"group": "floor-1"}))),
+            Ok(Some("floor-1"))
+        );
+        assert!(device_group(&spec(json!({"group": {}}))).is_err());
+        assert!(device_group(&spec(json!({"group": ""}))).is_err());
+    }
+}
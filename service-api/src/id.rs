@@ -6,3 +6,9 @@ pub const EXT_SENDER: &str = "sender";
 pub const EXT_APPLICATION_UID: &str = "applicationuid";
 pub const EXT_DEVICE_UID: &str = "deviceuid";
 pub const EXT_SENDER_UID: &str = "senderuid";
+
+/// Correlates a command with the reply of the device.
+pub const EXT_CORRELATION_ID: &str = "correlationid";
+
+/// The point in time a command expires, if it could not be delivered until then (RFC 3339).
+pub const EXT_EXPIRES: &str = "expires";
@@ -4,10 +4,11 @@ mod parser;
 #[cfg(feature = "nom")]
 pub use parser::*;
 
+use std::collections::HashMap;
 #[cfg(feature = "nom")]
 use std::convert::TryFrom;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct LabelSelector(pub Vec<Operation>);
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -18,6 +19,73 @@ pub enum Operation {
     NotIn(String, Vec<String>),
     Exists(String),
     NotExists(String),
+    /// The label must have a numeric value, greater than the provided number.
+    Gt(String, String),
+    /// The label must have a numeric value, less than the provided number.
+    Lt(String, String),
+    /// The value of the label must start with the provided prefix.
+    Prefix(String, String),
+}
+
+impl LabelSelector {
+    /// Check if a set of labels matches all operations of the selector.
+    ///
+    /// This uses the same semantics as the database queries, so a label which must not have a
+    /// specific value (`!=`, `notin`) must still be present.
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.0.iter().all(|op| op.matches(labels))
+    }
+}
+
+impl Operation {
+    /// Check if a set of labels matches the operation.
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Eq(label, value) => labels.get(label) == Some(value),
+            Self::NotEq(label, value) => matches!(labels.get(label), Some(v) if v != value),
+            Self::In(label, values) => matches!(labels.get(label), Some(v) if values.contains(v)),
+            Self::NotIn(label, values) => {
+                matches!(labels.get(label), Some(v) if !values.contains(v))
+            }
+            Self::Exists(label) => labels.contains_key(label),
+            Self::NotExists(label) => !labels.contains_key(label),
+            Self::Gt(label, value) => Self::compare(labels, label, value, |v, n| v > n),
+            Self::Lt(label, value) => Self::compare(labels, label, value, |v, n| v < n),
+            Self::Prefix(label, prefix) => {
+                matches!(labels.get(label), Some(v) if v.starts_with(prefix.as_str()))
+            }
+        }
+    }
+
+    fn compare<F>(labels: &HashMap<String, String>, label: &str, value: &str, f: F) -> bool
+    where
+        F: FnOnce(f64, f64) -> bool,
+    {
+        match (labels.get(label).and_then(|v| numeric(v)), numeric(value)) {
+            (Some(v), Some(n)) => f(v, n),
+            _ => false,
+        }
+    }
+}
+
+/// Parse a numeric value of a label.
+///
+/// In order to stay aligned with the database queries, this only accepts decimal numbers, with an
+/// optional sign and fraction (e.g. `-1.5`), but no exponent or special values.
+pub fn numeric(value: &str) -> Option<f64> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let (int, fraction) = match digits.split_once('.') {
+        Some((int, fraction)) => (int, Some(fraction)),
+        None => (digits, None),
+    };
+
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    if is_digits(int) && fraction.map_or(true, is_digits) {
+        value.parse().ok()
+    } else {
+        None
+    }
 }
 
 #[cfg(feature = "nom")]
@@ -37,3 +105,60 @@ impl TryFrom<String> for LabelSelector {
         Ok(LabelSelector(parser::parse_from(&value)?))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let labels: HashMap<String, String> = [("floor", "1"), ("room", "101")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let selector = LabelSelector(vec![
+            Operation::Eq("floor".into(), "1".into()),
+            Operation::Exists("room".into()),
+        ]);
+        assert!(selector.matches(&labels));
+
+        assert!(!Operation::NotEq("floor".into(), "1".into()).matches(&labels));
+        assert!(!Operation::NotEq("zone".into(), "1".into()).matches(&labels));
+        assert!(Operation::In("room".into(), vec!["101".into(), "102".into()]).matches(&labels));
+        assert!(!Operation::NotIn("room".into(), vec!["101".into()]).matches(&labels));
+        assert!(Operation::NotExists("zone".into()).matches(&labels));
+
+        assert!(LabelSelector::default().matches(&labels));
+    }
+
+    #[test]
+    fn test_matches_numeric() {
+        let labels: HashMap<String, String> = [("floor", "1"), ("room", "101a"), ("temp", "-1.5")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        assert!(Operation::Gt("floor".into(), "0".into()).matches(&labels));
+        assert!(!Operation::Gt("floor".into(), "1".into()).matches(&labels));
+        assert!(Operation::Lt("temp".into(), "-1".into()).matches(&labels));
+        assert!(!Operation::Lt("room".into(), "200".into()).matches(&labels));
+        assert!(!Operation::Gt("zone".into(), "0".into()).matches(&labels));
+
+        assert!(Operation::Prefix("room".into(), "10".into()).matches(&labels));
+        assert!(!Operation::Prefix("room".into(), "2".into()).matches(&labels));
+        assert!(!Operation::Prefix("zone".into(), "".into()).matches(&labels));
+    }
+
+    #[test]
+    fn test_numeric() {
+        assert_eq!(numeric("1"), Some(1.0));
+        assert_eq!(numeric("-1.5"), Some(-1.5));
+        assert_eq!(numeric(""), None);
+        assert_eq!(numeric("-"), None);
+        assert_eq!(numeric("1."), None);
+        assert_eq!(numeric(".5"), None);
+        assert_eq!(numeric("1e5"), None);
+        assert_eq!(numeric("inf"), None);
+    }
+}
@@ -10,6 +10,14 @@ pub struct ParserError {
     details: String,
 }
 
+impl ParserError {
+    pub(crate) fn new<S: Into<String>>(details: S) -> Self {
+        Self {
+            details: details.into(),
+        }
+    }
+}
+
 impl std::error::Error for ParserError {}
 
 impl core::fmt::Display for ParserError {
@@ -43,6 +51,9 @@ fn parse_one(input: &str) -> IResult<&str, Operation> {
         parse_not_in,
         parse_equals,
         parse_not_equals,
+        parse_prefix,
+        parse_greater_than,
+        parse_less_than,
         parse_exists,
         parse_not_exists,
     ))(input)
@@ -72,6 +83,27 @@ fn parse_not_equals(input: &str) -> IResult<&str, Operation> {
     )(input)
 }
 
+fn parse_prefix(input: &str) -> IResult<&str, Operation> {
+    map(
+        tuple((parse_label, space0, tag("^="), parse_value)),
+        |(label, _, _, value)| Operation::Prefix(label.into(), value.into()),
+    )(input)
+}
+
+fn parse_greater_than(input: &str) -> IResult<&str, Operation> {
+    map(
+        tuple((parse_label, space0, tag(">"), parse_number)),
+        |(label, _, _, value)| Operation::Gt(label.into(), value.into()),
+    )(input)
+}
+
+fn parse_less_than(input: &str) -> IResult<&str, Operation> {
+    map(
+        tuple((parse_label, space0, tag("<"), parse_number)),
+        |(label, _, _, value)| Operation::Lt(label.into(), value.into()),
+    )(input)
+}
+
 fn parse_in(input: &str) -> IResult<&str, Operation> {
     map(
         tuple((
@@ -126,6 +158,17 @@ fn parse_value(input: &str) -> IResult<&str, &str> {
     )(input)
 }
 
+fn parse_number(input: &str) -> IResult<&str, &str> {
+    map(
+        tuple((
+            space0,
+            recognize(tuple((opt(tag("-")), digit1, opt(pair(tag("."), digit1))))),
+            space0,
+        )),
+        |(_, result, _)| result,
+    )(input)
+}
+
 fn parse_raw_value(input: &str) -> IResult<&str, &str> {
     recognize(preceded(
         alphanumeric1,
@@ -219,6 +262,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_compare() {
+        assert_eq!(
+            parse_from("foo>1,bar < -1.5,baz^=abc, foo/bar ^= a-b"),
+            Ok(vec![
+                Operation::Gt("foo".into(), "1".into()),
+                Operation::Lt("bar".into(), "-1.5".into()),
+                Operation::Prefix("baz".into(), "abc".into()),
+                Operation::Prefix("foo/bar".into(), "a-b".into()),
+            ])
+        );
+        assert!(parse_from("foo>bar").is_err());
+        assert!(parse_from("foo<1.").is_err());
+    }
+
     #[test]
     fn test_parse_rem() {
         assert_eq!(
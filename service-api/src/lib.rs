@@ -1,9 +1,12 @@
 pub mod admin;
 pub mod auth;
 pub mod endpoints;
+pub mod fields;
+pub mod groups;
 mod id;
 pub mod kafka;
 pub mod labels;
+pub mod profiles;
 pub mod serde;
 pub mod services;
 pub mod token;
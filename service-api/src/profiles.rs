@@ -0,0 +1,91 @@
+//! Device profiles.
+//!
+//! A profile contains spec sections shared by many devices of an application. A device
+//! references its profile using the `profile` section of its spec, and its effective spec is
+//! the spec of the profile, overridden by the sections of the device.
+
+use drogue_client::meta;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The spec section of a device, containing the name of its profile.
+pub const DEVICE_PROFILE_SECTION: &str = "profile";
+
+/// Spec sections which must not be provided by a profile.
+///
+/// Credentials and aliases are indexed per device, and the membership in a group or profile must
+/// be explicit.
+pub const RESERVED_SECTIONS: &[&str] = &[
+    DEVICE_PROFILE_SECTION,
+    crate::groups::DEVICE_GROUP_SECTION,
+    "alias",
+    "authentication",
+    "credentials",
+];
+
+/// A profile of devices.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub metadata: meta::v1::ScopedMetadata,
+    #[serde(default)]
+    pub spec: Map<String, Value>,
+}
+
+/// Get the name of the profile a device references.
+///
+/// Returns an error if the section is present, but doesn't contain a name.
+pub fn device_profile(spec: &Map<String, Value>) -> Result<Option<&str>, String> {
+    match spec.get(DEVICE_PROFILE_SECTION) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(profile)) if !profile.is_empty() => Ok(Some(profile)),
+        Some(_) => Err(format!(
+            "The '{DEVICE_PROFILE_SECTION}' section must contain the name of a profile"
+        )),
+    }
+}
+
+/// Apply the spec of a profile to the spec of a device.
+///
+/// Sections of the device take precedence over the sections of the profile. Sections are not
+/// merged any deeper.
+pub fn apply_profile(spec: &mut Map<String, Value>, profile: &Map<String, Value>) {
+    for (section, value) in profile {
+        if !spec.contains_key(section) {
+            spec.insert(section.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn map(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        let mut spec = map(json!({"profile": "sensor", "ttn": {"app": "foo"}}));
+        apply_profile(
+            &mut spec,
+            &map(json!({"ttn": {"app": "bar", "region": "eu"}, "commands": {"timeout": 5}})),
+        );
+
+        assert_eq!(
+            Value::Object(spec),
+            json!({"profile": "sensor", "ttn": {"app": "foo"}, "commands": {"timeout": 5}})
+        );
+    }
+
+    #[test]
+    fn test_device_profile() {
+        assert_eq!(device_profile(&map(json!({}))), Ok(None));
+        assert_eq!(
+            device_profile(&map(json!({"profile": "sensor"}))),
+            Ok(Some("sensor"))
+        );
+        assert!(device_profile(&map(json!({"profile": 1}))).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::serde::is_default;
 use chrono::{DateTime, Utc};
+use drogue_client::{dialect, Section};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 pub const CONNECTION_TYPE_EVENT: &str = "io.drogue.connection.v1";
 
@@ -22,6 +24,9 @@ pub struct Id {
 pub struct DeviceState {
     pub device_uid: String,
     pub endpoint: String,
+    /// The type of endpoint, like `mqtt`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint_type: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub lwt: Option<LastWillTestament>,
 }
@@ -87,3 +92,148 @@ pub struct DeleteOptions {
     #[serde(default, skip_serializing_if = "is_default")]
     pub skip_lwt: bool,
 }
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedDevicesRequest {
+    /// Only list devices connected through this type of endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint_type: Option<String>,
+    /// Only list devices connected through this endpoint instance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedDevice {
+    pub device: String,
+    pub created: DateTime<Utc>,
+    pub state: DeviceState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedDevicesResponse {
+    /// The total number of connected devices, matching the filter.
+    pub total: u64,
+    /// Connected devices, sorted by name.
+    pub items: Vec<ConnectedDevice>,
+}
+
+/// The reason a connection of a device ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DisconnectCause {
+    /// The device disconnected cleanly.
+    Disconnected,
+    /// The connection was closed without a clean disconnect, triggering the last will (if any).
+    LastWill,
+    /// The connection was taken over by a new connection of the same device.
+    Takeover,
+    /// The endpoint owning the connection stopped refreshing its session.
+    Expired,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionHistoryEntry {
+    /// The endpoint instance which owned the connection.
+    pub endpoint: String,
+    /// The session of the endpoint instance.
+    pub session: String,
+    pub connected: DateTime<Utc>,
+    /// Missing if the device is still connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disconnected: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<DisconnectCause>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionHistoryRequest {
+    /// Only consider connections which ended at or after this point in time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Limit the number of returned entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionHistoryResponse {
+    /// Whether the device is currently connected.
+    pub connected: bool,
+    /// The number of connections in the requested time frame.
+    pub connects: u64,
+    /// The last time the device connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_connected: Option<DateTime<Utc>>,
+    /// The last time the device disconnected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_disconnected: Option<DateTime<Utc>>,
+    /// Connections, latest first.
+    pub entries: Vec<ConnectionHistoryEntry>,
+}
+
+/// Record the last event a device sent on a channel.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordValueRequest {
+    pub channel: String,
+    /// The time of the event, older events don't replace newer ones.
+    pub time: DateTime<Utc>,
+    /// The event, in the structured JSON format of Cloud Events.
+    pub event: Value,
+}
+
+/// The "lastValues" section of an application spec.
+///
+/// The last events of the devices of an application are only recorded, if the application has
+/// this section.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastValuesSpec {}
+
+dialect!(LastValuesSpec[Section::Spec => "lastValues"]);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastValuesRequest {
+    /// Only return the values of this device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Only return the values of this channel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+}
+
+/// The last event a device sent on a channel.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastValue {
+    pub device: String,
+    pub channel: String,
+    pub time: DateTime<Utc>,
+    /// The event, in the structured JSON format of Cloud Events.
+    pub event: Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastValuesResponse {
+    /// The total number of values, matching the filter.
+    pub total: u64,
+    /// Values, sorted by device and channel.
+    pub items: Vec<LastValue>,
+}
@@ -0,0 +1,180 @@
+//! Device twins.
+//!
+//! A twin holds the properties an operator wants a device to have ("desired"), and the properties
+//! the device last reported ("reported"). Both sides are versioned independently, the version
+//! being incremented with each change.
+
+use chrono::{DateTime, Utc};
+use drogue_client::{dialect, Section};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The default channel, used for reporting properties and receiving deltas.
+pub const DEFAULT_TWIN_CHANNEL: &str = "twin";
+
+/// The "twin" section of an application spec.
+///
+/// Twins of an application are only updated from events, and deltas are only sent, if the
+/// application has this section.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwinSpec {
+    /// The channel devices report their properties on, and receive deltas as commands.
+    #[serde(default = "default_channel")]
+    pub channel: String,
+}
+
+impl Default for TwinSpec {
+    fn default() -> Self {
+        Self {
+            channel: default_channel(),
+        }
+    }
+}
+
+fn default_channel() -> String {
+    DEFAULT_TWIN_CHANNEL.to_string()
+}
+
+dialect!(TwinSpec[Section::Spec => "twin"]);
+
+/// One side of a twin.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwinProperties {
+    #[serde(default)]
+    pub properties: Map<String, Value>,
+    #[serde(default)]
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceTwin {
+    pub desired: TwinProperties,
+    pub reported: TwinProperties,
+    /// Desired properties, which differ from the reported ones.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub delta: Map<String, Value>,
+}
+
+impl DeviceTwin {
+    pub fn new(desired: TwinProperties, reported: TwinProperties) -> Self {
+        let delta = delta(&desired.properties, &reported.properties);
+        Self {
+            desired,
+            reported,
+            delta,
+        }
+    }
+}
+
+/// An update of one side of a twin.
+///
+/// This is used by the API for the desired properties, as well as by the devices for reporting
+/// their properties.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwinUpdate {
+    #[serde(default)]
+    pub properties: Map<String, Value>,
+    /// Only apply the update if the current version matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+}
+
+/// The payload of a delta command, sent to the device.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwinDelta {
+    /// The version of the desired properties.
+    pub version: u64,
+    pub delta: Map<String, Value>,
+}
+
+/// Apply a JSON merge patch (RFC 7386) to a set of properties.
+pub fn merge(target: &mut Map<String, Value>, patch: &Map<String, Value>) {
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            Value::Object(patch) => {
+                let entry = target
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::Object(Default::default()));
+                if !entry.is_object() {
+                    *entry = Value::Object(Default::default());
+                }
+                if let Value::Object(entry) = entry {
+                    merge(entry, patch);
+                }
+            }
+            value => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Evaluate the desired properties, which are not reported yet.
+///
+/// Nested objects are compared property by property. Properties only present in the reported
+/// properties are ignored.
+pub fn delta(desired: &Map<String, Value>, reported: &Map<String, Value>) -> Map<String, Value> {
+    let mut result = Map::new();
+
+    for (key, value) in desired {
+        match (value, reported.get(key)) {
+            (Value::Object(desired), Some(Value::Object(reported))) => {
+                let delta = delta(desired, reported);
+                if !delta.is_empty() {
+                    result.insert(key.clone(), Value::Object(delta));
+                }
+            }
+            (value, Some(reported)) if value == reported => {}
+            (value, _) => {
+                result.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn map(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap()
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut target = map(json!({"a": 1, "b": {"c": 2, "d": 3}, "e": 4}));
+        merge(
+            &mut target,
+            &map(json!({"a": 2, "b": {"c": null, "f": 5}, "e": null, "g": {"h": 6}})),
+        );
+        assert_eq!(
+            Value::Object(target),
+            json!({"a": 2, "b": {"d": 3, "f": 5}, "g": {"h": 6}})
+        );
+    }
+
+    #[test]
+    fn test_delta() {
+        let desired = map(json!({"a": 1, "b": {"c": 2, "d": 3}, "e": [1, 2], "f": "foo"}));
+        let reported = map(json!({"a": 1, "b": {"c": 2, "d": 4}, "e": [1], "g": true}));
+
+        assert_eq!(
+            Value::Object(delta(&desired, &reported)),
+            json!({"b": {"d": 3}, "e": [1, 2], "f": "foo"})
+        );
+        assert!(delta(&reported, &reported).is_empty());
+    }
+}
@@ -4,9 +4,12 @@ use drogue_client::{
     error::ClientError,
     openid::{OpenIdTokenProvider, TokenInjector},
 };
-use drogue_cloud_service_api::services::device_state::{
-    CreateRequest, CreateResponse, DeleteOptions, DeleteRequest, DeviceState, InitResponse,
-    PingResponse,
+use drogue_cloud_service_api::services::{
+    device_state::{
+        CreateRequest, CreateResponse, DeleteOptions, DeleteRequest, DeviceState, InitResponse,
+        LastValuesRequest, LastValuesResponse, PingResponse, RecordValueRequest,
+    },
+    twin::{DeviceTwin, TwinUpdate},
 };
 use k8s_openapi::percent_encoding::{percent_encode, NON_ALPHANUMERIC};
 use reqwest::{Response, StatusCode};
@@ -181,6 +184,97 @@ impl DeviceStateClient {
         }
     }
 
+    /// Merge properties reported by a device into its twin.
+    #[instrument(level = "debug", err)]
+    pub async fn update_reported(
+        &self,
+        application: &str,
+        device: &str,
+        update: &TwinUpdate,
+    ) -> Result<DeviceTwin, ClientError> {
+        let url = self.url.join(&format!(
+            "/api/state/v1alpha1/twins/{}/{}/reported",
+            percent_encode(application.as_bytes(), NON_ALPHANUMERIC),
+            percent_encode(device.as_bytes(), NON_ALPHANUMERIC)
+        ))?;
+
+        let req = self
+            .client
+            .patch(url)
+            .propagate_current_context()
+            .inject_token(&self.token_provider)
+            .await?
+            .json(update);
+
+        let response: Response = req
+            .send()
+            .await
+            .map_err(|err| ClientError::Client(Box::new(err)))?;
+
+        handle_response(response, StatusCode::OK).await
+    }
+
+    /// Record the last event of a device on a channel.
+    #[instrument(level = "debug", skip(request), err)]
+    pub async fn record_value(
+        &self,
+        application: &str,
+        device: &str,
+        request: &RecordValueRequest,
+    ) -> Result<(), ClientError> {
+        let url = self.url.join(&format!(
+            "/api/state/v1alpha1/values/{}/{}",
+            percent_encode(application.as_bytes(), NON_ALPHANUMERIC),
+            percent_encode(device.as_bytes(), NON_ALPHANUMERIC)
+        ))?;
+
+        let req = self
+            .client
+            .post(url)
+            .propagate_current_context()
+            .inject_token(&self.token_provider)
+            .await?
+            .json(request);
+
+        let response: Response = req
+            .send()
+            .await
+            .map_err(|err| ClientError::Client(Box::new(err)))?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            code => super::default_error(code, response).await,
+        }
+    }
+
+    /// Get the last events of the devices of an application.
+    #[instrument(level = "debug", err)]
+    pub async fn last_values(
+        &self,
+        application: &str,
+        request: &LastValuesRequest,
+    ) -> Result<LastValuesResponse, ClientError> {
+        let url = self.url.join(&format!(
+            "/api/state/v1alpha1/values/{}",
+            percent_encode(application.as_bytes(), NON_ALPHANUMERIC),
+        ))?;
+
+        let req = self
+            .client
+            .get(url)
+            .propagate_current_context()
+            .inject_token(&self.token_provider)
+            .await?
+            .query(request);
+
+        let response: Response = req
+            .send()
+            .await
+            .map_err(|err| ClientError::Client(Box::new(err)))?;
+
+        handle_response(response, StatusCode::OK).await
+    }
+
     fn state_url(
         &self,
         session: &str,
@@ -7,6 +7,9 @@ pub struct StateControllerConfiguration {
     #[serde(default)]
     pub client: DeviceStateClientConfig,
     pub endpoint: String,
+    /// The type of the endpoint, reported with the state of each device.
+    #[serde(default)]
+    pub endpoint_type: Option<String>,
     /// The amount of time to ping again before the session expiration.
     #[serde(with = "humantime_serde", default = "default_delay_buffer")]
     pub delay_buffer: Duration,
@@ -27,6 +30,7 @@ impl Default for StateControllerConfiguration {
         Self {
             client: Default::default(),
             endpoint: "default".to_string(),
+            endpoint_type: None,
             delay_buffer: default_delay_buffer(),
             min_delay: default_min_delay(),
             retry_deletes: default_retry_deletes(),
@@ -39,6 +39,7 @@ pub struct StateController {
     client: DeviceStateClient,
     session: String,
     endpoint: String,
+    endpoint_type: Option<String>,
     retry_deletes: usize,
     kill_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
 }
@@ -120,6 +121,7 @@ impl StateController {
                 client: client.clone(),
                 session: session.clone(),
                 endpoint: config.endpoint,
+                endpoint_type: config.endpoint_type,
                 retry_deletes: config.retry_deletes,
                 kill_tx: Arc::new(Mutex::new(Some(kill_tx))),
             },
@@ -145,6 +147,7 @@ impl StateController {
         let state = DeviceState {
             device_uid: device.metadata.uid.clone(),
             endpoint: self.endpoint.clone(),
+            endpoint_type: self.endpoint_type.clone(),
             lwt: opts.lwt,
         };
 
@@ -201,6 +201,55 @@ impl<'c, C: Docker, SC> Drop for PostgresRunner<'c, C, SC> {
     }
 }
 
+pub struct MqttRunner<'c, C: Docker> {
+    pub host: String,
+    pub port: u16,
+    broker: Container<'c, C, GenericImage>,
+}
+
+impl<'c, C: 'c + Docker> MqttRunner<'c, C> {
+    pub fn new(cli: &'c C) -> Self {
+        log::info!(
+            "Starting MQTT broker (containerized: {})",
+            is_containerized()
+        );
+
+        // clients retry connecting, so there is no need to wait for the broker
+        let image = GenericImage::new("docker.io/library/eclipse-mosquitto:1.6")
+            .with_wait_for(WaitFor::Nothing);
+
+        let args = RunArgs::default().with_mapped_port((1883, 1883));
+        let (args, host) = if is_containerized() {
+            (
+                args.with_network("drogue").with_name("mosquitto"),
+                "mosquitto",
+            )
+        } else {
+            (args, "localhost")
+        };
+
+        let broker = cli.run_with_args(image, args);
+
+        Self {
+            host: host.into(),
+            port: 1883,
+            broker,
+        }
+    }
+}
+
+impl<'c, C: Docker> Drop for MqttRunner<'c, C> {
+    fn drop(&mut self) {
+        log::info!("Stopping MQTT broker");
+        self.broker.stop();
+    }
+}
+
+/// Run an MQTT broker, supporting shared subscriptions.
+pub fn mqtt<C: Docker>(cli: &C) -> MqttRunner<C> {
+    MqttRunner::new(cli)
+}
+
 pub fn client() -> Cli {
     match is_podman() {
         true => Cli::podman(),
@@ -0,0 +1,183 @@
+use crate::messages::{CommandOutcome, CommandReply, CommandRequest};
+use drogue_client::{
+    registry,
+    user::{self, v1::authz},
+};
+use drogue_cloud_endpoint_common::sender::UpstreamSender;
+use drogue_cloud_integration_common::commands::{
+    request_command, CommandOptions, Reply, ReplyOptions, ReplyRouter, RequestOutcome,
+};
+use drogue_cloud_service_api::auth::user::UserInformation;
+use serde_json::Value;
+use std::time::Duration;
+
+/// Everything required for sending commands through the websocket.
+#[derive(Clone, Debug)]
+pub struct CommandContext {
+    pub sender: UpstreamSender,
+    pub registry: registry::v1::Client,
+    pub client: reqwest::Client,
+    /// Consumes the device replies.
+    pub replies: ReplyRouter,
+    pub max_reply_timeout: Duration,
+}
+
+impl CommandContext {
+    /// Send a command and wait for the reply of the device.
+    pub async fn request(
+        &self,
+        application: String,
+        user: &UserInformation,
+        user_auth: Option<&user::v1::Client>,
+        request: CommandRequest,
+    ) -> CommandReply {
+        let request_id = request.request_id.clone();
+
+        let (outcome, reply) = match self.do_request(application, user, user_auth, request).await {
+            Ok(RequestOutcome::Reply(reply)) => (CommandOutcome::Reply, Some(reply)),
+            Ok(RequestOutcome::Timeout) => (CommandOutcome::Timeout, None),
+            Ok(RequestOutcome::Response(response)) if response.status().is_success() => {
+                (CommandOutcome::Accepted, None)
+            }
+            Ok(RequestOutcome::Response(response)) => {
+                log::info!("Failed to send command: {}", response.status());
+                (CommandOutcome::Failed, None)
+            }
+            Err(outcome) => (outcome, None),
+        };
+
+        let (content_type, payload) = match reply {
+            Some(reply) => (reply.content_type.clone(), Some(encode_payload(reply))),
+            None => (None, None),
+        };
+
+        CommandReply {
+            request_id,
+            outcome,
+            content_type,
+            payload,
+        }
+    }
+
+    async fn do_request(
+        &self,
+        application: String,
+        user: &UserInformation,
+        user_auth: Option<&user::v1::Client>,
+        request: CommandRequest,
+    ) -> Result<RequestOutcome, CommandOutcome> {
+        if let Some(user_auth) = user_auth {
+            let response = user_auth
+                .authorize(authz::AuthorizationRequest {
+                    application: application.clone(),
+                    permission: authz::Permission::Write,
+                    user_id: user.user_id().map(ToString::to_string),
+                    roles: user.roles().clone(),
+                })
+                .await
+                .map_err(|err| {
+                    log::info!("Failed to authorize: {err}");
+                    CommandOutcome::Failed
+                })?;
+
+            if let authz::Outcome::Deny = response.outcome {
+                return Err(CommandOutcome::NotAuthorized);
+            }
+        }
+
+        let response = futures::try_join!(
+            self.registry.get_app(&application),
+            self.registry
+                .get_device_and_gateways(&application, &request.device)
+        );
+
+        let (app, device_gateways) = match response {
+            Ok((Some(app), Some(device_gateways))) => (app, device_gateways),
+            Ok(_) => return Err(CommandOutcome::NotFound),
+            Err(err) => {
+                log::info!("Error looking up registry info: {err}");
+                return Err(CommandOutcome::Failed);
+            }
+        };
+
+        let (content_type, body) = match request.payload {
+            Some(payload) => (
+                Some("application/json".to_string()),
+                serde_json::to_vec(&payload).map_err(|_| CommandOutcome::Failed)?,
+            ),
+            None => (None, vec![]),
+        };
+
+        request_command(
+            app,
+            device_gateways.0,
+            device_gateways.1,
+            &self.sender,
+            self.client.clone(),
+            CommandOptions {
+                application,
+                device: request.device,
+                command: request.command,
+                content_type,
+                correlation_id: None,
+                not_before: None,
+                expires_at: None,
+            },
+            body.into(),
+            &ReplyOptions {
+                router: self.replies.clone(),
+                timeout: request.timeout.min(self.max_reply_timeout),
+            },
+        )
+        .await
+        .map_err(|err| {
+            log::info!("Failed to send command: {err}");
+            CommandOutcome::Failed
+        })
+    }
+}
+
+/// Embed JSON payloads, encode everything else as base64.
+fn encode_payload(reply: Reply) -> Value {
+    match serde_json::from_slice(&reply.payload) {
+        Ok(value) if is_json(reply.content_type.as_deref()) => value,
+        _ => Value::String(base64::encode(&reply.payload)),
+    }
+}
+
+fn is_json(content_type: Option<&str>) -> bool {
+    match content_type {
+        Some(content_type) => {
+            content_type.starts_with("application/json") || content_type.ends_with("+json")
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_payload() {
+        let reply = |content_type: Option<&str>, payload: &[u8]| Reply {
+            content_type: content_type.map(ToString::to_string),
+            payload: payload.to_vec(),
+        };
+
+        assert_eq!(
+            encode_payload(reply(Some("application/json"), br#"{"foo":1}"#)),
+            json!({"foo": 1})
+        );
+        assert_eq!(encode_payload(reply(None, b"42")), json!(42));
+        assert_eq!(
+            encode_payload(reply(Some("text/plain"), b"42")),
+            json!("NDI=")
+        );
+        assert_eq!(
+            encode_payload(reply(Some("application/json"), b"foo")),
+            json!("Zm9v")
+        );
+    }
+}
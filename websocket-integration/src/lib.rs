@@ -1,12 +1,18 @@
+mod command;
 mod messages;
 mod route;
 mod service;
 mod wshandler;
 
-use crate::service::Service;
+use crate::{command::CommandContext, service::Service};
 use actix::Actor;
 use actix_web::web;
 use drogue_client::user::v1::authz::Permission;
+use drogue_cloud_endpoint_common::{
+    sender::{ExternalClientPoolConfig, UpstreamSender},
+    sink::{self, MqttClientConfig},
+};
+use drogue_cloud_integration_common::commands::ReplyRouter;
 use drogue_cloud_service_api::{
     kafka::KafkaClientConfig,
     webapp::{self as actix_web},
@@ -17,12 +23,13 @@ use drogue_cloud_service_common::{
     app::Startup,
     auth::openid,
     auth::pat,
-    client::ClientConfig,
+    client::{ClientConfig, DeviceStateClient, DeviceStateClientConfig},
+    defaults,
 };
 use lazy_static::lazy_static;
 use prometheus::{labels, opts, register_int_gauge, IntGauge};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 lazy_static! {
     pub static ref CONNECTIONS_COUNTER: IntGauge = register_int_gauge!(opts!(
@@ -48,10 +55,42 @@ pub struct Config {
 
     pub oauth: openid::AuthenticatorConfig,
 
+    /// Enables sending commands through the websocket, using this Kafka configuration.
+    #[serde(default)]
+    pub command_kafka_sink: Option<KafkaClientConfig>,
+
+    /// Use an MQTT broker for events and commands, instead of Kafka.
+    ///
+    /// This also enables sending commands through the websocket.
+    #[serde(default)]
+    pub mqtt_backbone: Option<MqttClientConfig>,
+
+    /// Allows replaying the last events of devices to new connections, using the device state
+    /// service.
+    #[serde(default)]
+    pub last_values: Option<DeviceStateClientConfig>,
+
+    #[serde(default = "defaults::check_kafka_topic_ready")]
+    pub check_kafka_topic_ready: bool,
+
+    #[serde(default = "defaults::instance")]
+    pub instance: String,
+
+    #[serde(default)]
+    pub endpoint_pool: ExternalClientPoolConfig,
+
+    /// The maximum time a command request may wait for the reply of a device.
+    #[serde(with = "humantime_serde", default = "default_max_reply_timeout")]
+    pub max_reply_timeout: Duration,
+
     #[serde(default)]
     pub http: HttpConfig,
 }
 
+const fn default_max_reply_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
 pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()> {
     log::info!("Starting WebSocket integration service endpoint");
     log::info!("Kafka servers: {}", config.kafka.bootstrap_servers);
@@ -67,11 +106,42 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
 
     let registry = config.registry.into_client().await?;
 
+    // commands
+
+    let commands = match (config.command_kafka_sink, &config.mqtt_backbone) {
+        (None, None) => None,
+        (command_kafka_sink, mqtt_backbone) => Some(CommandContext {
+            sender: UpstreamSender::new(
+                config.instance,
+                sink::from_config(
+                    command_kafka_sink.unwrap_or_default(),
+                    Default::default(),
+                    mqtt_backbone.clone(),
+                    config.check_kafka_topic_ready,
+                )?,
+                config.endpoint_pool,
+            )?,
+            registry: registry.clone(),
+            client: reqwest::Client::new(),
+            replies: ReplyRouter::new(config.kafka.clone()).with_mqtt(mqtt_backbone.clone()),
+            max_reply_timeout: config.max_reply_timeout,
+        }),
+    };
+
+    // last values
+
+    let last_values = match config.last_values {
+        Some(last_values) => Some(DeviceStateClient::from_config(last_values).await?),
+        None => None,
+    };
+
     // create and start the service actor
     let service_addr = Service {
         clients: HashMap::default(),
         kafka_config: config.kafka,
+        mqtt_backbone: config.mqtt_backbone,
         registry,
+        last_values,
     }
     .start();
     let service_addr = web::Data::new(service_addr);
@@ -86,6 +156,9 @@ pub async fn run(config: Config, startup: &mut dyn Startup) -> anyhow::Result<()
         if let Some(user_auth) = user_auth.clone() {
             cfg.app_data(user_auth);
         }
+        if let Some(commands) = commands.clone() {
+            cfg.app_data(commands);
+        }
 
         cfg.service(
             web::scope("/{application}")
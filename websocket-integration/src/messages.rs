@@ -3,6 +3,9 @@ use cloudevents::Event;
 use drogue_client::integration::ws::v1::client;
 use drogue_cloud_integration_common::stream::EventStream;
 use drogue_cloud_service_common::error::ServiceError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
 use uuid::Uuid;
 
 // Service sends the kafka events in this message to WSHandler
@@ -18,6 +21,8 @@ pub struct Subscribe {
     pub err_addr: Recipient<StreamError>,
     pub application: String,
     pub consumer_group: Option<String>,
+    /// Send the last events of the devices, before the events of the stream.
+    pub retained: bool,
     pub id: Uuid,
 }
 
@@ -48,3 +53,72 @@ pub struct StreamError {
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Protocol(pub client::Message);
+
+/// A client request, not part of the standard protocol messages
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Request {
+    /// Send a command to a device, and wait for the reply
+    Command(CommandRequest),
+}
+
+/// Incoming (client) message, either a protocol message or a request
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Incoming {
+    Protocol(client::Message),
+    Request(Request),
+}
+
+#[derive(Clone, Debug, Deserialize, Message)]
+#[rtype(result = "()")]
+#[serde(rename_all = "camelCase")]
+pub struct CommandRequest {
+    /// An ID chosen by the client, which will be returned with the reply.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub device: String,
+    pub command: String,
+    /// The JSON payload of the command.
+    #[serde(default)]
+    pub payload: Option<Value>,
+    /// The time to wait for the reply of the device.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+/// Outgoing (server) messages, in addition to the events
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Outgoing {
+    CommandReply(CommandReply),
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandReply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub outcome: CommandOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The payload of the reply, JSON payloads are embedded, others are base64 encoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CommandOutcome {
+    /// The device replied.
+    Reply,
+    /// The device did not reply in time.
+    Timeout,
+    /// The command was sent to an external endpoint, no reply is available.
+    Accepted,
+    /// Sending commands is not enabled.
+    Disabled,
+    NotAuthorized,
+    NotFound,
+    Failed,
+}
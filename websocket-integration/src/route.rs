@@ -5,13 +5,15 @@ use actix_web::{
     Error, HttpRequest, HttpResponse,
 };
 use actix_web_actors::ws;
-use drogue_cloud_service_api::webapp as actix_web;
+use drogue_cloud_service_api::{auth::user::UserInformation, webapp as actix_web};
 use drogue_cloud_service_common::actix_auth::authentication::AuthenticatedUntil;
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
-pub struct GroupId {
+pub struct StreamOptions {
     group_id: Option<String>,
+    #[serde(default)]
+    retained: bool,
 }
 
 pub async fn start_connection(
@@ -19,8 +21,9 @@ pub async fn start_connection(
     stream: Payload,
     application: web::Path<String>,
     service_addr: web::Data<Addr<Service>>,
-    web::Query(group_id): web::Query<GroupId>,
+    web::Query(options): web::Query<StreamOptions>,
     auth_expiration: Option<web::ReqData<AuthenticatedUntil>>,
+    user: UserInformation,
 ) -> Result<HttpResponse, Error> {
     let application = application.into_inner();
 
@@ -30,8 +33,9 @@ pub async fn start_connection(
         application,
         None,
         service_addr,
-        group_id.group_id,
+        options,
         auth_expiration,
+        user,
     )
 }
 
@@ -40,8 +44,9 @@ pub async fn start_connection_with_channel_filter(
     stream: Payload,
     params: web::Path<(String, String)>,
     service_addr: web::Data<Addr<Service>>,
-    web::Query(group_id): web::Query<GroupId>,
+    web::Query(options): web::Query<StreamOptions>,
     auth_expiration: Option<web::ReqData<AuthenticatedUntil>>,
+    user: UserInformation,
 ) -> Result<HttpResponse, Error> {
     let (application, channel) = params.into_inner();
 
@@ -51,24 +56,28 @@ pub async fn start_connection_with_channel_filter(
         application,
         Some(channel),
         service_addr,
-        group_id.group_id,
+        options,
         auth_expiration,
+        user,
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn start_websocket(
     req: HttpRequest,
     stream: Payload,
     application: String,
     channel: Option<String>,
     service_addr: web::Data<Addr<Service>>,
-    group_id: Option<String>,
+    options: StreamOptions,
     auth_expiration: Option<web::ReqData<AuthenticatedUntil>>,
+    user: UserInformation,
 ) -> Result<HttpResponse, Error> {
     let auth_expiration = auth_expiration.map(|e| e.into_inner().0);
 
     let authenticator = req.app_data().cloned();
     let user_auth = req.app_data().cloned();
+    let commands = req.app_data().cloned();
 
     log::debug!(
         "Auth state - authenticator: {}, userAuth: {}",
@@ -79,12 +88,15 @@ fn start_websocket(
     // launch web socket actor
     let ws = WsHandler::new(
         application,
-        group_id,
+        options.group_id,
+        options.retained,
         channel,
         service_addr.get_ref().clone(),
         auth_expiration,
         authenticator,
         user_auth,
+        user,
+        commands,
     );
 
     ws::start(ws, &req, stream)
@@ -2,19 +2,28 @@ use crate::messages::{Disconnect, StreamError, Subscribe, WsEvent};
 use actix::{prelude::*, AsyncContext, SpawnHandle, WrapFuture};
 use anyhow::{anyhow, Result};
 use drogue_client::registry::v1::Client;
-use drogue_cloud_integration_common::stream::{EventStream, EventStreamConfig};
+use drogue_cloud_endpoint_common::sink::MqttClientConfig;
+use drogue_cloud_integration_common::{
+    retained::Retained,
+    stream::{EventStream, EventStreamConfig},
+};
 use drogue_cloud_service_api::kafka::{KafkaClientConfig, KafkaConfigExt, KafkaEventType};
-use drogue_cloud_service_common::error::ServiceError;
+use drogue_cloud_service_common::{client::DeviceStateClient, error::ServiceError};
 use futures::StreamExt;
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 use uuid::Uuid;
 
+/// Timeout for checking if the consumer group of a stream has committed offsets.
+const COMMITTED_OFFSETS_TIMEOUT: Duration = Duration::from_secs(5);
+
 // Service Actor.
 // Read from the kafka and forwards messages to the Web socket actors
 pub struct Service {
     pub clients: HashMap<Uuid, Stream>,
     pub kafka_config: KafkaClientConfig,
+    pub mqtt_backbone: Option<MqttClientConfig>,
     pub registry: Client,
+    pub last_values: Option<DeviceStateClient>,
 }
 
 impl Actor for Service {
@@ -36,15 +45,26 @@ impl Handler<Subscribe> for Service {
         let addr = msg.addr.clone();
         let registry_client = self.registry.clone();
         let kafka = self.kafka_config.clone();
+        let mqtt = self.mqtt_backbone.clone();
         let consumer_group = msg.consumer_group.clone();
+        let last_values = match msg.retained {
+            true => self.last_values.clone(),
+            false => None,
+        };
 
         let fut = async move {
             // set up a stream
             let stream =
-                Service::get_stream(registry_client, &kafka, app.clone(), consumer_group).await;
+                Service::get_stream(registry_client, &kafka, mqtt, app.clone(), consumer_group)
+                    .await;
             // run the stream
             let _ = match stream {
-                Ok(s) => Service::run_stream(s, addr.clone(), app.clone().as_str()).await,
+                Ok(s) => {
+                    if let Some(last_values) = last_values {
+                        Service::send_retained(&s, last_values, &app, &addr).await;
+                    }
+                    Service::run_stream(s, addr.clone(), app.clone().as_str()).await
+                }
                 Err(err) => {
                     log::warn!("Stream failed: {err}");
                     Err(anyhow!(err))
@@ -125,6 +145,7 @@ impl Service {
     async fn get_stream(
         registry: Client,
         kafka_config: &KafkaClientConfig,
+        mqtt: Option<MqttClientConfig>,
         application: String,
         group_id: Option<String>,
     ) -> Result<EventStream<'static>, ServiceError> {
@@ -142,23 +163,59 @@ impl Service {
             .ok_or_else(|| ServiceError::InternalError(String::from("Cannot find application")))?;
 
         // create stream
-        let stream = EventStream::new(EventStreamConfig {
-            kafka: app_res
-                .kafka_target(KafkaEventType::Events, kafka_config)
-                .map_err(|_| ServiceError::InternalError("This should be infallible".into()))?
-                .into(),
-            consumer_group: group_id.map(|group_id| format!("{application}.{group_id}")),
-        })
-        .map_err(|err| {
-            log::info!("Failed to subscribe to Kafka topic: {}", err);
-            ServiceError::InternalError("Failed to subscribe to Kafka topic".to_string())
-        })?;
+        let consumer_group = group_id.map(|group_id| format!("{application}.{group_id}"));
+        let stream = match mqtt {
+            Some(mqtt) => EventStream::new_mqtt(mqtt, &application, consumer_group),
+            None => EventStream::new(EventStreamConfig {
+                kafka: app_res
+                    .kafka_target(KafkaEventType::Events, kafka_config)
+                    .map_err(|_| ServiceError::InternalError("This should be infallible".into()))?
+                    .into(),
+                consumer_group,
+            })
+            .map_err(|err| {
+                log::info!("Failed to subscribe to Kafka topic: {}", err);
+                ServiceError::InternalError("Failed to subscribe to Kafka topic".to_string())
+            })?,
+        };
 
         // we started the stream, return it ...
         log::info!("Subscribed to Kafka topic: {}", &application);
         Ok(stream)
     }
 
+    /// Send the last event of each device and channel.
+    ///
+    /// Nothing is sent when the stream continues from committed offsets of its consumer group.
+    /// Failing to retrieve the last values doesn't fail the stream.
+    async fn send_retained(
+        stream: &EventStream<'_>,
+        last_values: DeviceStateClient,
+        application: &str,
+        recipient: &Recipient<WsEvent>,
+    ) {
+        match stream.has_committed_offsets(COMMITTED_OFFSETS_TIMEOUT) {
+            Ok(false) => {}
+            Ok(true) => {
+                log::debug!("Continuing from committed offsets, skipping retained events");
+                return;
+            }
+            Err(err) => {
+                log::info!("Failed to check for committed offsets: {err}");
+                return;
+            }
+        }
+
+        let mut retained = Retained::new(last_values, application);
+        while let Some(events) = retained.next_page().await {
+            for event in events {
+                if recipient.send(WsEvent(event)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
     async fn run_stream(
         mut stream: EventStream<'_>,
         recipient: Recipient<WsEvent>,
@@ -1,5 +1,9 @@
 use crate::{
-    messages::{Disconnect, Protocol, StreamError, Subscribe, WsEvent},
+    command::CommandContext,
+    messages::{
+        CommandOutcome, CommandReply, CommandRequest, Disconnect, Incoming, Outgoing, Protocol,
+        Request, StreamError, Subscribe, WsEvent,
+    },
     service::Service,
     CONNECTIONS_COUNTER,
 };
@@ -84,6 +88,8 @@ pub struct WsHandler {
     application: String,
     /// the optional consumer group
     group_id: Option<String>,
+    /// send the last events of the devices first
+    retained: bool,
     /// the optional channel filter
     channel: Option<String>,
     /// to exit the actor if the client was disconnected
@@ -94,21 +100,31 @@ pub struct WsHandler {
     /// It's optional, as some clients will use an access token, which are valid indefinitely
     auth_expiration: Option<DateTime<Utc>>,
     auth_context: Option<AuthContext>,
+    /// the authenticated user
+    user: UserInformation,
+    /// user authorizer, for sending commands
+    user_auth: Option<user::v1::Client>,
+    /// sending commands, if enabled
+    commands: Option<CommandContext>,
 }
 
 impl WsHandler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         application: String,
         group_id: Option<String>,
+        retained: bool,
         channel: Option<String>,
         service_addr: Addr<Service>,
         auth_expiration: Option<DateTime<Utc>>,
         authenticator: Option<openid::Authenticator>,
         user_auth: Option<user::v1::Client>,
+        user: UserInformation,
+        commands: Option<CommandContext>,
     ) -> WsHandler {
         CONNECTIONS_COUNTER.inc();
 
-        let auth_context = match (authenticator, user_auth) {
+        let auth_context = match (authenticator, user_auth.clone()) {
             (Some(authenticator), Some(user_auth)) => Some(AuthContext {
                 application: application.clone(),
                 authenticator,
@@ -120,12 +136,16 @@ impl WsHandler {
         WsHandler {
             application,
             group_id,
+            retained,
             channel,
             heartbeat: Instant::now(),
             service_addr,
             id: Uuid::new_v4(),
             auth_expiration,
             auth_context,
+            user,
+            user_auth,
+            commands,
         }
     }
 
@@ -159,10 +179,11 @@ impl WsHandler {
     /// Handle the parse result of a client protocol message.
     fn handle_protocol_message(
         ctx: &mut ws::WebsocketContext<Self>,
-        result: Result<client::Message, serde_json::Error>,
+        result: Result<Incoming, serde_json::Error>,
     ) {
         match result {
-            Ok(msg) => ctx.address().do_send(Protocol(msg)),
+            Ok(Incoming::Protocol(msg)) => ctx.address().do_send(Protocol(msg)),
+            Ok(Incoming::Request(Request::Command(request))) => ctx.address().do_send(request),
             Err(err) => {
                 ctx.close(Some(CloseReason {
                     code: CloseCode::Protocol,
@@ -199,6 +220,7 @@ impl Actor for WsHandler {
                 err_addr,
                 application: self.application.clone(),
                 consumer_group: self.group_id.clone(),
+                retained: self.retained,
                 id: self.id,
             })
             // We need to access the context when handling the future so we wrap it into an ActorFuture
@@ -243,14 +265,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsHandler {
             }
             Ok(ws::Message::Binary(data)) => {
                 INCOMING_MESSAGE.with_label_values(&["binary"]).inc();
-                Self::handle_protocol_message(
-                    ctx,
-                    serde_json::from_slice::<client::Message>(&data),
-                );
+                Self::handle_protocol_message(ctx, serde_json::from_slice::<Incoming>(&data));
             }
             Ok(ws::Message::Text(data)) => {
                 INCOMING_MESSAGE.with_label_values(&["text"]).inc();
-                Self::handle_protocol_message(ctx, serde_json::from_str::<client::Message>(&data));
+                Self::handle_protocol_message(ctx, serde_json::from_str::<Incoming>(&data));
             }
             Ok(ws::Message::Close(reason)) => {
                 INCOMING_MESSAGE.with_label_values(&["close"]).inc();
@@ -310,6 +329,42 @@ impl Handler<StreamError> for WsHandler {
     }
 }
 
+impl Handler<CommandRequest> for WsHandler {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, request: CommandRequest, _ctx: &mut Self::Context) -> Self::Result {
+        let commands = self.commands.clone();
+        let application = self.application.clone();
+        let user = self.user.clone();
+        let user_auth = self.user_auth.clone();
+
+        Box::pin(
+            async move {
+                match commands {
+                    Some(commands) => {
+                        commands
+                            .request(application, &user, user_auth.as_ref(), request)
+                            .await
+                    }
+                    None => CommandReply {
+                        request_id: request.request_id,
+                        outcome: CommandOutcome::Disabled,
+                        content_type: None,
+                        payload: None,
+                    },
+                }
+            }
+            .into_actor(self)
+            .map(|reply, _, ctx| {
+                match serde_json::to_string(&Outgoing::CommandReply(reply)) {
+                    Ok(reply) => ctx.text(reply),
+                    Err(err) => log::warn!("Could not serialize command reply: {err}"),
+                }
+            }),
+        )
+    }
+}
+
 impl Handler<Protocol> for WsHandler {
     type Result = ResponseActFuture<Self, ()>;
 